@@ -0,0 +1,199 @@
+use crate::ff_interop::encoder::{EncodePreset, EncodeSettings};
+use anyhow::{Context, bail};
+use std::{path::PathBuf, str::FromStr};
+
+pub const USAGE: &str = concat!(
+    "usage:\n",
+    "    ",
+    env!("CARGO_PKG_NAME"),
+    "                                 open the editor\n",
+    "    ",
+    env!("CARGO_PKG_NAME"),
+    " render <project> -o <output>    render a project without opening any windows\n",
+    "    ",
+    env!("CARGO_PKG_NAME"),
+    " snapshot <project> -o <png>     render one preview frame on the GPU to an image\n",
+    "\n",
+    "render options:\n",
+    "    --preset <h264|h265|webm>    encoding preset, picked from the output extension by default\n",
+    "    --crf <n>                    constant quality factor, lower is better\n",
+    "    --bitrate <bits/s>           target video bitrate, used instead of a crf\n",
+    "    --gop <frames>               maximum frames between keyframes\n",
+    "\n",
+    "snapshot options:\n",
+    "    --frame <n>                  frame to render, 0 by default\n",
+    "    --golden <png>               fail if the snapshot differs from this image\n",
+    "    --tolerance <n>              largest channel difference allowed, 2 by default\n",
+    "    --software                   render with a software adapter, for machines without a GPU",
+);
+
+/// How far a snapshot's channels may stray from the golden image by default,
+/// to allow for GPUs rounding differently.
+const DEFAULT_SNAPSHOT_TOLERANCE: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Gui,
+    Render {
+        project: PathBuf,
+        output: PathBuf,
+        settings: EncodeSettings,
+    },
+    Snapshot {
+        project: PathBuf,
+        output: PathBuf,
+        frame: u64,
+        golden: Option<PathBuf>,
+        tolerance: u8,
+        software: bool,
+    },
+}
+
+/// Parses the arguments after the program name.
+pub fn parse_args(mut args: impl Iterator<Item = String>) -> anyhow::Result<Command> {
+    let Some(command) = args.next() else {
+        return Ok(Command::Gui);
+    };
+
+    match command.as_str() {
+        "render" => {
+            let mut project = None;
+            let mut output: Option<PathBuf> = None;
+            let mut preset = None;
+            let mut crf = None;
+            let mut bitrate = None;
+            let mut gop = None;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "-o" | "--output" => {
+                        output = Some(args.next().context("expected a path after -o")?.into())
+                    }
+                    "--preset" => {
+                        let id = args.next().context("expected a name after --preset")?;
+                        preset = Some(
+                            EncodePreset::from_id(&id)
+                                .with_context(|| format!("unknown preset \"{id}\""))?,
+                        );
+                    }
+                    "--crf" => crf = Some(parse_number(&arg, args.next())?),
+                    "--bitrate" => bitrate = Some(parse_number(&arg, args.next())?),
+                    "--gop" => gop = Some(parse_number(&arg, args.next())?),
+                    _ if arg.starts_with('-') => bail!("unknown option \"{arg}\""),
+                    _ if project.is_none() => project = Some(arg.into()),
+                    _ => bail!("unexpected argument \"{arg}\""),
+                }
+            }
+
+            if crf.is_some() && bitrate.is_some() {
+                bail!("--crf and --bitrate can't be used together");
+            }
+            let output = output.context("render needs an output file, given with -o")?;
+            let mut settings = preset
+                .unwrap_or_else(|| EncodePreset::for_path(&output))
+                .settings();
+            if let Some(bitrate) = bitrate {
+                settings.video_bitrate = Some(bitrate);
+                settings.crf = None;
+            }
+            if crf.is_some() {
+                settings.crf = crf;
+            }
+            if gop.is_some() {
+                settings.gop = gop;
+            }
+
+            Ok(Command::Render {
+                project: project.context("render needs a project file")?,
+                output,
+                settings,
+            })
+        }
+        "snapshot" => {
+            let mut project = None;
+            let mut output: Option<PathBuf> = None;
+            let mut frame = 0;
+            let mut golden = None;
+            let mut tolerance = DEFAULT_SNAPSHOT_TOLERANCE;
+            let mut software = false;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "-o" | "--output" => {
+                        output = Some(args.next().context("expected a path after -o")?.into())
+                    }
+                    "--frame" => frame = parse_number(&arg, args.next())?,
+                    "--golden" => {
+                        golden = Some(
+                            args.next()
+                                .context("expected a path after --golden")?
+                                .into(),
+                        )
+                    }
+                    "--tolerance" => tolerance = parse_number(&arg, args.next())?,
+                    "--software" => software = true,
+                    _ if arg.starts_with('-') => bail!("unknown option \"{arg}\""),
+                    _ if project.is_none() => project = Some(arg.into()),
+                    _ => bail!("unexpected argument \"{arg}\""),
+                }
+            }
+
+            Ok(Command::Snapshot {
+                project: project.context("snapshot needs a project file")?,
+                output: output.context("snapshot needs an output image, given with -o")?,
+                frame,
+                golden,
+                tolerance,
+                software,
+            })
+        }
+        _ => bail!("unknown command \"{command}\""),
+    }
+}
+
+fn parse_number<T: FromStr>(option: &str, value: Option<String>) -> anyhow::Result<T> {
+    let value = value.with_context(|| format!("expected a number after {option}"))?;
+    value
+        .parse()
+        .ok()
+        .with_context(|| format!("\"{value}\" is not a valid number for {option}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ff_interop::encoder::Container;
+
+    fn parse(args: &str) -> anyhow::Result<Command> {
+        parse_args(args.split_whitespace().map(str::to_string))
+    }
+
+    fn render_settings(args: &str) -> EncodeSettings {
+        match parse(args).unwrap() {
+            Command::Render { settings, .. } => settings,
+            command => panic!("expected a render command, got {command:?}"),
+        }
+    }
+
+    #[test]
+    fn picks_the_preset_from_the_output_extension() {
+        let settings = render_settings("render project.jvp -o out.webm");
+        assert_eq!(settings, EncodePreset::WebOpus.settings());
+        assert_eq!(settings.container, Container::WebM);
+        let settings = render_settings("render project.jvp -o out.mp4");
+        assert_eq!(settings, EncodePreset::H264HighQuality.settings());
+    }
+
+    #[test]
+    fn bitrate_replaces_the_presets_crf() {
+        let settings = render_settings("render project.jvp -o out.mp4 --bitrate 8000000");
+        assert_eq!(settings.video_bitrate, Some(8_000_000));
+        assert_eq!(settings.crf, None);
+        let settings = render_settings("render project.jvp -o out.mp4 --crf 23");
+        assert_eq!(settings.video_bitrate, None);
+        assert_eq!(settings.crf, Some(23));
+    }
+
+    #[test]
+    fn refuses_crf_and_bitrate_together() {
+        assert!(parse("render project.jvp -o out.mp4 --crf 23 --bitrate 8000000").is_err());
+    }
+}
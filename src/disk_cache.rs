@@ -0,0 +1,110 @@
+use crate::project::MediaFingerprint;
+use anyhow::Context;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+/// The folder data of the given kind (e.g. "filmstrips") is cached in, under
+/// the platform's cache folder. Nothing in it is needed; it can all be made
+/// again from the media.
+pub fn cache_dir(kind: &str) -> PathBuf {
+    let base = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library").join("Caches"))
+    } else {
+        env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+    };
+    base.unwrap_or_else(env::temp_dir)
+        .join(env!("CARGO_PKG_NAME"))
+        .join(kind)
+}
+
+/// A file name for data made from `path`, which changes whenever `params`
+/// (whatever else the data depends on) differ.
+///
+/// With the file's fingerprint, the key is made from its content, so copies
+/// and moves of the file share their cached data. Otherwise, or if the file
+/// has been touched since it was fingerprinted, it's made from the path, and
+/// changes whenever the file's size or modification time does.
+pub fn source_key(
+    path: &Path,
+    fingerprint: Option<&MediaFingerprint>,
+    params: &str,
+) -> anyhow::Result<String> {
+    let metadata = fs::metadata(path)
+        .with_context(|| format!("failed to read metadata of {}", path.display()))?;
+
+    let mut hash = Fnv1a::default();
+    match fingerprint {
+        Some(fingerprint) if fingerprint.matches_metadata(&metadata) => {
+            hash.write(&fingerprint.size.to_le_bytes());
+            hash.write(&fingerprint.content_hash.to_le_bytes());
+            hash.write(&fingerprint.streams_hash.to_le_bytes());
+        }
+        _ => {
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .unwrap_or_default();
+            hash.write(path.to_string_lossy().as_bytes());
+            hash.write(&metadata.len().to_le_bytes());
+            hash.write(&modified.as_secs().to_le_bytes());
+            hash.write(&modified.subsec_nanos().to_le_bytes());
+        }
+    }
+    hash.write(params.as_bytes());
+    Ok(format!("{:016x}", hash.finish()))
+}
+
+/// Writes a file all at once, so a crash part way through can't leave a
+/// truncated file behind for the next run to read. If `write` fails, whatever
+/// it managed to write is deleted.
+pub fn write_atomic(
+    path: &Path,
+    write: impl FnOnce(&Path) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create folder {}", dir.display()))?;
+    }
+    // The real extension stays last, since writers like the encoder go by it.
+    let partial = match path.extension() {
+        Some(extension) => path.with_extension(Path::new("partial").with_extension(extension)),
+        None => path.with_extension("partial"),
+    };
+    if let Err(err) = write(&partial) {
+        let _ = fs::remove_file(&partial);
+        return Err(err);
+    }
+    fs::rename(&partial, path)
+        .with_context(|| format!("failed to move file into place at {}", path.display()))
+}
+
+/// The 64-bit FNV-1a hash. Unlike `DefaultHasher`, it's the same between
+/// builds, so cache keys and fingerprints stay valid across updates.
+pub struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv1a {
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
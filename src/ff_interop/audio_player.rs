@@ -0,0 +1,431 @@
+use crate::project::AudioSpec;
+use anyhow::Context;
+use ffmpeg_next::{
+    ChannelLayout, Rational, Rescale, codec, format, frame, rescale, software::resampling,
+};
+use log::info;
+
+/// A run of decoded audio, resampled to the project's `AudioSpec`. Samples are
+/// planar, with one `Vec` per output channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioChunk {
+    /// Position of the first sample, counted in output-rate samples from the
+    /// start of the stream.
+    pub start_sample: u64,
+    pub planes: Vec<Vec<f32>>,
+}
+
+impl AudioChunk {
+    pub fn sample_count(&self) -> usize {
+        self.planes.first().map(Vec::len).unwrap_or(0)
+    }
+}
+
+/// How far before the target a seek lands, in `rescale::TIME_BASE` units. In
+/// interleaved files the seek goes to a video keyframe, and the audio packet
+/// playing at that moment can come before it in the file.
+const SEEK_PREROLL: i64 = 500_000;
+
+pub struct FfmpegAudioDecoder {
+    stream_index: usize,
+    input_ctx: format::context::Input,
+
+    audio_decoder: codec::decoder::Audio,
+    /// Created from the first decoded frame, since some decoders only know
+    /// their real sample format and layout once they've seen data.
+    resampler_ctx: Option<resampling::Context>,
+
+    output: AudioSpec,
+    time_base: Rational,
+    start_pts: i64,
+    /// Output-rate position of the next sample the resampler will produce, or
+    /// `None` right after a seek, until we see a timestamped frame.
+    next_sample: Option<u64>,
+    /// Samples before this position are dropped, so that after seeking to a
+    /// keyframe we hand back audio starting exactly where it was asked for.
+    discard_before: u64,
+    /// The unread remainder of a chunk that `read_samples` only partly used.
+    pending_chunk: Option<AudioChunk>,
+    reached_eof: bool,
+}
+
+impl FfmpegAudioDecoder {
+    pub fn new(
+        input_ctx: format::context::Input,
+        stream_index: usize,
+        output: AudioSpec,
+    ) -> anyhow::Result<Self> {
+        let audio_stream = input_ctx
+            .stream(stream_index)
+            .with_context(|| format!("failed to locate stream at index {stream_index}"))?;
+        info!("got audio stream at index {stream_index}");
+        let time_base = audio_stream.time_base();
+        let start_pts = match audio_stream.start_time() {
+            ffmpeg_next::ffi::AV_NOPTS_VALUE => 0,
+            start_pts => start_pts,
+        };
+
+        let audio_decoder = codec::context::Context::from_parameters(audio_stream.parameters())
+            .context("failed to create audio decoder")
+            .and_then(|c| {
+                c.decoder()
+                    .audio()
+                    .context("failed to get audio from decoder context")
+            })?;
+        info!("created audio decoder");
+
+        Ok(Self {
+            stream_index,
+            input_ctx,
+            audio_decoder,
+            resampler_ctx: None,
+            output,
+            time_base,
+            start_pts,
+            next_sample: Some(0),
+            discard_before: 0,
+            pending_chunk: None,
+            reached_eof: false,
+        })
+    }
+
+    fn output_time_base(&self) -> Rational {
+        Rational::new(1, self.output.sample_rate as i32)
+    }
+
+    fn output_layout(&self) -> ChannelLayout {
+        ChannelLayout::default(self.output.channels as i32)
+    }
+
+    /// Seeks so that the next chunk returned starts at the given output-rate
+    /// sample position.
+    pub fn seek_to_sample(&mut self, sample: u64) -> anyhow::Result<()> {
+        let target_pts =
+            self.start_pts + (sample as i64).rescale(self.output_time_base(), self.time_base);
+        let start_ts = self.start_pts.rescale(self.time_base, rescale::TIME_BASE);
+        let seek_ts =
+            (target_pts.rescale(self.time_base, rescale::TIME_BASE) - SEEK_PREROLL).max(start_ts);
+        self.input_ctx
+            .seek(seek_ts, ..seek_ts)
+            .with_context(|| format!("failed to seek to audio sample {sample}"))?;
+        self.audio_decoder.flush();
+        // Anything buffered in the resampler belongs to the old position.
+        self.resampler_ctx = None;
+        self.next_sample = None;
+        self.discard_before = sample;
+        self.pending_chunk = None;
+        self.reached_eof = false;
+        Ok(())
+    }
+
+    /// Decodes and resamples the next run of audio, or returns `None` once the
+    /// stream is exhausted.
+    pub fn receive_chunk(&mut self) -> anyhow::Result<Option<AudioChunk>> {
+        if let Some(chunk) = self.pending_chunk.take() {
+            return Ok(Some(chunk));
+        }
+
+        loop {
+            let Some(resampled) = self.decode_next_resampled()? else {
+                return Ok(None);
+            };
+
+            let start_sample = self.next_sample.unwrap_or(0);
+            let sample_count = resampled.samples() as u64;
+            self.next_sample = Some(start_sample + sample_count);
+
+            let skip = self
+                .discard_before
+                .saturating_sub(start_sample)
+                .min(sample_count);
+            if skip == sample_count {
+                continue;
+            }
+            let planes = (0..self.output.channels as usize)
+                .map(|channel| resampled.plane::<f32>(channel)[skip as usize..].to_vec())
+                .collect();
+            return Ok(Some(AudioChunk {
+                start_sample: start_sample + skip,
+                planes,
+            }));
+        }
+    }
+
+    /// Output-rate position of the next sample `receive_chunk` will return, if
+    /// known without decoding.
+    pub fn position(&self) -> Option<u64> {
+        match &self.pending_chunk {
+            Some(chunk) => Some(chunk.start_sample),
+            None => self.next_sample.map(|next| next.max(self.discard_before)),
+        }
+    }
+
+    /// Reads exactly `count` samples starting at `start`, seeking if needed and
+    /// padding with silence past the end of the stream.
+    pub fn read_samples(&mut self, start: u64, count: usize) -> anyhow::Result<AudioChunk> {
+        if self.position() != Some(start) {
+            self.seek_to_sample(start)?;
+        }
+
+        let channels = self.output.channels as usize;
+        let mut planes = vec![Vec::with_capacity(count); channels];
+        while planes[0].len() < count {
+            let Some(mut chunk) = self.receive_chunk()? else {
+                break;
+            };
+            let wanted = count - planes[0].len();
+            if chunk.sample_count() > wanted {
+                let rest = chunk
+                    .planes
+                    .iter_mut()
+                    .map(|samples| samples.split_off(wanted))
+                    .collect();
+                self.pending_chunk = Some(AudioChunk {
+                    start_sample: chunk.start_sample + wanted as u64,
+                    planes: rest,
+                });
+            }
+            for (plane, samples) in planes.iter_mut().zip(chunk.planes) {
+                plane.extend(samples);
+            }
+        }
+        for plane in &mut planes {
+            plane.resize(count, 0.0);
+        }
+
+        Ok(AudioChunk {
+            start_sample: start,
+            planes,
+        })
+    }
+
+    fn decode_next_resampled(&mut self) -> anyhow::Result<Option<frame::Audio>> {
+        let stream_index = self.stream_index;
+        let mut decoded_frame = frame::Audio::empty();
+        loop {
+            if self.audio_decoder.receive_frame(&mut decoded_frame).is_ok() {
+                if self.next_sample.is_none() {
+                    let pts = decoded_frame
+                        .timestamp()
+                        .or(decoded_frame.pts())
+                        .context("decoded audio frame has no timestamp")?;
+                    let sample =
+                        (pts - self.start_pts).rescale(self.time_base, self.output_time_base());
+                    self.next_sample = Some(sample.max(0) as u64);
+                }
+                return self.resample(&decoded_frame).map(Some);
+            }
+            if self.reached_eof {
+                return self.flush_resampler();
+            }
+
+            match self
+                .input_ctx
+                .packets()
+                .find(|(stream, _)| stream.index() == stream_index)
+            {
+                Some((_, packet)) => self
+                    .audio_decoder
+                    .send_packet(&packet)
+                    .context("failed to send packet from input to audio decoder")?,
+                None => {
+                    self.reached_eof = true;
+                    self.audio_decoder
+                        .send_eof()
+                        .context("failed to flush audio decoder")?;
+                }
+            }
+        }
+    }
+
+    /// Drains the samples the resampler holds back once the decoder is done.
+    fn flush_resampler(&mut self) -> anyhow::Result<Option<frame::Audio>> {
+        let output_layout = self.output_layout();
+        let Some(resampler_ctx) = self.resampler_ctx.as_mut() else {
+            return Ok(None);
+        };
+        if resampler_ctx.delay().is_none() {
+            return Ok(None);
+        }
+
+        let mut flushed = frame::Audio::new(
+            format::Sample::F32(format::sample::Type::Planar),
+            1024,
+            output_layout,
+        );
+        resampler_ctx
+            .flush(&mut flushed)
+            .context("failed to flush audio resampler")?;
+        if flushed.samples() == 0 {
+            self.resampler_ctx = None;
+            return Ok(None);
+        }
+        Ok(Some(flushed))
+    }
+
+    fn resample(&mut self, decoded: &frame::Audio) -> anyhow::Result<frame::Audio> {
+        let input_layout = match decoded.channel_layout() {
+            layout if layout.is_empty() => ChannelLayout::default(decoded.channels() as i32),
+            layout => layout,
+        };
+        let input_definition = resampling::context::Definition {
+            format: decoded.format(),
+            channel_layout: input_layout,
+            rate: decoded.rate(),
+        };
+        if self
+            .resampler_ctx
+            .as_ref()
+            .is_none_or(|ctx| *ctx.input() != input_definition)
+        {
+            self.resampler_ctx = Some(
+                resampling::Context::get(
+                    decoded.format(),
+                    input_layout,
+                    decoded.rate(),
+                    format::Sample::F32(format::sample::Type::Planar),
+                    self.output_layout(),
+                    self.output.sample_rate,
+                )
+                .context("failed to create audio resampler")?,
+            );
+            info!("created audio resampler");
+        }
+
+        // Leave room for resampling up, plus whatever the resampler buffered.
+        let capacity = decoded.samples() as u64 * self.output.sample_rate as u64
+            / decoded.rate().max(1) as u64
+            + 256;
+        let mut resampled = frame::Audio::new(
+            format::Sample::F32(format::sample::Type::Planar),
+            capacity as usize,
+            self.output_layout(),
+        );
+        self.resampler_ctx
+            .as_mut()
+            .unwrap()
+            .run(decoded, &mut resampled)
+            .context("failed to resample decoded audio frame")?;
+        Ok(resampled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{project::FrameNum, test_media};
+    use ffmpeg_next::media;
+
+    /// Matches 16-bit FLAC, give or take a rounding.
+    const TOLERANCE: f32 = 1e-3;
+
+    fn open(output: AudioSpec) -> FfmpegAudioDecoder {
+        let input_ctx = format::input(test_media::tone_clip()).unwrap();
+        let stream_index = input_ctx
+            .streams()
+            .best(media::Type::Audio)
+            .unwrap()
+            .index();
+        FfmpegAudioDecoder::new(input_ctx, stream_index, output).unwrap()
+    }
+
+    /// Checks every channel of `chunk` against the tone, from its start.
+    fn assert_tone(chunk: &AudioChunk, sample_rate: u32) {
+        for plane in &chunk.planes {
+            for (sample, &level) in (chunk.start_sample..).zip(plane) {
+                let expected = test_media::tone_sample(sample, sample_rate);
+                assert!(
+                    (level - expected).abs() < TOLERANCE,
+                    "sample {sample} is {level} rather than {expected}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn resamples_to_the_output_spec() {
+        let spec = AudioSpec {
+            sample_rate: 44_100,
+            channels: 1,
+        };
+        let mut decoder = open(spec);
+        let chunk = decoder.read_samples(0, 3 * 44_100).unwrap();
+        assert_eq!(chunk.planes.len(), 1);
+        assert_eq!(chunk.sample_count(), 3 * 44_100);
+
+        // Downmixing scales the tone by however much the resampler likes, so
+        // compare its shape, past the resampler's edge effects at the start.
+        let samples = &chunk.planes[0][1000..];
+        let tone: Vec<f32> = (1000..chunk.sample_count() as u64)
+            .map(|sample| test_media::tone_sample(sample, spec.sample_rate))
+            .collect();
+        let dot = |a: &[f32], b: &[f32]| a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>();
+        let gain = dot(samples, &tone) / dot(&tone, &tone);
+        assert!(gain > 0.5, "the tone was downmixed to a gain of {gain}");
+        for (&level, &expected) in samples.iter().zip(&tone) {
+            assert!((level - gain * expected).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn seeks_to_exact_samples() {
+        let spec = AudioSpec::default();
+        let mut decoder = open(spec);
+        // Mid-packet, backwards, and on past a video keyframe.
+        for start in [100_000, 13_841, 0, 1, 19_200, 150_017] {
+            let chunk = decoder.read_samples(start, 500).unwrap();
+            assert_eq!(chunk.start_sample, start);
+            assert_tone(&chunk, spec.sample_rate);
+        }
+    }
+
+    #[test]
+    fn drops_samples_before_the_seek_target() {
+        let spec = AudioSpec::default();
+        let mut decoder = open(spec);
+        decoder.seek_to_sample(77_777).unwrap();
+        let chunk = decoder.receive_chunk().unwrap().unwrap();
+        assert_eq!(chunk.start_sample, 77_777);
+        assert_tone(&chunk, spec.sample_rate);
+    }
+
+    #[test]
+    fn splits_chunks_across_reads() {
+        let spec = AudioSpec::default();
+        let mut decoder = open(spec);
+        let mut start = 0;
+        // Shorter and longer than a decoded chunk, so reads end mid-chunk and
+        // span several.
+        for count in [100, 1, 7_000, 3, 20_000] {
+            let chunk = decoder.read_samples(start, count).unwrap();
+            assert_eq!(chunk.sample_count(), count);
+            assert_tone(&chunk, spec.sample_rate);
+            start += count as u64;
+            assert_eq!(decoder.position(), Some(start));
+        }
+    }
+
+    #[test]
+    fn pads_with_silence_past_the_end() {
+        let spec = AudioSpec::default();
+        let clip_samples =
+            spec.first_sample_of_frame(FrameNum(test_media::CLIP_FRAMES), test_media::CLIP_FPS);
+        let mut decoder = open(spec);
+
+        let chunk = decoder.read_samples(clip_samples - 1_000, 20_000).unwrap();
+        assert_eq!(chunk.sample_count(), 20_000);
+        let (tone, after) = chunk.planes[0].split_at(1_000);
+        assert_tone(
+            &AudioChunk {
+                start_sample: chunk.start_sample,
+                planes: vec![tone.to_vec()],
+            },
+            spec.sample_rate,
+        );
+        assert!(after.iter().all(|&level| level.abs() < TOLERANCE));
+
+        let chunk = decoder.read_samples(10 * clip_samples, 500).unwrap();
+        assert_eq!(chunk.sample_count(), 500);
+        assert!(chunk.planes.iter().flatten().all(|&level| level == 0.0));
+    }
+}
@@ -0,0 +1,664 @@
+use super::{audio_player::AudioChunk, image_to_rgba_frame};
+use crate::project::{AudioSpec, JadeRational, Resolution};
+use anyhow::{Context, bail};
+use ffmpeg_next::{
+    ChannelLayout, Dictionary, Packet, Rational, codec, encoder, format, frame, software::scaling,
+};
+use image::RgbaImage;
+use log::info;
+use std::path::Path;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Container {
+    Mp4,
+    Mkv,
+    WebM,
+}
+
+impl Container {
+    pub fn format_name(self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::Mkv => "matroska",
+            Self::WebM => "webm",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::Mkv => "mkv",
+            Self::WebM => "webm",
+        }
+    }
+
+    /// Whether a file with this extension is expected to hold this container.
+    pub fn matches_extension(self, extension: &str) -> bool {
+        let extension = extension.to_ascii_lowercase();
+        match self {
+            Self::Mp4 => matches!(extension.as_str(), "mp4" | "m4v" | "mov"),
+            Self::Mkv => extension == "mkv",
+            Self::WebM => extension == "webm",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VideoCodec {
+    H264,
+    H265,
+    Vp9,
+}
+
+impl VideoCodec {
+    pub fn encoder_name(self) -> &'static str {
+        match self {
+            Self::H264 => "libx264",
+            Self::H265 => "libx265",
+            Self::Vp9 => "libvpx-vp9",
+        }
+    }
+}
+
+// No preset picks MP3 or Vorbis yet, but FFmpeg is built with both.
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AudioCodec {
+    Aac,
+    Flac,
+    Mp3,
+    Opus,
+    Vorbis,
+}
+
+impl AudioCodec {
+    pub fn encoder_name(self) -> &'static str {
+        match self {
+            Self::Aac => "aac",
+            Self::Flac => "flac",
+            Self::Mp3 => "libmp3lame",
+            Self::Opus => "libopus",
+            Self::Vorbis => "libvorbis",
+        }
+    }
+}
+
+/// Everything about how an export is encoded. Start from an `EncodePreset` and
+/// override whichever fields need changing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodeSettings {
+    pub container: Container,
+    pub video_codec: VideoCodec,
+    /// `None` leaves the output without an audio stream.
+    pub audio_codec: Option<AudioCodec>,
+    /// Constant quality factor, used instead of `video_bitrate` when set.
+    pub crf: Option<u32>,
+    /// Target video bitrate in bits per second, ignored when `crf` is set.
+    pub video_bitrate: Option<usize>,
+    /// Target audio bitrate in bits per second, ignored by lossless codecs.
+    pub audio_bitrate: usize,
+    /// Maximum frames between keyframes, or the encoder's default if `None`.
+    pub gop: Option<u32>,
+    /// The x264/x265 speed preset, e.g. "medium" or "slower".
+    pub speed_preset: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EncodePreset {
+    H264HighQuality,
+    H265Archival,
+    WebOpus,
+}
+
+impl EncodePreset {
+    pub const ALL: [Self; 3] = [Self::H264HighQuality, Self::H265Archival, Self::WebOpus];
+
+    /// Short name used to pick the preset on the command line.
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::H264HighQuality => "h264",
+            Self::H265Archival => "h265",
+            Self::WebOpus => "webm",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|preset| preset.id() == id)
+    }
+
+    /// The preset that makes the most sense for an output file, judging by its
+    /// extension.
+    pub fn for_path(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("webm") => Self::WebOpus,
+            Some("mkv") => Self::H265Archival,
+            _ => Self::H264HighQuality,
+        }
+    }
+
+    pub fn settings(self) -> EncodeSettings {
+        match self {
+            Self::H264HighQuality => EncodeSettings {
+                container: Container::Mp4,
+                video_codec: VideoCodec::H264,
+                audio_codec: Some(AudioCodec::Aac),
+                crf: Some(18),
+                video_bitrate: None,
+                audio_bitrate: 256_000,
+                gop: None,
+                speed_preset: Some("slow".to_string()),
+            },
+            Self::H265Archival => EncodeSettings {
+                container: Container::Mkv,
+                video_codec: VideoCodec::H265,
+                audio_codec: Some(AudioCodec::Flac),
+                crf: Some(16),
+                video_bitrate: None,
+                audio_bitrate: 0,
+                gop: None,
+                speed_preset: Some("slower".to_string()),
+            },
+            Self::WebOpus => EncodeSettings {
+                container: Container::WebM,
+                video_codec: VideoCodec::Vp9,
+                audio_codec: Some(AudioCodec::Opus),
+                crf: Some(32),
+                video_bitrate: None,
+                audio_bitrate: 128_000,
+                gop: Some(240),
+                speed_preset: None,
+            },
+        }
+    }
+}
+
+struct VideoOutput {
+    encoder: encoder::Video,
+    stream_index: usize,
+    time_base: Rational,
+    scaler_ctx: scaling::Context,
+    resolution: Resolution,
+    frames_sent: i64,
+}
+
+struct AudioOutput {
+    encoder: encoder::Audio,
+    stream_index: usize,
+    time_base: Rational,
+    sample_format: format::Sample,
+    channel_layout: ChannelLayout,
+    /// Samples per frame the encoder wants.
+    frame_size: usize,
+    /// Whether the encoder accepts a short final frame.
+    variable_frame_size: bool,
+    /// Planar samples waiting for a full frame's worth to build up.
+    pending: Vec<Vec<f32>>,
+    samples_sent: i64,
+}
+
+/// Encodes rendered RGBA frames and mixed audio, and muxes them into a file.
+pub struct FfmpegEncoder {
+    output_ctx: format::context::Output,
+    video: VideoOutput,
+    audio: Option<AudioOutput>,
+}
+
+impl FfmpegEncoder {
+    pub fn new(
+        path: &Path,
+        resolution: Resolution,
+        fps: JadeRational,
+        audio_spec: AudioSpec,
+        settings: &EncodeSettings,
+    ) -> anyhow::Result<Self> {
+        // The container is given explicitly, so nothing else would stop a
+        // WebM stream being written into a file named .mp4.
+        if let Some(extension) = path.extension().and_then(|ext| ext.to_str())
+            && !settings.container.matches_extension(extension)
+        {
+            bail!(
+                "{} has a .{extension} extension, but is being encoded as {:?}; name it .{} \
+                 instead",
+                path.display(),
+                settings.container,
+                settings.container.extension()
+            );
+        }
+        let mut output_ctx = format::output_as(&path, settings.container.format_name())
+            .with_context(|| format!("failed to create output context for {}", path.display()))?;
+
+        let video = Self::add_video_output(&mut output_ctx, resolution, fps, settings)?;
+        let audio = settings
+            .audio_codec
+            .map(|audio_codec| {
+                Self::add_audio_output(&mut output_ctx, audio_spec, audio_codec, settings)
+            })
+            .transpose()?;
+
+        output_ctx
+            .write_header()
+            .context("failed to write output header")?;
+
+        Ok(Self {
+            output_ctx,
+            video,
+            audio,
+        })
+    }
+
+    fn add_video_output(
+        output_ctx: &mut format::context::Output,
+        resolution: Resolution,
+        fps: JadeRational,
+        settings: &EncodeSettings,
+    ) -> anyhow::Result<VideoOutput> {
+        let global_header = output_ctx
+            .format()
+            .flags()
+            .contains(format::Flags::GLOBAL_HEADER);
+        let encoder_name = settings.video_codec.encoder_name();
+        let codec = encoder::find_by_name(encoder_name)
+            .with_context(|| format!("{encoder_name} encoder is unavailable"))?;
+        let mut stream = output_ctx
+            .add_stream(codec)
+            .context("failed to add video stream to output")?;
+        let stream_index = stream.index();
+
+        let time_base = Rational::new(fps.den, fps.num);
+        let mut video_encoder = codec::context::Context::new_with_codec(codec)
+            .encoder()
+            .video()
+            .context("failed to create video encoder")?;
+        video_encoder.set_width(resolution.width);
+        video_encoder.set_height(resolution.height);
+        video_encoder.set_format(format::Pixel::YUV420P);
+        video_encoder.set_time_base(time_base);
+        video_encoder.set_frame_rate(Some(fps.ff_rational()));
+        if let Some(gop) = settings.gop {
+            video_encoder.set_gop(gop);
+        }
+        if settings.crf.is_none()
+            && let Some(bitrate) = settings.video_bitrate
+        {
+            video_encoder.set_bit_rate(bitrate);
+        }
+        if global_header {
+            video_encoder.set_flags(codec::Flags::GLOBAL_HEADER);
+        }
+
+        let mut options = Dictionary::new();
+        if let Some(crf) = settings.crf {
+            options.set("crf", &crf.to_string());
+            if settings.video_codec == VideoCodec::Vp9 {
+                // libvpx only does constant quality when the bitrate is zeroed.
+                options.set("b", "0");
+            }
+        }
+        if let Some(speed_preset) = &settings.speed_preset {
+            options.set("preset", speed_preset);
+        }
+        let video_encoder = video_encoder
+            .open_with(options)
+            .context("failed to open video encoder")?;
+        stream.set_parameters(&video_encoder);
+        stream.set_time_base(time_base);
+        info!("created {encoder_name} video encoder");
+
+        let scaler_ctx = scaling::context::Context::get(
+            format::Pixel::RGBA,
+            resolution.width,
+            resolution.height,
+            format::Pixel::YUV420P,
+            resolution.width,
+            resolution.height,
+            scaling::flag::Flags::BILINEAR,
+        )
+        .context("failed to create software scaler for pixel reformatting")?;
+
+        Ok(VideoOutput {
+            encoder: video_encoder,
+            stream_index,
+            time_base,
+            scaler_ctx,
+            resolution,
+            frames_sent: 0,
+        })
+    }
+
+    fn add_audio_output(
+        output_ctx: &mut format::context::Output,
+        audio_spec: AudioSpec,
+        audio_codec: AudioCodec,
+        settings: &EncodeSettings,
+    ) -> anyhow::Result<AudioOutput> {
+        let global_header = output_ctx
+            .format()
+            .flags()
+            .contains(format::Flags::GLOBAL_HEADER);
+        let encoder_name = audio_codec.encoder_name();
+        let codec = encoder::find_by_name(encoder_name)
+            .with_context(|| format!("{encoder_name} encoder is unavailable"))?;
+        let codec_audio = codec
+            .audio()
+            .with_context(|| format!("{encoder_name} is not an audio encoder"))?;
+
+        if let Some(mut rates) = codec_audio.rates()
+            && !rates.any(|rate| rate == audio_spec.sample_rate as i32)
+        {
+            bail!(
+                "{encoder_name} doesn't support the project's {} Hz sample rate",
+                audio_spec.sample_rate
+            );
+        }
+        let sample_format = match codec_audio.formats() {
+            Some(formats) => {
+                let formats: Vec<_> = formats.collect();
+                SUPPORTED_SAMPLE_FORMATS
+                    .into_iter()
+                    .find(|supported| formats.contains(supported))
+                    .with_context(|| {
+                        format!("{encoder_name} needs a sample format we can't produce")
+                    })?
+            }
+            None => SUPPORTED_SAMPLE_FORMATS[0],
+        };
+        let variable_frame_size = codec
+            .capabilities()
+            .contains(codec::Capabilities::VARIABLE_FRAME_SIZE);
+
+        let mut stream = output_ctx
+            .add_stream(codec)
+            .context("failed to add audio stream to output")?;
+        let stream_index = stream.index();
+
+        let time_base = Rational::new(1, audio_spec.sample_rate as i32);
+        let channel_layout = ChannelLayout::default(audio_spec.channels as i32);
+        let mut audio_encoder = codec::context::Context::new_with_codec(codec)
+            .encoder()
+            .audio()
+            .context("failed to create audio encoder")?;
+        audio_encoder.set_rate(audio_spec.sample_rate as i32);
+        audio_encoder.set_channel_layout(channel_layout);
+        audio_encoder.set_format(sample_format);
+        audio_encoder.set_time_base(time_base);
+        if settings.audio_bitrate > 0 {
+            audio_encoder.set_bit_rate(settings.audio_bitrate);
+        }
+        if global_header {
+            audio_encoder.set_flags(codec::Flags::GLOBAL_HEADER);
+        }
+
+        let audio_encoder = audio_encoder
+            .open_with(Dictionary::new())
+            .context("failed to open audio encoder")?;
+        stream.set_parameters(&audio_encoder);
+        stream.set_time_base(time_base);
+        info!("created {encoder_name} audio encoder");
+
+        let frame_size = match audio_encoder.frame_size() {
+            0 => 1024,
+            frame_size => frame_size as usize,
+        };
+        Ok(AudioOutput {
+            encoder: audio_encoder,
+            stream_index,
+            time_base,
+            sample_format,
+            channel_layout,
+            frame_size,
+            variable_frame_size,
+            pending: vec![vec![]; audio_spec.channels as usize],
+            samples_sent: 0,
+        })
+    }
+
+    pub fn has_audio(&self) -> bool {
+        self.audio.is_some()
+    }
+
+    /// Encodes the next frame. Frames must match the output resolution and are
+    /// timestamped in the order they're given.
+    pub fn encode_frame(&mut self, image: &RgbaImage) -> anyhow::Result<()> {
+        let video = &mut self.video;
+        anyhow::ensure!(
+            image.dimensions() == (video.resolution.width, video.resolution.height),
+            "frame is {}x{}, but the output is {}x{}",
+            image.width(),
+            image.height(),
+            video.resolution.width,
+            video.resolution.height
+        );
+
+        let rgba_frame = image_to_rgba_frame(image);
+        let mut yuv_frame = frame::Video::empty();
+        video
+            .scaler_ctx
+            .run(&rgba_frame, &mut yuv_frame)
+            .context("failed to convert rendered frame to the encoder's pixel format")?;
+        yuv_frame.set_pts(Some(video.frames_sent));
+        video.frames_sent += 1;
+
+        video
+            .encoder
+            .send_frame(&yuv_frame)
+            .context("failed to send frame to video encoder")?;
+        self.write_video_packets()
+    }
+
+    /// Queues mixed audio for encoding. Chunks are treated as contiguous, and
+    /// must have as many planes as the project has channels.
+    pub fn encode_audio(&mut self, chunk: &AudioChunk) -> anyhow::Result<()> {
+        let Some(audio) = self.audio.as_mut() else {
+            return Ok(());
+        };
+        anyhow::ensure!(
+            chunk.planes.len() == audio.pending.len(),
+            "audio has {} channels, but the output has {}",
+            chunk.planes.len(),
+            audio.pending.len()
+        );
+
+        for (pending, samples) in audio.pending.iter_mut().zip(&chunk.planes) {
+            pending.extend_from_slice(samples);
+        }
+        let frame_size = audio.frame_size;
+        while self
+            .audio
+            .as_ref()
+            .is_some_and(|audio| audio.pending[0].len() >= frame_size)
+        {
+            self.send_audio_frame(frame_size)?;
+        }
+        Ok(())
+    }
+
+    fn send_audio_frame(&mut self, sample_count: usize) -> anyhow::Result<()> {
+        let audio = self.audio.as_mut().expect("no audio output to send to");
+        let samples: Vec<Vec<f32>> = audio
+            .pending
+            .iter_mut()
+            .map(|pending| {
+                let mut samples: Vec<f32> =
+                    pending.drain(..sample_count.min(pending.len())).collect();
+                samples.resize(sample_count, 0.0);
+                samples
+            })
+            .collect();
+
+        let mut audio_frame =
+            frame::Audio::new(audio.sample_format, sample_count, audio.channel_layout);
+        audio_frame.set_rate(audio.time_base.denominator() as u32);
+        write_samples(&mut audio_frame, &samples);
+        audio_frame.set_pts(Some(audio.samples_sent));
+        audio.samples_sent += sample_count as i64;
+
+        audio
+            .encoder
+            .send_frame(&audio_frame)
+            .context("failed to send frame to audio encoder")?;
+        self.write_audio_packets()
+    }
+
+    /// Flushes the encoders and finalizes the output file.
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        let tail = self.audio.as_ref().map(|audio| {
+            // Encoders with fixed frame sizes need the tail padded out.
+            match audio.pending[0].len() {
+                0 => 0,
+                remaining if audio.variable_frame_size => remaining,
+                _ => audio.frame_size,
+            }
+        });
+        if let Some(tail) = tail {
+            if tail > 0 {
+                self.send_audio_frame(tail)?;
+            }
+            self.audio
+                .as_mut()
+                .unwrap()
+                .encoder
+                .send_eof()
+                .context("failed to flush audio encoder")?;
+            self.write_audio_packets()?;
+        }
+
+        self.video
+            .encoder
+            .send_eof()
+            .context("failed to flush video encoder")?;
+        self.write_video_packets()?;
+
+        self.output_ctx
+            .write_trailer()
+            .context("failed to write output trailer")?;
+        info!("finished encoding {} frames", self.video.frames_sent);
+        Ok(())
+    }
+
+    fn write_video_packets(&mut self) -> anyhow::Result<()> {
+        let stream_time_base = self.stream_time_base(self.video.stream_index)?;
+        let mut packet = Packet::empty();
+        while self.video.encoder.receive_packet(&mut packet).is_ok() {
+            packet.set_stream(self.video.stream_index);
+            packet.rescale_ts(self.video.time_base, stream_time_base);
+            packet
+                .write_interleaved(&mut self.output_ctx)
+                .context("failed to write encoded video packet")?;
+        }
+        Ok(())
+    }
+
+    fn write_audio_packets(&mut self) -> anyhow::Result<()> {
+        let Some(stream_index) = self.audio.as_ref().map(|audio| audio.stream_index) else {
+            return Ok(());
+        };
+        let stream_time_base = self.stream_time_base(stream_index)?;
+        let audio = self.audio.as_mut().unwrap();
+        let mut packet = Packet::empty();
+        while audio.encoder.receive_packet(&mut packet).is_ok() {
+            packet.set_stream(stream_index);
+            packet.rescale_ts(audio.time_base, stream_time_base);
+            packet
+                .write_interleaved(&mut self.output_ctx)
+                .context("failed to write encoded audio packet")?;
+        }
+        Ok(())
+    }
+
+    /// Muxers may pick their own stream time base when the header is written,
+    /// so always ask rather than assuming the one we set.
+    fn stream_time_base(&self, stream_index: usize) -> anyhow::Result<Rational> {
+        Ok(self
+            .output_ctx
+            .stream(stream_index)
+            .context("output stream vanished")?
+            .time_base())
+    }
+}
+
+/// Sample formats we know how to fill, in order of preference.
+const SUPPORTED_SAMPLE_FORMATS: [format::Sample; 4] = [
+    format::Sample::F32(format::sample::Type::Planar),
+    format::Sample::F32(format::sample::Type::Packed),
+    format::Sample::I16(format::sample::Type::Planar),
+    format::Sample::I16(format::sample::Type::Packed),
+];
+
+/// Fills an audio frame from planar f32 samples, converting to whichever of the
+/// `SUPPORTED_SAMPLE_FORMATS` the frame uses.
+fn write_samples(audio_frame: &mut frame::Audio, samples: &[Vec<f32>]) {
+    let sample_count = audio_frame.samples();
+    let channels = samples.len();
+    let to_i16 = |sample: f32| (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+
+    match audio_frame.format() {
+        format::Sample::F32(format::sample::Type::Planar) => {
+            for (channel, plane) in samples.iter().enumerate() {
+                audio_frame.plane_mut::<f32>(channel)[..sample_count].copy_from_slice(plane);
+            }
+        }
+        format::Sample::I16(format::sample::Type::Planar) => {
+            for (channel, plane) in samples.iter().enumerate() {
+                let out = audio_frame.plane_mut::<i16>(channel);
+                for (out, sample) in out.iter_mut().zip(plane) {
+                    *out = to_i16(*sample);
+                }
+            }
+        }
+        format::Sample::F32(format::sample::Type::Packed) => {
+            let out: &mut [f32] = bytemuck::cast_slice_mut(
+                &mut audio_frame.data_mut(0)[..sample_count * channels * 4],
+            );
+            for (index, out) in out.iter_mut().enumerate() {
+                *out = samples[index % channels][index / channels];
+            }
+        }
+        format::Sample::I16(format::sample::Type::Packed) => {
+            let out: &mut [i16] = bytemuck::cast_slice_mut(
+                &mut audio_frame.data_mut(0)[..sample_count * channels * 2],
+            );
+            for (index, out) in out.iter_mut().enumerate() {
+                *out = to_i16(samples[index % channels][index / channels]);
+            }
+        }
+        other => unreachable!("picked unsupported sample format {other:?}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_media::{CLIP_FPS, CLIP_SIZE, temp_dir};
+
+    #[test]
+    fn refuses_an_extension_for_another_container() {
+        let dir = temp_dir("encoder-extension");
+        let path = dir.join("out.mp4");
+        let settings = EncodePreset::WebOpus.settings();
+        let result =
+            FfmpegEncoder::new(&path, CLIP_SIZE, CLIP_FPS, AudioSpec::default(), &settings);
+        assert!(result.is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn matches_extensions_regardless_of_case() {
+        assert!(Container::Mp4.matches_extension("MP4"));
+        assert!(Container::Mkv.matches_extension("mkv"));
+        assert!(!Container::WebM.matches_extension("mp4"));
+    }
+
+    #[test]
+    fn picks_presets_by_extension_regardless_of_case() {
+        let preset = |path: &str| EncodePreset::for_path(Path::new(path));
+        assert_eq!(preset("out.webm"), EncodePreset::WebOpus);
+        assert_eq!(preset("out.WEBM"), EncodePreset::WebOpus);
+        assert_eq!(preset("out.Mkv"), EncodePreset::H265Archival);
+        assert_eq!(preset("out.MP4"), EncodePreset::H264HighQuality);
+        assert_eq!(preset("out"), EncodePreset::H264HighQuality);
+    }
+}
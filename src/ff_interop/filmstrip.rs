@@ -0,0 +1,299 @@
+use super::{
+    rgba_frame_to_image, stream_duration_secs, stream_fps, video_player::FfmpegVideoDecoder,
+};
+use crate::{
+    disk_cache,
+    project::{FrameNum, MediaFingerprint},
+};
+use anyhow::{Context, bail, ensure};
+use image::{GenericImageView, RgbaImage};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Tiles are shrunk to this height, which is about what the timeline shows.
+pub const FILMSTRIP_TILE_HEIGHT: u32 = 48;
+/// Tiles are never closer together than this, in seconds.
+const MIN_TILE_INTERVAL_SECS: f64 = 1.0;
+/// Long media gets tiles further apart, so it never has more than this many.
+const MAX_TILES: u64 = 600;
+/// Bumped whenever the way filmstrips are made changes, so old cached ones
+/// aren't used.
+const FILMSTRIP_CACHE_VERSION: u32 = 1;
+
+/// Small frames taken from a video stream at regular intervals, for showing
+/// what's in it at a glance.
+#[derive(Debug, Clone)]
+pub struct Filmstrip {
+    pub stream_index: usize,
+    /// The time between tiles, in seconds. The first tile is the first frame.
+    pub interval_secs: f64,
+    /// Every tile is the same size.
+    pub tiles: Vec<RgbaImage>,
+}
+
+impl Filmstrip {
+    /// The index of the tile showing the stream at `secs` into it: the last
+    /// one taken at or before then.
+    pub fn tile_index_at_secs(&self, secs: f64) -> Option<usize> {
+        let index = (secs.max(0.0) / self.interval_secs).floor() as usize;
+        (!self.tiles.is_empty()).then(|| index.min(self.tiles.len() - 1))
+    }
+
+    pub fn tile_at_secs(&self, secs: f64) -> Option<&RgbaImage> {
+        self.tiles.get(self.tile_index_at_secs(secs)?)
+    }
+
+    pub fn tile_size(&self) -> (u32, u32) {
+        self.tiles
+            .first()
+            .map(|tile| tile.dimensions())
+            .unwrap_or_default()
+    }
+}
+
+/// Decodes tiles from a video stream of a file, `FILMSTRIP_TILE_HEIGHT` pixels
+/// high. `on_progress` is told how many of the tiles are done after each one.
+/// Stops with an error once `is_cancelled` returns true.
+pub fn generate_filmstrip(
+    path: &Path,
+    stream_index: usize,
+    is_cancelled: impl Fn() -> bool,
+    mut on_progress: impl FnMut(u64, u64),
+) -> anyhow::Result<Filmstrip> {
+    let input_ctx = ffmpeg_next::format::input(path)
+        .context("failed to get input format context for file from ffmpeg")?;
+    let stream = input_ctx
+        .stream(stream_index)
+        .with_context(|| format!("failed to locate stream at index {stream_index}"))?;
+    let fps = stream_fps(&stream);
+    let duration_secs = stream_duration_secs(&input_ctx, &stream);
+
+    let interval_secs = (duration_secs / MAX_TILES as f64).max(MIN_TILE_INTERVAL_SECS);
+    let tile_count = ((duration_secs / interval_secs).ceil() as u64).max(1);
+    let mut decoder = FfmpegVideoDecoder::new_scaled(
+        input_ctx,
+        stream_index,
+        fps,
+        u32::MAX,
+        FILMSTRIP_TILE_HEIGHT,
+    )?;
+
+    let mut tiles = Vec::with_capacity(tile_count as usize);
+    for index in 0..tile_count {
+        if is_cancelled() {
+            bail!("filmstrip was cancelled");
+        }
+        on_progress(index, tile_count);
+        let frame = FrameNum::at_secs(index as f64 * interval_secs, fps);
+        let decoded = decoder
+            .decode_frame_at(frame)
+            .with_context(|| format!("failed to decode filmstrip tile {index}"))?;
+        tiles.push(rgba_frame_to_image(&decoded));
+    }
+    on_progress(tile_count, tile_count);
+
+    Ok(Filmstrip {
+        stream_index,
+        interval_secs,
+        tiles,
+    })
+}
+
+/// What's saved next to a filmstrip's tiles, to know how to cut them up again.
+#[derive(Serialize, Deserialize)]
+struct FilmstripMetadata {
+    interval_secs: f64,
+    tile_count: u32,
+}
+
+/// Keeps generated filmstrips on disk, so they're only made once per file.
+/// Each is stored as one PNG with its tiles side by side, and a JSON file
+/// describing them.
+#[derive(Debug, Clone)]
+pub struct FilmstripCache {
+    dir: PathBuf,
+}
+
+impl FilmstripCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// The cache in the app's usual cache folder.
+    pub fn in_cache_dir() -> Self {
+        Self::new(disk_cache::cache_dir("filmstrips"))
+    }
+
+    /// Loads the filmstrip of a stream from the cache if it's there and the
+    /// file hasn't changed since, or generates and caches it otherwise.
+    pub fn get_or_generate(
+        &self,
+        path: &Path,
+        fingerprint: Option<&MediaFingerprint>,
+        stream_index: usize,
+        is_cancelled: impl Fn() -> bool,
+        on_progress: impl FnMut(u64, u64),
+    ) -> anyhow::Result<Filmstrip> {
+        let key = disk_cache::source_key(
+            path,
+            fingerprint,
+            &format!(
+                "filmstrip v{FILMSTRIP_CACHE_VERSION} stream {stream_index} height \
+                 {FILMSTRIP_TILE_HEIGHT}"
+            ),
+        )?;
+        match self.load(&key, stream_index) {
+            Ok(Some(filmstrip)) => return Ok(filmstrip),
+            Ok(None) => {}
+            Err(err) => warn!("ignoring unreadable cached filmstrip {key}: {err:#}"),
+        }
+
+        let filmstrip = generate_filmstrip(path, stream_index, is_cancelled, on_progress)?;
+        if let Err(err) = self.store(&key, &filmstrip) {
+            warn!("failed to cache filmstrip for {}: {err:#}", path.display());
+        }
+        Ok(filmstrip)
+    }
+
+    fn load(&self, key: &str, stream_index: usize) -> anyhow::Result<Option<Filmstrip>> {
+        let (image_path, metadata_path) = self.paths(key);
+        if !metadata_path.exists() || !image_path.exists() {
+            return Ok(None);
+        }
+        let metadata: FilmstripMetadata = serde_json::from_slice(&fs::read(&metadata_path)?)?;
+        let sheet = image::open(&image_path)?.into_rgba8();
+        ensure!(
+            metadata.tile_count > 0 && sheet.width() % metadata.tile_count == 0,
+            "tile sheet is {} pixels wide, which doesn't fit {} tiles",
+            sheet.width(),
+            metadata.tile_count
+        );
+
+        let tile_width = sheet.width() / metadata.tile_count;
+        let tiles = (0..metadata.tile_count)
+            .map(|index| {
+                sheet
+                    .view(index * tile_width, 0, tile_width, sheet.height())
+                    .to_image()
+            })
+            .collect();
+        info!("loaded cached filmstrip {key}");
+        Ok(Some(Filmstrip {
+            stream_index,
+            interval_secs: metadata.interval_secs,
+            tiles,
+        }))
+    }
+
+    fn store(&self, key: &str, filmstrip: &Filmstrip) -> anyhow::Result<()> {
+        let (tile_width, tile_height) = filmstrip.tile_size();
+        let mut sheet = RgbaImage::new(tile_width * filmstrip.tiles.len() as u32, tile_height);
+        for (index, tile) in filmstrip.tiles.iter().enumerate() {
+            image::imageops::replace(&mut sheet, tile, index as i64 * tile_width as i64, 0);
+        }
+
+        let (image_path, metadata_path) = self.paths(key);
+        disk_cache::write_atomic(&image_path, |partial| {
+            Ok(sheet.save_with_format(partial, image::ImageFormat::Png)?)
+        })?;
+        let metadata = FilmstripMetadata {
+            interval_secs: filmstrip.interval_secs,
+            tile_count: filmstrip.tiles.len() as u32,
+        };
+        // Written last, since it's what marks the filmstrip as complete.
+        disk_cache::write_atomic(&metadata_path, |partial| {
+            Ok(fs::write(partial, serde_json::to_vec(&metadata)?)?)
+        })
+    }
+
+    fn paths(&self, key: &str) -> (PathBuf, PathBuf) {
+        (
+            self.dir.join(format!("{key}.png")),
+            self.dir.join(format!("{key}.json")),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_media::{self, CLIP_FPS, CLIP_FRAMES, CLIP_SIZE};
+    use std::cell::Cell;
+
+    /// The numbered clip is five seconds long, so gets a tile every second.
+    const TILE_COUNT: usize = 5;
+
+    fn assert_tiles_of_numbered_clip(filmstrip: &Filmstrip) {
+        assert_eq!(CLIP_FRAMES, TILE_COUNT as u64 * CLIP_FPS.num as u64);
+        assert_eq!(filmstrip.tiles.len(), TILE_COUNT);
+        assert_eq!(filmstrip.interval_secs, MIN_TILE_INTERVAL_SECS);
+        let width = CLIP_SIZE.width * FILMSTRIP_TILE_HEIGHT / CLIP_SIZE.height;
+        assert_eq!(filmstrip.tile_size(), (width, FILMSTRIP_TILE_HEIGHT));
+        for (index, tile) in filmstrip.tiles.iter().enumerate() {
+            assert_eq!(tile.dimensions(), filmstrip.tile_size());
+            assert_eq!(
+                test_media::frame_number_of(tile),
+                index as u64 * CLIP_FPS.num as u64,
+                "tile {index} is from the wrong frame"
+            );
+        }
+    }
+
+    #[test]
+    fn takes_a_tile_every_interval() {
+        let mut progress = vec![];
+        let filmstrip = generate_filmstrip(
+            test_media::numbered_clip(),
+            0,
+            || false,
+            |done, total| progress.push((done, total)),
+        )
+        .unwrap();
+        assert_tiles_of_numbered_clip(&filmstrip);
+        let total = TILE_COUNT as u64;
+        assert_eq!(progress.last(), Some(&(total, total)));
+        assert_eq!(filmstrip.tile_index_at_secs(2.5), Some(2));
+        assert_eq!(filmstrip.tile_index_at_secs(60.0), Some(TILE_COUNT - 1));
+    }
+
+    #[test]
+    fn stops_when_cancelled() {
+        let result = generate_filmstrip(test_media::numbered_clip(), 0, || true, |_, _| {});
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn loads_from_the_cache_the_second_time() {
+        let dir = test_media::temp_dir("filmstrip-cache");
+        let clip = dir.join("clip.mp4");
+        fs::copy(test_media::numbered_clip(), &clip).unwrap();
+        let cache = FilmstripCache::new(dir.join("cache"));
+
+        let generated = Cell::new(0);
+        let get = || {
+            cache
+                .get_or_generate(
+                    &clip,
+                    None,
+                    0,
+                    || false,
+                    |_, _| generated.set(generated.get() + 1),
+                )
+                .unwrap()
+        };
+        let first = get();
+        assert!(generated.get() > 0);
+        generated.set(0);
+        let second = get();
+        assert_eq!(generated.get(), 0, "filmstrip was generated again");
+
+        assert_tiles_of_numbered_clip(&second);
+        assert_eq!(second.interval_secs, first.interval_secs);
+        assert_eq!(second.tiles, first.tiles);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,13 +1,18 @@
+pub mod audio_player;
+pub mod encoder;
+pub mod filmstrip;
+mod probe;
+pub mod proxy;
 pub mod video_player;
+pub mod waveform;
 
 use crate::project::{
-    AudioMediaStream, BasicStreamInfo, MediaInfo, MediaLength, MediaStream, VideoMediaStream,
+    BasicStreamInfo, JadeRational, MediaFingerprint, MediaInfo, MediaLength, MediaStream,
 };
 use anyhow::Context;
-use std::{
-    path::{Path, PathBuf},
-    thread::JoinHandle,
-};
+use ffmpeg_next::{format::Pixel, frame};
+use image::RgbaImage;
+use std::path::PathBuf;
 
 pub fn load_media_sync(path: PathBuf) -> anyhow::Result<MediaInfo> {
     let input_ctx = ffmpeg_next::format::input(&path)
@@ -16,19 +21,20 @@ pub fn load_media_sync(path: PathBuf) -> anyhow::Result<MediaInfo> {
 
     for stream in input_ctx.streams() {
         let index = stream.index();
-        let param = stream.parameters();
         let length = MediaLength {
             time_base_length: stream.duration() as u64,
             time_base: stream.time_base().into(),
         };
 
-        streams.push(match param.medium() {
-            ffmpeg_next::media::Type::Video => {
-                MediaStream::Video(BasicStreamInfo { index, length }, VideoMediaStream {})
-            }
-            ffmpeg_next::media::Type::Audio => {
-                MediaStream::Audio(BasicStreamInfo { index, length }, AudioMediaStream {})
-            }
+        streams.push(match stream.parameters().medium() {
+            ffmpeg_next::media::Type::Video => MediaStream::Video(
+                BasicStreamInfo { index, length },
+                probe::probe_video(&stream)?,
+            ),
+            ffmpeg_next::media::Type::Audio => MediaStream::Audio(
+                BasicStreamInfo { index, length },
+                probe::probe_audio(&stream)?,
+            ),
             _ => {
                 log::warn!("unknown medium for stream {}", stream.index());
                 continue;
@@ -36,9 +42,70 @@ pub fn load_media_sync(path: PathBuf) -> anyhow::Result<MediaInfo> {
         });
     }
 
-    Ok(MediaInfo { path, streams })
+    let fingerprint = MediaFingerprint::of_file(&path, &streams)?;
+    let name = path
+        .file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned();
+    Ok(MediaInfo {
+        name,
+        path,
+        fingerprint: Some(fingerprint),
+        streams,
+        proxy: None,
+    })
 }
 
-fn frames_from_packet() -> Vec<()> {
-    vec![]
+/// The frame rate of a video stream, for counting its frames in. Falls back to
+/// 30 fps if the container doesn't say.
+pub fn stream_fps(stream: &ffmpeg_next::Stream) -> JadeRational {
+    match stream.avg_frame_rate() {
+        rate if rate.numerator() > 0 && rate.denominator() > 0 => rate.into(),
+        _ => JadeRational { num: 30, den: 1 },
+    }
+}
+
+/// How long a stream lasts in seconds. Some containers only know the length
+/// of the whole file, which is used when the stream doesn't say.
+pub fn stream_duration_secs(
+    input_ctx: &ffmpeg_next::format::context::Input,
+    stream: &ffmpeg_next::Stream,
+) -> f64 {
+    match stream.duration() {
+        duration if duration > 0 => {
+            duration as f64 * JadeRational::from(stream.time_base()).to_f64()
+        }
+        _ => input_ctx.duration().max(0) as f64 / ffmpeg_next::ffi::AV_TIME_BASE as f64,
+    }
+}
+
+/// Copies an RGBA video frame into a tightly-packed image, dropping any padding
+/// ffmpeg added to the end of each row.
+pub fn rgba_frame_to_image(rgba_frame: &frame::Video) -> RgbaImage {
+    let (width, height) = (rgba_frame.width(), rgba_frame.height());
+    let row_len = width as usize * 4;
+    let mut pixels = Vec::with_capacity(row_len * height as usize);
+    for row in rgba_frame
+        .data(0)
+        .chunks(rgba_frame.stride(0))
+        .take(height as usize)
+    {
+        pixels.extend_from_slice(&row[..row_len]);
+    }
+    RgbaImage::from_raw(width, height, pixels).expect("rgba frame had too few pixels")
+}
+
+pub fn image_to_rgba_frame(image: &RgbaImage) -> frame::Video {
+    let mut rgba_frame = frame::Video::new(Pixel::RGBA, image.width(), image.height());
+    let row_len = image.width() as usize * 4;
+    let stride = rgba_frame.stride(0);
+    for (dst_row, src_row) in rgba_frame
+        .data_mut(0)
+        .chunks_mut(stride)
+        .zip(image.as_raw().chunks(row_len))
+    {
+        dst_row[..row_len].copy_from_slice(src_row);
+    }
+    rgba_frame
 }
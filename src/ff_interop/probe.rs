@@ -0,0 +1,220 @@
+use crate::project::{AudioMediaStream, ColorInfo, JadeRational, VideoMediaStream};
+use anyhow::Context;
+use ffmpeg_next::{
+    ChannelLayout, Rational, Stream,
+    codec::{self, Profile},
+    format::Sample,
+    packet::{SideData, side_data::Type as SideDataType},
+};
+
+/// Reads what's known about a video stream from its parameters and side data,
+/// without decoding anything.
+pub fn probe_video(stream: &Stream) -> anyhow::Result<VideoMediaStream> {
+    let param = stream.parameters();
+    let codec_id = param.id();
+    let video = codec::context::Context::from_parameters(param)
+        .and_then(|context| context.decoder().video())
+        .with_context(|| {
+            format!(
+                "failed to read video parameters of stream {}",
+                stream.index()
+            )
+        })?;
+
+    let avg_frame_rate = known_rate(stream.avg_frame_rate());
+    let real_frame_rate = known_rate(stream.rate());
+    let variable_frame_rate = match (avg_frame_rate, real_frame_rate) {
+        (Some(avg), Some(real)) => {
+            avg.num as i64 * real.den as i64 != real.num as i64 * avg.den as i64
+        }
+        _ => false,
+    };
+
+    Ok(VideoMediaStream {
+        codec: codec_name(codec_id),
+        profile: profile_name(video.profile()),
+        width: video.width(),
+        height: video.height(),
+        sample_aspect_ratio: known_rate(video.aspect_ratio()),
+        pixel_format: video
+            .format()
+            .descriptor()
+            .map(|descriptor| descriptor.name().to_string()),
+        color: ColorInfo {
+            space: video.color_space().name().map(str::to_string),
+            primaries: video.color_primaries().name().map(str::to_string),
+            transfer: video
+                .color_transfer_characteristic()
+                .name()
+                .map(str::to_string),
+            range: video.color_range().name().map(str::to_string),
+        },
+        rotation: stream.side_data().find_map(display_rotation).unwrap_or(0),
+        avg_frame_rate,
+        real_frame_rate,
+        variable_frame_rate,
+    })
+}
+
+/// Reads what's known about an audio stream from its parameters, without
+/// decoding anything.
+pub fn probe_audio(stream: &Stream) -> anyhow::Result<AudioMediaStream> {
+    let param = stream.parameters();
+    let codec_id = param.id();
+    // Not exposed by the safe API. Compressed codecs usually leave both unset.
+    let (raw_bits, coded_bits) = unsafe {
+        let param = &*param.as_ptr();
+        (param.bits_per_raw_sample, param.bits_per_coded_sample)
+    };
+    let audio = codec::context::Context::from_parameters(param)
+        .and_then(|context| context.decoder().audio())
+        .with_context(|| {
+            format!(
+                "failed to read audio parameters of stream {}",
+                stream.index()
+            )
+        })?;
+
+    let sample_format = audio.format();
+    Ok(AudioMediaStream {
+        codec: codec_name(codec_id),
+        profile: profile_name(audio.profile()),
+        sample_rate: audio.rate(),
+        channels: audio.channels(),
+        channel_layout: layout_name(audio.channel_layout()),
+        sample_format: (sample_format != Sample::None).then(|| sample_format.name().to_string()),
+        bit_depth: [raw_bits, coded_bits]
+            .into_iter()
+            .find(|&bits| bits > 0)
+            .map(|bits| bits as u32),
+    })
+}
+
+fn codec_name(id: codec::Id) -> Option<String> {
+    (id != codec::Id::None).then(|| id.name().to_string())
+}
+
+fn profile_name(profile: Profile) -> Option<String> {
+    match profile {
+        Profile::Unknown | Profile::Reserved => None,
+        profile => Some(format!("{profile:?}")),
+    }
+}
+
+/// FFmpeg uses 0/0 or 0/1 for rates and ratios it doesn't know.
+fn known_rate(rate: Rational) -> Option<JadeRational> {
+    (rate.numerator() != 0 && rate.denominator() != 0).then(|| rate.into())
+}
+
+/// The layouts FFmpeg has names for. `ChannelLayout` wraps a union, so these
+/// are compared with `==` rather than matched on.
+const LAYOUT_NAMES: [(ChannelLayout, &str); 10] = [
+    (ChannelLayout::MONO, "mono"),
+    (ChannelLayout::STEREO, "stereo"),
+    (ChannelLayout::_2POINT1, "2.1"),
+    (ChannelLayout::SURROUND, "3.0"),
+    (ChannelLayout::QUAD, "quad"),
+    (ChannelLayout::_5POINT0, "5.0(side)"),
+    (ChannelLayout::_5POINT1, "5.1(side)"),
+    (ChannelLayout::_5POINT0_BACK, "5.0"),
+    (ChannelLayout::_5POINT1_BACK, "5.1"),
+    (ChannelLayout::_7POINT1, "7.1"),
+];
+
+/// Names layouts the way FFmpeg does, and the rest by how many channels they
+/// have.
+fn layout_name(layout: ChannelLayout) -> Option<String> {
+    if layout.is_empty() {
+        return None;
+    }
+    let name = LAYOUT_NAMES
+        .iter()
+        .find(|(known, _)| *known == layout)
+        .map(|(_, name)| name.to_string());
+    Some(name.unwrap_or_else(|| format!("{} channels", layout.channels())))
+}
+
+/// Reads the clockwise rotation out of a display matrix side data.
+fn display_rotation(side_data: SideData) -> Option<i32> {
+    if side_data.kind() != SideDataType::DisplayMatrix {
+        return None;
+    }
+    let matrix: Vec<i32> = side_data
+        .data()
+        .chunks_exact(4)
+        .take(9)
+        .map(|bytes| i32::from_ne_bytes(bytes.try_into().unwrap()))
+        .collect();
+    matrix_rotation(&matrix)
+}
+
+/// The clockwise rotation of a display matrix, rounded to whole degrees in
+/// 0..360. The matrix is 3x3 `i32`s, with the rotation in the 16.16 fixed
+/// point top-left 2x2.
+fn matrix_rotation(matrix: &[i32]) -> Option<i32> {
+    if matrix.len() < 9 {
+        return None;
+    }
+    let matrix = matrix.iter().map(|&value| value as f64).collect::<Vec<_>>();
+    let scale_x = matrix[0].hypot(matrix[3]);
+    let scale_y = matrix[1].hypot(matrix[4]);
+    if scale_x == 0.0 || scale_y == 0.0 {
+        return None;
+    }
+    // FFmpeg's av_display_rotation_get gives the negation of this, as it
+    // counts counter-clockwise.
+    let clockwise = (matrix[1] / scale_y)
+        .atan2(matrix[0] / scale_x)
+        .to_degrees();
+    Some(clockwise.round().rem_euclid(360.0) as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A display matrix the way FFmpeg's av_display_rotation_set makes one,
+    /// from a counter-clockwise angle.
+    fn rotation_matrix(counter_clockwise: f64) -> [i32; 9] {
+        let (sin, cos) = counter_clockwise.to_radians().sin_cos();
+        let fixed = |value: f64| (value * 65536.0).round() as i32;
+        [
+            fixed(cos),
+            fixed(-sin),
+            0,
+            fixed(sin),
+            fixed(cos),
+            0,
+            0,
+            0,
+            1 << 30,
+        ]
+    }
+
+    #[test]
+    fn reads_portrait_phone_video_as_turned_clockwise() {
+        // What phones write for video shot upright: the tag says rotate=90.
+        let matrix = [0, 65536, 0, -65536, 0, 0, 0, 0, 1 << 30];
+        assert_eq!(matrix_rotation(&matrix), Some(90));
+        assert_eq!(matrix, rotation_matrix(-90.0));
+    }
+
+    #[test]
+    fn reads_every_quarter_turn() {
+        for clockwise in [0, 90, 180, 270] {
+            let matrix = rotation_matrix(-clockwise as f64);
+            assert_eq!(matrix_rotation(&matrix), Some(clockwise));
+        }
+        assert_eq!(matrix_rotation(&rotation_matrix(90.0)), Some(270));
+    }
+
+    #[test]
+    fn ignores_scaling_and_bad_matrices() {
+        let mut matrix = rotation_matrix(-90.0);
+        matrix[1] *= 2;
+        matrix[3] *= 2;
+        assert_eq!(matrix_rotation(&matrix), Some(90));
+        assert_eq!(matrix_rotation(&[0; 9]), None);
+        assert_eq!(matrix_rotation(&matrix[..4]), None);
+    }
+}
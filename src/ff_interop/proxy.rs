@@ -0,0 +1,259 @@
+use super::{
+    encoder::{Container, EncodeSettings, FfmpegEncoder, VideoCodec},
+    rgba_frame_to_image, stream_duration_secs, stream_fps,
+    video_player::FfmpegVideoDecoder,
+};
+use crate::{
+    disk_cache,
+    project::{AudioSpec, FrameNum, MediaFingerprint, ProxyMedia, Resolution},
+};
+use anyhow::{Context, bail, ensure};
+use image::GenericImageView;
+use log::info;
+use std::path::{Path, PathBuf};
+
+/// Proxies are shrunk to this height, keeping the shape of the picture.
+pub const PROXY_HEIGHT: u32 = 540;
+/// The index of the video in a proxy file, which is the only stream in it.
+pub const PROXY_STREAM_INDEX: usize = 0;
+/// Bumped whenever the way proxies are made changes, so old cached ones aren't
+/// used.
+const PROXY_CACHE_VERSION: u32 = 1;
+
+/// H.264 with every frame a keyframe, so seeking anywhere in a proxy only
+/// ever decodes the one frame.
+pub fn proxy_settings() -> EncodeSettings {
+    EncodeSettings {
+        container: Container::Mp4,
+        video_codec: VideoCodec::H264,
+        audio_codec: None,
+        crf: Some(23),
+        video_bitrate: None,
+        audio_bitrate: 0,
+        gop: Some(1),
+        speed_preset: Some("veryfast".to_string()),
+    }
+}
+
+/// Re-encodes a video stream of a file into `output`, `PROXY_HEIGHT` pixels
+/// high and at the stream's own frame rate. `on_progress` is told how many
+/// frames are done after each one. Stops with an error once `is_cancelled`
+/// returns true.
+pub fn generate_proxy(
+    source: &Path,
+    stream_index: usize,
+    output: &Path,
+    is_cancelled: impl Fn() -> bool,
+    mut on_progress: impl FnMut(u64, u64),
+) -> anyhow::Result<()> {
+    let input_ctx = ffmpeg_next::format::input(source)
+        .context("failed to get input format context for file from ffmpeg")?;
+    let stream = input_ctx
+        .stream(stream_index)
+        .with_context(|| format!("failed to locate stream at index {stream_index}"))?;
+    let fps = stream_fps(&stream);
+    let frame_count =
+        ((stream_duration_secs(&input_ctx, &stream) * fps.to_f64()).ceil() as u64).max(1);
+    let mut decoder =
+        FfmpegVideoDecoder::new_scaled(input_ctx, stream_index, fps, u32::MAX, PROXY_HEIGHT)?;
+
+    // YUV 4:2:0 needs an even frame size, so an odd last row or column is
+    // cropped off.
+    let first = rgba_frame_to_image(
+        &decoder
+            .decode_frame_at(FrameNum(0))
+            .context("failed to decode first frame")?,
+    );
+    let resolution = Resolution {
+        width: first.width() - first.width() % 2,
+        height: first.height() - first.height() % 2,
+    };
+    ensure!(
+        resolution.width > 0 && resolution.height > 0,
+        "video is too small to make a proxy of"
+    );
+    let mut encoder = FfmpegEncoder::new(
+        output,
+        resolution,
+        fps,
+        AudioSpec::default(),
+        &proxy_settings(),
+    )?;
+
+    for index in 0..frame_count {
+        if is_cancelled() {
+            bail!("proxy was cancelled");
+        }
+        on_progress(index, frame_count);
+        let decoded = decoder
+            .decode_frame_at(FrameNum(index))
+            .with_context(|| format!("failed to decode frame {index}"))?;
+        let mut image = rgba_frame_to_image(&decoded);
+        if image.dimensions() != (resolution.width, resolution.height) {
+            image = image
+                .view(0, 0, resolution.width, resolution.height)
+                .to_image();
+        }
+        encoder
+            .encode_frame(&image)
+            .with_context(|| format!("failed to encode frame {index}"))?;
+    }
+    encoder.finish()?;
+    on_progress(frame_count, frame_count);
+    Ok(())
+}
+
+/// Keeps generated proxies on disk, so each file only has one made for it
+/// however many projects use it.
+#[derive(Debug, Clone)]
+pub struct ProxyCache {
+    dir: PathBuf,
+}
+
+impl ProxyCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// The cache in the app's usual cache folder.
+    pub fn in_cache_dir() -> Self {
+        Self::new(disk_cache::cache_dir("proxies"))
+    }
+
+    /// Finds the proxy of a stream in the cache if it's there and the file
+    /// hasn't changed since, or generates and caches it otherwise.
+    pub fn get_or_generate(
+        &self,
+        path: &Path,
+        fingerprint: Option<&MediaFingerprint>,
+        stream_index: usize,
+        is_cancelled: impl Fn() -> bool,
+        on_progress: impl FnMut(u64, u64),
+    ) -> anyhow::Result<ProxyMedia> {
+        let key = disk_cache::source_key(
+            path,
+            fingerprint,
+            &format!("proxy v{PROXY_CACHE_VERSION} stream {stream_index} height {PROXY_HEIGHT}"),
+        )?;
+        let proxy_path = self
+            .dir
+            .join(format!("{key}.{}", proxy_settings().container.extension()));
+
+        if proxy_path.exists() {
+            info!("using cached proxy {key}");
+        } else {
+            disk_cache::write_atomic(&proxy_path, |partial| {
+                generate_proxy(path, stream_index, partial, is_cancelled, on_progress)
+            })?;
+        }
+        Ok(ProxyMedia {
+            path: proxy_path,
+            stream_index,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_media::{self, CLIP_FPS};
+    use image::imageops::{self, FilterType};
+    use std::fs;
+
+    const FRAMES: u64 = 24;
+    /// Taller than a proxy, and shaped so shrinking it to `PROXY_HEIGHT` gives
+    /// an odd width of 723.
+    const SOURCE_SIZE: Resolution = Resolution {
+        width: 964,
+        height: 720,
+    };
+
+    /// A numbered clip at `SOURCE_SIZE`.
+    fn write_source(path: &Path) {
+        ffmpeg_next::init().unwrap();
+        let mut encoder = FfmpegEncoder::new(
+            path,
+            SOURCE_SIZE,
+            CLIP_FPS,
+            AudioSpec::default(),
+            &test_media::clip_settings(),
+        )
+        .unwrap();
+        for frame in 0..FRAMES {
+            let image = imageops::resize(
+                &test_media::numbered_frame(frame),
+                SOURCE_SIZE.width,
+                SOURCE_SIZE.height,
+                FilterType::Nearest,
+            );
+            encoder.encode_frame(&image).unwrap();
+        }
+        encoder.finish().unwrap();
+    }
+
+    #[test]
+    fn makes_small_intra_only_proxies() {
+        let dir = test_media::temp_dir("proxy-generate");
+        let source = dir.join("source.mp4");
+        let proxy = dir.join("proxy.mp4");
+        write_source(&source);
+        generate_proxy(&source, 0, &proxy, || false, |_, _| {}).unwrap();
+
+        let mut input_ctx = ffmpeg_next::format::input(&proxy).unwrap();
+        assert_eq!(input_ctx.streams().count(), 1);
+        let mut packets = 0;
+        for (stream, packet) in input_ctx.packets() {
+            assert_eq!(stream.index(), PROXY_STREAM_INDEX);
+            assert!(packet.is_key(), "packet {packets} is not a keyframe");
+            packets += 1;
+        }
+        assert_eq!(packets, FRAMES);
+
+        let input_ctx = ffmpeg_next::format::input(&proxy).unwrap();
+        let mut decoder = FfmpegVideoDecoder::new(input_ctx, PROXY_STREAM_INDEX, CLIP_FPS).unwrap();
+        for frame in 0..FRAMES {
+            let image = rgba_frame_to_image(&decoder.decode_frame_at(FrameNum(frame)).unwrap());
+            // The odd column left over from shrinking is cropped off.
+            assert_eq!(image.dimensions(), (722, PROXY_HEIGHT));
+            assert_eq!(test_media::frame_number_of(&image), frame);
+        }
+    }
+
+    #[test]
+    fn reuses_cached_proxies() {
+        let dir = test_media::temp_dir("proxy-cache");
+        let source = dir.join("source.mp4");
+        write_source(&source);
+        let cache = ProxyCache::new(dir.join("cache"));
+
+        let mut progress = vec![];
+        let proxy = cache
+            .get_or_generate(
+                &source,
+                None,
+                0,
+                || false,
+                |done, total| progress.push((done, total)),
+            )
+            .unwrap();
+        assert_eq!(progress.last(), Some(&(FRAMES, FRAMES)));
+        assert_eq!(proxy.stream_index, 0);
+        let modified = fs::metadata(&proxy.path).unwrap().modified().unwrap();
+
+        let cached = cache
+            .get_or_generate(
+                &source,
+                None,
+                0,
+                || false,
+                |_, _| panic!("cached proxy was generated again"),
+            )
+            .unwrap();
+        assert_eq!(cached, proxy);
+        assert_eq!(
+            fs::metadata(&cached.path).unwrap().modified().unwrap(),
+            modified
+        );
+        assert_eq!(fs::read_dir(dir.join("cache")).unwrap().count(), 1);
+    }
+}
@@ -1,22 +1,65 @@
+use crate::{
+    project::{FrameNum, JadeRational},
+    render::{YuvColorimetry, YuvFrame, YuvLayout, YuvPlane, YuvRange},
+};
 use anyhow::Context;
-use ffmpeg_next::{codec, format, frame, software::scaling};
+use ffmpeg_next::{
+    Rational, Rescale, Rounding, codec, format, format::Pixel, frame, rescale, software::scaling,
+};
 use log::info;
 
+/// How many frames ahead of the last decoded frame a request may be before we
+/// seek instead of decoding forward.
+const MAX_DECODE_AHEAD: u64 = 60;
+
 pub struct FfmpegVideoDecoder {
     stream_index: usize,
     input_ctx: format::context::Input,
 
     video_decoder: codec::decoder::Video,
     scaler_ctx: scaling::Context,
+    /// From the stream's color metadata, for frames handed out as YUV.
+    colorimetry: YuvColorimetry,
+
+    fps: JadeRational,
+    time_base: Rational,
+    start_pts: i64,
+    /// The most recently decoded frame (still in the decoder's pixel format),
+    /// kept so a request for the same or next frame doesn't need to seek.
+    last_frame: Option<(FrameNum, frame::Video)>,
+    /// A frame decoded past the last request, to be handed out next.
+    overshoot_frame: Option<(FrameNum, frame::Video)>,
+    reached_eof: bool,
 }
 
 #[allow(unused)]
 impl FfmpegVideoDecoder {
-    pub fn new(mut input_ctx: format::context::Input, stream_index: usize) -> anyhow::Result<Self> {
+    pub fn new(
+        input_ctx: format::context::Input,
+        stream_index: usize,
+        fps: JadeRational,
+    ) -> anyhow::Result<Self> {
+        Self::new_scaled(input_ctx, stream_index, fps, u32::MAX, u32::MAX)
+    }
+
+    /// Like `new`, but frames are shrunk to fit within `max_width` by
+    /// `max_height` while keeping their aspect ratio. They're never enlarged.
+    pub fn new_scaled(
+        mut input_ctx: format::context::Input,
+        stream_index: usize,
+        fps: JadeRational,
+        max_width: u32,
+        max_height: u32,
+    ) -> anyhow::Result<Self> {
         let video_stream = input_ctx
             .stream(stream_index)
             .with_context(|| format!("failed to locate stream at index {stream_index}"))?;
         info!("got video stream at index {stream_index}");
+        let time_base = video_stream.time_base();
+        let start_pts = match video_stream.start_time() {
+            ffmpeg_next::ffi::AV_NOPTS_VALUE => 0,
+            start_pts => start_pts,
+        };
 
         let video_decoder = codec::context::Context::from_parameters(video_stream.parameters())
             .context("failed to create video decoder")
@@ -27,25 +70,41 @@ impl FfmpegVideoDecoder {
             })?;
         info!("created video decoder");
 
+        let (width, height) = (video_decoder.width(), video_decoder.height());
+        let colorimetry = YuvColorimetry::from_names(
+            video_decoder.color_space().name(),
+            video_decoder.color_range().name(),
+            height,
+        );
+        let scale = f64::min(
+            max_width as f64 / width.max(1) as f64,
+            max_height as f64 / height.max(1) as f64,
+        )
+        .min(1.0);
         let scaler_ctx = scaling::context::Context::get(
             video_decoder.format(),
-            video_decoder.width(),
-            video_decoder.height(),
+            width,
+            height,
             format::Pixel::RGBA,
-            video_decoder.width(),
-            video_decoder.height(),
+            ((width as f64 * scale).round() as u32).max(1),
+            ((height as f64 * scale).round() as u32).max(1),
             scaling::flag::Flags::BILINEAR,
         )
         .context("failed to create software scaler for pixel reformatting")?;
         info!("created software scaler");
 
-        let packet_iter = input_ctx.packets();
-
         Ok(Self {
             stream_index,
             input_ctx,
             video_decoder,
             scaler_ctx,
+            colorimetry,
+            fps,
+            time_base,
+            start_pts,
+            last_frame: None,
+            overshoot_frame: None,
+            reached_eof: false,
         })
     }
 
@@ -83,4 +142,302 @@ impl FfmpegVideoDecoder {
             };
         }
     }
+
+    /// The length of one project frame, which doubles as the time base that
+    /// `FrameNum`s are counted in.
+    fn frame_time_base(&self) -> Rational {
+        Rational::new(self.fps.den, self.fps.num)
+    }
+
+    fn frame_to_pts(&self, frame: FrameNum) -> i64 {
+        self.start_pts + (frame.0 as i64).rescale(self.frame_time_base(), self.time_base)
+    }
+
+    fn pts_to_frame(&self, pts: i64) -> FrameNum {
+        let frame = (pts - self.start_pts).rescale_with(
+            self.time_base,
+            self.frame_time_base(),
+            Rounding::NearInfinity,
+        );
+        FrameNum(frame.max(0) as u64)
+    }
+
+    /// Seeks to the closest keyframe at or before the given frame. The next
+    /// decoded frame will be at or before the requested one.
+    pub fn seek_to(&mut self, frame: FrameNum) -> anyhow::Result<()> {
+        let target_ts = self
+            .frame_to_pts(frame)
+            .rescale(self.time_base, rescale::TIME_BASE);
+        self.input_ctx
+            .seek(target_ts, ..target_ts)
+            .with_context(|| format!("failed to seek to frame {}", frame.0))?;
+        self.video_decoder.flush();
+        self.last_frame = None;
+        self.overshoot_frame = None;
+        self.reached_eof = false;
+        Ok(())
+    }
+
+    /// Decodes the frame shown at the given project frame, converted to RGBA.
+    /// If the source has no frame exactly at that time (e.g. a lower frame
+    /// rate than the project), the most recent frame before it is returned.
+    pub fn decode_frame_at(&mut self, frame: FrameNum) -> anyhow::Result<frame::Video> {
+        let decoded = self.decode_raw_frame_at(frame)?;
+        let mut rgba_frame = frame::Video::empty();
+        self.scaler_ctx
+            .run(&decoded, &mut rgba_frame)
+            .context("failed to convert decoded video frame to rgba pixel format")?;
+        Ok(rgba_frame)
+    }
+
+    /// Like `decode_frame_at`, but leaves the frame in its YUV planes at full
+    /// size for converting on the GPU. `None` if the stream's pixel format
+    /// isn't one the GPU path handles, in which case `decode_frame_at` should
+    /// be used instead.
+    pub fn decode_yuv_frame_at(&mut self, frame: FrameNum) -> anyhow::Result<Option<YuvFrame>> {
+        let (layout, bit_depth, full_range) = match self.video_decoder.format() {
+            Pixel::YUV420P => (YuvLayout::Planar420, 8, false),
+            Pixel::YUVJ420P => (YuvLayout::Planar420, 8, true),
+            Pixel::YUV422P => (YuvLayout::Planar422, 8, false),
+            Pixel::YUVJ422P => (YuvLayout::Planar422, 8, true),
+            Pixel::NV12 => (YuvLayout::SemiPlanar420, 8, false),
+            Pixel::YUV420P10LE => (YuvLayout::Planar420, 10, false),
+            Pixel::YUV422P10LE => (YuvLayout::Planar422, 10, false),
+            _ => return Ok(None),
+        };
+        let decoded = self.decode_raw_frame_at(frame)?;
+
+        let mut colorimetry = self.colorimetry;
+        // The "J" formats are full range whatever the stream says.
+        if full_range {
+            colorimetry.range = YuvRange::Full;
+        }
+        let bytes_per_sample = if bit_depth > 8 { 2 } else { 1 };
+        let planes = (0..decoded.planes())
+            .map(|index| {
+                let components = if layout.is_interleaved() && index == 1 {
+                    2
+                } else {
+                    1
+                };
+                let (width, height) = (decoded.plane_width(index), decoded.plane_height(index));
+                let row_len = (width * components * bytes_per_sample) as usize;
+                let mut data = Vec::with_capacity(row_len * height as usize);
+                for row in decoded
+                    .data(index)
+                    .chunks(decoded.stride(index))
+                    .take(height as usize)
+                {
+                    data.extend_from_slice(&row[..row_len]);
+                }
+                YuvPlane {
+                    width,
+                    height,
+                    components,
+                    data,
+                }
+            })
+            .collect();
+
+        Ok(Some(YuvFrame {
+            width: decoded.width(),
+            height: decoded.height(),
+            layout,
+            bit_depth,
+            colorimetry,
+            planes,
+        }))
+    }
+
+    fn decode_raw_frame_at(&mut self, frame: FrameNum) -> anyhow::Result<frame::Video> {
+        if let Some((last_num, last)) = &self.last_frame
+            && *last_num <= frame
+            && self
+                .overshoot_frame
+                .as_ref()
+                .is_some_and(|(next_num, _)| frame < *next_num)
+        {
+            // The last frame is still on screen at the requested time.
+            return Ok(last.clone());
+        }
+
+        let can_decode_forward = self
+            .last_frame
+            .as_ref()
+            .is_some_and(|(last, _)| *last <= frame && frame.0 - last.0 <= MAX_DECODE_AHEAD);
+        if !can_decode_forward {
+            self.seek_to(frame)?;
+        }
+
+        loop {
+            let next = match self.overshoot_frame.take() {
+                Some(next) => Some(next),
+                None => self.decode_next_raw()?,
+            };
+            let Some((next_num, next_frame)) = next else {
+                // Past the end of the stream, the final frame stays up.
+                return self
+                    .last_frame
+                    .as_ref()
+                    .map(|(_, last)| last.clone())
+                    .with_context(|| format!("stream has no frame at {}", frame.0));
+            };
+
+            if next_num == frame {
+                self.last_frame = Some((next_num, next_frame.clone()));
+                return Ok(next_frame);
+            } else if next_num > frame {
+                return match self.last_frame.as_ref() {
+                    Some((_, last)) => {
+                        let last = last.clone();
+                        self.overshoot_frame = Some((next_num, next_frame));
+                        Ok(last)
+                    }
+                    None => {
+                        // Nothing precedes this frame, which happens when the
+                        // stream starts late; show the first frame we have.
+                        self.last_frame = Some((next_num, next_frame.clone()));
+                        Ok(next_frame)
+                    }
+                };
+            }
+
+            self.last_frame = Some((next_num, next_frame));
+        }
+    }
+
+    /// Decodes the next frame in presentation order along with its project
+    /// frame number, or `None` once the stream is exhausted.
+    fn decode_next_raw(&mut self) -> anyhow::Result<Option<(FrameNum, frame::Video)>> {
+        let stream_index = self.stream_index;
+        let mut decoded_frame = frame::Video::empty();
+        loop {
+            if self.video_decoder.receive_frame(&mut decoded_frame).is_ok() {
+                let pts = decoded_frame
+                    .timestamp()
+                    .or(decoded_frame.pts())
+                    .context("decoded video frame has no timestamp")?;
+                return Ok(Some((self.pts_to_frame(pts), decoded_frame)));
+            }
+            if self.reached_eof {
+                return Ok(None);
+            }
+
+            match self
+                .input_ctx
+                .packets()
+                .find(|(stream, _)| stream.index() == stream_index)
+            {
+                Some((_, packet)) => self
+                    .video_decoder
+                    .send_packet(&packet)
+                    .context("failed to send packet from input to video decoder")?,
+                None => {
+                    self.reached_eof = true;
+                    self.video_decoder
+                        .send_eof()
+                        .context("failed to flush video decoder")?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ff_interop::rgba_frame_to_image,
+        test_media::{self, CLIP_FPS, CLIP_FRAMES, CLIP_GOP},
+    };
+
+    fn open(fps: JadeRational) -> FfmpegVideoDecoder {
+        let input_ctx = format::input(test_media::numbered_clip()).unwrap();
+        FfmpegVideoDecoder::new(input_ctx, 0, fps).unwrap()
+    }
+
+    fn shown_at(decoder: &mut FfmpegVideoDecoder, frame: u64) -> u64 {
+        let decoded = decoder.decode_frame_at(FrameNum(frame)).unwrap();
+        test_media::frame_number_of(&rgba_frame_to_image(&decoded))
+    }
+
+    #[test]
+    fn decodes_every_frame_in_order() {
+        let mut decoder = open(CLIP_FPS);
+        for frame in 0..CLIP_FRAMES {
+            assert_eq!(shown_at(&mut decoder, frame), frame);
+        }
+    }
+
+    #[test]
+    fn steps_backwards() {
+        let mut decoder = open(CLIP_FPS);
+        for frame in (0..CLIP_FRAMES).rev() {
+            assert_eq!(shown_at(&mut decoder, frame), frame);
+        }
+    }
+
+    #[test]
+    fn seeks_to_keyframes_and_between_them() {
+        let gop = CLIP_GOP as u64;
+        let mut decoder = open(CLIP_FPS);
+        for frame in [
+            gop * 5,
+            gop * 5 + 1,
+            gop * 2 - 1,
+            gop * 10,
+            3,
+            gop * 3 + gop / 2,
+            0,
+            CLIP_FRAMES - 1,
+            gop,
+            gop * 7 + 5,
+        ] {
+            assert_eq!(
+                shown_at(&mut decoder, frame),
+                frame,
+                "after seeking to {frame}"
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_ahead_or_seeks_depending_on_distance() {
+        let mut decoder = open(CLIP_FPS);
+        assert_eq!(shown_at(&mut decoder, 5), 5);
+        // Close enough to decode forward to.
+        let ahead = 5 + MAX_DECODE_AHEAD;
+        assert_eq!(shown_at(&mut decoder, ahead), ahead);
+        // Too far, so this seeks.
+        let beyond = CLIP_FRAMES - 2;
+        assert!(beyond - ahead > MAX_DECODE_AHEAD);
+        assert_eq!(shown_at(&mut decoder, beyond), beyond);
+    }
+
+    #[test]
+    fn holds_frames_when_the_project_rate_is_higher() {
+        // At twice the clip's rate, every source frame is shown twice, the
+        // second time from the frame held back after overshooting.
+        let mut decoder = open(JadeRational::new(CLIP_FPS.num * 2, CLIP_FPS.den));
+        for frame in 0..40 {
+            assert_eq!(
+                shown_at(&mut decoder, frame),
+                frame / 2,
+                "at project frame {frame}"
+            );
+        }
+        for frame in [101, 100, 57, 58, 20] {
+            assert_eq!(
+                shown_at(&mut decoder, frame),
+                frame / 2,
+                "at project frame {frame}"
+            );
+        }
+    }
+
+    #[test]
+    fn holds_the_last_frame_past_the_end() {
+        let mut decoder = open(CLIP_FPS);
+        assert_eq!(shown_at(&mut decoder, CLIP_FRAMES + 10), CLIP_FRAMES - 1);
+    }
 }
@@ -0,0 +1,492 @@
+use super::{audio_player::FfmpegAudioDecoder, probe};
+use crate::{
+    disk_cache,
+    project::{AudioSpec, FrameSpan, JadeRational, MediaFingerprint},
+};
+use anyhow::{Context, bail, ensure};
+use log::{info, warn};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// How many samples each peak of the finest level covers.
+const BASE_SAMPLES_PER_PEAK: u64 = 256;
+/// How many times more samples each level's peaks cover than the last's.
+const LEVEL_FACTOR: u64 = 4;
+/// Used if the stream doesn't say what its sample rate is.
+const FALLBACK_SAMPLE_RATE: u32 = 48000;
+/// Starts every peak file, followed by the format version.
+const PEAK_FILE_MAGIC: &[u8; 4] = b"JVPK";
+const PEAK_FILE_VERSION: u32 = 1;
+/// The size of a peak file's header: magic, version, sample rate, sample
+/// count and samples per peak.
+const PEAK_FILE_HEADER_LEN: usize = 4 + 4 + 4 + 8 + 8;
+
+/// The loudest and quietest samples of a run of audio, and its RMS level.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Peak {
+    pub min: f32,
+    pub max: f32,
+    pub rms: f32,
+}
+
+impl Peak {
+    /// What a peak starts as before any samples are measured into it.
+    const UNMEASURED: Peak = Peak {
+        min: f32::MAX,
+        max: f32::MIN,
+        rms: 0.0,
+    };
+
+    /// Combines the peaks of runs of audio, each paired with how many samples
+    /// long it is, into the peak of all of them together. Silent if there are
+    /// none.
+    fn merge(peaks: impl IntoIterator<Item = (Peak, u64)>) -> Peak {
+        let mut merged = Peak::UNMEASURED;
+        let (mut squares, mut len) = (0.0f64, 0u64);
+        for (peak, peak_len) in peaks {
+            merged.min = merged.min.min(peak.min);
+            merged.max = merged.max.max(peak.max);
+            squares += (peak.rms as f64).powi(2) * peak_len as f64;
+            len += peak_len;
+        }
+        if len == 0 {
+            return Peak::default();
+        }
+        merged.rms = (squares / len as f64).sqrt() as f32;
+        merged
+    }
+}
+
+/// Peaks over runs of the same length, all but the last of which are full.
+#[derive(Debug, Clone)]
+pub struct PeakLevel {
+    pub samples_per_peak: u64,
+    pub peaks: Vec<Peak>,
+}
+
+/// An audio stream's peaks, mixed down to mono, at several zoom levels.
+#[derive(Debug, Clone)]
+pub struct Waveform {
+    pub stream_index: usize,
+    pub sample_rate: u32,
+    pub sample_count: u64,
+    /// From finest to coarsest. Each level's peaks cover `LEVEL_FACTOR` times
+    /// as many samples as the last's, down to a single peak.
+    pub levels: Vec<PeakLevel>,
+}
+
+impl Waveform {
+    /// Builds every zoom level from the finest one.
+    fn from_base(
+        stream_index: usize,
+        sample_rate: u32,
+        sample_count: u64,
+        base: Vec<Peak>,
+    ) -> Self {
+        let mut levels = vec![PeakLevel {
+            samples_per_peak: BASE_SAMPLES_PER_PEAK,
+            peaks: base,
+        }];
+        while let Some(last) = levels.last()
+            && last.peaks.len() > 1
+        {
+            let samples_per_peak = last.samples_per_peak * LEVEL_FACTOR;
+            let peaks = last
+                .peaks
+                .chunks(LEVEL_FACTOR as usize)
+                .enumerate()
+                .map(|(index, chunk)| {
+                    let start = index as u64 * samples_per_peak;
+                    Peak::merge(chunk.iter().enumerate().map(|(offset, peak)| {
+                        let peak_start = start + offset as u64 * last.samples_per_peak;
+                        let len = sample_count
+                            .saturating_sub(peak_start)
+                            .min(last.samples_per_peak);
+                        (*peak, len)
+                    }))
+                })
+                .collect();
+            levels.push(PeakLevel {
+                samples_per_peak,
+                peaks,
+            });
+        }
+        Self {
+            stream_index,
+            sample_rate,
+            sample_count,
+            levels,
+        }
+    }
+
+    /// The peaks of `span` (in frames of the stream at `fps`) split into
+    /// `width` equal columns, e.g. one per pixel. Columns past the end of the
+    /// stream are silent.
+    pub fn peaks_for(&self, span: &FrameSpan, fps: JadeRational, width: usize) -> Vec<Peak> {
+        let spec = AudioSpec {
+            sample_rate: self.sample_rate,
+            channels: 1,
+        };
+        if width == 0 {
+            return vec![];
+        }
+        let start = spec.first_sample_of_frame(span.from, fps);
+        let end = spec.first_sample_of_frame(span.to_excl, fps);
+        let samples_per_column = (end - start) as f64 / width as f64;
+
+        // The coarsest level that still has at least one peak per column.
+        let level = self
+            .levels
+            .iter()
+            .rev()
+            .find(|level| level.samples_per_peak as f64 <= samples_per_column)
+            .unwrap_or(&self.levels[0]);
+
+        (0..width)
+            .map(|column| {
+                let column_start = start + (column as f64 * samples_per_column) as u64;
+                let column_end = (start + ((column + 1) as f64 * samples_per_column) as u64)
+                    .max(column_start + 1);
+                self.level_peak(level, column_start, column_end)
+            })
+            .collect()
+    }
+
+    /// The peak of samples `start..end`, from the peaks of `level` covering
+    /// them.
+    fn level_peak(&self, level: &PeakLevel, start: u64, end: u64) -> Peak {
+        let end = end.min(self.sample_count);
+        if start >= end {
+            return Peak::default();
+        }
+        let first = (start / level.samples_per_peak) as usize;
+        let last = ((end - 1) / level.samples_per_peak) as usize;
+        let peaks = level
+            .peaks
+            .get(first..=last.min(level.peaks.len().saturating_sub(1)));
+        Peak::merge(peaks.into_iter().flatten().map(|peak| (*peak, 1)))
+    }
+
+    /// The finest level, as saved in a peak file. The rest are quick to
+    /// rebuild from it.
+    fn to_bytes(&self) -> Vec<u8> {
+        let base = &self.levels[0].peaks;
+        let mut bytes = Vec::with_capacity(PEAK_FILE_HEADER_LEN + base.len() * 12);
+        bytes.extend_from_slice(PEAK_FILE_MAGIC);
+        bytes.extend_from_slice(&PEAK_FILE_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&self.sample_count.to_le_bytes());
+        bytes.extend_from_slice(&BASE_SAMPLES_PER_PEAK.to_le_bytes());
+        for peak in base {
+            for value in [peak.min, peak.max, peak.rms] {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    fn from_bytes(stream_index: usize, bytes: &[u8]) -> anyhow::Result<Self> {
+        ensure!(
+            bytes.len() >= PEAK_FILE_HEADER_LEN && bytes.starts_with(PEAK_FILE_MAGIC),
+            "not a peak file"
+        );
+        let (header, body) = bytes.split_at(PEAK_FILE_HEADER_LEN);
+        let u32_at = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+        let u64_at = |at: usize| u64::from_le_bytes(header[at..at + 8].try_into().unwrap());
+        let version = u32_at(4);
+        if version != PEAK_FILE_VERSION {
+            bail!("peak file is version {version}, expected {PEAK_FILE_VERSION}");
+        }
+        ensure!(
+            u64_at(20) == BASE_SAMPLES_PER_PEAK,
+            "peak file has {} samples per peak, expected {BASE_SAMPLES_PER_PEAK}",
+            u64_at(20)
+        );
+        let (sample_rate, sample_count) = (u32_at(8), u64_at(12));
+        ensure!(
+            body.len() % 12 == 0
+                && (body.len() / 12) as u64 == sample_count.div_ceil(BASE_SAMPLES_PER_PEAK),
+            "peak file holds {} bytes of peaks, which doesn't match its {sample_count} samples",
+            body.len()
+        );
+
+        let base = body
+            .chunks_exact(12)
+            .map(|peak| {
+                let f32_at = |at: usize| f32::from_le_bytes(peak[at..at + 4].try_into().unwrap());
+                Peak {
+                    min: f32_at(0),
+                    max: f32_at(4),
+                    rms: f32_at(8),
+                }
+            })
+            .collect();
+        Ok(Self::from_base(
+            stream_index,
+            sample_rate,
+            sample_count,
+            base,
+        ))
+    }
+}
+
+/// Decodes a whole audio stream of a file once, mixed down to mono at its own
+/// sample rate, and measures its peaks. `on_progress` is told how far through
+/// the stream decoding is, in samples. Stops with an error once
+/// `is_cancelled` returns true.
+pub fn generate_waveform(
+    path: &Path,
+    stream_index: usize,
+    is_cancelled: impl Fn() -> bool,
+    mut on_progress: impl FnMut(u64, u64),
+) -> anyhow::Result<Waveform> {
+    let input_ctx = ffmpeg_next::format::input(path)
+        .context("failed to get input format context for file from ffmpeg")?;
+    let stream = input_ctx
+        .stream(stream_index)
+        .with_context(|| format!("failed to locate stream at index {stream_index}"))?;
+    let sample_rate = match probe::probe_audio(&stream)?.sample_rate {
+        0 => FALLBACK_SAMPLE_RATE,
+        rate => rate,
+    };
+    let expected_samples = (stream.duration().max(0) as f64
+        * JadeRational::from(stream.time_base()).to_f64()
+        * sample_rate as f64) as u64;
+    let mut decoder = FfmpegAudioDecoder::new(
+        input_ctx,
+        stream_index,
+        AudioSpec {
+            sample_rate,
+            channels: 1,
+        },
+    )?;
+
+    let mut base = vec![];
+    // The samples of the peak being measured, which may span chunks.
+    let mut current = Peak::UNMEASURED;
+    let (mut current_len, mut current_squares) = (0u64, 0.0f64);
+    let mut sample_count = 0u64;
+    while let Some(chunk) = decoder.receive_chunk()? {
+        if is_cancelled() {
+            bail!("waveform was cancelled");
+        }
+        for &sample in &chunk.planes[0] {
+            current.min = current.min.min(sample);
+            current.max = current.max.max(sample);
+            current_squares += (sample as f64).powi(2);
+            current_len += 1;
+            if current_len == BASE_SAMPLES_PER_PEAK {
+                current.rms = (current_squares / current_len as f64).sqrt() as f32;
+                base.push(current);
+                current = Peak::UNMEASURED;
+                (current_len, current_squares) = (0, 0.0);
+            }
+        }
+        sample_count += chunk.sample_count() as u64;
+        on_progress(sample_count, expected_samples.max(sample_count));
+    }
+    if current_len > 0 {
+        current.rms = (current_squares / current_len as f64).sqrt() as f32;
+        base.push(current);
+    }
+
+    info!(
+        "measured {} peaks from {sample_count} samples of {}",
+        base.len(),
+        path.display()
+    );
+    Ok(Waveform::from_base(
+        stream_index,
+        sample_rate,
+        sample_count,
+        base,
+    ))
+}
+
+/// Keeps measured waveforms on disk as peak files, so each stream is only
+/// decoded once.
+#[derive(Debug, Clone)]
+pub struct WaveformCache {
+    dir: PathBuf,
+}
+
+impl WaveformCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// The cache in the app's usual cache folder.
+    pub fn in_cache_dir() -> Self {
+        Self::new(disk_cache::cache_dir("waveforms"))
+    }
+
+    /// Loads the waveform of a stream from the cache if it's there and the
+    /// file hasn't changed since, or generates and caches it otherwise.
+    pub fn get_or_generate(
+        &self,
+        path: &Path,
+        fingerprint: Option<&MediaFingerprint>,
+        stream_index: usize,
+        is_cancelled: impl Fn() -> bool,
+        on_progress: impl FnMut(u64, u64),
+    ) -> anyhow::Result<Waveform> {
+        let key = disk_cache::source_key(
+            path,
+            fingerprint,
+            &format!("waveform stream {stream_index}"),
+        )?;
+        let peak_path = self.dir.join(format!("{key}.peaks"));
+        if peak_path.exists() {
+            match fs::read(&peak_path)
+                .map_err(anyhow::Error::from)
+                .and_then(|bytes| Waveform::from_bytes(stream_index, &bytes))
+            {
+                Ok(waveform) => {
+                    info!("loaded cached waveform {key}");
+                    return Ok(waveform);
+                }
+                Err(err) => warn!("ignoring unreadable cached waveform {key}: {err:#}"),
+            }
+        }
+
+        let waveform = generate_waveform(path, stream_index, is_cancelled, on_progress)?;
+        let stored = disk_cache::write_atomic(&peak_path, |partial| {
+            Ok(fs::write(partial, waveform.to_bytes())?)
+        });
+        if let Err(err) = stored {
+            warn!("failed to cache waveform for {}: {err:#}", path.display());
+        }
+        Ok(waveform)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        project::FrameNum,
+        test_media::{self, CLIP_FPS, CLIP_FRAMES, TONE_AMPLITUDE},
+    };
+    use ffmpeg_next::media;
+
+    /// The tone is on both channels, and the resampler mixes each into mono
+    /// at -3 dB, so it comes out this much louder.
+    const DOWNMIX_GAIN: f32 = std::f32::consts::SQRT_2;
+    /// Matches 16-bit FLAC, give or take a rounding.
+    const TOLERANCE: f32 = 2e-3;
+
+    fn tone_waveform() -> Waveform {
+        let clip = test_media::tone_clip();
+        let stream_index = ffmpeg_next::format::input(clip)
+            .unwrap()
+            .streams()
+            .best(media::Type::Audio)
+            .unwrap()
+            .index();
+        let mut done = vec![];
+        let waveform = generate_waveform(
+            clip,
+            stream_index,
+            || false,
+            |samples, _| done.push(samples),
+        )
+        .unwrap();
+        assert!(done.is_sorted());
+        assert_eq!(done.last(), Some(&waveform.sample_count));
+        waveform
+    }
+
+    /// The peak of the tone over `samples`, as it should measure in mono.
+    fn expected_peak(samples: std::ops::Range<u64>, sample_rate: u32) -> Peak {
+        let levels: Vec<f32> = samples
+            .map(|n| test_media::tone_sample(n, sample_rate) * DOWNMIX_GAIN)
+            .collect();
+        let squares: f64 = levels.iter().map(|&level| (level as f64).powi(2)).sum();
+        Peak {
+            min: levels.iter().copied().fold(f32::MAX, f32::min),
+            max: levels.iter().copied().fold(f32::MIN, f32::max),
+            rms: (squares / levels.len() as f64).sqrt() as f32,
+        }
+    }
+
+    fn assert_peak(actual: Peak, expected: Peak, what: &str) {
+        for (field, actual, expected) in [
+            ("min", actual.min, expected.min),
+            ("max", actual.max, expected.max),
+            ("rms", actual.rms, expected.rms),
+        ] {
+            assert!(
+                (actual - expected).abs() < TOLERANCE,
+                "{field} of {what} is {actual} rather than {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn measures_the_peaks_of_a_known_tone() {
+        let waveform = tone_waveform();
+        let sample_rate = waveform.sample_rate;
+        assert_eq!(sample_rate, AudioSpec::default().sample_rate);
+        let spec = AudioSpec {
+            sample_rate,
+            channels: 1,
+        };
+        assert_eq!(
+            waveform.sample_count,
+            spec.first_sample_of_frame(FrameNum(CLIP_FRAMES), CLIP_FPS)
+        );
+
+        for level in &waveform.levels {
+            assert_eq!(
+                level.peaks.len() as u64,
+                waveform.sample_count.div_ceil(level.samples_per_peak)
+            );
+            for (index, &peak) in level.peaks.iter().enumerate() {
+                let start = index as u64 * level.samples_per_peak;
+                let end = (start + level.samples_per_peak).min(waveform.sample_count);
+                assert_peak(
+                    peak,
+                    expected_peak(start..end, sample_rate),
+                    &format!("peak {index} of {}", level.samples_per_peak),
+                );
+            }
+        }
+
+        // Even the finest peaks hold a whole cycle of the tone, so all of
+        // them reach its full amplitude.
+        let amplitude = TONE_AMPLITUDE * DOWNMIX_GAIN;
+        let whole = waveform.levels.last().unwrap();
+        assert_eq!(whole.peaks.len(), 1);
+        assert_peak(
+            whole.peaks[0],
+            Peak {
+                min: -amplitude,
+                max: amplitude,
+                rms: amplitude / std::f32::consts::SQRT_2,
+            },
+            "the whole clip",
+        );
+    }
+
+    #[test]
+    fn splits_spans_into_columns() {
+        let waveform = tone_waveform();
+        let span = FrameSpan::with_len(FrameNum(30), 60).unwrap();
+        let columns = waveform.peaks_for(&span, CLIP_FPS, 8);
+        assert_eq!(columns.len(), 8);
+        for (index, &column) in columns.iter().enumerate() {
+            assert!(
+                (column.max - TONE_AMPLITUDE * DOWNMIX_GAIN).abs() < TOLERANCE,
+                "column {index} peaks at {}",
+                column.max
+            );
+        }
+
+        let past_the_end = FrameSpan::with_len(FrameNum(CLIP_FRAMES), 30).unwrap();
+        for column in waveform.peaks_for(&past_the_end, CLIP_FPS, 4) {
+            assert_eq!(column, Peak::default());
+        }
+    }
+}
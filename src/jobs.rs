@@ -0,0 +1,601 @@
+use crate::{
+    AppEvent,
+    ff_interop::{
+        self,
+        filmstrip::{Filmstrip, FilmstripCache},
+        proxy::ProxyCache,
+        waveform::{Waveform, WaveformCache},
+    },
+    project::{self, MediaFingerprint, MediaInfo, MediaKey, ProxyMedia, RelinkMatch},
+    render::CancelToken,
+};
+use anyhow::Context;
+use fltk::app::Sender;
+use log::{info, warn};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, mpsc},
+    thread,
+};
+use tokio::{runtime, sync::oneshot};
+
+/// How many jobs may run at once. The rest wait their turn.
+const MAX_CONCURRENT_JOBS: usize = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct JobId(u64);
+
+/// Something a job made, handed to the UI thread as soon as it's ready rather
+/// than when the whole job finishes.
+#[derive(Debug, Clone)]
+pub enum JobOutput {
+    Media(MediaInfo),
+    Filmstrip {
+        key: MediaKey,
+        /// The file the filmstrip was made from, in case the media has been
+        /// relinked since.
+        path: PathBuf,
+        filmstrip: Arc<Filmstrip>,
+    },
+    Waveform {
+        key: MediaKey,
+        path: PathBuf,
+        waveform: Arc<Waveform>,
+    },
+    Proxy {
+        key: MediaKey,
+        path: PathBuf,
+        proxy: ProxyMedia,
+    },
+    /// The best file found for each missing media that any were found for.
+    RelinkMatches(Vec<RelinkMatch>),
+    /// Media whose files no longer match their fingerprints.
+    ChangedMedia(Vec<MediaKey>),
+    /// Fresh fingerprints for media whose files were touched without being
+    /// changed, so they're recognized by their metadata again next time.
+    /// Each comes with the file it was taken of, in case the media has been
+    /// relinked since.
+    RefreshedFingerprints(Vec<(MediaKey, PathBuf, MediaFingerprint)>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobResult {
+    Done,
+    Cancelled,
+    /// The error, formatted, since events have to be `Clone`.
+    Failed(String),
+}
+
+/// Sent as `AppEvent::Job` while a job runs. `Finished` is always the last
+/// event for a job.
+#[derive(Debug, Clone)]
+pub enum JobEvent {
+    Progress { done: u64, total: u64 },
+    Output(JobOutput),
+    Finished(JobResult),
+}
+
+/// Handed to a running job, for reporting back to the UI thread.
+pub struct JobContext {
+    id: JobId,
+    event_sender: Sender<AppEvent>,
+    cancel: CancelToken,
+}
+
+impl JobContext {
+    /// Jobs should check this between steps, and return early once it's set.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    pub fn progress(&self, done: u64, total: u64) {
+        self.send(JobEvent::Progress { done, total });
+    }
+
+    pub fn output(&self, output: JobOutput) {
+        self.send(JobEvent::Output(output));
+    }
+
+    fn send(&self, event: JobEvent) {
+        self.event_sender.send(AppEvent::Job(self.id, event));
+    }
+}
+
+/// What the job status display shows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobStatus {
+    pub label: String,
+    /// How far through the running jobs are, or `None` if none have reported
+    /// any progress yet.
+    pub fraction: Option<f64>,
+}
+
+struct RunningJob {
+    name: String,
+    cancel: CancelToken,
+    done: u64,
+    total: u64,
+}
+
+/// Runs slow work (probing, thumbnails and the like) on a tokio runtime off the
+/// UI thread. Jobs report back through `AppEvent::Job`, which the UI thread
+/// passes to `handle_event` to keep track of what's still running.
+pub struct JobSystem {
+    runtime: runtime::Handle,
+    /// The runtime's thread stops once this is dropped.
+    _shutdown: oneshot::Sender<()>,
+    event_sender: Sender<AppEvent>,
+    next_id: u64,
+    running: BTreeMap<JobId, RunningJob>,
+}
+
+impl JobSystem {
+    pub fn new(event_sender: Sender<AppEvent>) -> Self {
+        let (handle_sender, handle_receiver) = mpsc::channel();
+        let (shutdown, shutdown_receiver) = oneshot::channel::<()>();
+        thread::Builder::new()
+            .name("jobs".to_string())
+            .spawn(move || {
+                let runtime = runtime::Builder::new_current_thread()
+                    .max_blocking_threads(MAX_CONCURRENT_JOBS)
+                    .enable_all()
+                    .build()
+                    .expect("failed to build job runtime");
+                handle_sender
+                    .send(runtime.handle().clone())
+                    .expect("job system went away before its runtime started");
+                // Resolves with an error once the sender is dropped.
+                let _ = runtime.block_on(shutdown_receiver);
+            })
+            .expect("failed to spawn job thread");
+        let runtime = handle_receiver
+            .recv()
+            .expect("job thread exited before starting its runtime");
+
+        Self {
+            runtime,
+            _shutdown: shutdown,
+            event_sender,
+            next_id: 0,
+            running: BTreeMap::new(),
+        }
+    }
+
+    /// Starts a job. `name` is shown while it runs, e.g. "Importing media".
+    pub fn spawn(
+        &mut self,
+        name: impl Into<String>,
+        job: impl FnOnce(&JobContext) -> anyhow::Result<()> + Send + 'static,
+    ) -> JobId {
+        let id = JobId(self.next_id);
+        self.next_id += 1;
+        let name = name.into();
+        let cancel = CancelToken::default();
+        let context = JobContext {
+            id,
+            event_sender: self.event_sender,
+            cancel: cancel.clone(),
+        };
+
+        let event_sender = self.event_sender;
+        let job_name = name.clone();
+        self.runtime.spawn(async move {
+            // Jobs are blocking FFmpeg and file system work, so they get a
+            // thread of their own rather than holding up the runtime.
+            let result = tokio::task::spawn_blocking(move || {
+                let result = job(&context);
+                (result, context.is_cancelled())
+            })
+            .await;
+            let result = match result {
+                Ok((_, true)) => JobResult::Cancelled,
+                Ok((Ok(()), false)) => JobResult::Done,
+                Ok((Err(err), false)) => {
+                    warn!("job \"{job_name}\" failed: {err:#}");
+                    JobResult::Failed(format!("{err:#}"))
+                }
+                Err(err) => JobResult::Failed(format!("job panicked: {err}")),
+            };
+            event_sender.send(AppEvent::Job(id, JobEvent::Finished(result)));
+        });
+
+        info!("started job {} \"{name}\"", id.0);
+        self.running.insert(
+            id,
+            RunningJob {
+                name,
+                cancel,
+                done: 0,
+                total: 0,
+            },
+        );
+        id
+    }
+
+    /// Keeps track of progress, and forgets jobs once they finish. Returns the
+    /// name of the job the event is for.
+    pub fn handle_event(&mut self, id: JobId, event: &JobEvent) -> Option<String> {
+        match event {
+            JobEvent::Progress { done, total } => {
+                let job = self.running.get_mut(&id)?;
+                (job.done, job.total) = (*done, *total);
+                Some(job.name.clone())
+            }
+            JobEvent::Output(_) => self.running.get(&id).map(|job| job.name.clone()),
+            JobEvent::Finished(result) => {
+                let job = self.running.remove(&id)?;
+                info!("job {} \"{}\" finished: {result:?}", id.0, job.name);
+                Some(job.name)
+            }
+        }
+    }
+
+    pub fn cancel_all(&self) {
+        for job in self.running.values() {
+            job.cancel.cancel();
+        }
+    }
+
+    /// A summary of what's running, or `None` when idle.
+    pub fn status(&self) -> Option<JobStatus> {
+        let first = self.running.values().next()?;
+        let label = match self.running.len() {
+            1 => first.name.clone(),
+            count => format!("{} (+{} more)", first.name, count - 1),
+        };
+        let (done, total) = self.running.values().fold((0, 0), |(done, total), job| {
+            (done + job.done, total + job.total)
+        });
+        Some(JobStatus {
+            label,
+            fraction: (total > 0).then(|| done as f64 / total as f64),
+        })
+    }
+}
+
+impl Drop for JobSystem {
+    fn drop(&mut self) {
+        self.cancel_all();
+    }
+}
+
+/// A job that probes files and hands back each one FFmpeg can read as
+/// `JobOutput::Media`. Folders are searched for files, including subfolders.
+/// Files that can't be read are skipped, and only fail the job if nothing
+/// could be imported at all.
+pub fn import_media_job(
+    paths: Vec<PathBuf>,
+) -> impl FnOnce(&JobContext) -> anyhow::Result<()> + Send + 'static {
+    move |context| {
+        let mut files = vec![];
+        for path in &paths {
+            collect_files(path, &mut files)?;
+        }
+        let total = files.len() as u64;
+
+        let mut imported = 0;
+        let mut last_error = None;
+        for (done, file) in files.into_iter().enumerate() {
+            if context.is_cancelled() {
+                break;
+            }
+            context.progress(done as u64, total);
+            info!("import file \"{}\"", file.display());
+            match ff_interop::load_media_sync(file.clone()) {
+                Ok(info) if info.streams.is_empty() => {
+                    info!("skipping {}, it has no audio or video", file.display());
+                }
+                Ok(info) => {
+                    context.output(JobOutput::Media(info));
+                    imported += 1;
+                }
+                Err(err) => {
+                    warn!("skipping {}: {err:#}", file.display());
+                    last_error = Some(err.context(format!("failed to import {}", file.display())));
+                }
+            }
+        }
+        context.progress(total, total);
+
+        match last_error {
+            Some(err) if imported == 0 => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A job that searches folders, including subfolders, for files to relink
+/// missing media to, handing back the best match for each as one
+/// `JobOutput::RelinkMatches`. Only files with the same name or size as some
+/// missing media are probed, so big folders are quick to search.
+pub fn relink_search_job(
+    missing: Vec<(MediaKey, MediaInfo)>,
+    folders: Vec<PathBuf>,
+) -> impl FnOnce(&JobContext) -> anyhow::Result<()> + Send + 'static {
+    move |context| {
+        let mut files = vec![];
+        for folder in &folders {
+            collect_files(folder, &mut files)?;
+        }
+        let total = files.len() as u64;
+
+        let mut best = BTreeMap::<MediaKey, RelinkMatch>::new();
+        for (done, file) in files.into_iter().enumerate() {
+            if context.is_cancelled() {
+                return Ok(());
+            }
+            context.progress(done as u64, total);
+            let Ok(metadata) = fs::metadata(&file) else {
+                continue;
+            };
+            let worth_probing: Vec<_> = missing
+                .iter()
+                .filter(|(_, info)| project::could_relink_to(info, &file, metadata.len()))
+                .collect();
+            if worth_probing.is_empty() {
+                continue;
+            }
+            let candidate = match ff_interop::load_media_sync(file.clone()) {
+                Ok(candidate) => candidate,
+                Err(err) => {
+                    info!("not relinking to {}: {err:#}", file.display());
+                    continue;
+                }
+            };
+
+            for (key, info) in worth_probing {
+                let Some(confidence) = project::relink_confidence(info, &candidate) else {
+                    continue;
+                };
+                if best
+                    .get(key)
+                    .is_none_or(|found| confidence > found.confidence)
+                {
+                    info!(
+                        "found {} for missing {} ({confidence:?})",
+                        file.display(),
+                        info.path.display()
+                    );
+                    best.insert(
+                        *key,
+                        RelinkMatch {
+                            key: *key,
+                            old_path: info.path.clone(),
+                            new_path: file.clone(),
+                            confidence,
+                        },
+                    );
+                }
+            }
+        }
+        context.progress(total, total);
+
+        context.output(JobOutput::RelinkMatches(best.into_values().collect()));
+        Ok(())
+    }
+}
+
+/// A job that checks media files against their fingerprints, handing back
+/// the ones that have been replaced since as `JobOutput::ChangedMedia`. Only
+/// files whose size or modification time differ are sampled again, and those
+/// that turn out to have the same content are handed back with their new
+/// fingerprints as `JobOutput::RefreshedFingerprints`. Missing or unreadable
+/// files and media without fingerprints are skipped.
+pub fn check_media_job(
+    media: Vec<(MediaKey, MediaInfo)>,
+) -> impl FnOnce(&JobContext) -> anyhow::Result<()> + Send + 'static {
+    move |context| {
+        let total = media.len() as u64;
+        let mut changed = vec![];
+        let mut refreshed = vec![];
+        for (done, (key, info)) in media.iter().enumerate() {
+            if context.is_cancelled() {
+                return Ok(());
+            }
+            context.progress(done as u64, total);
+            let Some(fingerprint) = &info.fingerprint else {
+                continue;
+            };
+            match fs::metadata(&info.path) {
+                Ok(metadata) if fingerprint.matches_metadata(&metadata) => continue,
+                Ok(_) => {}
+                Err(_) => continue,
+            }
+            let current = match MediaFingerprint::of_file(&info.path, &info.streams) {
+                Ok(current) => current,
+                Err(err) => {
+                    warn!("not checking {}: {err:#}", info.path.display());
+                    continue;
+                }
+            };
+            if current.same_content(fingerprint) {
+                refreshed.push((*key, info.path.clone(), current));
+            } else {
+                warn!("{} has changed since it was imported", info.path.display());
+                changed.push(*key);
+            }
+        }
+        context.progress(total, total);
+
+        if !refreshed.is_empty() {
+            context.output(JobOutput::RefreshedFingerprints(refreshed));
+        }
+        if !changed.is_empty() {
+            context.output(JobOutput::ChangedMedia(changed));
+        }
+        Ok(())
+    }
+}
+
+/// Adds `path` to `files` if it's a file, or every file under it (skipping
+/// hidden ones) if it's a folder. Links to folders inside it aren't followed,
+/// so a link back up the tree can't send this round in circles.
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    let mut entries = fs::read_dir(path)
+        .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("failed to list folder {}", path.display()))?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => collect_files(&entry.path(), files)?,
+            _ => files.push(entry.path()),
+        }
+    }
+    Ok(())
+}
+
+/// A job that makes the filmstrip of a video stream, or loads it from `cache`
+/// if it's been made before.
+pub fn filmstrip_job(
+    key: MediaKey,
+    path: PathBuf,
+    fingerprint: Option<MediaFingerprint>,
+    stream_index: usize,
+    cache: FilmstripCache,
+) -> impl FnOnce(&JobContext) -> anyhow::Result<()> + Send + 'static {
+    move |context| {
+        let filmstrip = cache
+            .get_or_generate(
+                &path,
+                fingerprint.as_ref(),
+                stream_index,
+                || context.is_cancelled(),
+                |done, total| context.progress(done, total),
+            )
+            .with_context(|| format!("failed to make filmstrip for {}", path.display()))?;
+        context.output(JobOutput::Filmstrip {
+            key,
+            path,
+            filmstrip: Arc::new(filmstrip),
+        });
+        Ok(())
+    }
+}
+
+/// A job that measures the waveform of an audio stream, or loads it from
+/// `cache` if it's been measured before.
+pub fn waveform_job(
+    key: MediaKey,
+    path: PathBuf,
+    fingerprint: Option<MediaFingerprint>,
+    stream_index: usize,
+    cache: WaveformCache,
+) -> impl FnOnce(&JobContext) -> anyhow::Result<()> + Send + 'static {
+    move |context| {
+        let waveform = cache
+            .get_or_generate(
+                &path,
+                fingerprint.as_ref(),
+                stream_index,
+                || context.is_cancelled(),
+                |done, total| context.progress(done, total),
+            )
+            .with_context(|| format!("failed to measure waveform of {}", path.display()))?;
+        context.output(JobOutput::Waveform {
+            key,
+            path,
+            waveform: Arc::new(waveform),
+        });
+        Ok(())
+    }
+}
+
+/// A job that makes a proxy of a video stream, or finds the one made before in
+/// `cache`.
+pub fn proxy_job(
+    key: MediaKey,
+    path: PathBuf,
+    fingerprint: Option<MediaFingerprint>,
+    stream_index: usize,
+    cache: ProxyCache,
+) -> impl FnOnce(&JobContext) -> anyhow::Result<()> + Send + 'static {
+    move |context| {
+        let proxy = cache
+            .get_or_generate(
+                &path,
+                fingerprint.as_ref(),
+                stream_index,
+                || context.is_cancelled(),
+                |done, total| context.progress(done, total),
+            )
+            .with_context(|| format!("failed to make proxy of {}", path.display()))?;
+        context.output(JobOutput::Proxy { key, path, proxy });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_media;
+    use anyhow::bail;
+    use std::time::Duration;
+
+    /// Waits for a job to finish, keeping `jobs` up to date with its events
+    /// on the way.
+    fn wait_for_result(
+        jobs: &mut JobSystem,
+        receiver: &fltk::app::Receiver<AppEvent>,
+        id: JobId,
+    ) -> JobResult {
+        test_media::wait_for_event(receiver, |event| match event {
+            AppEvent::Job(event_id, event) if event_id == id => {
+                jobs.handle_event(id, &event);
+                match event {
+                    JobEvent::Finished(result) => Some(result),
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn reports_cancelled_jobs_as_cancelled() {
+        let (_turn, sender, receiver) = test_media::event_channel();
+        let mut jobs = JobSystem::new(sender);
+        let (started_sender, started) = mpsc::channel();
+        let id = jobs.spawn("Waiting to be cancelled", move |context| {
+            started_sender.send(()).unwrap();
+            while !context.is_cancelled() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            // Jobs usually stop by failing, which mustn't count as failing.
+            bail!("job was cancelled")
+        });
+        assert!(jobs.status().is_some());
+        started.recv_timeout(Duration::from_secs(10)).unwrap();
+
+        jobs.cancel_all();
+        assert_eq!(
+            wait_for_result(&mut jobs, &receiver, id),
+            JobResult::Cancelled
+        );
+        assert_eq!(jobs.status(), None);
+    }
+
+    #[test]
+    fn reports_failed_jobs_as_failed() {
+        let (_turn, sender, receiver) = test_media::event_channel();
+        let mut jobs = JobSystem::new(sender);
+        let id = jobs.spawn("Failing", |context| {
+            context.progress(1, 2);
+            bail!("something went wrong")
+        });
+        assert_eq!(
+            wait_for_result(&mut jobs, &receiver, id),
+            JobResult::Failed("something went wrong".to_string())
+        );
+        assert_eq!(jobs.status(), None);
+    }
+}
@@ -1,34 +1,75 @@
+mod cli;
+mod disk_cache;
 mod ff_interop;
+mod jobs;
+mod playback;
 mod project;
+mod render;
+#[cfg(test)]
+mod test_media;
 mod ui;
 
-use std::path::Path;
+use std::{path::PathBuf, time::Instant};
 
-use anyhow::Context;
+use anyhow::{Context, ensure};
 use env_logger::Env;
-use ff_interop::video_player::FfmpegVideoDecoder;
-use ffmpeg_next::{Codec, Rational, frame::Video, media::Type};
+use ff_interop::proxy::ProxyCache;
+use ffmpeg_next::Rational;
 use fltk::{
     app::{self, Sender},
-    dialog::{FileDialogAction, FileDialogType, NativeFileChooser},
+    dialog::{FileDialogAction, FileDialogOptions, FileDialogType, NativeFileChooser},
     enums::{Color, Event, Shortcut},
     menu::MenuFlag,
     prelude::*,
     window::Window,
 };
+use jobs::{JobEvent, JobId, JobOutput, JobResult, JobSystem};
 use log::{error, info, warn};
-use project::MediaProject;
-use slotmap::SlotMap;
-use ui::{UserInterface, WgpuState};
+use playback::{PlaybackControl, PlaybackEngine};
+use project::{
+    AudioSpec, EditCommand, FrameNum, MediaFingerprint, MediaKey, MediaPool, MediaProject,
+    PROJECT_FILE_EXTENSION, RelinkConfidence, RelinkMatch, Resolution, Timeline, UndoStack,
+};
+use render::{
+    Compositor, DEFAULT_FRAME_CACHE_BUDGET, FrameCache, FramePrefetcher, SharedFrameCache,
+};
+use ui::{MediaPoolPanel, PreviewZoom, TimelineWidget, UserInterface, WgpuState};
 
 pub const APP_TITLE_AND_VERSION: &str =
     concat!(env!("CARGO_PKG_NAME"), " v", env!("CARGO_PKG_VERSION"));
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub enum AppEvent {
     ResizePreview(u32, u32),
     RedrawPreview,
     MenuFileImport,
+    MenuFileImportFolder,
+    MenuFileRelink,
+    MenuFileOpen,
+    MenuFileSave,
+    MenuFileSaveAs,
+    MenuEditUndo,
+    MenuEditRedo,
+    MediaPoolRename,
+    MediaPoolReveal,
+    MediaPoolMakeProxy,
+    MediaPoolRemove,
+    TimelineEdit(EditCommand),
+    /// Clips started being dragged, so the edits until the drag ends should
+    /// make up a single undo step.
+    TimelineDragStarted,
+    /// The mouse was released after dragging clips, so the next edit should
+    /// get its own undo step.
+    TimelineDragEnded,
+    TimelineSeek(FrameNum),
+    MenuPlayback(PlaybackControl),
+    MenuPlaybackToggleProxies,
+    MenuViewZoom(PreviewZoom),
+    /// The preview was dragged by this many surface pixels.
+    PanPreview(f64, f64),
+    PlaybackTick,
+    Job(JobId, JobEvent),
+    JobsCancel,
 }
 
 #[allow(unused)]
@@ -38,8 +79,17 @@ struct MainApp<'a> {
     event_receiver: app::Receiver<AppEvent>,
     fltk_ui: UserInterface,
     preview_subwindow: Window,
+    media_pool: MediaPoolPanel,
+    timeline: TimelineWidget,
+    compositor: Compositor,
+    frame_cache: SharedFrameCache,
+    prefetcher: FramePrefetcher,
+    playback: PlaybackEngine,
+    jobs: JobSystem,
     wgpu_state: WgpuState<'a>,
     open_project: MediaProject,
+    project_path: Option<PathBuf>,
+    undo_stack: UndoStack,
 }
 
 impl MainApp<'_> {
@@ -53,6 +103,16 @@ impl MainApp<'_> {
 
         let mut ui = ui::UserInterface::make_window();
         Self::init_main_window(&mut ui, event_sender);
+        let open_project = MediaProject {
+            fps: Rational::new(30, 1).into(),
+            frame_count: 300,
+            resolution: Resolution::default(),
+            audio: AudioSpec::default(),
+            media: MediaPool::default(),
+            timeline: Timeline::default(),
+        };
+        let media_pool = MediaPoolPanel::new(&mut ui.media_group, event_sender);
+        let timeline = TimelineWidget::new(&mut ui.timeline_group, &open_project, event_sender);
         ui.main_window.show();
         info!("initialized main window");
 
@@ -60,6 +120,26 @@ impl MainApp<'_> {
         preview_subwindow.resize_callback(move |_, _, _, w, h| {
             event_sender.send(AppEvent::ResizePreview(w as u32, h as u32));
         });
+        preview_subwindow.handle({
+            let mut last_coords = (0, 0);
+            move |window, event| match event {
+                Event::Push => {
+                    last_coords = app::event_coords();
+                    true
+                }
+                Event::Drag => {
+                    let (x, y) = app::event_coords();
+                    let scale = window.pixels_per_unit() as f64;
+                    event_sender.send(AppEvent::PanPreview(
+                        (x - last_coords.0) as f64 * scale,
+                        (y - last_coords.1) as f64 * scale,
+                    ));
+                    last_coords = (x, y);
+                    true
+                }
+                _ => false,
+            }
+        });
         preview_subwindow.show();
         info!("initialized preview subwindow");
 
@@ -68,18 +148,30 @@ impl MainApp<'_> {
         wgpu_state.redraw();
         info!("initialized wgpu & preview rendering");
 
+        let frame_cache = FrameCache::shared(DEFAULT_FRAME_CACHE_BUDGET);
+        // Previews use proxies where there are any until they're turned off.
+        let mut compositor = Compositor::with_cache(frame_cache.clone());
+        compositor.set_use_proxies(true);
+        let mut playback = PlaybackEngine::new(event_sender, frame_cache.clone());
+        playback.set_use_proxies(&open_project, true);
+
         Self {
             fltk_app,
             event_sender,
             event_receiver,
             fltk_ui: ui,
             preview_subwindow,
+            media_pool,
+            timeline,
+            compositor,
+            prefetcher: FramePrefetcher::new(frame_cache.clone()),
+            playback,
+            frame_cache,
+            jobs: JobSystem::new(event_sender),
             wgpu_state,
-            open_project: MediaProject {
-                fps: Rational::new(30, 1).into(),
-                frame_count: 300,
-                media: SlotMap::default(),
-            },
+            open_project,
+            project_path: None,
+            undo_stack: UndoStack::default(),
         }
     }
 
@@ -96,6 +188,27 @@ impl MainApp<'_> {
             }
         });
 
+        ui.main_menu_bar.add_emit(
+            "File/Open...",
+            Shortcut::Ctrl | 'o',
+            MenuFlag::empty(),
+            event_sender,
+            AppEvent::MenuFileOpen,
+        );
+        ui.main_menu_bar.add_emit(
+            "File/Save",
+            Shortcut::Ctrl | 's',
+            MenuFlag::empty(),
+            event_sender,
+            AppEvent::MenuFileSave,
+        );
+        ui.main_menu_bar.add_emit(
+            "File/Save As...",
+            Shortcut::Ctrl | Shortcut::Shift | 's',
+            MenuFlag::MenuDivider,
+            event_sender,
+            AppEvent::MenuFileSaveAs,
+        );
         ui.main_menu_bar.add_emit(
             "File/Import Ya'll",
             Shortcut::None,
@@ -103,6 +216,101 @@ impl MainApp<'_> {
             event_sender,
             AppEvent::MenuFileImport,
         );
+        ui.main_menu_bar.add_emit(
+            "File/Import Folder...",
+            Shortcut::None,
+            MenuFlag::empty(),
+            event_sender,
+            AppEvent::MenuFileImportFolder,
+        );
+        ui.main_menu_bar.add_emit(
+            "File/Relink Missing Media...",
+            Shortcut::None,
+            MenuFlag::empty(),
+            event_sender,
+            AppEvent::MenuFileRelink,
+        );
+        ui.main_menu_bar.add_emit(
+            "Edit/Undo",
+            Shortcut::Ctrl | 'z',
+            MenuFlag::empty(),
+            event_sender,
+            AppEvent::MenuEditUndo,
+        );
+        ui.main_menu_bar.add_emit(
+            "Edit/Redo",
+            Shortcut::Ctrl | Shortcut::Shift | 'z',
+            MenuFlag::empty(),
+            event_sender,
+            AppEvent::MenuEditRedo,
+        );
+        for (path, shortcut, flag, control) in [
+            (
+                "Playback/Play|Pause",
+                Shortcut::from_char(' '),
+                MenuFlag::empty(),
+                PlaybackControl::PlayPause,
+            ),
+            (
+                "Playback/Stop",
+                Shortcut::None,
+                MenuFlag::empty(),
+                PlaybackControl::Stop,
+            ),
+            (
+                "Playback/Loop",
+                Shortcut::None,
+                MenuFlag::Toggle | MenuFlag::MenuDivider,
+                PlaybackControl::ToggleLoop,
+            ),
+            (
+                "Playback/Shuttle Reverse",
+                Shortcut::from_char('j'),
+                MenuFlag::empty(),
+                PlaybackControl::ShuttleReverse,
+            ),
+            (
+                "Playback/Shuttle Pause",
+                Shortcut::from_char('k'),
+                MenuFlag::empty(),
+                PlaybackControl::ShuttlePause,
+            ),
+            (
+                "Playback/Shuttle Forward",
+                Shortcut::from_char('l'),
+                MenuFlag::empty(),
+                PlaybackControl::ShuttleForward,
+            ),
+        ] {
+            ui.main_menu_bar.add_emit(
+                path,
+                shortcut,
+                flag,
+                event_sender,
+                AppEvent::MenuPlayback(control),
+            );
+        }
+        ui.main_menu_bar.add_emit(
+            "Playback/Use Proxies",
+            Shortcut::None,
+            MenuFlag::Toggle | MenuFlag::Value,
+            event_sender,
+            AppEvent::MenuPlaybackToggleProxies,
+        );
+        for zoom in PreviewZoom::ALL {
+            let flag = if zoom == PreviewZoom::default() {
+                MenuFlag::Radio | MenuFlag::Value
+            } else {
+                MenuFlag::Radio
+            };
+            ui.main_menu_bar.add_emit(
+                &format!("View/Zoom/{}", zoom.label()),
+                Shortcut::None,
+                flag,
+                event_sender,
+                AppEvent::MenuViewZoom(zoom),
+            );
+        }
     }
 
     fn make_and_add_preview_subwindow(preview_group: &mut impl GroupExt) -> Window {
@@ -125,57 +333,526 @@ impl MainApp<'_> {
                     }
                     AppEvent::RedrawPreview => self.wgpu_state.redraw(),
                     AppEvent::MenuFileImport => self.prompt_import_media(),
+                    AppEvent::MenuFileImportFolder => self.prompt_import_folder(),
+                    AppEvent::MenuFileRelink => self.prompt_relink_missing_media(),
+                    AppEvent::MenuFileOpen => self.prompt_open_project(),
+                    AppEvent::MenuFileSave => self.save_project(),
+                    AppEvent::MenuFileSaveAs => self.prompt_save_project_as(),
+                    AppEvent::MenuEditUndo => self.undo(),
+                    AppEvent::MenuEditRedo => self.redo(),
+                    AppEvent::MediaPoolRename => self.prompt_rename_media(),
+                    AppEvent::MediaPoolReveal => self.reveal_selected_media(),
+                    AppEvent::MediaPoolMakeProxy => self.make_proxy_for_selected_media(),
+                    AppEvent::MediaPoolRemove => self.remove_selected_media(),
+                    AppEvent::TimelineEdit(command) => self.edit(command),
+                    AppEvent::TimelineDragStarted => self.undo_stack.begin_merge(),
+                    AppEvent::TimelineDragEnded => self.undo_stack.end_merge(),
+                    AppEvent::TimelineSeek(frame) => {
+                        self.playback.seek(&self.open_project, frame);
+                        if self.playback.is_playing() {
+                            self.prefetcher.cancel();
+                        } else {
+                            self.show_frame(frame);
+                        }
+                    }
+                    AppEvent::MenuPlayback(control) => {
+                        self.playback.control(&self.open_project, control);
+                        if self.playback.is_playing() {
+                            self.prefetcher.cancel();
+                        } else {
+                            self.timeline.set_playhead(self.playback.position());
+                            self.show_frame(self.playback.position());
+                            self.log_frame_cache_stats();
+                        }
+                    }
+                    AppEvent::MenuPlaybackToggleProxies => {
+                        self.set_use_proxies(!self.compositor.uses_proxies())
+                    }
+                    AppEvent::MenuViewZoom(zoom) => self.wgpu_state.set_zoom(zoom),
+                    AppEvent::PanPreview(dx, dy) => self.wgpu_state.pan_by(dx, dy),
+                    AppEvent::PlaybackTick => {
+                        if let Some((frame, image)) = self.playback.tick(&self.open_project) {
+                            self.wgpu_state.write_texture_rgba(
+                                image.width(),
+                                image.height(),
+                                &image,
+                            );
+                            self.timeline.set_playhead(frame);
+                        }
+                    }
+                    AppEvent::Job(id, event) => self.job_event(id, event),
+                    AppEvent::JobsCancel => self.jobs.cancel_all(),
                 }
             }
         }
     }
 
-    fn prompt_import_media(&mut self) {
+    fn update_title(&mut self) {
+        match &self.project_path {
+            Some(path) => {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                self.fltk_ui
+                    .main_window
+                    .set_label(&format!("{name} - {APP_TITLE_AND_VERSION}"));
+            }
+            None => self.fltk_ui.main_window.set_label(APP_TITLE_AND_VERSION),
+        }
+    }
+
+    fn prompt_open_project(&mut self) {
         let mut chooser = NativeFileChooser::new(FileDialogType::BrowseFile);
-        match chooser.try_show() {
-            Ok(FileDialogAction::Success) => {
-                let file = chooser.filename();
-                let file_str = file.to_string_lossy();
-                info!("import file \"{file_str}\"");
+        chooser.set_filter(&format!("JadeVid Project\t*.{PROJECT_FILE_EXTENSION}"));
+        if let Ok(FileDialogAction::Success) = chooser.try_show() {
+            let file = chooser.filename();
+            info!("open project \"{}\"", file.display());
+
+            match project::load_project(&file) {
+                Ok(project) => {
+                    self.open_project = project;
+                    self.undo_stack.clear();
+                    self.playback.stop();
+                    // Media keys from the last project may mean different
+                    // media in this one.
+                    self.reset_decoders();
+                    self.frame_cache.lock().unwrap().clear();
+                    self.timeline.set_playhead(FrameNum(0));
+                    self.project_changed();
+                    self.project_path = Some(file);
+                    self.update_title();
+                    self.check_media();
+                    self.offer_relink();
+                }
+                Err(err) => {
+                    fltk::dialog::alert_default(&format!("Failed to open project!\n\n{err:#}"))
+                }
+            }
+        }
+    }
+
+    fn save_project(&mut self) {
+        match self.project_path.clone() {
+            Some(path) => self.save_project_to(path),
+            None => self.prompt_save_project_as(),
+        }
+    }
+
+    fn prompt_save_project_as(&mut self) {
+        let mut chooser = NativeFileChooser::new(FileDialogType::BrowseSaveFile);
+        chooser.set_filter(&format!("JadeVid Project\t*.{PROJECT_FILE_EXTENSION}"));
+        chooser.set_option(FileDialogOptions::SaveAsConfirm);
+        if let Ok(FileDialogAction::Success) = chooser.try_show() {
+            let mut file = chooser.filename();
+            if file.extension().is_none() {
+                file.set_extension(PROJECT_FILE_EXTENSION);
+            }
+            self.save_project_to(file);
+        }
+    }
+
+    fn save_project_to(&mut self, path: PathBuf) {
+        info!("save project \"{}\"", path.display());
+
+        match project::save_project(&self.open_project, &path) {
+            Ok(()) => {
+                self.project_path = Some(path);
+                self.update_title();
+            }
+            Err(err) => fltk::dialog::alert_default(&format!("Failed to save project!\n\n{err:#}")),
+        }
+    }
+
+    /// Applies an edit to the open project, recording it so it can be undone.
+    fn edit(&mut self, command: EditCommand) {
+        let name = command.name();
+        if let Err(err) = self.undo_stack.push(&mut self.open_project, command) {
+            fltk::dialog::alert_default(&format!("Failed to {name}!\n\n{err:#}"));
+        }
+        self.project_changed();
+    }
+
+    /// Brings every view of the project up to date after it changes.
+    fn project_changed(&mut self) {
+        self.media_pool.refresh(&self.open_project, &mut self.jobs);
+        self.timeline.set_project(&self.open_project);
+        if self.playback.is_playing() {
+            self.playback.project_changed(&self.open_project);
+        } else {
+            self.show_frame(self.timeline.playhead());
+        }
+    }
+
+    /// Renders the project at the given frame into the preview, and starts
+    /// caching the frames around it.
+    fn show_frame(&mut self, frame: FrameNum) {
+        match self.compositor.render_preview(&self.open_project, frame) {
+            Ok(preview) => self.wgpu_state.write_preview_frame(&preview),
+            Err(err) => warn!("failed to render preview of frame {}: {err:#}", frame.0),
+        }
+        self.prefetcher
+            .prefetch(&self.open_project, frame, self.compositor.uses_proxies());
+    }
+
+    /// Replaces the preview's compositor and prefetcher, and with them every
+    /// decoder they had open. Playback opens its own each time it starts.
+    fn reset_decoders(&mut self) {
+        let use_proxies = self.compositor.uses_proxies();
+        self.compositor = Compositor::with_cache(self.frame_cache.clone());
+        self.compositor.set_use_proxies(use_proxies);
+        self.prefetcher = FramePrefetcher::new(self.frame_cache.clone());
+    }
+
+    /// Switches the preview between decoding proxies and the original media.
+    /// Exports always use the originals either way.
+    fn set_use_proxies(&mut self, use_proxies: bool) {
+        info!("preview uses proxies: {use_proxies}");
+        self.compositor.set_use_proxies(use_proxies);
+        self.playback
+            .set_use_proxies(&self.open_project, use_proxies);
+        if !self.playback.is_playing() {
+            self.show_frame(self.timeline.playhead());
+        }
+    }
+
+    fn log_frame_cache_stats(&self) {
+        let stats = self.frame_cache.lock().unwrap().stats();
+        info!(
+            "frame cache: {:.1}% hit rate ({} hits, {} misses), {} frames using {} MiB, {} evicted",
+            stats.hit_rate() * 100.0,
+            stats.hits,
+            stats.misses,
+            stats.entries,
+            stats.bytes_used / (1024 * 1024),
+            stats.evictions
+        );
+    }
+
+    fn undo(&mut self) {
+        let name = self.undo_stack.undo_name();
+        match self.undo_stack.undo(&mut self.open_project) {
+            Ok(true) => {
+                info!("undid {}", name.unwrap_or_default());
+                self.project_changed();
+            }
+            Ok(false) => {}
+            Err(err) => fltk::dialog::alert_default(&format!("Failed to undo!\n\n{err:#}")),
+        }
+    }
+
+    fn redo(&mut self) {
+        let name = self.undo_stack.redo_name();
+        match self.undo_stack.redo(&mut self.open_project) {
+            Ok(true) => {
+                info!("redid {}", name.unwrap_or_default());
+                self.project_changed();
+            }
+            Ok(false) => {}
+            Err(err) => fltk::dialog::alert_default(&format!("Failed to redo!\n\n{err:#}")),
+        }
+    }
+
+    fn prompt_import_media(&mut self) {
+        let mut chooser = NativeFileChooser::new(FileDialogType::BrowseMultiFile);
+        if let Ok(FileDialogAction::Success) = chooser.try_show() {
+            self.import_media(chooser.filenames());
+        }
+    }
+
+    fn prompt_import_folder(&mut self) {
+        let mut chooser = NativeFileChooser::new(FileDialogType::BrowseDir);
+        if let Ok(FileDialogAction::Success) = chooser.try_show() {
+            self.import_media(vec![chooser.filename()]);
+        }
+    }
+
+    /// Probes files in the background, adding them to the media pool as each
+    /// one is ready.
+    fn import_media(&mut self, paths: Vec<PathBuf>) {
+        let name = match &paths[..] {
+            [path] => format!(
+                "Importing {}",
+                path.file_name().unwrap_or_default().to_string_lossy()
+            ),
+            _ => format!("Importing {} items", paths.len()),
+        };
+        self.jobs.spawn(name, jobs::import_media_job(paths));
+        self.media_pool.set_job_status(self.jobs.status());
+    }
+
+    /// Checks in the background whether any media files have been replaced
+    /// since they were imported.
+    fn check_media(&mut self) {
+        let media = self
+            .open_project
+            .media
+            .iter()
+            .filter(|(_, info)| info.fingerprint.is_some())
+            .map(|(key, info)| (key, info.clone()))
+            .collect::<Vec<_>>();
+        if !media.is_empty() {
+            self.jobs
+                .spawn("Checking media files", jobs::check_media_job(media));
+            self.media_pool.set_job_status(self.jobs.status());
+        }
+    }
+
+    /// Drops frames cached from media whose files have been replaced, and
+    /// says which they are.
+    fn warn_changed_media(&mut self, keys: Vec<MediaKey>) {
+        let mut names = vec![];
+        for key in keys {
+            let Some(info) = self.open_project.media.get(key) else {
+                continue;
+            };
+            self.frame_cache.lock().unwrap().invalidate_media(key);
+            names.push(info.name.clone());
+        }
+        if !names.is_empty() {
+            self.reset_decoders();
+            self.project_changed();
+            fltk::dialog::alert_default(&format!(
+                "These media files have changed since they were imported, so clips using them \
+                 may not look the same:\n\n{}",
+                names.join("\n")
+            ));
+        }
+    }
+
+    /// Keeps fingerprints taken again of files that were touched but not
+    /// changed, so cached data is found by content again. This isn't an edit
+    /// anyone made, so it isn't undoable; it's saved with the project.
+    fn refresh_fingerprints(&mut self, refreshed: Vec<(MediaKey, PathBuf, MediaFingerprint)>) {
+        for (key, path, fingerprint) in refreshed {
+            // The media may have been removed or relinked since.
+            if let Some(info) = self.open_project.media.get_mut(key)
+                && info.path == path
+            {
+                info.fingerprint = Some(fingerprint);
+            }
+        }
+    }
+
+    /// Asks whether to search for missing media, if any is missing from the
+    /// project just opened.
+    fn offer_relink(&mut self) {
+        let missing = project::missing_media(&self.open_project);
+        if missing.is_empty() {
+            return;
+        }
+        warn!("{} media file(s) are missing", missing.len());
+        let message = format!(
+            "{} media file(s) in this project can't be found. Search folders for them?",
+            missing.len()
+        );
+        if fltk::dialog::choice2_default(&message, "Not Now", "Search...", "") == Some(1) {
+            self.search_for_missing_media(missing);
+        }
+    }
+
+    fn prompt_relink_missing_media(&mut self) {
+        let missing = project::missing_media(&self.open_project);
+        if missing.is_empty() {
+            fltk::dialog::message_default("No media is missing.");
+            return;
+        }
+        self.search_for_missing_media(missing);
+    }
+
+    /// Asks for folders to look in, and searches them in the background. The
+    /// matches found are offered by `offer_relink_matches`.
+    fn search_for_missing_media(&mut self, missing: Vec<MediaKey>) {
+        let mut chooser = NativeFileChooser::new(FileDialogType::BrowseMultiDir);
+        chooser.set_title("Choose folders to search for missing media");
+        if let Ok(FileDialogAction::Success) = chooser.try_show() {
+            let missing = missing
+                .into_iter()
+                .filter_map(|key| Some((key, self.open_project.media.get(key)?.clone())))
+                .collect();
+            self.jobs.spawn(
+                "Searching for missing media",
+                jobs::relink_search_job(missing, chooser.filenames()),
+            );
+            self.media_pool.set_job_status(self.jobs.status());
+        }
+    }
 
+    /// Lists the files found for missing media, and relinks them all in one
+    /// edit if that's what's wanted.
+    fn offer_relink_matches(&mut self, matches: Vec<RelinkMatch>) {
+        // Anything relinked some other way since the search started is left
+        // alone.
+        let matches: Vec<_> = matches
+            .into_iter()
+            .filter(|relink| {
+                self.open_project
+                    .media
+                    .get(relink.key)
+                    .is_some_and(|info| info.path == relink.old_path && !info.path.exists())
+            })
+            .collect();
+        if matches.is_empty() {
+            fltk::dialog::message_default("No matching files were found for the missing media.");
+            return;
+        }
+
+        let mut message = format!(
+            "Found files for {} of {} missing media:\n",
+            matches.len(),
+            project::missing_media(&self.open_project).len()
+        );
+        for relink in &matches {
+            let note = match relink.confidence {
+                RelinkConfidence::Exact => "",
+                RelinkConfidence::Renamed => " (renamed)",
+                RelinkConfidence::SameName => " (content not checked)",
+            };
+            message += &format!(
+                "\n{} -> {}{note}",
+                self.open_project.media[relink.key].name,
+                relink.new_path.display()
+            );
+        }
+        if fltk::dialog::choice2_default(&message, "Cancel", "Relink", "") == Some(1) {
+            match EditCommand::relink_media(&self.open_project, matches) {
+                Ok(command) => self.edit(command),
+                Err(err) => warn!("failed to relink media: {err:#}"),
+            }
+        }
+    }
+
+    fn job_event(&mut self, id: JobId, event: JobEvent) {
+        let name = self.jobs.handle_event(id, &event);
+        match event {
+            JobEvent::Progress { .. } => {}
+            JobEvent::Output(JobOutput::Media(info)) => {
                 match self
-                    .make_player(&chooser.filename())
-                    .context("failed to create ffmpeg interop video decoder for file {file_str}")
+                    .open_project
+                    .media
+                    .values()
+                    .find(|existing| existing.same_content_as(&info))
                 {
-                    Ok(mut player) => {
-                        let mut frames = vec![];
-                        while frames.is_empty() {
-                            frames = player.receive_frames_from_packet().unwrap();
-                        }
-                        let img = &frames[0];
-                        let rgba = img.data(0);
-                        info!(
-                            "rgba length: {}x{}({})",
-                            img.width(),
-                            img.height(),
-                            rgba.len()
-                        );
-                        self.wgpu_state
-                            .write_texture_rgba(img.width(), img.height(), rgba);
+                    Some(existing) => info!(
+                        "not importing {}, it's the same as {} already in the project",
+                        info.path.display(),
+                        existing.name
+                    ),
+                    None => self.edit(EditCommand::import_media(info)),
+                }
+            }
+            JobEvent::Output(JobOutput::Filmstrip {
+                key,
+                path,
+                filmstrip,
+            }) => {
+                self.media_pool.set_filmstrip(key, &path, &filmstrip);
+                self.timeline.set_filmstrip(key, &path, filmstrip);
+            }
+            JobEvent::Output(JobOutput::Waveform {
+                key,
+                path,
+                waveform,
+            }) => self.timeline.set_waveform(key, &path, waveform),
+            JobEvent::Output(JobOutput::Proxy { key, path, proxy }) => {
+                // The media may have been removed or relinked since.
+                if let Some(info) = self.open_project.media.get(key)
+                    && info.path == path
+                    && info.proxy.as_ref() != Some(&proxy)
+                {
+                    match EditCommand::set_proxy(&self.open_project, key, Some(proxy)) {
+                        Ok(command) => self.edit(command),
+                        Err(err) => warn!("failed to set proxy: {err:#}"),
                     }
-                    Err(err) => fltk::dialog::alert_default(&format!(
-                        "Failed to create video decoder!\n\n{err}"
-                    )),
                 }
             }
-            _ => {}
+            JobEvent::Output(JobOutput::RelinkMatches(matches)) => {
+                self.offer_relink_matches(matches)
+            }
+            JobEvent::Output(JobOutput::ChangedMedia(keys)) => self.warn_changed_media(keys),
+            JobEvent::Output(JobOutput::RefreshedFingerprints(refreshed)) => {
+                self.refresh_fingerprints(refreshed)
+            }
+            JobEvent::Finished(JobResult::Failed(err)) => {
+                fltk::dialog::alert_default(&format!(
+                    "{} failed!\n\n{err}",
+                    name.as_deref().unwrap_or("Background job")
+                ));
+            }
+            JobEvent::Finished(JobResult::Done | JobResult::Cancelled) => {}
         }
+        self.media_pool.set_job_status(self.jobs.status());
     }
 
-    fn make_player(&mut self, file: &Path) -> anyhow::Result<FfmpegVideoDecoder> {
-        let ictx =
-            ffmpeg_next::format::input(&file).context("failed to load format info for file")?;
-        let stream_index = ictx
-            .streams()
-            .best(Type::Video)
-            .context("failed to locate best video stream")?
-            .index();
-        FfmpegVideoDecoder::new(ictx, stream_index)
+    fn prompt_rename_media(&mut self) {
+        let Some(key) = self.media_pool.selected() else {
+            return;
+        };
+        let Some(info) = self.open_project.media.get(key) else {
+            return;
+        };
+        if let Some(name) = fltk::dialog::input_default("Rename media:", &info.name)
+            && name != info.name
+        {
+            match EditCommand::rename_media(&self.open_project, key, name) {
+                Ok(command) => self.edit(command),
+                Err(err) => warn!("failed to rename media: {err:#}"),
+            }
+        }
+    }
+
+    fn reveal_selected_media(&mut self) {
+        let Some(info) = self
+            .media_pool
+            .selected()
+            .and_then(|key| self.open_project.media.get(key))
+        else {
+            return;
+        };
+        if let Err(err) = ui::reveal_in_file_manager(&info.path) {
+            fltk::dialog::alert_default(&format!("Failed to open file manager!\n\n{err}"));
+        }
+    }
+
+    /// Makes a proxy of the selected media's video in the background, which
+    /// the preview switches to once it's done.
+    fn make_proxy_for_selected_media(&mut self) {
+        let Some(key) = self.media_pool.selected() else {
+            return;
+        };
+        let Some(info) = self.open_project.media.get(key) else {
+            return;
+        };
+        let Some((stream, _)) = info.video_streams().next() else {
+            fltk::dialog::alert_default("Only media with video can have a proxy.");
+            return;
+        };
+        self.jobs.spawn(
+            format!("Making proxy of {}", info.name),
+            jobs::proxy_job(
+                key,
+                info.path.clone(),
+                info.fingerprint.clone(),
+                stream.index,
+                ProxyCache::in_cache_dir(),
+            ),
+        );
+        self.media_pool.set_job_status(self.jobs.status());
+    }
+
+    fn remove_selected_media(&mut self) {
+        let Some(key) = self.media_pool.selected() else {
+            return;
+        };
+        let clip_count = self.open_project.timeline.clips_using(key).count();
+        if clip_count > 0 {
+            fltk::dialog::alert_default(&format!(
+                "This media is used by {clip_count} clip(s) on the timeline. Delete them before \
+                 removing it from the project."
+            ));
+            return;
+        }
+        match EditCommand::remove_media(&self.open_project, key) {
+            Ok(command) => self.edit(command),
+            Err(err) => warn!("failed to remove media: {err:#}"),
+        }
     }
 }
 
@@ -183,5 +860,78 @@ fn main() {
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
     ffmpeg_next::init().expect("failed to initialize ffmpeg!");
 
-    MainApp::new().run_loop();
+    match cli::parse_args(std::env::args().skip(1)) {
+        Ok(cli::Command::Gui) => MainApp::new().run_loop(),
+        Ok(cli::Command::Render {
+            project,
+            output,
+            settings,
+        }) => {
+            let mut last_report = Instant::now();
+            let result = project::load_project(&project).and_then(|project| {
+                render::render_project(
+                    &project,
+                    &output,
+                    &settings,
+                    &render::CancelToken::default(),
+                    |progress| {
+                        if last_report.elapsed().as_secs() >= 1 {
+                            info!(
+                                "rendered frame {}/{} ({:.0}%)",
+                                progress.frames_done,
+                                progress.frames_total,
+                                progress.fraction() * 100.0
+                            );
+                            last_report = Instant::now();
+                        }
+                    },
+                )
+            });
+            if let Err(err) = result {
+                error!("render failed: {err:#}");
+                std::process::exit(1);
+            }
+        }
+        Ok(cli::Command::Snapshot {
+            project,
+            output,
+            frame,
+            golden,
+            tolerance,
+            software,
+        }) => {
+            let result = project::load_project(&project).and_then(|project| {
+                let snapshot = ui::render_snapshot(&project, FrameNum(frame), software)?;
+                snapshot
+                    .save(&output)
+                    .with_context(|| format!("failed to save snapshot to {}", output.display()))?;
+                info!("saved snapshot of frame {frame} to {}", output.display());
+
+                let Some(golden) = golden else {
+                    return Ok(());
+                };
+                let expected = image::open(&golden)
+                    .with_context(|| format!("failed to read golden image {}", golden.display()))?
+                    .to_rgba8();
+                let difference = ui::max_difference(&snapshot, &expected)
+                    .with_context(|| format!("snapshot doesn't match {}", golden.display()))?;
+                ensure!(
+                    difference <= tolerance,
+                    "snapshot differs from {} by up to {difference}, more than the tolerance of \
+                     {tolerance}",
+                    golden.display()
+                );
+                info!("snapshot matches {}", golden.display());
+                Ok(())
+            });
+            if let Err(err) = result {
+                error!("snapshot failed: {err:#}");
+                std::process::exit(1);
+            }
+        }
+        Err(err) => {
+            eprintln!("{err:#}\n\n{}", cli::USAGE);
+            std::process::exit(2);
+        }
+    }
 }
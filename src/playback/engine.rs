@@ -0,0 +1,602 @@
+use super::{AudioClock, AudioSink, AvSync, MasterClock, NullAudioSink, WallClock};
+use crate::{
+    AppEvent,
+    ff_interop::audio_player::AudioChunk,
+    project::{FrameNum, JadeRational, MediaProject},
+    render::{AudioMixer, Compositor, SharedFrameCache},
+};
+use fltk::app::Sender;
+use image::RgbaImage;
+use log::{info, warn};
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicI64, Ordering},
+        mpsc::{self, Receiver, SyncSender, TryRecvError},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How many decoded frames may wait to be shown before the decode thread
+/// blocks.
+const FRAME_QUEUE_DEPTH: usize = 8;
+/// Fastest shuttle speed reachable by tapping J or L repeatedly.
+const MAX_SHUTTLE_SPEED: i32 = 8;
+/// How far ahead of what's been heard the audio thread keeps the sink filled.
+const AUDIO_LEAD: Duration = Duration::from_millis(250);
+/// How long the audio thread waits when the sink is full.
+const AUDIO_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// The transport controls, as sent by the playback menu.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PlaybackControl {
+    PlayPause,
+    Stop,
+    ToggleLoop,
+    /// J: play backwards, faster each time it's pressed.
+    ShuttleReverse,
+    /// K: pause.
+    ShuttlePause,
+    /// L: play forwards, faster each time it's pressed.
+    ShuttleForward,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct PlaybackStats {
+    pub frames_presented: u64,
+    /// Frames that were decoded but skipped, because playback had already
+    /// moved past them by the time they were ready.
+    pub frames_dropped: u64,
+}
+
+type DecodedFrame = anyhow::Result<(FrameNum, RgbaImage)>;
+
+/// Renders frames ahead of the playhead on a background thread.
+struct DecodeThread {
+    frames: Receiver<DecodedFrame>,
+    /// The frame playback is due to show, kept up to date by `tick` so the
+    /// thread can skip frames it would only finish too late.
+    target: Arc<AtomicI64>,
+    stop: Arc<AtomicBool>,
+}
+
+impl DecodeThread {
+    /// Starts rendering at `start`, moving `step` frames at a time, until
+    /// `end` (exclusive) or the start of the timeline is reached.
+    fn spawn(
+        project: MediaProject,
+        cache: SharedFrameCache,
+        use_proxies: bool,
+        start: FrameNum,
+        step: i64,
+        end: FrameNum,
+    ) -> Self {
+        let (sender, frames) = mpsc::sync_channel(FRAME_QUEUE_DEPTH);
+        let target = Arc::new(AtomicI64::new(start.0 as i64));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (thread_target, thread_stop) = (target.clone(), stop.clone());
+        thread::Builder::new()
+            .name("playback decode".to_string())
+            .spawn(move || {
+                let mut compositor = Compositor::with_cache(cache);
+                compositor.set_use_proxies(use_proxies);
+                let frames = FrameRange { start, step, end };
+                decode_frames(
+                    project,
+                    compositor,
+                    frames,
+                    sender,
+                    thread_target,
+                    thread_stop,
+                )
+            })
+            .expect("failed to spawn playback decode thread");
+        Self {
+            frames,
+            target,
+            stop,
+        }
+    }
+}
+
+impl Drop for DecodeThread {
+    fn drop(&mut self) {
+        // The thread also notices the receiver going away the next time it
+        // tries to send, if it's blocked on a full queue.
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The frames a decode thread renders: from `start`, moving `step` frames at
+/// a time, until `end` (exclusive) or the start of the timeline.
+#[derive(Debug, Copy, Clone)]
+struct FrameRange {
+    start: FrameNum,
+    step: i64,
+    end: FrameNum,
+}
+
+fn decode_frames(
+    project: MediaProject,
+    mut compositor: Compositor,
+    FrameRange { start, step, end }: FrameRange,
+    sender: SyncSender<DecodedFrame>,
+    target: Arc<AtomicI64>,
+    stop: Arc<AtomicBool>,
+) {
+    let mut frame = start.0 as i64;
+    while !stop.load(Ordering::Relaxed) && frame >= 0 && frame < end.0 as i64 {
+        let frame_num = FrameNum(frame as u64);
+        let rendered = compositor
+            .render_frame(&project, frame_num)
+            .map(|image| (frame_num, image));
+        let failed = rendered.is_err();
+        if sender.send(rendered).is_err() || failed {
+            return;
+        }
+        frame += step;
+        // Once rendering falls behind playback, the frames in between would
+        // only be dropped, so go straight to the one that's due.
+        let target = target.load(Ordering::Relaxed);
+        if (step > 0 && frame < target) || (step < 0 && frame > target) {
+            frame = target;
+        }
+    }
+}
+
+/// Sends `AppEvent::PlaybackTick` once per frame interval, so the UI thread
+/// knows when to present the next frame.
+struct PacerThread {
+    stop: Arc<AtomicBool>,
+}
+
+impl PacerThread {
+    fn spawn(fps: JadeRational, event_sender: Sender<AppEvent>) -> Self {
+        let interval = Duration::from_secs_f64(fps.den as f64 / fps.num as f64);
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        thread::Builder::new()
+            .name("playback pacer".to_string())
+            .spawn(move || {
+                let mut deadline = Instant::now();
+                while !thread_stop.load(Ordering::Relaxed) {
+                    deadline += interval;
+                    let now = Instant::now();
+                    if deadline < now {
+                        // We fell behind; don't try to catch up with a burst.
+                        deadline = now;
+                    }
+                    spin_sleep::sleep(deadline - now);
+                    event_sender.send(AppEvent::PlaybackTick);
+                }
+            })
+            .expect("failed to spawn playback pacer thread");
+        Self { stop }
+    }
+}
+
+impl Drop for PacerThread {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Mixes the project's audio from the playhead onwards into a sink, keeping
+/// it a little ahead of what's been played. Video follows the sink's clock.
+struct AudioThread {
+    stop: Arc<AtomicBool>,
+}
+
+impl AudioThread {
+    fn spawn(project: MediaProject, start: FrameNum, sink: Arc<Mutex<dyn AudioSink>>) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        thread::Builder::new()
+            .name("playback audio".to_string())
+            .spawn(move || feed_audio(project, start, sink, thread_stop))
+            .expect("failed to spawn playback audio thread");
+        Self { stop }
+    }
+}
+
+impl Drop for AudioThread {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn feed_audio(
+    project: MediaProject,
+    start: FrameNum,
+    sink: Arc<Mutex<dyn AudioSink>>,
+    stop: Arc<AtomicBool>,
+) {
+    let lead_samples = (AUDIO_LEAD.as_secs_f64() * project.audio.sample_rate as f64) as u64;
+    // Once mixing fails, or the timeline runs out, silence keeps the clock
+    // going so video carries on to the end.
+    let mut mixer = Some(AudioMixer::default());
+    let mut frame = start;
+    while !stop.load(Ordering::Relaxed) {
+        let ahead = {
+            let sink = sink.lock().expect("audio sink lock was poisoned");
+            sink.queued_samples() - sink.played_samples()
+        };
+        if ahead >= lead_samples {
+            thread::sleep(AUDIO_POLL_INTERVAL);
+            continue;
+        }
+
+        let mixed = mixer.as_mut().map(|mixer| mixer.mix_frame(&project, frame));
+        let chunk = match mixed {
+            Some(Ok(chunk)) => chunk,
+            failed => {
+                if let Some(Err(err)) = failed {
+                    warn!("playing silence, failed to mix audio: {err:#}");
+                    mixer = None;
+                }
+                silence(&project, frame)
+            }
+        };
+        sink.lock()
+            .expect("audio sink lock was poisoned")
+            .queue(&chunk);
+        frame = FrameNum(frame.0 + 1);
+    }
+}
+
+fn silence(project: &MediaProject, frame: FrameNum) -> AudioChunk {
+    let start_sample = project.audio.first_sample_of_frame(frame, project.fps);
+    let end_sample = project
+        .audio
+        .first_sample_of_frame(FrameNum(frame.0 + 1), project.fps);
+    AudioChunk {
+        start_sample,
+        planes: vec![
+            vec![0.0; (end_sample - start_sample) as usize];
+            project.audio.channels as usize
+        ],
+    }
+}
+
+/// Everything running while the project is playing.
+struct Playing {
+    /// Multiple of normal speed, negative when playing backwards.
+    speed: i32,
+    /// Decides which frame is due. Follows the audio when playing at normal
+    /// speed, and the system clock otherwise, since audio isn't played while
+    /// shuttling.
+    sync: AvSync,
+    decode: DecodeThread,
+    _pacer: PacerThread,
+    _audio: Option<AudioThread>,
+    /// A decoded frame that isn't due to be shown yet.
+    pending: Option<(FrameNum, RgbaImage)>,
+}
+
+/// Plays the project back in real time. Frames are rendered ahead on a
+/// background thread and handed out by `tick` as they come due, skipping any
+/// that are too late.
+pub struct PlaybackEngine {
+    event_sender: Sender<AppEvent>,
+    /// Shared with the preview, so frames played once don't need decoding
+    /// again to scrub over them.
+    cache: SharedFrameCache,
+    /// Whether frames are decoded from proxies where media has them.
+    use_proxies: bool,
+    position: FrameNum,
+    looping: bool,
+    playing: Option<Playing>,
+    stats: PlaybackStats,
+}
+
+impl PlaybackEngine {
+    pub fn new(event_sender: Sender<AppEvent>, cache: SharedFrameCache) -> Self {
+        Self {
+            event_sender,
+            cache,
+            use_proxies: false,
+            position: FrameNum(0),
+            looping: false,
+            playing: None,
+            stats: PlaybackStats::default(),
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing.is_some()
+    }
+
+    /// The last frame presented, or the frame playback will start from.
+    pub fn position(&self) -> FrameNum {
+        self.position
+    }
+
+    /// The current playback speed, in multiples of normal speed. Zero when
+    /// paused and negative when playing backwards.
+    pub fn speed(&self) -> i32 {
+        self.playing
+            .as_ref()
+            .map(|playing| playing.speed)
+            .unwrap_or(0)
+    }
+
+    /// Switches between decoding proxies and originals, restarting playback
+    /// from where it is if playing.
+    pub fn set_use_proxies(&mut self, project: &MediaProject, use_proxies: bool) {
+        if self.use_proxies != use_proxies {
+            self.use_proxies = use_proxies;
+            self.project_changed(project);
+        }
+    }
+
+    pub fn control(&mut self, project: &MediaProject, control: PlaybackControl) {
+        match control {
+            PlaybackControl::PlayPause if self.is_playing() => self.pause(),
+            PlaybackControl::PlayPause => self.play(project, 1),
+            PlaybackControl::Stop => self.stop(),
+            PlaybackControl::ToggleLoop => self.looping = !self.looping,
+            PlaybackControl::ShuttleReverse => {
+                let speed = match self.speed() {
+                    speed if speed < 0 => (speed * 2).max(-MAX_SHUTTLE_SPEED),
+                    _ => -1,
+                };
+                self.play(project, speed);
+            }
+            PlaybackControl::ShuttlePause => self.pause(),
+            PlaybackControl::ShuttleForward => {
+                let speed = match self.speed() {
+                    speed if speed > 0 => (speed * 2).min(MAX_SHUTTLE_SPEED),
+                    _ => 1,
+                };
+                self.play(project, speed);
+            }
+        }
+    }
+
+    /// Starts playing from the current position at a multiple of normal
+    /// speed, restarting any playback already going.
+    pub fn play(&mut self, project: &MediaProject, speed: i32) {
+        let end = project.timeline.end_frame();
+        if speed == 0 || end.0 == 0 {
+            self.pause();
+            return;
+        }
+        if speed > 0 && self.position >= FrameNum(end.0 - 1) {
+            // Playing from the end starts over, like most players do.
+            self.position = FrameNum(0);
+        }
+
+        let (clock, audio): (Box<dyn MasterClock>, _) = if speed == 1 {
+            // There's no audio device output yet, so audio is played into a
+            // sink that only keeps time.
+            let sink = Arc::new(Mutex::new(NullAudioSink::new(project.audio.sample_rate)));
+            let audio = AudioThread::spawn(project.clone(), self.position, sink.clone());
+            (Box::new(AudioClock::new(sink)), Some(audio))
+        } else {
+            (Box::new(WallClock::new(speed as f64)), None)
+        };
+
+        self.stats = PlaybackStats::default();
+        self.playing = Some(Playing {
+            speed,
+            sync: AvSync::new(project.fps, self.position, speed as f64, clock),
+            decode: DecodeThread::spawn(
+                project.clone(),
+                self.cache.clone(),
+                self.use_proxies,
+                self.position,
+                speed as i64,
+                end,
+            ),
+            _pacer: PacerThread::spawn(project.fps, self.event_sender),
+            _audio: audio,
+            pending: None,
+        });
+        info!("playing from frame {} at {speed}x", self.position.0);
+    }
+
+    pub fn pause(&mut self) {
+        if let Some(playing) = self.playing.take() {
+            let sync = playing.sync.stats();
+            info!(
+                "paused at frame {} ({} presented, {} dropped, A/V error mean {:.1}ms max {:.1}ms, {} resyncs)",
+                self.position.0,
+                self.stats.frames_presented,
+                self.stats.frames_dropped,
+                sync.mean_abs_error_secs() * 1000.0,
+                sync.max_abs_error_secs * 1000.0,
+                sync.resyncs
+            );
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.pause();
+        self.position = FrameNum(0);
+    }
+
+    /// Moves the playhead, carrying on playing from there if playing.
+    pub fn seek(&mut self, project: &MediaProject, frame: FrameNum) {
+        self.position = frame;
+        if let Some(speed) = self.playing.as_ref().map(|playing| playing.speed) {
+            self.play(project, speed);
+        }
+    }
+
+    /// Frames already rendered are out of date once the project changes, so
+    /// playback restarts from where it is.
+    pub fn project_changed(&mut self, project: &MediaProject) {
+        self.seek(project, self.position);
+    }
+
+    /// Called on every `AppEvent::PlaybackTick`. Returns the frame to present,
+    /// if a new one is due.
+    pub fn tick(&mut self, project: &MediaProject) -> Option<(FrameNum, RgbaImage)> {
+        let playing = self.playing.as_mut()?;
+        let end = project.timeline.end_frame().0 as i64;
+        let target = playing.sync.target_frame();
+        playing.decode.target.store(target, Ordering::Relaxed);
+        if target < 0 || target >= end {
+            if self.looping {
+                let restart = if playing.speed > 0 { 0 } else { end - 1 };
+                self.seek(project, FrameNum(restart.max(0) as u64));
+            } else {
+                self.pause();
+            }
+            return None;
+        }
+
+        let forward = playing.speed > 0;
+        let is_due = |frame: FrameNum| {
+            if forward {
+                frame.0 as i64 <= target
+            } else {
+                frame.0 as i64 >= target
+            }
+        };
+
+        let mut due = None;
+        loop {
+            let next = match playing.pending.take() {
+                Some(pending) => pending,
+                None => match playing.decode.frames.try_recv() {
+                    Ok(Ok(frame)) => frame,
+                    Ok(Err(err)) => {
+                        warn!("stopping playback, failed to render frame: {err:#}");
+                        self.pause();
+                        return None;
+                    }
+                    Err(TryRecvError::Empty | TryRecvError::Disconnected) => break,
+                },
+            };
+            if !is_due(next.0) {
+                playing.pending = Some(next);
+                break;
+            }
+            if due.replace(next).is_some() {
+                self.stats.frames_dropped += 1;
+            }
+        }
+
+        let (frame, image) = due?;
+        playing.sync.frame_presented(frame);
+        self.position = frame;
+        self.stats.frames_presented += 1;
+        Some((frame, image))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        project::Timeline,
+        render::{DEFAULT_FRAME_CACHE_BUDGET, FrameCache},
+        test_media::{self, CLIP_FRAMES},
+    };
+
+    fn engine(sender: Sender<AppEvent>) -> PlaybackEngine {
+        PlaybackEngine::new(sender, FrameCache::shared(DEFAULT_FRAME_CACHE_BUDGET))
+    }
+
+    /// Sends each control in turn, checking the speed after each.
+    fn assert_speeds(
+        engine: &mut PlaybackEngine,
+        project: &MediaProject,
+        steps: &[(PlaybackControl, i32)],
+    ) {
+        for &(control, speed) in steps {
+            engine.control(project, control);
+            assert_eq!(engine.speed(), speed, "after {control:?}");
+            assert_eq!(engine.is_playing(), speed != 0, "after {control:?}");
+        }
+    }
+
+    #[test]
+    fn shuttles_with_j_k_and_l() {
+        use PlaybackControl::{ShuttleForward as L, ShuttlePause as K, ShuttleReverse as J};
+
+        let (_turn, sender, _) = test_media::event_channel();
+        let (project, _) =
+            test_media::numbered_project(test_media::numbered_clip(), CLIP_FRAMES).unwrap();
+        let mut engine = engine(sender);
+        assert_eq!(engine.speed(), 0);
+
+        engine.seek(&project, FrameNum(60));
+        assert_speeds(
+            &mut engine,
+            &project,
+            &[
+                // Each tap doubles the speed, up to the limit.
+                (L, 1),
+                (L, 2),
+                (L, 4),
+                (L, 8),
+                (L, 8),
+                (K, 0),
+                (K, 0),
+                (J, -1),
+                (J, -2),
+                (J, -4),
+                (J, -8),
+                (J, -8),
+                // Changing direction starts again from normal speed.
+                (L, 1),
+                (J, -1),
+                (K, 0),
+            ],
+        );
+        // Only presenting frames moves the position, and nothing ticked.
+        assert_eq!(engine.position(), FrameNum(60));
+    }
+
+    #[test]
+    fn plays_pauses_and_stops() {
+        use PlaybackControl::{PlayPause, ShuttleForward, ShuttleReverse, Stop, ToggleLoop};
+
+        let (_turn, sender, _) = test_media::event_channel();
+        let (project, _) =
+            test_media::numbered_project(test_media::numbered_clip(), CLIP_FRAMES).unwrap();
+        let mut engine = engine(sender);
+
+        engine.seek(&project, FrameNum(30));
+        assert_speeds(
+            &mut engine,
+            &project,
+            &[
+                (PlayPause, 1),
+                (PlayPause, 0),
+                (ShuttleReverse, -1),
+                (ShuttleReverse, -2),
+                // Play/pause pauses any shuttling, and then plays at normal
+                // speed.
+                (PlayPause, 0),
+                (PlayPause, 1),
+                (ShuttleForward, 2),
+                (Stop, 0),
+            ],
+        );
+        assert_eq!(engine.position(), FrameNum(0));
+
+        assert!(!engine.looping);
+        engine.control(&project, ToggleLoop);
+        assert!(engine.looping);
+        engine.control(&project, ToggleLoop);
+        assert!(!engine.looping);
+
+        // Playing forwards from the last frame starts over.
+        engine.seek(&project, FrameNum(CLIP_FRAMES - 1));
+        engine.control(&project, PlayPause);
+        assert_eq!(engine.speed(), 1);
+        assert_eq!(engine.position(), FrameNum(0));
+        engine.control(&project, Stop);
+
+        // An empty timeline has nothing to play.
+        let empty = MediaProject {
+            timeline: Timeline::default(),
+            ..project
+        };
+        assert_speeds(&mut engine, &empty, &[(ShuttleForward, 0), (PlayPause, 0)]);
+    }
+}
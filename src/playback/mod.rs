@@ -0,0 +1,5 @@
+mod engine;
+mod sync;
+
+pub use engine::*;
+pub use sync::*;
@@ -0,0 +1,312 @@
+use crate::{
+    ff_interop::audio_player::AudioChunk,
+    project::{FrameNum, JadeRational},
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+/// How far the video clock may drift from the master clock, in seconds, before
+/// it's snapped back instead of being nudged.
+const RESYNC_THRESHOLD_SECS: f64 = 0.1;
+/// The fraction of the drift removed on every update while in sync, which
+/// keeps corrections small enough not to cause visible stutter.
+const DRIFT_CORRECTION: f64 = 0.1;
+
+/// The clock playback follows, in seconds of media played since playback
+/// started. Negative when playing backwards.
+pub trait MasterClock: Send {
+    fn elapsed_secs(&self) -> f64;
+}
+
+/// Follows the system clock, for when there's no audio to follow (e.g. while
+/// shuttling at other than normal speed).
+pub struct WallClock {
+    started: Instant,
+    speed: f64,
+}
+
+impl WallClock {
+    pub fn new(speed: f64) -> Self {
+        Self {
+            started: Instant::now(),
+            speed,
+        }
+    }
+}
+
+impl MasterClock for WallClock {
+    fn elapsed_secs(&self) -> f64 {
+        self.started.elapsed().as_secs_f64() * self.speed
+    }
+}
+
+/// Somewhere mixed audio goes to be heard. The sink reports how much of what
+/// it has been given has actually been played, which is what video follows.
+pub trait AudioSink: Send {
+    fn sample_rate(&self) -> u32;
+
+    fn queue(&mut self, chunk: &AudioChunk);
+
+    /// Total samples given to `queue` so far.
+    fn queued_samples(&self) -> u64;
+
+    /// Total samples played so far. Never more than `queued_samples`.
+    fn played_samples(&self) -> u64;
+}
+
+/// An audio sink that throws samples away, but plays them at the same pace a
+/// real device would, including stalling when it runs out. Used when there's
+/// no audio device, such as on a headless machine.
+pub struct NullAudioSink {
+    sample_rate: u32,
+    queued: u64,
+    /// Samples played as of `resumed_at`, which is reset whenever the sink
+    /// runs dry and then gets more samples.
+    played_before_resume: u64,
+    resumed_at: Option<Instant>,
+}
+
+impl NullAudioSink {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            queued: 0,
+            played_before_resume: 0,
+            resumed_at: None,
+        }
+    }
+}
+
+impl AudioSink for NullAudioSink {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn queue(&mut self, chunk: &AudioChunk) {
+        let played = self.played_samples();
+        if played >= self.queued {
+            // Starved (or not yet started), so playing picks up from here.
+            self.played_before_resume = played;
+            self.resumed_at = Some(Instant::now());
+        }
+        self.queued += chunk.sample_count() as u64;
+    }
+
+    fn queued_samples(&self) -> u64 {
+        self.queued
+    }
+
+    fn played_samples(&self) -> u64 {
+        let Some(resumed_at) = self.resumed_at else {
+            return 0;
+        };
+        let since_resume = (resumed_at.elapsed().as_secs_f64() * self.sample_rate as f64) as u64;
+        (self.played_before_resume + since_resume).min(self.queued)
+    }
+}
+
+/// Follows how far an audio sink has played.
+pub struct AudioClock {
+    sink: Arc<Mutex<dyn AudioSink>>,
+}
+
+impl AudioClock {
+    pub fn new(sink: Arc<Mutex<dyn AudioSink>>) -> Self {
+        Self { sink }
+    }
+}
+
+impl MasterClock for AudioClock {
+    fn elapsed_secs(&self) -> f64 {
+        let sink = self.sink.lock().expect("audio sink lock was poisoned");
+        sink.played_samples() as f64 / sink.sample_rate() as f64
+    }
+}
+
+/// How closely presented video has matched the master clock.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct SyncStats {
+    pub frames_measured: u64,
+    /// Video time minus master clock time for the last frame presented, in
+    /// seconds. Positive means video is ahead.
+    pub last_error_secs: f64,
+    pub max_abs_error_secs: f64,
+    pub total_abs_error_secs: f64,
+    /// How many times the video clock drifted too far and was snapped back.
+    pub resyncs: u64,
+}
+
+impl SyncStats {
+    pub fn mean_abs_error_secs(&self) -> f64 {
+        self.total_abs_error_secs / self.frames_measured.max(1) as f64
+    }
+}
+
+/// Decides which frame to show from a master clock. The video clock runs
+/// smoothly off the system clock, and is steered towards the master clock so
+/// that jitter in the master (e.g. audio being played in bursts) doesn't make
+/// frames stutter.
+pub struct AvSync {
+    fps: JadeRational,
+    start_frame: FrameNum,
+    speed: f64,
+    clock: Box<dyn MasterClock>,
+    video_secs: f64,
+    last_update: Instant,
+    stats: SyncStats,
+}
+
+impl AvSync {
+    pub fn new(
+        fps: JadeRational,
+        start_frame: FrameNum,
+        speed: f64,
+        clock: Box<dyn MasterClock>,
+    ) -> Self {
+        Self {
+            fps,
+            start_frame,
+            speed,
+            clock,
+            video_secs: 0.0,
+            last_update: Instant::now(),
+            stats: SyncStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> SyncStats {
+        self.stats
+    }
+
+    fn frames_per_sec(&self) -> f64 {
+        self.fps.num as f64 / self.fps.den as f64
+    }
+
+    /// Advances the video clock and returns the frame that should be showing.
+    /// May be negative or past the end, when playback has run off either end.
+    pub fn target_frame(&mut self) -> i64 {
+        let now = Instant::now();
+        self.video_secs += (now - self.last_update).as_secs_f64() * self.speed;
+        self.last_update = now;
+
+        let drift = self.video_secs - self.clock.elapsed_secs();
+        if drift.abs() > RESYNC_THRESHOLD_SECS {
+            self.video_secs -= drift;
+            self.stats.resyncs += 1;
+        } else {
+            self.video_secs -= drift * DRIFT_CORRECTION;
+        }
+
+        self.start_frame.0 as i64 + (self.video_secs * self.frames_per_sec()).floor() as i64
+    }
+
+    /// Measures how far off the master clock a frame is as it's presented.
+    pub fn frame_presented(&mut self, frame: FrameNum) {
+        let frame_secs = (frame.0 as f64 - self.start_frame.0 as f64) / self.frames_per_sec();
+        let error = frame_secs - self.clock.elapsed_secs();
+        self.stats.frames_measured += 1;
+        self.stats.last_error_secs = error;
+        self.stats.max_abs_error_secs = self.stats.max_abs_error_secs.max(error.abs());
+        self.stats.total_abs_error_secs += error.abs();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ops::Range, thread, time::Duration};
+
+    const FPS: JadeRational = JadeRational { num: 30, den: 1 };
+    const SAMPLE_RATE: u32 = 48_000;
+    /// How far ahead of what's been played audio is kept queued.
+    const LEAD_SAMPLES: u64 = SAMPLE_RATE as u64 / 20;
+    const PLAY_TIME: Duration = Duration::from_millis(800);
+
+    /// An audio device whose clock runs `rate` times as fast as the system's,
+    /// as cheap sound cards tend to a little.
+    struct SkewedClock {
+        sink: Arc<Mutex<NullAudioSink>>,
+        rate: f64,
+    }
+
+    impl MasterClock for SkewedClock {
+        fn elapsed_secs(&self) -> f64 {
+            let sink = self.sink.lock().unwrap();
+            sink.played_samples() as f64 / SAMPLE_RATE as f64 * self.rate
+        }
+    }
+
+    fn top_up(sink: &Mutex<NullAudioSink>) {
+        let mut sink = sink.lock().unwrap();
+        let ahead = sink.queued_samples() - sink.played_samples();
+        if ahead < LEAD_SAMPLES {
+            let chunk = AudioChunk {
+                start_sample: sink.queued_samples(),
+                planes: vec![vec![0.0; (LEAD_SAMPLES - ahead) as usize]; 2],
+            };
+            sink.queue(&chunk);
+        }
+    }
+
+    /// Plays for `PLAY_TIME` the way the playback engine does, presenting
+    /// whichever frame is due every frame interval. No audio is queued while
+    /// the time played is within `starved`.
+    fn play(rate: f64, starved: Range<Duration>) -> SyncStats {
+        let sink = Arc::new(Mutex::new(NullAudioSink::new(SAMPLE_RATE)));
+        top_up(&sink);
+        let clock = SkewedClock {
+            sink: sink.clone(),
+            rate,
+        };
+        let mut sync = AvSync::new(FPS, FrameNum(0), 1.0, Box::new(clock));
+
+        let interval = Duration::from_secs_f64(FPS.den as f64 / FPS.num as f64);
+        let started = Instant::now();
+        while started.elapsed() < PLAY_TIME {
+            if !starved.contains(&started.elapsed()) {
+                top_up(&sink);
+            }
+            let target = sync.target_frame();
+            sync.frame_presented(FrameNum(target.max(0) as u64));
+            thread::sleep(interval);
+        }
+        sync.stats()
+    }
+
+    fn assert_in_sync(stats: SyncStats) {
+        let frame_secs = FPS.den as f64 / FPS.num as f64;
+        assert!(stats.frames_measured > 10, "{stats:?}");
+        assert!(stats.mean_abs_error_secs() < frame_secs, "{stats:?}");
+        assert!(
+            stats.max_abs_error_secs < RESYNC_THRESHOLD_SECS,
+            "{stats:?}"
+        );
+        assert_eq!(stats.resyncs, 0, "{stats:?}");
+    }
+
+    #[test]
+    fn follows_a_slow_audio_clock() {
+        assert_in_sync(play(0.98, Duration::ZERO..Duration::ZERO));
+    }
+
+    #[test]
+    fn follows_a_fast_audio_clock() {
+        assert_in_sync(play(1.02, Duration::ZERO..Duration::ZERO));
+    }
+
+    #[test]
+    fn resyncs_when_audio_stalls() {
+        // The sink runs dry after its lead, then the clock stands still for
+        // longer than the threshold while video carries on.
+        let stall = Duration::from_millis(200)..Duration::from_millis(500);
+        let stats = play(1.0, stall);
+        assert!(stats.resyncs >= 1, "{stats:?}");
+        // Snapping back keeps what's presented from falling behind with it.
+        assert!(
+            stats.max_abs_error_secs < RESYNC_THRESHOLD_SECS,
+            "{stats:?}"
+        );
+    }
+}
@@ -0,0 +1,417 @@
+use super::{
+    Clip, ClipId, FrameNum, FrameSpan, JadeRational, MediaInfo, MediaKey, MediaProject, ProxyMedia,
+    RelinkMatch, TrackRef,
+};
+use anyhow::{Context, bail, ensure};
+
+/// A single reversible change to a project. Every edit made from the UI goes
+/// through one of these, so that it can be undone.
+///
+/// Commands carry everything needed to revert them, captured when they're
+/// built from the current state of the project.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EditCommand {
+    /// Adds a file to the media pool. `key` is filled in when the command is
+    /// first applied, and the media goes back under it when it's redone.
+    ImportMedia {
+        info: MediaInfo,
+        key: Option<MediaKey>,
+    },
+    /// Takes unused media out of the pool. Undoing it puts the media back
+    /// under the same key.
+    RemoveMedia {
+        key: MediaKey,
+        info: MediaInfo,
+    },
+    RenameMedia {
+        key: MediaKey,
+        old_name: String,
+        new_name: String,
+    },
+    /// Points media at the files found for them after they went missing, all
+    /// in one step.
+    RelinkMedia {
+        matches: Vec<RelinkMatch>,
+    },
+    /// Attaches a proxy to media, or takes it away with `None`.
+    SetProxy {
+        key: MediaKey,
+        old_proxy: Option<ProxyMedia>,
+        new_proxy: Option<ProxyMedia>,
+    },
+    AddClip {
+        track: TrackRef,
+        clip: Clip,
+    },
+    RemoveClip {
+        track: TrackRef,
+        clip: Clip,
+    },
+    MoveClip {
+        id: ClipId,
+        old_track: TrackRef,
+        old_start: FrameNum,
+        new_track: TrackRef,
+        new_start: FrameNum,
+    },
+    TrimClip {
+        id: ClipId,
+        old_source: FrameSpan,
+        old_start: FrameNum,
+        new_source: FrameSpan,
+        new_start: FrameNum,
+    },
+    SetFps {
+        old_fps: JadeRational,
+        new_fps: JadeRational,
+    },
+}
+
+impl EditCommand {
+    pub fn import_media(info: MediaInfo) -> Self {
+        Self::ImportMedia { info, key: None }
+    }
+
+    pub fn remove_media(project: &MediaProject, key: MediaKey) -> anyhow::Result<Self> {
+        let info = project.media.get(key).context("no such media to remove")?;
+        Ok(Self::RemoveMedia {
+            key,
+            info: info.clone(),
+        })
+    }
+
+    pub fn rename_media(
+        project: &MediaProject,
+        key: MediaKey,
+        new_name: String,
+    ) -> anyhow::Result<Self> {
+        let info = project.media.get(key).context("no such media to rename")?;
+        Ok(Self::RenameMedia {
+            key,
+            old_name: info.name.clone(),
+            new_name,
+        })
+    }
+
+    pub fn relink_media(project: &MediaProject, matches: Vec<RelinkMatch>) -> anyhow::Result<Self> {
+        ensure!(!matches.is_empty(), "no media to relink");
+        for relink in &matches {
+            ensure!(
+                project.media.contains_key(relink.key),
+                "no such media to relink"
+            );
+        }
+        Ok(Self::RelinkMedia { matches })
+    }
+
+    pub fn set_proxy(
+        project: &MediaProject,
+        key: MediaKey,
+        new_proxy: Option<ProxyMedia>,
+    ) -> anyhow::Result<Self> {
+        let info = project
+            .media
+            .get(key)
+            .context("no such media to set the proxy of")?;
+        Ok(Self::SetProxy {
+            key,
+            old_proxy: info.proxy.clone(),
+            new_proxy,
+        })
+    }
+
+    /// Places a new clip, checking the stream and track line up the same way
+    /// `MediaProject::add_clip` does.
+    pub fn add_clip(
+        project: &MediaProject,
+        track: TrackRef,
+        media: MediaKey,
+        stream_index: usize,
+        source: FrameSpan,
+        timeline_start: FrameNum,
+    ) -> anyhow::Result<Self> {
+        project.check_stream_fits_track(track, media, stream_index)?;
+        Ok(Self::AddClip {
+            track,
+            clip: Clip {
+                id: project.timeline.next_clip_id(),
+                media,
+                stream_index,
+                source,
+                timeline_start,
+            },
+        })
+    }
+
+    pub fn remove_clip(project: &MediaProject, id: ClipId) -> anyhow::Result<Self> {
+        let (track, clip) = project
+            .timeline
+            .find_clip(id)
+            .context("no such clip to remove")?;
+        Ok(Self::RemoveClip {
+            track,
+            clip: clip.clone(),
+        })
+    }
+
+    pub fn move_clip(
+        project: &MediaProject,
+        id: ClipId,
+        new_track: TrackRef,
+        new_start: FrameNum,
+    ) -> anyhow::Result<Self> {
+        let (old_track, clip) = project
+            .timeline
+            .find_clip(id)
+            .context("no such clip to move")?;
+        Ok(Self::MoveClip {
+            id,
+            old_track,
+            old_start: clip.timeline_start,
+            new_track,
+            new_start,
+        })
+    }
+
+    pub fn trim_clip(
+        project: &MediaProject,
+        id: ClipId,
+        new_source: FrameSpan,
+        new_start: FrameNum,
+    ) -> anyhow::Result<Self> {
+        let (_, clip) = project
+            .timeline
+            .find_clip(id)
+            .context("no such clip to trim")?;
+        Ok(Self::TrimClip {
+            id,
+            old_source: clip.source.clone(),
+            old_start: clip.timeline_start,
+            new_source,
+            new_start,
+        })
+    }
+
+    pub fn set_fps(project: &MediaProject, new_fps: JadeRational) -> Self {
+        Self::SetFps {
+            old_fps: project.fps,
+            new_fps,
+        }
+    }
+
+    /// A short description for menus, e.g. "Undo Move Clip".
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ImportMedia { .. } => "Import Media",
+            Self::RemoveMedia { .. } => "Remove Media",
+            Self::RenameMedia { .. } => "Rename Media",
+            Self::RelinkMedia { .. } => "Relink Media",
+            Self::SetProxy {
+                new_proxy: None, ..
+            } => "Remove Proxy",
+            Self::SetProxy { .. } => "Create Proxy",
+            Self::AddClip { .. } => "Add Clip",
+            Self::RemoveClip { .. } => "Delete Clip",
+            Self::MoveClip { .. } => "Move Clip",
+            Self::TrimClip { .. } => "Trim Clip",
+            Self::SetFps { .. } => "Change Frame Rate",
+        }
+    }
+
+    /// Makes the change. On error the project is left as it was.
+    pub fn apply(&mut self, project: &mut MediaProject) -> anyhow::Result<()> {
+        match self {
+            Self::ImportMedia { info, key } => match key {
+                Some(key) => project.media.restore(*key, info.clone())?,
+                None => *key = Some(project.media.insert(info.clone())),
+            },
+            Self::RemoveMedia { key, .. } => {
+                if project.timeline.clips_using(*key).next().is_some() {
+                    bail!("media is still used by clips on the timeline");
+                }
+                project
+                    .media
+                    .remove(*key)
+                    .context("no such media to remove")?;
+            }
+            Self::RenameMedia { key, new_name, .. } => {
+                project
+                    .media
+                    .get_mut(*key)
+                    .context("no such media to rename")?
+                    .name = new_name.clone();
+            }
+            Self::RelinkMedia { matches } => {
+                ensure!(
+                    matches
+                        .iter()
+                        .all(|relink| project.media.contains_key(relink.key)),
+                    "no such media to relink"
+                );
+                for relink in matches.iter() {
+                    project.media[relink.key].path = relink.new_path.clone();
+                }
+            }
+            Self::SetProxy { key, new_proxy, .. } => {
+                project
+                    .media
+                    .get_mut(*key)
+                    .context("no such media to set the proxy of")?
+                    .proxy = new_proxy.clone();
+            }
+            Self::AddClip { track, clip } => project.timeline.insert_clip(*track, clip.clone())?,
+            Self::RemoveClip { clip, .. } => {
+                project
+                    .timeline
+                    .remove_clip(clip.id)
+                    .context("no such clip to remove")?;
+            }
+            Self::MoveClip {
+                id,
+                new_track,
+                new_start,
+                ..
+            } => project.timeline.move_clip(*id, *new_track, *new_start)?,
+            Self::TrimClip {
+                id,
+                new_source,
+                new_start,
+                ..
+            } => project
+                .timeline
+                .trim_clip(*id, new_source.clone(), *new_start)?,
+            Self::SetFps { new_fps, .. } => project.fps = *new_fps,
+        }
+        Ok(())
+    }
+
+    /// Undoes the change. Only valid right after `apply`, or after the commands
+    /// applied since have all been reverted.
+    pub fn revert(&mut self, project: &mut MediaProject) -> anyhow::Result<()> {
+        match self {
+            Self::ImportMedia { key, .. } => {
+                let key = key.context("media import was never applied")?;
+                project
+                    .media
+                    .remove(key)
+                    .context("imported media is no longer in the project")?;
+            }
+            Self::RemoveMedia { key, info } => project.media.restore(*key, info.clone())?,
+            Self::RenameMedia { key, old_name, .. } => {
+                project
+                    .media
+                    .get_mut(*key)
+                    .context("renamed media is no longer in the project")?
+                    .name = old_name.clone();
+            }
+            Self::RelinkMedia { matches } => {
+                ensure!(
+                    matches
+                        .iter()
+                        .all(|relink| project.media.contains_key(relink.key)),
+                    "relinked media is no longer in the project"
+                );
+                for relink in matches.iter() {
+                    project.media[relink.key].path = relink.old_path.clone();
+                }
+            }
+            Self::SetProxy { key, old_proxy, .. } => {
+                project
+                    .media
+                    .get_mut(*key)
+                    .context("media with a new proxy is no longer in the project")?
+                    .proxy = old_proxy.clone();
+            }
+            Self::AddClip { clip, .. } => {
+                project
+                    .timeline
+                    .remove_clip(clip.id)
+                    .context("added clip is no longer on the timeline")?;
+                project.timeline.release_clip_id(clip.id);
+            }
+            Self::RemoveClip { track, clip } => {
+                project.timeline.insert_clip(*track, clip.clone())?
+            }
+            Self::MoveClip {
+                id,
+                old_track,
+                old_start,
+                ..
+            } => project.timeline.move_clip(*id, *old_track, *old_start)?,
+            Self::TrimClip {
+                id,
+                old_source,
+                old_start,
+                ..
+            } => project
+                .timeline
+                .trim_clip(*id, old_source.clone(), *old_start)?,
+            Self::SetFps { old_fps, .. } => project.fps = *old_fps,
+        }
+        Ok(())
+    }
+
+    /// Folds `next` into this command if it continues the same edit, like the
+    /// steps of dragging a clip. Returns whether it could be merged.
+    pub fn merge(&mut self, next: &Self) -> bool {
+        match (self, next) {
+            (
+                Self::MoveClip {
+                    id,
+                    new_track,
+                    new_start,
+                    ..
+                },
+                Self::MoveClip {
+                    id: next_id,
+                    new_track: next_track,
+                    new_start: next_start,
+                    ..
+                },
+            ) if id == next_id => {
+                *new_track = *next_track;
+                *new_start = *next_start;
+                true
+            }
+            (
+                Self::TrimClip {
+                    id,
+                    new_source,
+                    new_start,
+                    ..
+                },
+                Self::TrimClip {
+                    id: next_id,
+                    new_source: next_source,
+                    new_start: next_start,
+                    ..
+                },
+            ) if id == next_id => {
+                *new_source = next_source.clone();
+                *new_start = *next_start;
+                true
+            }
+            (
+                Self::RenameMedia { key, new_name, .. },
+                Self::RenameMedia {
+                    key: next_key,
+                    new_name: next_name,
+                    ..
+                },
+            ) if key == next_key => {
+                *new_name = next_name.clone();
+                true
+            }
+            (
+                Self::SetFps { new_fps, .. },
+                Self::SetFps {
+                    new_fps: next_fps, ..
+                },
+            ) => {
+                *new_fps = *next_fps;
+                true
+            }
+            _ => false,
+        }
+    }
+}
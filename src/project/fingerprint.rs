@@ -0,0 +1,219 @@
+use super::MediaStream;
+use crate::disk_cache::Fnv1a;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, Metadata},
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    time::UNIX_EPOCH,
+};
+
+/// How many bytes are read from each place sampled in a file.
+const SAMPLE_CHUNK_LEN: u64 = 64 * 1024;
+/// How many places are sampled, spread evenly from the start of a file to its
+/// end. Files smaller than all of them together are read whole.
+const SAMPLE_CHUNK_COUNT: u64 = 8;
+
+/// A summary of a media file quick enough to take on import, kept with the
+/// project so files can be recognized without having them on hand.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MediaFingerprint {
+    pub size: u64,
+    /// When the file was last modified, in nanoseconds since the Unix epoch,
+    /// or zero if the platform doesn't say.
+    pub modified_nanos: u64,
+    /// A hash of the size and chunks sampled through the file.
+    pub content_hash: u64,
+    /// A hash of the probed stream parameters.
+    pub streams_hash: u64,
+}
+
+impl MediaFingerprint {
+    /// Fingerprints the file at `path`, which `streams` were probed from.
+    pub fn of_file(path: &Path, streams: &[MediaStream]) -> anyhow::Result<Self> {
+        let metadata = fs::metadata(path)
+            .with_context(|| format!("failed to read metadata of {}", path.display()))?;
+        let content_hash = sample_file(path, metadata.len())
+            .with_context(|| format!("failed to sample {}", path.display()))?;
+        Ok(Self {
+            size: metadata.len(),
+            modified_nanos: modified_nanos(&metadata),
+            content_hash,
+            streams_hash: hash_streams(streams),
+        })
+    }
+
+    /// Whether the two fingerprints are of the same media, even if it's been
+    /// copied since and so modified at a different time.
+    pub fn same_content(&self, other: &Self) -> bool {
+        self.size == other.size
+            && self.content_hash == other.content_hash
+            && self.streams_hash == other.streams_hash
+    }
+
+    /// Whether the file's size and modification time are still as they were
+    /// when it was fingerprinted, which is taken to mean it hasn't changed.
+    pub fn matches_metadata(&self, metadata: &Metadata) -> bool {
+        self.size == metadata.len() && self.modified_nanos == modified_nanos(metadata)
+    }
+}
+
+fn modified_nanos(metadata: &Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|modified| modified.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+fn sample_file(path: &Path, size: u64) -> anyhow::Result<u64> {
+    let mut file = File::open(path)?;
+    let mut hash = Fnv1a::default();
+    hash.write(&size.to_le_bytes());
+    if size <= SAMPLE_CHUNK_LEN * SAMPLE_CHUNK_COUNT {
+        let mut contents = Vec::with_capacity(size as usize);
+        file.read_to_end(&mut contents)?;
+        hash.write(&contents);
+        return Ok(hash.finish());
+    }
+
+    let mut chunk = vec![0; SAMPLE_CHUNK_LEN as usize];
+    let last_offset = size - SAMPLE_CHUNK_LEN;
+    for index in 0..SAMPLE_CHUNK_COUNT {
+        file.seek(SeekFrom::Start(
+            last_offset * index / (SAMPLE_CHUNK_COUNT - 1),
+        ))?;
+        file.read_exact(&mut chunk)?;
+        hash.write(&chunk);
+    }
+    Ok(hash.finish())
+}
+
+fn hash_streams(streams: &[MediaStream]) -> u64 {
+    fn write_str(hash: &mut Fnv1a, value: &Option<String>) {
+        hash.write(value.as_deref().unwrap_or_default().as_bytes());
+        // Keeps neighbouring strings from running into each other.
+        hash.write(&[0xff]);
+    }
+
+    let mut hash = Fnv1a::default();
+    for stream in streams {
+        let info = stream.info();
+        hash.write(&(info.index as u64).to_le_bytes());
+        hash.write(&info.length.time_base_length.to_le_bytes());
+        hash.write(&info.length.time_base.num.to_le_bytes());
+        hash.write(&info.length.time_base.den.to_le_bytes());
+        match stream {
+            MediaStream::Video(_, video) => {
+                hash.write(b"video");
+                write_str(&mut hash, &video.codec);
+                write_str(&mut hash, &video.pixel_format);
+                hash.write(&video.width.to_le_bytes());
+                hash.write(&video.height.to_le_bytes());
+            }
+            MediaStream::Audio(_, audio) => {
+                hash.write(b"audio");
+                write_str(&mut hash, &audio.codec);
+                write_str(&mut hash, &audio.sample_format);
+                hash.write(&audio.sample_rate.to_le_bytes());
+                hash.write(&audio.channels.to_le_bytes());
+            }
+        }
+    }
+    hash.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_media;
+    use std::time::Duration;
+
+    /// The largest file that's read whole rather than sampled.
+    const WHOLE_FILE_LIMIT: u64 = SAMPLE_CHUNK_LEN * SAMPLE_CHUNK_COUNT;
+
+    fn contents(len: u64) -> Vec<u8> {
+        (0..len).map(|byte| (byte * 7 % 251) as u8).collect()
+    }
+
+    fn fingerprint(path: &Path, contents: &[u8]) -> MediaFingerprint {
+        fs::write(path, contents).unwrap();
+        MediaFingerprint::of_file(path, &[]).unwrap()
+    }
+
+    #[test]
+    fn hashes_small_files_whole() {
+        let path = test_media::temp_dir("fingerprint-small").join("small.bin");
+        for len in [0, 1000, WHOLE_FILE_LIMIT] {
+            let contents = contents(len);
+            let mut hash = Fnv1a::default();
+            hash.write(&len.to_le_bytes());
+            hash.write(&contents);
+            let fingerprint = fingerprint(&path, &contents);
+            assert_eq!(fingerprint.size, len);
+            assert_eq!(fingerprint.content_hash, hash.finish(), "{len} bytes");
+        }
+    }
+
+    #[test]
+    fn samples_large_files() {
+        let path = test_media::temp_dir("fingerprint-large").join("large.bin");
+        let size = WHOLE_FILE_LIMIT * 2;
+        let original = contents(size);
+        let fingerprint_of = |change_at: u64| {
+            let mut changed = original.clone();
+            changed[change_at as usize] ^= 0xff;
+            fingerprint(&path, &changed).content_hash
+        };
+        let hash = fingerprint(&path, &original).content_hash;
+
+        // The first, a middle, and the last byte of the sampled chunks.
+        let chunk_3 = (size - SAMPLE_CHUNK_LEN) * 3 / (SAMPLE_CHUNK_COUNT - 1);
+        for change_at in [0, chunk_3 + SAMPLE_CHUNK_LEN / 2, size - 1] {
+            assert_ne!(fingerprint_of(change_at), hash, "byte {change_at} changed");
+        }
+        // Just past the first chunk, before the second starts.
+        assert_eq!(fingerprint_of(SAMPLE_CHUNK_LEN), hash);
+    }
+
+    #[test]
+    fn compares_content_regardless_of_modification_time() {
+        let path = test_media::temp_dir("fingerprint-same").join("clip.bin");
+        let original = fingerprint(&path, &contents(1000));
+        let copied = MediaFingerprint {
+            modified_nanos: original.modified_nanos + 1,
+            ..original.clone()
+        };
+        assert!(original.same_content(&copied));
+
+        let edited = fingerprint(&path, &contents(1001));
+        assert!(!original.same_content(&edited));
+        let restreamed = MediaFingerprint {
+            streams_hash: original.streams_hash + 1,
+            ..original.clone()
+        };
+        assert!(!original.same_content(&restreamed));
+    }
+
+    #[test]
+    fn notices_rewritten_files_by_their_metadata() {
+        let path = test_media::temp_dir("fingerprint-metadata").join("clip.bin");
+        let fingerprint = fingerprint(&path, &contents(1000));
+        assert!(fingerprint.matches_metadata(&fs::metadata(&path).unwrap()));
+
+        // Rewritten at the same size, then at another.
+        let mut changed = contents(1000);
+        changed[0] ^= 0xff;
+        fs::write(&path, &changed).unwrap();
+        let file = File::options().write(true).open(&path).unwrap();
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        file.set_modified(modified + Duration::from_secs(1))
+            .unwrap();
+        assert!(!fingerprint.matches_metadata(&fs::metadata(&path).unwrap()));
+
+        fs::write(&path, contents(2000)).unwrap();
+        assert!(!fingerprint.matches_metadata(&fs::metadata(&path).unwrap()));
+    }
+}
@@ -1,24 +1,557 @@
 use super::FrameNum;
+use anyhow::{Context, bail};
 use serde::{Deserialize, Serialize};
 use std::ops::Range;
 
+/// A run of frames, from `from` up to but not including `to_excl`. `from` must
+/// never be after `to_excl`, which `new` and deserializing check; spans built
+/// from the fields directly are trusted to get this right.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedFrameSpan")]
 pub struct FrameSpan {
     pub from: FrameNum,
     pub to_excl: FrameNum,
 }
 
+impl FrameSpan {
+    pub fn new(from: FrameNum, to_excl: FrameNum) -> anyhow::Result<Self> {
+        if from > to_excl {
+            bail!(
+                "frame span starts at {} but ends before it, at {}",
+                from.0,
+                to_excl.0
+            );
+        }
+        Ok(Self { from, to_excl })
+    }
+
+    /// The span `len` frames long, starting at `from`. Fails if it would end
+    /// past the last frame number there is.
+    pub fn with_len(from: FrameNum, len: u64) -> anyhow::Result<Self> {
+        let to_excl = from
+            .0
+            .checked_add(len)
+            .with_context(|| format!("frame span of {len} frames from {} is too long", from.0))?;
+        Ok(Self {
+            from,
+            to_excl: FrameNum(to_excl),
+        })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.to_excl.0.saturating_sub(self.from.0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, frame: FrameNum) -> bool {
+        self.from <= frame && frame < self.to_excl
+    }
+
+    /// Whether the two spans share at least one frame.
+    pub fn overlaps(&self, other: &Self) -> bool {
+        !self.is_empty()
+            && !other.is_empty()
+            && self.from < other.to_excl
+            && other.from < self.to_excl
+    }
+
+    /// Whether every frame of `other` is also in this span. Empty spans are
+    /// within anything.
+    pub fn encloses(&self, other: &Self) -> bool {
+        other.is_empty() || (self.from <= other.from && other.to_excl <= self.to_excl)
+    }
+
+    /// The frames in both spans, or `None` if they don't overlap.
+    pub fn intersection(&self, other: &Self) -> Option<Self> {
+        self.overlaps(other).then(|| Self {
+            from: self.from.max(other.from),
+            to_excl: self.to_excl.min(other.to_excl),
+        })
+    }
+
+    /// The frames in either span, or `None` if they have a gap between them,
+    /// since that can't be a single span.
+    pub fn union(&self, other: &Self) -> Option<Self> {
+        if self.is_empty() {
+            return Some(other.clone());
+        }
+        if other.is_empty() {
+            return Some(self.clone());
+        }
+        (self.from <= other.to_excl && other.from <= self.to_excl).then(|| Self {
+            from: self.from.min(other.from),
+            to_excl: self.to_excl.max(other.to_excl),
+        })
+    }
+
+    /// The frames of this span that aren't in `other`, which may be a part
+    /// before `other` and a part after it. Parts that would be empty are
+    /// `None`.
+    pub fn difference(&self, other: &Self) -> (Option<Self>, Option<Self>) {
+        if self.is_empty() {
+            return (None, None);
+        }
+        if !self.overlaps(other) {
+            return if self.from < other.from {
+                (Some(self.clone()), None)
+            } else {
+                (None, Some(self.clone()))
+            };
+        }
+        let before = Self {
+            from: self.from,
+            to_excl: other.from,
+        };
+        let after = Self {
+            from: other.to_excl,
+            to_excl: self.to_excl,
+        };
+        (
+            (self.from < other.from).then_some(before),
+            (other.to_excl < self.to_excl).then_some(after),
+        )
+    }
+
+    /// Splits into the frames before `frame` and the frames from it on, or
+    /// `None` unless both parts would have frames in them.
+    pub fn split_at(&self, frame: FrameNum) -> Option<(Self, Self)> {
+        if !(self.from < frame && frame < self.to_excl) {
+            return None;
+        }
+        Some((
+            Self {
+                from: self.from,
+                to_excl: frame,
+            },
+            Self {
+                from: frame,
+                to_excl: self.to_excl,
+            },
+        ))
+    }
+
+    /// Moves the span by `offset` frames, or `None` if that would move it
+    /// before the first frame.
+    pub fn shift(&self, offset: i64) -> Option<Self> {
+        let from = self.from.0.checked_add_signed(offset)?;
+        let to_excl = self.to_excl.0.checked_add_signed(offset)?;
+        Some(Self {
+            from: FrameNum(from),
+            to_excl: FrameNum(to_excl),
+        })
+    }
+
+    /// Shrinks the span to fit within `bounds`. A span entirely outside the
+    /// bounds becomes an empty span at the nearest edge of them.
+    pub fn clamp(&self, bounds: &Self) -> Self {
+        Self {
+            from: self.from.clamp(bounds.from, bounds.to_excl),
+            to_excl: self.to_excl.clamp(bounds.from, bounds.to_excl),
+        }
+    }
+}
+
+/// The fields of a `FrameSpan` as read from a file, before they're checked.
+#[derive(Deserialize)]
+struct UncheckedFrameSpan {
+    from: FrameNum,
+    to_excl: FrameNum,
+}
+
+impl TryFrom<UncheckedFrameSpan> for FrameSpan {
+    type Error = anyhow::Error;
+
+    fn try_from(value: UncheckedFrameSpan) -> Result<Self, Self::Error> {
+        Self::new(value.from, value.to_excl)
+    }
+}
+
 impl From<FrameSpan> for Range<u64> {
     fn from(value: FrameSpan) -> Self {
         value.from.0..value.to_excl.0
     }
 }
 
-impl From<Range<u64>> for FrameSpan {
-    fn from(value: Range<u64>) -> Self {
-        Self {
-            from: FrameNum(value.start),
-            to_excl: FrameNum(value.end),
+impl TryFrom<Range<u64>> for FrameSpan {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Range<u64>) -> Result<Self, Self::Error> {
+        Self::new(FrameNum(value.start), FrameNum(value.end))
+    }
+}
+
+/// Any set of frames, kept as the fewest spans that cover them: sorted, with
+/// no two spans overlapping or touching, and none empty.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrameSpanSet {
+    spans: Vec<FrameSpan>,
+}
+
+impl FrameSpanSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn spans(&self) -> &[FrameSpan] {
+        &self.spans
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    /// The number of frames in the set.
+    pub fn len(&self) -> u64 {
+        self.spans.iter().map(FrameSpan::len).sum()
+    }
+
+    pub fn clear(&mut self) {
+        self.spans.clear();
+    }
+
+    pub fn contains(&self, frame: FrameNum) -> bool {
+        let index = self.spans.partition_point(|span| span.to_excl <= frame);
+        self.spans
+            .get(index)
+            .is_some_and(|span| span.contains(frame))
+    }
+
+    /// Whether any frame of `span` is in the set.
+    pub fn overlaps(&self, span: &FrameSpan) -> bool {
+        let index = self
+            .spans
+            .partition_point(|other| other.to_excl <= span.from);
+        self.spans
+            .get(index)
+            .is_some_and(|other| other.overlaps(span))
+    }
+
+    /// Whether every frame of `span` is in the set.
+    pub fn encloses(&self, span: &FrameSpan) -> bool {
+        let index = self
+            .spans
+            .partition_point(|other| other.to_excl <= span.from);
+        span.is_empty()
+            || self
+                .spans
+                .get(index)
+                .is_some_and(|other| other.encloses(span))
+    }
+
+    pub fn insert(&mut self, span: FrameSpan) {
+        if span.is_empty() {
+            return;
+        }
+        // Every span overlapping or touching the new one merges into it.
+        let start = self
+            .spans
+            .partition_point(|other| other.to_excl < span.from);
+        let end = self
+            .spans
+            .partition_point(|other| other.from <= span.to_excl);
+        let merged = self.spans[start..end]
+            .iter()
+            .fold(span, |merged, other| FrameSpan {
+                from: merged.from.min(other.from),
+                to_excl: merged.to_excl.max(other.to_excl),
+            });
+        self.spans.splice(start..end, [merged]);
+    }
+
+    pub fn remove(&mut self, span: &FrameSpan) {
+        if span.is_empty() {
+            return;
+        }
+        let start = self
+            .spans
+            .partition_point(|other| other.to_excl <= span.from);
+        let end = self
+            .spans
+            .partition_point(|other| other.from < span.to_excl);
+        let remaining: Vec<FrameSpan> = self.spans[start..end]
+            .iter()
+            .flat_map(|other| {
+                let (before, after) = other.difference(span);
+                before.into_iter().chain(after)
+            })
+            .collect();
+        self.spans.splice(start..end, remaining);
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        let mut union = self.clone();
+        for span in &other.spans {
+            union.insert(span.clone());
+        }
+        union
+    }
+
+    pub fn intersection(&self, other: &Self) -> Self {
+        let mut spans = vec![];
+        let (mut a, mut b) = (self.spans.iter().peekable(), other.spans.iter().peekable());
+        while let (Some(span_a), Some(span_b)) = (a.peek(), b.peek()) {
+            spans.extend(span_a.intersection(span_b));
+            // Whichever ends first can't overlap anything further on.
+            if span_a.to_excl <= span_b.to_excl {
+                a.next();
+            } else {
+                b.next();
+            }
+        }
+        Self { spans }
+    }
+
+    pub fn difference(&self, other: &Self) -> Self {
+        let mut difference = self.clone();
+        for span in &other.spans {
+            difference.remove(span);
+        }
+        difference
+    }
+
+    /// The frames within `bounds` that aren't in the set.
+    pub fn gaps_within(&self, bounds: &FrameSpan) -> Self {
+        Self::from_iter([bounds.clone()]).difference(self)
+    }
+}
+
+impl FromIterator<FrameSpan> for FrameSpanSet {
+    fn from_iter<T: IntoIterator<Item = FrameSpan>>(iter: T) -> Self {
+        let mut set = Self::new();
+        for span in iter {
+            set.insert(span);
+        }
+        set
+    }
+}
+
+impl IntoIterator for FrameSpanSet {
+    type Item = FrameSpan;
+    type IntoIter = std::vec::IntoIter<FrameSpan>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.spans.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    /// Frames used by the exhaustive tests. Every span within these is tried.
+    const LIMIT: u64 = 8;
+
+    fn span(range: Range<u64>) -> FrameSpan {
+        FrameSpan::try_from(range).unwrap()
+    }
+
+    fn every_span() -> impl Iterator<Item = FrameSpan> + Clone {
+        (0..=LIMIT).flat_map(|from| (from..=LIMIT).map(move |to_excl| span(from..to_excl)))
+    }
+
+    /// Every set of frames within `LIMIT`, as a set of spans and as the frames
+    /// themselves.
+    fn every_set() -> impl Iterator<Item = (FrameSpanSet, BTreeSet<u64>)> + Clone {
+        (0..1u32 << LIMIT).map(|bits| {
+            let frames: BTreeSet<u64> = (0..LIMIT).filter(|frame| bits & 1 << frame != 0).collect();
+            let set = frames.iter().map(|frame| span(*frame..frame + 1)).collect();
+            (set, frames)
+        })
+    }
+
+    fn frames_of(span: &FrameSpan) -> BTreeSet<u64> {
+        Range::from(span.clone()).collect()
+    }
+
+    fn frames_of_set(set: &FrameSpanSet) -> BTreeSet<u64> {
+        set.spans().iter().flat_map(frames_of).collect()
+    }
+
+    fn assert_fewest_spans(set: &FrameSpanSet) {
+        assert!(set.spans().iter().all(|span| !span.is_empty()), "{set:?}");
+        assert!(
+            set.spans()
+                .windows(2)
+                .all(|pair| pair[0].to_excl < pair[1].from),
+            "{set:?}"
+        );
+    }
+
+    #[test]
+    fn rejects_backwards_spans() {
+        assert!(FrameSpan::new(FrameNum(5), FrameNum(4)).is_err());
+        assert!(FrameSpan::new(FrameNum(5), FrameNum(5)).unwrap().is_empty());
+        assert!(FrameSpan::with_len(FrameNum(u64::MAX), 1).is_err());
+        assert_eq!(FrameSpan::with_len(FrameNum(3), 2).unwrap(), span(3..5));
+    }
+
+    #[test]
+    fn rejects_backwards_spans_when_deserializing() {
+        let span: FrameSpan = serde_json::from_str(r#"{"from":2,"to_excl":5}"#).unwrap();
+        assert_eq!(span, FrameSpan::try_from(2..5).unwrap());
+        assert!(serde_json::from_str::<FrameSpan>(r#"{"from":5,"to_excl":2}"#).is_err());
+    }
+
+    #[test]
+    fn span_intersection_has_the_common_frames() {
+        for a in every_span() {
+            for b in every_span() {
+                let common: BTreeSet<u64> = frames_of(&a)
+                    .intersection(&frames_of(&b))
+                    .copied()
+                    .collect();
+                match a.intersection(&b) {
+                    Some(both) => assert_eq!(frames_of(&both), common, "{a:?} {b:?}"),
+                    None => assert!(common.is_empty(), "{a:?} {b:?}"),
+                }
+                assert_eq!(a.overlaps(&b), !common.is_empty(), "{a:?} {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn span_union_merges_overlapping_and_adjacent_spans() {
+        for a in every_span() {
+            for b in every_span() {
+                let either: BTreeSet<u64> = frames_of(&a).union(&frames_of(&b)).copied().collect();
+                let contiguous = either
+                    .first()
+                    .zip(either.last())
+                    .is_none_or(|(first, last)| (last - first + 1) as usize == either.len());
+                match a.union(&b) {
+                    Some(union) => {
+                        assert!(contiguous, "{a:?} {b:?}");
+                        assert_eq!(frames_of(&union), either, "{a:?} {b:?}");
+                    }
+                    None => assert!(!contiguous, "{a:?} {b:?}"),
+                }
+            }
+        }
+        assert_eq!(span(2..4).union(&span(4..7)), Some(span(2..7)));
+        assert_eq!(span(4..7).union(&span(2..4)), Some(span(2..7)));
+        assert_eq!(span(2..4).union(&span(5..7)), None);
+    }
+
+    #[test]
+    fn span_difference_has_the_frames_on_either_side() {
+        for a in every_span() {
+            for b in every_span() {
+                let (before, after) = a.difference(&b);
+                let left: BTreeSet<u64> =
+                    frames_of(&a).difference(&frames_of(&b)).copied().collect();
+                let parts: Vec<FrameSpan> = before.iter().chain(&after).cloned().collect();
+                assert!(parts.iter().all(|part| !part.is_empty()), "{a:?} {b:?}");
+                assert_eq!(
+                    parts.iter().flat_map(frames_of).collect::<BTreeSet<u64>>(),
+                    left,
+                    "{a:?} {b:?}"
+                );
+                if let Some(before) = before {
+                    assert!(before.to_excl <= b.from || b.is_empty(), "{a:?} {b:?}");
+                }
+                if let Some(after) = after {
+                    assert!(after.from >= b.to_excl || b.is_empty(), "{a:?} {b:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn set_insert_merges_overlapping_and_adjacent_spans() {
+        let set = FrameSpanSet::from_iter([span(0..2), span(4..6), span(2..4), span(8..9)]);
+        assert_eq!(set.spans(), [span(0..6), span(8..9)]);
+        let set = FrameSpanSet::from_iter([span(1..3), span(5..7), span(2..6), span(7..7)]);
+        assert_eq!(set.spans(), [span(1..7)]);
+
+        for (mut set, mut frames) in every_set() {
+            for span in every_span() {
+                let mut set = set.clone();
+                set.insert(span.clone());
+                assert_fewest_spans(&set);
+                let expected: BTreeSet<u64> = frames.union(&frames_of(&span)).copied().collect();
+                assert_eq!(frames_of_set(&set), expected, "{set:?} {span:?}");
+            }
+            set.clear();
+            frames.clear();
+            assert!(set.is_empty());
+        }
+    }
+
+    #[test]
+    fn set_remove_leaves_the_other_frames() {
+        for (set, frames) in every_set() {
+            for span in every_span() {
+                let mut set = set.clone();
+                set.remove(&span);
+                assert_fewest_spans(&set);
+                let expected: BTreeSet<u64> =
+                    frames.difference(&frames_of(&span)).copied().collect();
+                assert_eq!(frames_of_set(&set), expected, "{set:?} {span:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn set_queries_match_the_frames() {
+        for (set, frames) in every_set() {
+            assert_eq!(set.len(), frames.len() as u64);
+            for frame in 0..=LIMIT {
+                assert_eq!(set.contains(FrameNum(frame)), frames.contains(&frame));
+            }
+            for span in every_span() {
+                let span_frames = frames_of(&span);
+                assert_eq!(
+                    set.overlaps(&span),
+                    !span_frames.is_disjoint(&frames),
+                    "{set:?} {span:?}"
+                );
+                assert_eq!(
+                    set.encloses(&span),
+                    span_frames.is_subset(&frames),
+                    "{set:?} {span:?}"
+                );
+                let gaps: BTreeSet<u64> = span_frames.difference(&frames).copied().collect();
+                let found = set.gaps_within(&span);
+                assert_fewest_spans(&found);
+                assert_eq!(frames_of_set(&found), gaps, "{set:?} {span:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn set_operations_match_the_frames() {
+        // Every pair of sets within the first six frames, to keep this quick.
+        let sets: Vec<_> = every_set()
+            .filter(|(_, frames)| frames.iter().all(|frame| *frame < 6))
+            .collect();
+        for (a, a_frames) in &sets {
+            for (b, b_frames) in &sets {
+                let union = a.union(b);
+                let intersection = a.intersection(b);
+                let difference = a.difference(b);
+                for result in [&union, &intersection, &difference] {
+                    assert_fewest_spans(result);
+                }
+                assert_eq!(
+                    frames_of_set(&union),
+                    a_frames.union(b_frames).copied().collect(),
+                    "{a:?} {b:?}"
+                );
+                assert_eq!(
+                    frames_of_set(&intersection),
+                    a_frames.intersection(b_frames).copied().collect(),
+                    "{a:?} {b:?}"
+                );
+                assert_eq!(
+                    frames_of_set(&difference),
+                    a_frames.difference(b_frames).copied().collect(),
+                    "{a:?} {b:?}"
+                );
+            }
         }
     }
 }
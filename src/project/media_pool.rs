@@ -0,0 +1,241 @@
+use super::{MediaInfo, MediaKey};
+use anyhow::ensure;
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+use slotmap::{Key, KeyData};
+use std::{
+    collections::{BTreeMap, btree_map},
+    ops::{Index, IndexMut},
+};
+
+/// The media imported into a project, by key. Works like a `SlotMap`, except
+/// that no key is ever handed out twice, so removed media can be put back
+/// under its old key with `restore`. That way undoing a removal leaves every
+/// reference to the media valid.
+#[derive(Debug, Clone)]
+pub struct MediaPool {
+    entries: BTreeMap<MediaKey, MediaInfo>,
+    /// The slot index the next new key gets. Slot 0 is never used, as in a
+    /// `SlotMap`.
+    next_index: u32,
+}
+
+impl Default for MediaPool {
+    fn default() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            next_index: 1,
+        }
+    }
+}
+
+// Two pools are equal when they hold the same entries under the same keys,
+// however many keys each has handed out.
+impl PartialEq for MediaPool {
+    fn eq(&self, other: &Self) -> bool {
+        self.entries == other.entries
+    }
+}
+
+impl MediaPool {
+    pub fn insert(&mut self, info: MediaInfo) -> MediaKey {
+        let key = KeyData::from_ffi((1 << 32) | self.next_index as u64).into();
+        self.next_index += 1;
+        self.entries.insert(key, info);
+        key
+    }
+
+    /// Puts media back under the key it had before it was removed.
+    pub fn restore(&mut self, key: MediaKey, info: MediaInfo) -> anyhow::Result<()> {
+        let index = key.data().as_ffi() as u32;
+        ensure!(
+            index != 0 && index < self.next_index,
+            "media key was never handed out by this pool"
+        );
+        ensure!(
+            !self.entries.contains_key(&key),
+            "media is already in the pool"
+        );
+        self.entries.insert(key, info);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: MediaKey) -> Option<MediaInfo> {
+        self.entries.remove(&key)
+    }
+
+    pub fn get(&self, key: MediaKey) -> Option<&MediaInfo> {
+        self.entries.get(&key)
+    }
+
+    pub fn get_mut(&mut self, key: MediaKey) -> Option<&mut MediaInfo> {
+        self.entries.get_mut(&key)
+    }
+
+    pub fn contains_key(&self, key: MediaKey) -> bool {
+        self.entries.contains_key(&key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (MediaKey, &MediaInfo)> + '_ {
+        self.entries.iter().map(|(key, info)| (*key, info))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = MediaKey> + '_ {
+        self.entries.keys().copied()
+    }
+
+    pub fn values(&self) -> btree_map::Values<'_, MediaKey, MediaInfo> {
+        self.entries.values()
+    }
+
+    pub fn values_mut(&mut self) -> btree_map::ValuesMut<'_, MediaKey, MediaInfo> {
+        self.entries.values_mut()
+    }
+}
+
+impl Index<MediaKey> for MediaPool {
+    type Output = MediaInfo;
+
+    fn index(&self, key: MediaKey) -> &MediaInfo {
+        self.get(key).expect("no media in the pool with this key")
+    }
+}
+
+impl IndexMut<MediaKey> for MediaPool {
+    fn index_mut(&mut self, key: MediaKey) -> &mut MediaInfo {
+        self.get_mut(key)
+            .expect("no media in the pool with this key")
+    }
+}
+
+impl<'a> IntoIterator for &'a MediaPool {
+    type Item = (MediaKey, &'a MediaInfo);
+    type IntoIter = std::iter::Map<
+        btree_map::Iter<'a, MediaKey, MediaInfo>,
+        fn((&'a MediaKey, &'a MediaInfo)) -> (MediaKey, &'a MediaInfo),
+    >;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter().map(|(key, info)| (*key, info))
+    }
+}
+
+/// One slot of the pool as a `SlotMap` serializes it, so project files keep
+/// the shape they had when the pool was one. Odd versions hold a value.
+#[derive(Serialize, Deserialize)]
+struct PoolSlot<T> {
+    value: Option<T>,
+    version: u32,
+}
+
+impl Serialize for MediaPool {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut slots: Vec<PoolSlot<&MediaInfo>> = (0..self.next_index)
+            .map(|_| PoolSlot {
+                value: None,
+                version: 0,
+            })
+            .collect();
+        for (key, info) in &self.entries {
+            let ffi = key.data().as_ffi();
+            slots[ffi as u32 as usize] = PoolSlot {
+                value: Some(info),
+                version: (ffi >> 32) as u32,
+            };
+        }
+        slots.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for MediaPool {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let slots: Vec<PoolSlot<MediaInfo>> = Deserialize::deserialize(deserializer)?;
+        let next_index = u32::try_from(slots.len().max(1))
+            .map_err(|_| de::Error::custom("too many media pool slots"))?;
+        let mut entries = BTreeMap::new();
+        for (index, slot) in slots.into_iter().enumerate() {
+            match (slot.value, slot.version % 2 == 1) {
+                (Some(_), _) if index == 0 => {
+                    return Err(de::Error::custom("first media pool slot is not empty"));
+                }
+                (Some(info), true) => {
+                    let key = KeyData::from_ffi(((slot.version as u64) << 32) | index as u64);
+                    entries.insert(key.into(), info);
+                }
+                (None, false) => {}
+                _ => return Err(de::Error::custom("inconsistent media pool slot")),
+            }
+        }
+        Ok(Self {
+            entries,
+            next_index,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn info(name: &str) -> MediaInfo {
+        MediaInfo {
+            path: PathBuf::from(format!("/media/{name}.mp4")),
+            name: name.to_string(),
+            fingerprint: None,
+            proxy: None,
+            streams: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn never_hands_out_a_key_twice() {
+        let mut pool = MediaPool::default();
+        let first = pool.insert(info("a"));
+        pool.remove(first);
+        let second = pool.insert(info("b"));
+        assert_ne!(first, second);
+        assert!(!pool.contains_key(first));
+    }
+
+    #[test]
+    fn restores_removed_media_under_its_old_key() {
+        let mut pool = MediaPool::default();
+        let key = pool.insert(info("a"));
+        let removed = pool.remove(key).unwrap();
+        pool.insert(info("b"));
+        pool.restore(key, removed).unwrap();
+        assert_eq!(pool[key].name, "a");
+        assert!(pool.restore(key, info("c")).is_err());
+        assert!(pool.restore(MediaKey::default(), info("c")).is_err());
+    }
+
+    #[test]
+    fn serializes_like_a_slot_map() {
+        let mut slot_map = slotmap::SlotMap::<MediaKey, MediaInfo>::with_key();
+        let kept = slot_map.insert(info("kept"));
+        let removed = slot_map.insert(info("removed"));
+        slot_map.remove(removed);
+        let reused = slot_map.insert(info("reused"));
+
+        let json = serde_json::to_string(&slot_map).unwrap();
+        let mut pool: MediaPool = serde_json::from_str(&json).unwrap();
+        assert_eq!(pool[kept].name, "kept");
+        assert_eq!(pool[reused].name, "reused");
+        assert!(!pool.contains_key(removed));
+        assert_eq!(serde_json::to_string(&pool).unwrap(), json);
+
+        let new = pool.insert(info("new"));
+        assert!(new != kept && new != reused);
+        let reloaded: MediaPool =
+            serde_json::from_str(&serde_json::to_string(&pool).unwrap()).unwrap();
+        assert_eq!(reloaded, pool);
+    }
+}
@@ -1,10 +1,98 @@
-use super::{JadeRational, MediaInfo, MediaKey};
+use super::{
+    ClipId, FrameNum, FrameSpan, JadeRational, MediaKey, MediaPool, MediaStream, Timeline,
+    TrackKind, TrackRef,
+};
+use anyhow::{Context, bail};
 use serde::{Deserialize, Serialize};
-use slotmap::SlotMap;
 
-#[derive(Serialize, Deserialize)]
+/// The sample rate and channel count all audio is resampled to on decode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AudioSpec {
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl AudioSpec {
+    /// The first sample that plays during the given frame, when frames are
+    /// `fps` per second.
+    pub fn first_sample_of_frame(&self, frame: FrameNum, fps: JadeRational) -> u64 {
+        (frame.0 as u128 * self.sample_rate as u128 * fps.den as u128 / fps.num as u128) as u64
+    }
+}
+
+impl Default for AudioSpec {
+    fn default() -> Self {
+        Self {
+            sample_rate: 48_000,
+            channels: 2,
+        }
+    }
+}
+
+/// The size of the frames the project renders to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for Resolution {
+    fn default() -> Self {
+        Self {
+            width: 1920,
+            height: 1080,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct MediaProject {
     pub fps: JadeRational,
     pub frame_count: u32,
-    pub media: SlotMap<MediaKey, MediaInfo>,
+    pub resolution: Resolution,
+    pub audio: AudioSpec,
+    pub media: MediaPool,
+    pub timeline: Timeline,
+}
+
+impl MediaProject {
+    /// Places a stream of a media pool entry onto the timeline, making sure the
+    /// stream exists and matches the kind of track it is going onto.
+    pub fn add_clip(
+        &mut self,
+        track: TrackRef,
+        media: MediaKey,
+        stream_index: usize,
+        source: FrameSpan,
+        timeline_start: FrameNum,
+    ) -> anyhow::Result<ClipId> {
+        self.check_stream_fits_track(track, media, stream_index)?;
+        self.timeline
+            .add_clip(track, media, stream_index, source, timeline_start)
+    }
+
+    pub fn check_stream_fits_track(
+        &self,
+        track: TrackRef,
+        media: MediaKey,
+        stream_index: usize,
+    ) -> anyhow::Result<()> {
+        let info = self
+            .media
+            .get(media)
+            .context("clip references media that is not in the project")?;
+        let stream = info
+            .streams
+            .iter()
+            .find(|stream| stream.info().index == stream_index)
+            .with_context(|| format!("media has no stream at index {stream_index}"))?;
+        match (stream, track.kind) {
+            (MediaStream::Video(..), TrackKind::Video)
+            | (MediaStream::Audio(..), TrackKind::Audio) => Ok(()),
+            _ => bail!(
+                "stream {stream_index} cannot be placed on a {:?} track",
+                track.kind
+            ),
+        }
+    }
 }
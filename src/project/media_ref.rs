@@ -1,4 +1,4 @@
-use super::JadeRational;
+use super::{JadeRational, MediaFingerprint, Rounding};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
@@ -6,8 +6,27 @@ slotmap::new_key_type! { pub struct MediaKey; }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MediaInfo {
+    /// Shown in the media pool. Starts out as the file name, but can be
+    /// renamed freely.
+    pub name: String,
     pub path: PathBuf,
+    /// Recognizes the file if it moves, is replaced, or is imported again.
+    /// `None` for media imported before fingerprints were taken.
+    pub fingerprint: Option<MediaFingerprint>,
     pub streams: Vec<MediaStream>,
+    /// A lighter copy of the video to decode for previews instead, if one
+    /// has been made. Exports always use `path`.
+    pub proxy: Option<ProxyMedia>,
+}
+
+/// A low resolution, intra-frame only copy of one of a file's video streams,
+/// which is much cheaper to decode and seek in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProxyMedia {
+    pub path: PathBuf,
+    /// The stream of the original file the proxy was made from. The proxy
+    /// file holds just that stream.
+    pub stream_index: usize,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -22,11 +41,51 @@ pub struct BasicStreamInfo {
     pub length: MediaLength,
 }
 
+/// How a video stream's pixel values map to colors. Each is FFmpeg's name for
+/// the value, or `None` if the file doesn't say.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColorInfo {
+    pub space: Option<String>,
+    pub primaries: Option<String>,
+    pub transfer: Option<String>,
+    pub range: Option<String>,
+}
+
+/// Details probed from a video stream. Anything unknown is `None` (or zero for
+/// the frame size), including for media imported before it was recorded.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub struct VideoMediaStream {}
+pub struct VideoMediaStream {
+    pub codec: Option<String>,
+    pub profile: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    pub sample_aspect_ratio: Option<JadeRational>,
+    pub pixel_format: Option<String>,
+    pub color: ColorInfo,
+    /// Degrees the picture should be turned clockwise to display upright, as
+    /// phones record.
+    pub rotation: i32,
+    /// Frames per second averaged over the whole stream.
+    pub avg_frame_rate: Option<JadeRational>,
+    /// The lowest frame rate every timestamp fits on, which differs from the
+    /// average when the frame rate varies.
+    pub real_frame_rate: Option<JadeRational>,
+    pub variable_frame_rate: bool,
+}
 
+/// Details probed from an audio stream. Anything unknown is `None` (or zero
+/// for the counts), including for media imported before it was recorded.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub struct AudioMediaStream {}
+pub struct AudioMediaStream {
+    pub codec: Option<String>,
+    pub profile: Option<String>,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub channel_layout: Option<String>,
+    pub sample_format: Option<String>,
+    /// Bits per sample in the source, before decoding.
+    pub bit_depth: Option<u32>,
+}
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum MediaStream {
@@ -34,6 +93,78 @@ pub enum MediaStream {
     Audio(BasicStreamInfo, AudioMediaStream),
 }
 
+impl MediaInfo {
+    pub fn video_streams(&self) -> impl Iterator<Item = (&BasicStreamInfo, &VideoMediaStream)> {
+        self.streams.iter().filter_map(|stream| match stream {
+            MediaStream::Video(info, video) => Some((info, video)),
+            MediaStream::Audio(..) => None,
+        })
+    }
+
+    pub fn audio_streams(&self) -> impl Iterator<Item = (&BasicStreamInfo, &AudioMediaStream)> {
+        self.streams.iter().filter_map(|stream| match stream {
+            MediaStream::Audio(info, audio) => Some((info, audio)),
+            MediaStream::Video(..) => None,
+        })
+    }
+
+    /// Whether both are known to be the same file's content, wherever it is.
+    pub fn same_content_as(&self, other: &MediaInfo) -> bool {
+        match (&self.fingerprint, &other.fingerprint) {
+            (Some(a), Some(b)) => a.same_content(b),
+            _ => false,
+        }
+    }
+
+    /// The proxy to decode in place of the given stream, if there is one.
+    pub fn proxy_for(&self, stream_index: usize) -> Option<&ProxyMedia> {
+        self.proxy
+            .as_ref()
+            .filter(|proxy| proxy.stream_index == stream_index)
+    }
+
+    /// The length of the longest stream, in seconds.
+    pub fn duration_secs(&self) -> f64 {
+        self.streams
+            .iter()
+            .map(|stream| stream.info().length.secs())
+            .fold(0.0, f64::max)
+    }
+}
+
+impl VideoMediaStream {
+    /// The shape of the picture as displayed, accounting for non-square
+    /// pixels but not rotation.
+    pub fn display_aspect_ratio(&self) -> Option<f64> {
+        if self.width == 0 || self.height == 0 {
+            return None;
+        }
+        let pixel_aspect = self
+            .sample_aspect_ratio
+            .map(|sar| sar.num as f64 / sar.den as f64)
+            .unwrap_or(1.0);
+        Some(self.width as f64 * pixel_aspect / self.height as f64)
+    }
+}
+
+impl MediaLength {
+    pub fn secs(&self) -> f64 {
+        self.time_base_length as f64 * self.time_base.num as f64 / self.time_base.den as f64
+    }
+
+    /// How many whole frames fit in this length at the given frame rate.
+    pub fn frames_at(&self, fps: JadeRational) -> u64 {
+        JadeRational::rescale(
+            self.time_base_length as i64,
+            self.time_base,
+            fps.recip(),
+            Rounding::Down,
+        )
+        .unwrap_or(0)
+        .max(0) as u64
+    }
+}
+
 impl MediaStream {
     #[allow(unused)]
     pub fn info(&self) -> BasicStreamInfo {
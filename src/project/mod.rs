@@ -1,11 +1,26 @@
+mod edit_command;
+mod fingerprint;
 mod framenum;
 mod framespan;
+mod media_pool;
 mod media_project;
 mod media_ref;
+mod project_file;
 mod rational;
+mod relink;
+mod timecode;
+mod timeline;
+mod undo_stack;
 
+pub use edit_command::*;
+pub use fingerprint::*;
 pub use framenum::*;
 pub use framespan::*;
+pub use media_pool::*;
 pub use media_project::*;
 pub use media_ref::*;
+pub use project_file::*;
 pub use rational::*;
+pub use relink::*;
+pub use timeline::*;
+pub use undo_stack::*;
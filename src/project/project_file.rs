@@ -0,0 +1,490 @@
+use super::MediaProject;
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+pub const PROJECT_FILE_EXTENSION: &str = "jvp";
+
+/// Bump this whenever the serialized shape of `MediaProject` changes, and add a
+/// migration from the previous version to `MIGRATIONS`.
+pub const CURRENT_FORMAT_VERSION: u32 = 7;
+
+/// Upgrades the JSON of a project file in-place from one format version to the
+/// next. `MIGRATIONS[0]` takes a version 1 file to version 2, and so on.
+type Migration = fn(&mut Value) -> anyhow::Result<()>;
+
+const MIGRATIONS: &[Migration] = &[
+    add_resolution,
+    add_media_names_and_sizes,
+    add_stream_details,
+    add_proxies,
+    add_file_sizes,
+    add_fingerprints,
+];
+
+fn project_object(value: &mut Value) -> anyhow::Result<&mut Map<String, Value>> {
+    value
+        .get_mut("project")
+        .and_then(Value::as_object_mut)
+        .context("project file has no project object")
+}
+
+/// Version 2 gave projects an output resolution.
+fn add_resolution(value: &mut Value) -> anyhow::Result<()> {
+    project_object(value)?
+        .entry("resolution")
+        .or_insert(json!({ "width": 1920, "height": 1080 }));
+    Ok(())
+}
+
+/// The media pool entries, skipping the empty slots `MediaPool` serializes.
+fn media_objects(value: &mut Value) -> anyhow::Result<Vec<&mut Map<String, Value>>> {
+    let slots = project_object(value)?
+        .get_mut("media")
+        .and_then(Value::as_array_mut)
+        .context("project has no media pool")?;
+    Ok(slots
+        .iter_mut()
+        .filter_map(|slot| slot.get_mut("value").and_then(Value::as_object_mut))
+        .collect())
+}
+
+/// Version 3 gave media a display name, and video streams their frame size.
+/// The size of already imported streams isn't known, so it's left as zero.
+fn add_media_names_and_sizes(value: &mut Value) -> anyhow::Result<()> {
+    for media in media_objects(value)? {
+        let name = media
+            .get("path")
+            .and_then(Value::as_str)
+            .map(Path::new)
+            .and_then(Path::file_name)
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        media.entry("name").or_insert(Value::String(name));
+
+        let streams = media.get_mut("streams").and_then(Value::as_array_mut);
+        for stream in streams.into_iter().flatten() {
+            if let Some(Value::Array(fields)) = stream.get_mut("Video")
+                && let Some(Value::Object(video)) = fields.get_mut(1)
+            {
+                video.entry("width").or_insert(json!(0));
+                video.entry("height").or_insert(json!(0));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Version 4 recorded codec, format and color details for every stream. None
+/// of it is known for media already imported, so it's all left unknown.
+fn add_stream_details(value: &mut Value) -> anyhow::Result<()> {
+    let video_defaults = json!({
+        "codec": null,
+        "profile": null,
+        "sample_aspect_ratio": null,
+        "pixel_format": null,
+        "color": { "space": null, "primaries": null, "transfer": null, "range": null },
+        "rotation": 0,
+        "avg_frame_rate": null,
+        "real_frame_rate": null,
+        "variable_frame_rate": false,
+    });
+    let audio_defaults = json!({
+        "codec": null,
+        "profile": null,
+        "sample_rate": 0,
+        "channels": 0,
+        "channel_layout": null,
+        "sample_format": null,
+        "bit_depth": null,
+    });
+
+    for media in media_objects(value)? {
+        let streams = media.get_mut("streams").and_then(Value::as_array_mut);
+        for stream in streams.into_iter().flatten() {
+            for (kind, defaults) in [("Video", &video_defaults), ("Audio", &audio_defaults)] {
+                if let Some(Value::Array(fields)) = stream.get_mut(kind)
+                    && let Some(Value::Object(details)) = fields.get_mut(1)
+                    && let Value::Object(defaults) = defaults
+                {
+                    for (field, default) in defaults {
+                        details.entry(field).or_insert_with(|| default.clone());
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Version 5 let media have a proxy for previews. None have one yet.
+fn add_proxies(value: &mut Value) -> anyhow::Result<()> {
+    for media in media_objects(value)? {
+        media.entry("proxy").or_insert(Value::Null);
+    }
+    Ok(())
+}
+
+/// Version 6 recorded the size of media files, for finding them again when
+/// they move. The size of media already imported isn't known.
+fn add_file_sizes(value: &mut Value) -> anyhow::Result<()> {
+    for media in media_objects(value)? {
+        media.entry("file_size").or_insert(Value::Null);
+    }
+    Ok(())
+}
+
+/// Version 7 replaced media file sizes with fingerprints, which include the
+/// size. A fingerprint can't be made without the file, so sizes recorded by
+/// version 6 are dropped.
+fn add_fingerprints(value: &mut Value) -> anyhow::Result<()> {
+    for media in media_objects(value)? {
+        media.remove("file_size");
+        media.entry("fingerprint").or_insert(Value::Null);
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct ProjectFileOut<'a> {
+    format_version: u32,
+    project: &'a MediaProject,
+}
+
+#[derive(Deserialize)]
+struct ProjectFileHeader {
+    format_version: u32,
+}
+
+#[derive(Deserialize)]
+struct ProjectFileIn {
+    project: MediaProject,
+}
+
+/// Writes the project to disk, storing media paths relative to the project
+/// file where it's possible to do so. Proxy paths are left absolute, since
+/// they point into the cache folder rather than somewhere near the project.
+pub fn save_project(project: &MediaProject, path: &Path) -> anyhow::Result<()> {
+    let project_dir = project_dir(path)?;
+    let mut project = project.clone();
+    for info in project.media.values_mut() {
+        if let Some(relative) = relative_path(&project_dir, &info.path) {
+            info.path = relative;
+        }
+    }
+
+    let json = serde_json::to_string_pretty(&ProjectFileOut {
+        format_version: CURRENT_FORMAT_VERSION,
+        project: &project,
+    })
+    .context("failed to serialize project")?;
+
+    // Write next to the destination and swap it in, so a failed save never
+    // leaves a half-written project behind.
+    let temp_path = path.with_extension(format!("{PROJECT_FILE_EXTENSION}.tmp"));
+    fs::write(&temp_path, json)
+        .with_context(|| format!("failed to write project to {}", temp_path.display()))?;
+    fs::rename(&temp_path, path)
+        .with_context(|| format!("failed to move project into place at {}", path.display()))?;
+    Ok(())
+}
+
+/// Reads a project from disk, migrating it from older format versions and
+/// resolving relative media paths against the project file's directory.
+pub fn load_project(path: &Path) -> anyhow::Result<MediaProject> {
+    let json = fs::read_to_string(path)
+        .with_context(|| format!("failed to read project file {}", path.display()))?;
+    let mut value: Value = serde_json::from_str(&json).context("project file is not valid JSON")?;
+
+    let ProjectFileHeader { format_version } = ProjectFileHeader::deserialize(&value)
+        .context("project file is missing its format version")?;
+    if format_version == 0 || format_version > CURRENT_FORMAT_VERSION {
+        bail!(
+            "project file has format version {format_version}, but this version of {} only \
+             supports up to {CURRENT_FORMAT_VERSION}",
+            env!("CARGO_PKG_NAME")
+        );
+    }
+    for (index, migration) in MIGRATIONS
+        .iter()
+        .enumerate()
+        .skip(format_version as usize - 1)
+    {
+        let from_version = index + 1;
+        migration(&mut value).with_context(|| {
+            format!(
+                "failed to migrate project from format version {from_version} to {}",
+                from_version + 1
+            )
+        })?;
+    }
+
+    let ProjectFileIn { mut project } =
+        ProjectFileIn::deserialize(value).context("failed to deserialize project")?;
+    let project_dir = project_dir(path)?;
+    for info in project.media.values_mut() {
+        if info.path.is_relative() {
+            info.path = project_dir.join(&info.path);
+        }
+    }
+    Ok(project)
+}
+
+fn project_dir(project_path: &Path) -> anyhow::Result<PathBuf> {
+    let project_path = std::path::absolute(project_path)
+        .context("failed to resolve absolute path of project file")?;
+    project_path
+        .parent()
+        .map(Path::to_path_buf)
+        .context("project file has no parent directory")
+}
+
+/// Expresses `path` relative to `base_dir`, or `None` if the two have nothing
+/// but the filesystem root in common.
+fn relative_path(base_dir: &Path, path: &Path) -> Option<PathBuf> {
+    if !base_dir.is_absolute() || !path.is_absolute() {
+        return None;
+    }
+
+    let mut base_components = base_dir.components().peekable();
+    let mut path_components = path.components().peekable();
+    let mut shared_dirs = 0;
+    while let (Some(a), Some(b)) = (base_components.peek(), path_components.peek()) {
+        if a != b {
+            break;
+        }
+        if matches!(a, Component::Normal(_)) {
+            shared_dirs += 1;
+        }
+        base_components.next();
+        path_components.next();
+    }
+    if shared_dirs == 0 {
+        return None;
+    }
+
+    let mut relative = PathBuf::new();
+    for _ in base_components {
+        relative.push(Component::ParentDir);
+    }
+    relative.extend(path_components);
+    Some(relative)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        project::{
+            AudioSpec, FrameNum, JadeRational, MediaInfo, MediaPool, Resolution, Timeline,
+            TrackKind,
+        },
+        test_media,
+    };
+
+    fn project() -> MediaProject {
+        MediaProject {
+            fps: JadeRational::new(30, 1),
+            frame_count: 300,
+            resolution: Resolution::default(),
+            audio: AudioSpec::default(),
+            media: MediaPool::default(),
+            timeline: Timeline::default(),
+        }
+    }
+
+    fn media(path: impl Into<PathBuf>) -> MediaInfo {
+        MediaInfo {
+            name: "clip".to_string(),
+            path: path.into(),
+            fingerprint: None,
+            streams: Vec::new(),
+            proxy: None,
+        }
+    }
+
+    fn write_json(path: &Path, value: &Value) {
+        fs::write(path, serde_json::to_string_pretty(value).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn migrates_a_version_1_project() {
+        let dir = test_media::temp_dir("project-v1");
+        let path = dir.join("old.jvp");
+        let length = json!({ "time_base_length": 150, "time_base": { "num": 1, "den": 30 } });
+        write_json(
+            &path,
+            &json!({
+                "format_version": 1,
+                "project": {
+                    "fps": { "num": 30, "den": 1 },
+                    "frame_count": 150,
+                    "audio": { "sample_rate": 48000, "channels": 2 },
+                    "media": [
+                        { "value": null, "version": 0 },
+                        {
+                            "value": {
+                                "path": "clips/shot.mp4",
+                                "streams": [
+                                    { "Video": [{ "index": 0, "length": length }, {}] },
+                                    { "Audio": [{ "index": 1, "length": length }, {}] },
+                                ],
+                            },
+                            "version": 1,
+                        },
+                    ],
+                    "timeline": {
+                        "video_tracks": [{
+                            "name": "V1",
+                            "clips": [{
+                                "id": 0,
+                                "media": { "idx": 1, "version": 1 },
+                                "stream_index": 0,
+                                "source": { "from": 0, "to_excl": 150 },
+                                "timeline_start": 0,
+                            }],
+                        }],
+                        "audio_tracks": [{ "name": "A1", "clips": [] }],
+                        "next_clip_id": 1,
+                    },
+                },
+            }),
+        );
+
+        let project = load_project(&path).unwrap();
+        assert_eq!(project.resolution, Resolution::default());
+        let (key, info) = project.media.iter().next().unwrap();
+        assert_eq!(info.name, "shot.mp4");
+        assert_eq!(info.path, dir.join("clips/shot.mp4"));
+        assert_eq!(info.fingerprint, None);
+        assert_eq!(info.proxy, None);
+
+        let (_, video) = info.video_streams().next().unwrap();
+        assert_eq!((video.width, video.height), (0, 0));
+        assert_eq!(video.codec, None);
+        assert_eq!(video.rotation, 0);
+        assert!(!video.variable_frame_rate);
+        let (_, audio) = info.audio_streams().next().unwrap();
+        assert_eq!((audio.sample_rate, audio.channels), (0, 0));
+        assert_eq!(audio.bit_depth, None);
+
+        let clip = &project.timeline.tracks(TrackKind::Video)[0].clips()[0];
+        assert_eq!(clip.media, key);
+        assert_eq!(clip.timeline_span().to_excl, FrameNum(150));
+    }
+
+    #[test]
+    fn drops_version_6_file_sizes() {
+        let dir = test_media::temp_dir("project-v6");
+        let path = dir.join("old.jvp");
+        let mut project = project();
+        project.media.insert(media(dir.join("clip.mp4")));
+
+        let mut value = json!({ "format_version": 6, "project": &project });
+        for info in media_objects(&mut value).unwrap() {
+            info.remove("fingerprint");
+            info.insert("file_size".to_string(), json!(1234));
+        }
+        write_json(&path, &value);
+
+        let loaded = load_project(&path).unwrap();
+        assert_eq!(loaded, project);
+
+        save_project(&loaded, &path).unwrap();
+        let saved: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let saved_media = &saved["project"]["media"][1]["value"];
+        assert!(saved_media.get("file_size").is_none());
+        assert_eq!(saved_media["fingerprint"], Value::Null);
+    }
+
+    #[test]
+    fn rejects_unknown_format_versions() {
+        let dir = test_media::temp_dir("project-versions");
+        let path = dir.join("project.jvp");
+        for version in [0, CURRENT_FORMAT_VERSION + 1] {
+            write_json(
+                &path,
+                &json!({ "format_version": version, "project": &project() }),
+            );
+            let error = load_project(&path).unwrap_err();
+            assert!(
+                error
+                    .to_string()
+                    .contains(&format!("format version {version}")),
+                "{error:#}"
+            );
+        }
+
+        write_json(
+            &path,
+            &json!({ "format_version": CURRENT_FORMAT_VERSION, "project": &project() }),
+        );
+        assert_eq!(load_project(&path).unwrap(), project());
+    }
+
+    #[test]
+    fn finds_relative_paths() {
+        let base = Path::new("/home/me/projects/film");
+        assert_eq!(
+            relative_path(base, Path::new("/home/me/projects/film/media/a.mp4")),
+            Some(PathBuf::from("media/a.mp4"))
+        );
+        assert_eq!(
+            relative_path(base, Path::new("/home/me/projects/footage/b.mp4")),
+            Some(PathBuf::from("../footage/b.mp4"))
+        );
+        assert_eq!(
+            relative_path(base, Path::new("/home/me/c.mp4")),
+            Some(PathBuf::from("../../c.mp4"))
+        );
+        assert_eq!(relative_path(base, Path::new("/mnt/d.mp4")), None);
+        assert_eq!(relative_path(base, Path::new("e.mp4")), None);
+        assert_eq!(relative_path(Path::new("film"), Path::new("/e.mp4")), None);
+    }
+
+    #[test]
+    fn round_trips_media_paths() {
+        let dir = test_media::temp_dir("project-paths");
+        let project_dir = dir.join("film");
+        fs::create_dir_all(&project_dir).unwrap();
+        let path = project_dir.join("film.jvp");
+
+        let inside = project_dir.join("media").join("inside.mp4");
+        let sibling = dir.join("footage").join("sibling.mp4");
+        let other_root = Path::new("/jadevid-elsewhere/other.mp4").to_path_buf();
+        let mut project = project();
+        for media_path in [&inside, &sibling, &other_root] {
+            project.media.insert(media(media_path));
+        }
+        save_project(&project, &path).unwrap();
+
+        let saved: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let saved_paths: Vec<_> = (1..=3)
+            .map(|slot| saved["project"]["media"][slot]["value"]["path"].clone())
+            .collect();
+        assert_eq!(
+            saved_paths,
+            [
+                json!("media/inside.mp4"),
+                json!("../footage/sibling.mp4"),
+                json!("/jadevid-elsewhere/other.mp4"),
+            ]
+        );
+        assert!(!path.with_extension("jvp.tmp").exists());
+
+        let loaded = load_project(&path).unwrap();
+        let loaded_paths: Vec<_> = loaded.media.values().map(|info| &info.path).collect();
+        assert_eq!(
+            loaded_paths,
+            [
+                &inside,
+                &project_dir.join("../footage/sibling.mp4"),
+                &other_root
+            ]
+        );
+    }
+}
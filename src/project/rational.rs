@@ -1,16 +1,228 @@
 use ffmpeg_next::Rational;
 use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Ordering,
+    hash::{Hash, Hasher},
+};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// A fraction, used for frame rates and time bases. Two rationals are equal
+/// when their values are, so `30000/1001 == 60000/2002`. The denominator
+/// should never be zero, except where FFmpeg uses `0/0` for "unknown".
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct JadeRational {
     pub num: i32,
     pub den: i32,
 }
 
+/// How `JadeRational::rescale` rounds results that fall between integers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Rounding {
+    /// Towards negative infinity.
+    Down,
+    /// Towards positive infinity.
+    Up,
+    /// To the nearest integer, with halves away from zero.
+    Nearest,
+}
+
+fn gcd(mut a: i64, mut b: i64) -> i64 {
+    (a, b) = (a.abs(), b.abs());
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Divides `num` by `den` (which must be positive), rounding as asked.
+fn div_rounded(num: i128, den: i128, rounding: Rounding) -> i128 {
+    let down = num.div_euclid(den);
+    let remainder = num.rem_euclid(den);
+    match rounding {
+        Rounding::Down => down,
+        Rounding::Up if remainder == 0 => down,
+        Rounding::Up => down + 1,
+        Rounding::Nearest => match (remainder * 2).cmp(&den) {
+            Ordering::Less => down,
+            Ordering::Greater => down + 1,
+            Ordering::Equal if num < 0 => down,
+            Ordering::Equal => down + 1,
+        },
+    }
+}
+
 impl JadeRational {
+    pub const ZERO: Self = Self { num: 0, den: 1 };
+    pub const ONE: Self = Self { num: 1, den: 1 };
+
+    /// Makes a rational in its reduced form.
+    pub fn new(num: i32, den: i32) -> Self {
+        Self { num, den }.reduced()
+    }
+
     pub fn ff_rational(self) -> Rational {
         self.into()
     }
+
+    /// The same value with no common factors, and the sign on the numerator.
+    /// `0/0` is left alone.
+    pub fn reduced(self) -> Self {
+        Self::from_i64(self.num as i64, self.den as i64).unwrap_or(self)
+    }
+
+    /// Reduces `num/den` into a rational, if it fits once reduced.
+    fn from_i64(num: i64, den: i64) -> Option<Self> {
+        let divisor = gcd(num, den);
+        if divisor == 0 {
+            return None;
+        }
+        let sign = if den < 0 { -1 } else { 1 };
+        Some(Self {
+            num: (num / divisor * sign).try_into().ok()?,
+            den: (den / divisor * sign).try_into().ok()?,
+        })
+    }
+
+    pub fn recip(self) -> Self {
+        Self {
+            num: self.den,
+            den: self.num,
+        }
+        .reduced()
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        Self::from_i64(
+            self.num as i64 * other.den as i64 + other.num as i64 * self.den as i64,
+            self.den as i64 * other.den as i64,
+        )
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        Self::from_i64(
+            self.num as i64 * other.den as i64 - other.num as i64 * self.den as i64,
+            self.den as i64 * other.den as i64,
+        )
+    }
+
+    pub fn checked_mul(self, other: Self) -> Option<Self> {
+        Self::from_i64(
+            self.num as i64 * other.num as i64,
+            self.den as i64 * other.den as i64,
+        )
+    }
+
+    /// `None` when dividing by zero, as well as on overflow.
+    pub fn checked_div(self, other: Self) -> Option<Self> {
+        if other.num == 0 {
+            return None;
+        }
+        Self::from_i64(
+            self.num as i64 * other.den as i64,
+            self.den as i64 * other.num as i64,
+        )
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+
+    /// The closest rational to `value` with a denominator no larger than
+    /// `max_den`, or `None` if it's not finite or too large to represent.
+    pub fn from_f64(value: f64, max_den: i32) -> Option<Self> {
+        if !value.is_finite() || value.abs() > i32::MAX as f64 || max_den < 1 {
+            return None;
+        }
+        let (max_num, max_den) = (i32::MAX as i64, max_den as i64);
+        let target = value.abs();
+
+        // Walk the continued fraction of the value, keeping the last two
+        // convergents, until the next one would be too large.
+        let (mut prev_num, mut prev_den) = (0i64, 1i64);
+        let (mut num, mut den) = (1i64, 0i64);
+        let mut remainder = target;
+        loop {
+            let term = remainder.floor();
+            // The term is checked before multiplying, since the remainder can
+            // be huge once it's down to rounding error.
+            let next = (term <= max_num as f64)
+                .then(|| (term as i64 * num + prev_num, term as i64 * den + prev_den))
+                .filter(|&(next_num, next_den)| next_num <= max_num && next_den <= max_den);
+            let Some((next_num, next_den)) = next else {
+                // The best approximation may be a semiconvergent between the
+                // last convergent and the next.
+                let steps =
+                    ((max_den - prev_den) / den.max(1)).min((max_num - prev_num) / num.max(1));
+                let (semi_num, semi_den) = (steps * num + prev_num, steps * den + prev_den);
+                let error = |num: i64, den: i64| (num as f64 / den as f64 - target).abs();
+                if steps > 0 && semi_den > 0 && error(semi_num, semi_den) < error(num, den) {
+                    (num, den) = (semi_num, semi_den);
+                }
+                break;
+            };
+            (prev_num, prev_den, num, den) = (num, den, next_num, next_den);
+
+            let fraction = remainder - term;
+            if fraction == 0.0 || num as f64 / den as f64 == target {
+                break;
+            }
+            remainder = 1.0 / fraction;
+        }
+
+        let sign = if value < 0.0 { -1 } else { 1 };
+        Self::from_i64(num * sign, den)
+    }
+
+    /// Converts a count of `from` units into `to` units, like FFmpeg's
+    /// `av_rescale_q_rnd`. Intermediate results can't overflow; `None` is
+    /// only returned when the result doesn't fit in an `i64`, or `to` is zero.
+    pub fn rescale(value: i64, from: Self, to: Self, rounding: Rounding) -> Option<i64> {
+        let num = value as i128 * from.num as i128 * to.den as i128;
+        let den = from.den as i128 * to.num as i128;
+        if den == 0 {
+            return None;
+        }
+        let (num, den) = if den < 0 { (-num, -den) } else { (num, den) };
+        div_rounded(num, den, rounding).try_into().ok()
+    }
+}
+
+impl PartialEq for JadeRational {
+    fn eq(&self, other: &Self) -> bool {
+        let (a, b) = (self.reduced(), other.reduced());
+        a.num == b.num && a.den == b.den
+    }
+}
+
+impl Eq for JadeRational {}
+
+impl Hash for JadeRational {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let reduced = self.reduced();
+        reduced.num.hash(state);
+        reduced.den.hash(state);
+    }
+}
+
+/// Orders by value, with FFmpeg's "unknown" `0/0` before everything else, and
+/// a zero denominator otherwise counting as infinity of the numerator's sign.
+impl Ord for JadeRational {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (a, b) = (self.reduced(), other.reduced());
+        let is_unknown = |value: Self| value.num == 0 && value.den == 0;
+        match (is_unknown(a), is_unknown(b)) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            _ if a.den == 0 && b.den == 0 => a.num.signum().cmp(&b.num.signum()),
+            _ => (a.num as i64 * b.den as i64).cmp(&(b.num as i64 * a.den as i64)),
+        }
+    }
+}
+
+impl PartialOrd for JadeRational {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
 impl From<JadeRational> for Rational {
@@ -27,3 +239,155 @@ impl From<Rational> for JadeRational {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn r(num: i32, den: i32) -> JadeRational {
+        JadeRational { num, den }
+    }
+
+    #[test]
+    fn equal_values_are_equal_and_hash_alike() {
+        assert_eq!(r(30000, 1001), r(60000, 2002));
+        assert_eq!(r(1, -2), r(-1, 2));
+        assert_ne!(r(1, 2), r(2, 1));
+        let set: HashSet<_> = [r(30000, 1001), r(60000, 2002), r(-30000, -1001)].into();
+        assert_eq!(set.len(), 1);
+        assert_eq!(r(6, -4).reduced(), JadeRational { num: -3, den: 2 });
+        assert_eq!(r(0, 0).reduced(), JadeRational { num: 0, den: 0 });
+    }
+
+    #[test]
+    fn orders_by_value() {
+        let mut values = vec![
+            r(1, 2),
+            r(-1, 3),
+            r(30000, 1001),
+            r(30, 1),
+            r(0, 5),
+            r(2, 4),
+        ];
+        values.sort();
+        assert_eq!(
+            values,
+            [
+                r(-1, 3),
+                r(0, 1),
+                r(1, 2),
+                r(1, 2),
+                r(30000, 1001),
+                r(30, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn orders_unknown_and_infinite_values_consistently() {
+        let unknown = r(0, 0);
+        assert_eq!(unknown.cmp(&unknown), Ordering::Equal);
+        for value in [r(-1, 0), r(-5, 1), r(0, 1), r(7, 3), r(1, 0)] {
+            assert_eq!(unknown.cmp(&value), Ordering::Less, "{value:?}");
+            assert_eq!(value.cmp(&unknown), Ordering::Greater, "{value:?}");
+            assert_eq!(value.cmp(&value), Ordering::Equal, "{value:?}");
+        }
+        assert!(r(-1, 0) < r(i32::MIN + 1, 1));
+        assert!(r(1, 0) > r(i32::MAX, 1));
+        assert!(r(-3, 0) < r(3, 0));
+        assert_eq!(r(2, 0).cmp(&r(5, 0)), Ordering::Equal);
+    }
+
+    #[test]
+    fn does_checked_arithmetic() {
+        assert_eq!(r(1, 2).checked_add(r(1, 3)), Some(r(5, 6)));
+        assert_eq!(r(1, 2).checked_sub(r(1, 3)), Some(r(1, 6)));
+        assert_eq!(r(2, 3).checked_mul(r(3, 4)), Some(r(1, 2)));
+        assert_eq!(r(1, 2).checked_div(r(1, 4)), Some(r(2, 1)));
+        assert_eq!(r(1, 2).checked_div(JadeRational::ZERO), None);
+        assert_eq!(r(i32::MAX, 1).checked_mul(r(2, 1)), None);
+        assert_eq!(r(1001, 30000).recip(), r(30000, 1001));
+    }
+
+    #[test]
+    fn approximates_floats() {
+        assert_eq!(JadeRational::from_f64(0.5, 100), Some(r(1, 2)));
+        assert_eq!(JadeRational::from_f64(-0.75, 100), Some(r(-3, 4)));
+        assert_eq!(
+            JadeRational::from_f64(30000.0 / 1001.0, 1001),
+            Some(r(30000, 1001))
+        );
+        assert_eq!(JadeRational::from_f64(29.97, 100), Some(r(2997, 100)));
+        assert_eq!(
+            JadeRational::from_f64(std::f64::consts::PI, 1000),
+            Some(r(355, 113))
+        );
+        // A semiconvergent beats the last convergent here.
+        assert_eq!(
+            JadeRational::from_f64(std::f64::consts::PI, 110),
+            Some(r(333, 106))
+        );
+        assert_eq!(
+            JadeRational::from_f64(1e-12, 1000),
+            Some(JadeRational::ZERO)
+        );
+        assert_eq!(JadeRational::from_f64(f64::NAN, 1000), None);
+        assert_eq!(JadeRational::from_f64(f64::INFINITY, 1000), None);
+        assert_eq!(JadeRational::from_f64(1e10, 1000), None);
+        assert_eq!(JadeRational::from_f64(0.5, 0), None);
+    }
+
+    #[test]
+    fn approximates_any_float_without_overflowing() {
+        let mut value = 0.001f64;
+        while value < 1e9 {
+            for max_den in [1, 1001, 1 << 20, i32::MAX] {
+                let approx = JadeRational::from_f64(value, max_den).unwrap();
+                assert!(
+                    approx.den >= 1 && approx.den <= max_den,
+                    "{value} {approx:?}"
+                );
+                let error = (approx.to_f64() - value).abs();
+                assert!(
+                    error <= 0.5 / approx.den as f64 + 1e-9,
+                    "{value} {approx:?}"
+                );
+            }
+            value = value * 1.37 + 0.000_123;
+        }
+    }
+
+    #[test]
+    fn rescales_with_rounding() {
+        let (ms, ntsc) = (r(1, 1000), r(1001, 30000));
+        assert_eq!(
+            JadeRational::rescale(1001, ms, ntsc, Rounding::Down),
+            Some(30)
+        );
+        assert_eq!(
+            JadeRational::rescale(1000, ms, ntsc, Rounding::Down),
+            Some(29)
+        );
+        assert_eq!(
+            JadeRational::rescale(1000, ms, ntsc, Rounding::Up),
+            Some(30)
+        );
+        assert_eq!(
+            JadeRational::rescale(-1, r(1, 2), r(1, 1), Rounding::Nearest),
+            Some(-1)
+        );
+        assert_eq!(
+            JadeRational::rescale(1, r(1, 2), r(1, 1), Rounding::Nearest),
+            Some(1)
+        );
+        assert_eq!(
+            JadeRational::rescale(5, ms, JadeRational::ZERO, Rounding::Down),
+            None
+        );
+        assert_eq!(
+            JadeRational::rescale(i64::MAX, r(2, 1), r(1, 1), Rounding::Down),
+            None
+        );
+    }
+}
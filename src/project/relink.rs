@@ -0,0 +1,360 @@
+use super::{MediaInfo, MediaKey, MediaProject, MediaStream};
+use std::path::{Path, PathBuf};
+
+/// How far apart the lengths of a missing file and a candidate for it may be,
+/// in seconds, to allow for containers rounding durations differently.
+const DURATION_TOLERANCE_SECS: f64 = 0.1;
+
+/// How sure a relink match is, from least to most.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RelinkConfidence {
+    /// Same name, length and streams, but the missing file has no
+    /// fingerprint to compare content with.
+    SameName,
+    /// Same content, length and streams under a different name.
+    Renamed,
+    /// Same name, content, length and streams.
+    Exact,
+}
+
+/// A file found to stand in for missing media.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelinkMatch {
+    pub key: MediaKey,
+    /// Where the media was expected to be, to notice if it's been relinked
+    /// some other way since the search started.
+    pub old_path: PathBuf,
+    pub new_path: PathBuf,
+    pub confidence: RelinkConfidence,
+}
+
+/// The media in the project whose files can't be found.
+pub fn missing_media(project: &MediaProject) -> Vec<MediaKey> {
+    project
+        .media
+        .iter()
+        .filter(|(_, info)| !info.path.exists())
+        .map(|(key, _)| key)
+        .collect()
+}
+
+/// Whether a file is worth probing as a candidate for missing media, judging
+/// only by its name and size.
+pub fn could_relink_to(missing: &MediaInfo, candidate: &Path, candidate_size: u64) -> bool {
+    same_file_name(&missing.path, candidate)
+        || missing
+            .fingerprint
+            .as_ref()
+            .is_some_and(|fingerprint| fingerprint.size == candidate_size)
+}
+
+/// How sure we can be that `candidate`, probed from a file that exists, is the
+/// same media as `missing`, or `None` if it can't be. The length and streams
+/// always have to agree, and either the name or the content too. Content is
+/// compared by fingerprint, which only media imported before fingerprints
+/// were taken lack.
+pub fn relink_confidence(missing: &MediaInfo, candidate: &MediaInfo) -> Option<RelinkConfidence> {
+    if (missing.duration_secs() - candidate.duration_secs()).abs() > DURATION_TOLERANCE_SECS
+        || missing.streams.len() != candidate.streams.len()
+        || !missing
+            .streams
+            .iter()
+            .zip(&candidate.streams)
+            .all(|(a, b)| streams_match(a, b))
+    {
+        return None;
+    }
+
+    let same_name = same_file_name(&missing.path, &candidate.path);
+    match (&missing.fingerprint, &candidate.fingerprint) {
+        (Some(a), Some(b)) if !a.same_content(b) => None,
+        (Some(_), Some(_)) if same_name => Some(RelinkConfidence::Exact),
+        (Some(_), Some(_)) => Some(RelinkConfidence::Renamed),
+        _ if same_name => Some(RelinkConfidence::SameName),
+        _ => None,
+    }
+}
+
+fn same_file_name(a: &Path, b: &Path) -> bool {
+    match (a.file_name(), b.file_name()) {
+        (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+        _ => false,
+    }
+}
+
+/// Whether two streams look like the same one. Details that are unknown on
+/// either side, as for media imported by older versions, aren't compared.
+fn streams_match(a: &MediaStream, b: &MediaStream) -> bool {
+    fn agree<T: PartialEq>(a: &Option<T>, b: &Option<T>) -> bool {
+        a.is_none() || b.is_none() || a == b
+    }
+    fn agree_nonzero<T: PartialEq + Default>(a: T, b: T) -> bool {
+        a == T::default() || b == T::default() || a == b
+    }
+
+    match (a, b) {
+        (MediaStream::Video(a_info, a), MediaStream::Video(b_info, b)) => {
+            a_info.index == b_info.index
+                && agree(&a.codec, &b.codec)
+                && agree_nonzero(a.width, b.width)
+                && agree_nonzero(a.height, b.height)
+        }
+        (MediaStream::Audio(a_info, a), MediaStream::Audio(b_info, b)) => {
+            a_info.index == b_info.index
+                && agree(&a.codec, &b.codec)
+                && agree_nonzero(a.sample_rate, b.sample_rate)
+                && agree_nonzero(a.channels, b.channels)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::{
+        AudioMediaStream, BasicStreamInfo, ColorInfo, JadeRational, MediaFingerprint, MediaLength,
+        VideoMediaStream,
+    };
+
+    fn length(secs: f64) -> MediaLength {
+        MediaLength {
+            time_base_length: (secs * 1000.0).round() as u64,
+            time_base: JadeRational::new(1, 1000),
+        }
+    }
+
+    fn video(index: usize, codec: Option<&str>, width: u32) -> MediaStream {
+        MediaStream::Video(
+            BasicStreamInfo {
+                index,
+                length: length(10.0),
+            },
+            VideoMediaStream {
+                codec: codec.map(str::to_string),
+                profile: None,
+                width,
+                height: width * 9 / 16,
+                sample_aspect_ratio: None,
+                pixel_format: None,
+                color: ColorInfo::default(),
+                rotation: 0,
+                avg_frame_rate: None,
+                real_frame_rate: None,
+                variable_frame_rate: false,
+            },
+        )
+    }
+
+    fn audio(index: usize, codec: Option<&str>, sample_rate: u32) -> MediaStream {
+        MediaStream::Audio(
+            BasicStreamInfo {
+                index,
+                length: length(10.0),
+            },
+            AudioMediaStream {
+                codec: codec.map(str::to_string),
+                profile: None,
+                sample_rate,
+                channels: 2,
+                channel_layout: None,
+                sample_format: None,
+                bit_depth: None,
+            },
+        )
+    }
+
+    fn fingerprint(content_hash: u64) -> MediaFingerprint {
+        MediaFingerprint {
+            size: 1000,
+            modified_nanos: 0,
+            content_hash,
+            streams_hash: 0,
+        }
+    }
+
+    fn media(path: &str) -> MediaInfo {
+        MediaInfo {
+            name: "clip".to_string(),
+            path: PathBuf::from(path),
+            fingerprint: Some(fingerprint(1)),
+            streams: vec![video(0, Some("h264"), 1920), audio(1, Some("aac"), 48_000)],
+            proxy: None,
+        }
+    }
+
+    #[test]
+    fn rates_candidates() {
+        let missing = media("/old/Clip.mp4");
+        let unfingerprinted = MediaInfo {
+            fingerprint: None,
+            ..media("/old/Clip.mp4")
+        };
+        let cases: Vec<(&str, &MediaInfo, MediaInfo, Option<RelinkConfidence>)> = vec![
+            (
+                "same name and content",
+                &missing,
+                media("/new/Clip.mp4"),
+                Some(RelinkConfidence::Exact),
+            ),
+            (
+                "name differing only in case",
+                &missing,
+                media("/new/CLIP.MP4"),
+                Some(RelinkConfidence::Exact),
+            ),
+            (
+                "same content renamed",
+                &missing,
+                media("/new/take-2.mp4"),
+                Some(RelinkConfidence::Renamed),
+            ),
+            (
+                "same name without a fingerprint to compare",
+                &unfingerprinted,
+                media("/new/clip.mp4"),
+                Some(RelinkConfidence::SameName),
+            ),
+            (
+                "renamed without a fingerprint to compare",
+                &unfingerprinted,
+                media("/new/take-2.mp4"),
+                None,
+            ),
+            (
+                "different content under the same name",
+                &missing,
+                MediaInfo {
+                    fingerprint: Some(fingerprint(2)),
+                    ..media("/new/Clip.mp4")
+                },
+                None,
+            ),
+            (
+                "stream count",
+                &missing,
+                MediaInfo {
+                    streams: vec![video(0, Some("h264"), 1920)],
+                    ..media("/new/Clip.mp4")
+                },
+                None,
+            ),
+            (
+                "video codec",
+                &missing,
+                MediaInfo {
+                    streams: vec![video(0, Some("hevc"), 1920), audio(1, Some("aac"), 48_000)],
+                    ..media("/new/Clip.mp4")
+                },
+                None,
+            ),
+            (
+                "audio codec",
+                &missing,
+                MediaInfo {
+                    streams: vec![video(0, Some("h264"), 1920), audio(1, Some("opus"), 48_000)],
+                    ..media("/new/Clip.mp4")
+                },
+                None,
+            ),
+        ];
+
+        for (name, missing, candidate, expected) in cases {
+            assert_eq!(relink_confidence(missing, &candidate), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn checks_lengths_against_the_tolerance() {
+        let missing = media("/old/clip.mp4");
+        for (offset, matches) in [
+            (0.0, true),
+            (DURATION_TOLERANCE_SECS / 2.0, true),
+            (-DURATION_TOLERANCE_SECS / 2.0, true),
+            (DURATION_TOLERANCE_SECS * 2.0, false),
+            (-DURATION_TOLERANCE_SECS * 2.0, false),
+        ] {
+            let mut candidate = media("/new/clip.mp4");
+            for stream in &mut candidate.streams {
+                let (MediaStream::Video(info, _) | MediaStream::Audio(info, _)) = stream;
+                info.length = length(10.0 + offset);
+            }
+            assert_eq!(
+                relink_confidence(&missing, &candidate).is_some(),
+                matches,
+                "{offset} seconds longer"
+            );
+        }
+    }
+
+    #[test]
+    fn compares_streams_where_both_are_known() {
+        let cases = [
+            (
+                video(0, Some("h264"), 1920),
+                video(0, Some("h264"), 1920),
+                true,
+            ),
+            (video(0, Some("h264"), 1920), video(0, None, 0), true),
+            (
+                video(0, Some("h264"), 1920),
+                video(0, Some("vp9"), 1920),
+                false,
+            ),
+            (
+                video(0, Some("h264"), 1920),
+                video(0, Some("h264"), 1280),
+                false,
+            ),
+            (
+                video(0, Some("h264"), 1920),
+                video(1, Some("h264"), 1920),
+                false,
+            ),
+            (
+                audio(1, Some("aac"), 48_000),
+                audio(1, Some("aac"), 48_000),
+                true,
+            ),
+            (audio(1, Some("aac"), 48_000), audio(1, None, 0), true),
+            (
+                audio(1, Some("aac"), 48_000),
+                audio(1, Some("aac"), 44_100),
+                false,
+            ),
+            (
+                audio(1, Some("aac"), 48_000),
+                audio(1, Some("mp3"), 48_000),
+                false,
+            ),
+            (video(0, None, 0), audio(0, None, 0), false),
+        ];
+        for (a, b, expected) in cases {
+            assert_eq!(streams_match(&a, &b), expected, "{a:?} against {b:?}");
+            assert_eq!(streams_match(&b, &a), expected, "{b:?} against {a:?}");
+        }
+    }
+
+    #[test]
+    fn picks_candidates_by_name_or_size() {
+        let missing = media("/old/Clip.mp4");
+        assert!(could_relink_to(&missing, Path::new("/new/clip.MP4"), 5));
+        assert!(could_relink_to(&missing, Path::new("/new/other.mp4"), 1000));
+        assert!(!could_relink_to(&missing, Path::new("/new/other.mp4"), 5));
+
+        let unfingerprinted = MediaInfo {
+            fingerprint: None,
+            ..missing
+        };
+        assert!(could_relink_to(
+            &unfingerprinted,
+            Path::new("/new/CLIP.mp4"),
+            5
+        ));
+        assert!(!could_relink_to(
+            &unfingerprinted,
+            Path::new("/new/other.mp4"),
+            1000
+        ));
+    }
+}
@@ -0,0 +1,274 @@
+use super::{FrameNum, JadeRational, Rounding};
+use anyhow::{Context, bail, ensure};
+use std::{fmt, str::FromStr};
+
+/// A SMPTE timecode, labelling a frame as hours, minutes, seconds and frames.
+/// Written "hh:mm:ss:ff", or "hh:mm:ss;ff" when drop-frame.
+///
+/// Timecode counts whole frames at the nearest integer rate, so at 29.97 fps
+/// it runs slower than the clock. Drop-frame timecode makes up for that by
+/// skipping the first two labels (four at 59.94) of every minute, except every
+/// tenth minute. No frames are dropped, only labels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Timecode {
+    pub hours: u32,
+    pub minutes: u32,
+    pub seconds: u32,
+    pub frames: u32,
+    pub drop_frame: bool,
+}
+
+/// The whole number of frames a second of timecode counts at a frame rate.
+pub fn timecode_base(fps: JadeRational) -> u32 {
+    ((fps.num as f64 / fps.den as f64).round() as u32).max(1)
+}
+
+/// How many labels drop-frame timecode skips each minute at a frame rate, or
+/// `None` if it isn't an NTSC rate (29.97 or 59.94) where drop-frame applies.
+fn dropped_per_minute(fps: JadeRational) -> Option<u32> {
+    let base = timecode_base(fps);
+    let is_ntsc = fps.num as i64 * 1001 == base as i64 * 1000 * fps.den as i64;
+    match base {
+        30 | 60 if is_ntsc => Some(base / 15),
+        _ => None,
+    }
+}
+
+impl Timecode {
+    /// Labels a frame, using drop-frame timecode at the rates it applies to.
+    pub fn from_frame(frame: FrameNum, fps: JadeRational) -> Self {
+        let base = timecode_base(fps) as u64;
+        let mut count = frame.0;
+        let drop_frame = match dropped_per_minute(fps) {
+            Some(dropped) => {
+                // Add back the labels skipped so far, then count as usual.
+                let dropped = dropped as u64;
+                let per_ten_minutes = base * 600 - dropped * 9;
+                let per_minute = base * 60 - dropped;
+                let tens = count / per_ten_minutes;
+                let rest = count % per_ten_minutes;
+                count += dropped * 9 * tens;
+                if rest >= dropped {
+                    count += dropped * ((rest - dropped) / per_minute);
+                }
+                true
+            }
+            None => false,
+        };
+
+        Self {
+            hours: (count / (base * 3600)) as u32,
+            minutes: (count / (base * 60) % 60) as u32,
+            seconds: (count / base % 60) as u32,
+            frames: (count % base) as u32,
+            drop_frame,
+        }
+    }
+
+    /// The frame this timecode labels. Fails if it isn't a valid label at
+    /// this rate, such as a frame count past the end of a second, or a label
+    /// that drop-frame timecode skips.
+    pub fn to_frame(self, fps: JadeRational) -> anyhow::Result<FrameNum> {
+        let base = timecode_base(fps);
+        ensure!(
+            self.minutes < 60 && self.seconds < 60,
+            "timecode {self} has more than 59 minutes or seconds"
+        );
+        ensure!(
+            self.frames < base,
+            "timecode {self} has more than {} frames in a second",
+            base - 1
+        );
+
+        let total_minutes = self.hours as u64 * 60 + self.minutes as u64;
+        let count = (total_minutes * 60 + self.seconds as u64) * base as u64 + self.frames as u64;
+        if !self.drop_frame {
+            return Ok(FrameNum(count));
+        }
+
+        let dropped = dropped_per_minute(fps).with_context(|| {
+            format!(
+                "drop-frame timecode doesn't apply at {}/{} fps",
+                fps.num, fps.den
+            )
+        })?;
+        if self.seconds == 0 && self.frames < dropped && !self.minutes.is_multiple_of(10) {
+            bail!("timecode {self} is skipped by drop-frame timecode");
+        }
+        let skipped = dropped as u64 * (total_minutes - total_minutes / 10);
+        Ok(FrameNum(count - skipped))
+    }
+}
+
+impl fmt::Display for Timecode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let separator = if self.drop_frame { ';' } else { ':' };
+        write!(
+            f,
+            "{:02}:{:02}:{:02}{separator}{:02}",
+            self.hours, self.minutes, self.seconds, self.frames
+        )
+    }
+}
+
+/// Parses "hh:mm:ss:ff", or drop-frame "hh:mm:ss;ff" (also accepting "." or
+/// "," before the frames, as some tools write it). Whether the fields are in
+/// range for a frame rate is checked by `to_frame`.
+impl FromStr for Timecode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s
+            .rfind([':', ';', '.', ','])
+            .with_context(|| format!("timecode {s:?} has no frames field"))?;
+        let (time, frames) = s.split_at(split);
+        let drop_frame = !frames.starts_with(':');
+
+        let fields = time
+            .split(':')
+            .chain([&frames[1..]])
+            .map(|field| {
+                ensure!(
+                    !field.is_empty() && field.bytes().all(|byte| byte.is_ascii_digit()),
+                    "timecode {s:?} has a field that isn't a number"
+                );
+                field
+                    .parse()
+                    .with_context(|| format!("timecode {s:?} has a field that's too large"))
+            })
+            .collect::<anyhow::Result<Vec<u32>>>()?;
+        let [hours, minutes, seconds, frames] = fields[..] else {
+            bail!("timecode {s:?} doesn't have four fields, like \"hh:mm:ss:ff\"");
+        };
+
+        Ok(Self {
+            hours,
+            minutes,
+            seconds,
+            frames,
+            drop_frame,
+        })
+    }
+}
+
+impl FrameNum {
+    /// When this frame starts, in seconds from the start of the timeline.
+    pub fn secs(self, fps: JadeRational) -> f64 {
+        self.0 as f64 * fps.den as f64 / fps.num as f64
+    }
+
+    /// The frame showing at a time, in seconds. Times before zero give the
+    /// first frame.
+    pub fn at_secs(secs: f64, fps: JadeRational) -> Self {
+        Self((secs * fps.num as f64 / fps.den as f64).floor().max(0.0) as u64)
+    }
+
+    /// The first timestamp, in `time_base` units, that falls within this
+    /// frame.
+    pub fn to_pts(self, fps: JadeRational, time_base: JadeRational) -> i64 {
+        // Rounding up keeps the timestamp inside the frame, rather than at the
+        // end of the one before.
+        JadeRational::rescale(self.0 as i64, fps.recip(), time_base, Rounding::Up)
+            .unwrap_or(i64::MAX)
+    }
+
+    /// The frame showing at a timestamp in `time_base` units. Timestamps
+    /// before zero give the first frame.
+    pub fn from_pts(pts: i64, fps: JadeRational, time_base: JadeRational) -> Self {
+        let frame = JadeRational::rescale(pts.max(0), time_base, fps.recip(), Rounding::Down);
+        Self(frame.unwrap_or(0) as u64)
+    }
+
+    pub fn timecode(self, fps: JadeRational) -> Timecode {
+        Timecode::from_frame(self, fps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILM: JadeRational = JadeRational {
+        num: 24000,
+        den: 1001,
+    };
+    const NTSC: JadeRational = JadeRational {
+        num: 30000,
+        den: 1001,
+    };
+    const NTSC_HIGH: JadeRational = JadeRational {
+        num: 60000,
+        den: 1001,
+    };
+
+    /// Every frame of the first 25 hours would take too long, so every frame
+    /// of the first 20 minutes, then a spread of frames up to 25 hours.
+    fn frames(fps: JadeRational) -> impl Iterator<Item = FrameNum> {
+        let per_minute = timecode_base(fps) as u64 * 60;
+        (0..per_minute * 20)
+            .chain((0..per_minute * 60 * 25).step_by(997))
+            .map(FrameNum)
+    }
+
+    #[test]
+    fn round_trips_every_frame() {
+        for fps in [FILM, NTSC, NTSC_HIGH] {
+            for frame in frames(fps) {
+                let timecode = frame.timecode(fps);
+                assert_eq!(
+                    timecode.to_frame(fps).unwrap(),
+                    frame,
+                    "{timecode} at {fps:?}"
+                );
+                let parsed: Timecode = timecode.to_string().parse().unwrap();
+                assert_eq!(parsed, timecode);
+            }
+        }
+    }
+
+    #[test]
+    fn never_lands_on_a_dropped_label() {
+        for (fps, dropped) in [(NTSC, 2), (NTSC_HIGH, 4)] {
+            for frame in frames(fps) {
+                let timecode = frame.timecode(fps);
+                assert!(timecode.drop_frame);
+                let skipped = timecode.seconds == 0
+                    && timecode.frames < dropped
+                    && !timecode.minutes.is_multiple_of(10);
+                assert!(!skipped, "frame {} is labelled {timecode}", frame.0);
+            }
+        }
+    }
+
+    #[test]
+    fn only_ntsc_rates_drop_labels() {
+        assert!(!FrameNum(0).timecode(FILM).drop_frame);
+        assert!(
+            !FrameNum(0)
+                .timecode(JadeRational { num: 30, den: 1 })
+                .drop_frame
+        );
+        assert!(FrameNum(0).timecode(NTSC).drop_frame);
+        assert_eq!(FrameNum(1800).timecode(NTSC).to_string(), "00:01:00;02");
+        assert_eq!(FrameNum(17982).timecode(NTSC).to_string(), "00:10:00;00");
+        assert_eq!(
+            FrameNum(3600).timecode(NTSC_HIGH).to_string(),
+            "00:01:00;04"
+        );
+        assert!(
+            "00:01:00;01"
+                .parse::<Timecode>()
+                .unwrap()
+                .to_frame(NTSC)
+                .is_err()
+        );
+        assert!(
+            "00:10:00;01"
+                .parse::<Timecode>()
+                .unwrap()
+                .to_frame(NTSC)
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,605 @@
+use super::{FrameNum, FrameSpan, FrameSpanSet, MediaKey};
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ClipId(pub u64);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TrackKind {
+    Video,
+    Audio,
+}
+
+/// Tracks are addressed by their kind and their position within that kind,
+/// with index 0 being the bottom-most video track (or top-most audio track).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TrackRef {
+    pub kind: TrackKind,
+    pub index: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Clip {
+    pub id: ClipId,
+    pub media: MediaKey,
+    pub stream_index: usize,
+    /// The frames of the source media this clip plays, in project frames.
+    pub source: FrameSpan,
+    pub timeline_start: FrameNum,
+}
+
+impl Clip {
+    pub fn len(&self) -> u64 {
+        self.source.len()
+    }
+
+    /// Where the clip sits on the timeline. A clip reaching past the last
+    /// frame number is cut short there.
+    pub fn timeline_span(&self) -> FrameSpan {
+        FrameSpan {
+            from: self.timeline_start,
+            to_excl: FrameNum(self.timeline_start.0.saturating_add(self.len())),
+        }
+    }
+
+    /// Maps a frame on the timeline to the frame of the source media shown at
+    /// that point, if this clip covers it.
+    pub fn source_frame_at(&self, timeline_frame: FrameNum) -> Option<FrameNum> {
+        self.timeline_span()
+            .contains(timeline_frame)
+            .then(|| FrameNum(self.source.from.0 + (timeline_frame.0 - self.timeline_start.0)))
+    }
+}
+
+/// A row of clips. Deserializing checks the clips are sorted, not empty and
+/// don't overlap, as every method that changes a track keeps them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedTrack")]
+pub struct Track {
+    pub name: String,
+    /// Kept sorted by timeline start, and never overlapping.
+    clips: Vec<Clip>,
+}
+
+impl Track {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            clips: vec![],
+        }
+    }
+
+    pub fn clips(&self) -> &[Clip] {
+        &self.clips
+    }
+
+    pub fn clip(&self, id: ClipId) -> Option<&Clip> {
+        self.clips.iter().find(|clip| clip.id == id)
+    }
+
+    pub fn clip_at(&self, frame: FrameNum) -> Option<&Clip> {
+        self.clips
+            .iter()
+            .find(|clip| clip.source_frame_at(frame).is_some())
+    }
+
+    pub fn end_frame(&self) -> FrameNum {
+        self.clips
+            .last()
+            .map(|clip| clip.timeline_span().to_excl)
+            .unwrap_or(FrameNum(0))
+    }
+
+    /// Whether the given span is free on this track, optionally ignoring one
+    /// clip (for moving or trimming a clip in-place).
+    pub fn is_free(&self, span: &FrameSpan, ignore: Option<ClipId>) -> bool {
+        self.clips
+            .iter()
+            .filter(|clip| Some(clip.id) != ignore)
+            .all(|clip| !clip.timeline_span().overlaps(span))
+    }
+
+    pub fn insert_clip(&mut self, clip: Clip) -> anyhow::Result<()> {
+        if clip.source.is_empty() {
+            bail!("clip {:?} has an empty source span", clip.id);
+        }
+        if !self.is_free(&clip.timeline_span(), None) {
+            bail!(
+                "clip {:?} overlaps another clip on track \"{}\"",
+                clip.id,
+                self.name
+            );
+        }
+
+        let index = self
+            .clips
+            .partition_point(|other| other.timeline_start < clip.timeline_start);
+        self.clips.insert(index, clip);
+        Ok(())
+    }
+
+    pub fn remove_clip(&mut self, id: ClipId) -> Option<Clip> {
+        let index = self.clips.iter().position(|clip| clip.id == id)?;
+        Some(self.clips.remove(index))
+    }
+}
+
+/// Every track of a project. Deserializing checks clip ids are unique and all
+/// below the next one to be handed out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedTimeline")]
+pub struct Timeline {
+    video_tracks: Vec<Track>,
+    audio_tracks: Vec<Track>,
+    next_clip_id: u64,
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Self {
+            video_tracks: vec![Track::new("V1")],
+            audio_tracks: vec![Track::new("A1")],
+            next_clip_id: 0,
+        }
+    }
+}
+
+impl Timeline {
+    pub fn tracks(&self, kind: TrackKind) -> &[Track] {
+        match kind {
+            TrackKind::Video => &self.video_tracks,
+            TrackKind::Audio => &self.audio_tracks,
+        }
+    }
+
+    fn tracks_mut(&mut self, kind: TrackKind) -> &mut Vec<Track> {
+        match kind {
+            TrackKind::Video => &mut self.video_tracks,
+            TrackKind::Audio => &mut self.audio_tracks,
+        }
+    }
+
+    pub fn track(&self, track: TrackRef) -> Option<&Track> {
+        self.tracks(track.kind).get(track.index)
+    }
+
+    pub fn track_refs(&self) -> impl Iterator<Item = TrackRef> + '_ {
+        [TrackKind::Video, TrackKind::Audio]
+            .into_iter()
+            .flat_map(|kind| {
+                (0..self.tracks(kind).len()).map(move |index| TrackRef { kind, index })
+            })
+    }
+
+    pub fn add_track(&mut self, kind: TrackKind, name: impl Into<String>) -> TrackRef {
+        let tracks = self.tracks_mut(kind);
+        tracks.push(Track::new(name));
+        TrackRef {
+            kind,
+            index: tracks.len() - 1,
+        }
+    }
+
+    pub fn insert_track(&mut self, at: TrackRef, track: Track) -> anyhow::Result<()> {
+        let tracks = self.tracks_mut(at.kind);
+        if at.index > tracks.len() {
+            bail!("track index {} is out of bounds", at.index);
+        }
+        tracks.insert(at.index, track);
+        Ok(())
+    }
+
+    pub fn remove_track(&mut self, track: TrackRef) -> Option<Track> {
+        let tracks = self.tracks_mut(track.kind);
+        (track.index < tracks.len()).then(|| tracks.remove(track.index))
+    }
+
+    pub fn find_clip(&self, id: ClipId) -> Option<(TrackRef, &Clip)> {
+        self.track_refs().find_map(|track_ref| {
+            self.track(track_ref)
+                .and_then(|track| track.clip(id))
+                .map(|clip| (track_ref, clip))
+        })
+    }
+
+    pub fn clips_using(&self, media: MediaKey) -> impl Iterator<Item = &Clip> + '_ {
+        self.video_tracks
+            .iter()
+            .chain(&self.audio_tracks)
+            .flat_map(Track::clips)
+            .filter(move |clip| clip.media == media)
+    }
+
+    /// The frames with a clip on them on any track of a kind.
+    pub fn covered_frames(&self, kind: TrackKind) -> FrameSpanSet {
+        self.tracks(kind)
+            .iter()
+            .flat_map(Track::clips)
+            .map(Clip::timeline_span)
+            .collect()
+    }
+
+    pub fn end_frame(&self) -> FrameNum {
+        self.video_tracks
+            .iter()
+            .chain(&self.audio_tracks)
+            .map(Track::end_frame)
+            .max()
+            .unwrap_or(FrameNum(0))
+    }
+
+    /// The id the next clip added to the timeline will get.
+    pub fn next_clip_id(&self) -> ClipId {
+        ClipId(self.next_clip_id)
+    }
+
+    /// Hands back the most recently allocated clip id, so that undoing the
+    /// addition of a clip leaves the timeline exactly as it was.
+    pub fn release_clip_id(&mut self, id: ClipId) {
+        if self.next_clip_id == id.0 + 1 && self.find_clip(id).is_none() {
+            self.next_clip_id = id.0;
+        }
+    }
+
+    pub fn add_clip(
+        &mut self,
+        track: TrackRef,
+        media: MediaKey,
+        stream_index: usize,
+        source: FrameSpan,
+        timeline_start: FrameNum,
+    ) -> anyhow::Result<ClipId> {
+        let id = ClipId(self.next_clip_id);
+        self.insert_clip(
+            track,
+            Clip {
+                id,
+                media,
+                stream_index,
+                source,
+                timeline_start,
+            },
+        )?;
+        Ok(id)
+    }
+
+    /// Inserts an already-constructed clip, keeping its id. Used to restore
+    /// clips that were previously removed.
+    pub fn insert_clip(&mut self, track: TrackRef, clip: Clip) -> anyhow::Result<()> {
+        if self.find_clip(clip.id).is_some() {
+            bail!("clip {:?} is already on the timeline", clip.id);
+        }
+        let next_clip_id = self.next_clip_id.max(clip.id.0 + 1);
+        self.tracks_mut(track.kind)
+            .get_mut(track.index)
+            .with_context(|| format!("no track at {track:?}"))?
+            .insert_clip(clip)?;
+        self.next_clip_id = next_clip_id;
+        Ok(())
+    }
+
+    pub fn remove_clip(&mut self, id: ClipId) -> Option<(TrackRef, Clip)> {
+        let (track_ref, _) = self.find_clip(id)?;
+        let clip = self.tracks_mut(track_ref.kind)[track_ref.index].remove_clip(id)?;
+        Some((track_ref, clip))
+    }
+
+    /// Moves a clip to a new start frame, possibly on another track of the
+    /// same kind. Leaves the timeline untouched if the destination is taken.
+    pub fn move_clip(
+        &mut self,
+        id: ClipId,
+        to_track: TrackRef,
+        timeline_start: FrameNum,
+    ) -> anyhow::Result<()> {
+        let (from_track, clip) = self.find_clip(id).context("no such clip to move")?;
+        if from_track.kind != to_track.kind {
+            bail!("clips cannot move between video and audio tracks");
+        }
+        let mut moved = clip.clone();
+        moved.timeline_start = timeline_start;
+        self.replace_clip(from_track, to_track, moved)
+    }
+
+    /// Changes which frames of the source a clip plays and where it starts.
+    pub fn trim_clip(
+        &mut self,
+        id: ClipId,
+        source: FrameSpan,
+        timeline_start: FrameNum,
+    ) -> anyhow::Result<()> {
+        let (track_ref, clip) = self.find_clip(id).context("no such clip to trim")?;
+        let mut trimmed = clip.clone();
+        trimmed.source = source;
+        trimmed.timeline_start = timeline_start;
+        self.replace_clip(track_ref, track_ref, trimmed)
+    }
+
+    fn replace_clip(
+        &mut self,
+        from_track: TrackRef,
+        to_track: TrackRef,
+        clip: Clip,
+    ) -> anyhow::Result<()> {
+        let destination = self
+            .track(to_track)
+            .with_context(|| format!("no track at {to_track:?}"))?;
+        if !destination.is_free(&clip.timeline_span(), Some(clip.id)) {
+            bail!(
+                "clip {:?} would overlap another clip on track \"{}\"",
+                clip.id,
+                destination.name
+            );
+        }
+
+        let original = self.tracks_mut(from_track.kind)[from_track.index]
+            .remove_clip(clip.id)
+            .context("clip vanished from its track")?;
+        if let Err(err) = self.tracks_mut(to_track.kind)[to_track.index].insert_clip(clip) {
+            self.tracks_mut(from_track.kind)[from_track.index]
+                .insert_clip(original)
+                .with_context(|| {
+                    format!("failed to restore clip to its original position after: {err:#}")
+                })?;
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+/// The fields of a `Track` as read from a file, before they're checked.
+#[derive(Deserialize)]
+struct UncheckedTrack {
+    name: String,
+    clips: Vec<Clip>,
+}
+
+impl TryFrom<UncheckedTrack> for Track {
+    type Error = anyhow::Error;
+
+    fn try_from(value: UncheckedTrack) -> Result<Self, Self::Error> {
+        if let Some(clip) = value.clips.iter().find(|clip| clip.source.is_empty()) {
+            bail!("clip {:?} has an empty source span", clip.id);
+        }
+        for pair in value.clips.windows(2) {
+            if pair[1].timeline_start < pair[0].timeline_span().to_excl {
+                bail!(
+                    "clip {:?} is out of order or overlaps clip {:?} on track \"{}\"",
+                    pair[1].id,
+                    pair[0].id,
+                    value.name
+                );
+            }
+        }
+        Ok(Self {
+            name: value.name,
+            clips: value.clips,
+        })
+    }
+}
+
+/// The fields of a `Timeline` as read from a file, before they're checked.
+#[derive(Deserialize)]
+struct UncheckedTimeline {
+    video_tracks: Vec<Track>,
+    audio_tracks: Vec<Track>,
+    next_clip_id: u64,
+}
+
+impl TryFrom<UncheckedTimeline> for Timeline {
+    type Error = anyhow::Error;
+
+    fn try_from(value: UncheckedTimeline) -> Result<Self, Self::Error> {
+        let mut ids = HashSet::new();
+        for clip in value
+            .video_tracks
+            .iter()
+            .chain(&value.audio_tracks)
+            .flat_map(Track::clips)
+        {
+            if !ids.insert(clip.id) {
+                bail!("clip {:?} is on the timeline more than once", clip.id);
+            }
+            if clip.id.0 >= value.next_clip_id {
+                bail!(
+                    "clip {:?} is not below the next clip id, {}",
+                    clip.id,
+                    value.next_clip_id
+                );
+            }
+        }
+        Ok(Self {
+            video_tracks: value.video_tracks,
+            audio_tracks: value.audio_tracks,
+            next_clip_id: value.next_clip_id,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::Range;
+
+    const V1: TrackRef = TrackRef {
+        kind: TrackKind::Video,
+        index: 0,
+    };
+    const A1: TrackRef = TrackRef {
+        kind: TrackKind::Audio,
+        index: 0,
+    };
+
+    fn add(
+        timeline: &mut Timeline,
+        track: TrackRef,
+        start: u64,
+        len: u64,
+    ) -> anyhow::Result<ClipId> {
+        timeline.add_clip(
+            track,
+            MediaKey::default(),
+            0,
+            FrameSpan::with_len(FrameNum(0), len)?,
+            FrameNum(start),
+        )
+    }
+
+    fn starts(timeline: &Timeline, track: TrackRef) -> Vec<u64> {
+        timeline
+            .track(track)
+            .unwrap()
+            .clips()
+            .iter()
+            .map(|clip| clip.timeline_start.0)
+            .collect()
+    }
+
+    #[test]
+    fn insert_keeps_clips_sorted() {
+        let mut timeline = Timeline::default();
+        add(&mut timeline, V1, 20, 5).unwrap();
+        add(&mut timeline, V1, 0, 5).unwrap();
+        add(&mut timeline, V1, 10, 5).unwrap();
+        assert_eq!(starts(&timeline, V1), [0, 10, 20]);
+        assert_eq!(timeline.end_frame(), FrameNum(25));
+    }
+
+    #[test]
+    fn adjacent_clips_are_allowed() {
+        let mut timeline = Timeline::default();
+        add(&mut timeline, V1, 0, 10).unwrap();
+        add(&mut timeline, V1, 10, 10).unwrap();
+        assert_eq!(starts(&timeline, V1), [0, 10]);
+        let clip = timeline.track(V1).unwrap().clip_at(FrameNum(10)).unwrap();
+        assert_eq!(clip.timeline_start, FrameNum(10));
+    }
+
+    #[test]
+    fn overlapping_clips_are_rejected() {
+        let mut timeline = Timeline::default();
+        add(&mut timeline, V1, 10, 10).unwrap();
+        for (start, len) in [(5, 6), (19, 5), (12, 2), (0, 30), (10, 10)] {
+            assert!(
+                add(&mut timeline, V1, start, len).is_err(),
+                "clip at {start} for {len} frames should overlap"
+            );
+        }
+        assert_eq!(starts(&timeline, V1), [10]);
+        // Other tracks are unaffected.
+        add(&mut timeline, A1, 10, 10).unwrap();
+    }
+
+    #[test]
+    fn empty_clips_are_rejected() {
+        let mut timeline = Timeline::default();
+        assert!(add(&mut timeline, V1, 0, 0).is_err());
+    }
+
+    #[test]
+    fn move_clip_within_and_between_tracks() {
+        let mut timeline = Timeline::default();
+        let v2 = timeline.add_track(TrackKind::Video, "V2");
+        let a = add(&mut timeline, V1, 0, 10).unwrap();
+        let b = add(&mut timeline, V1, 20, 10).unwrap();
+
+        timeline.move_clip(a, V1, FrameNum(10)).unwrap();
+        assert_eq!(starts(&timeline, V1), [10, 20]);
+        // A clip may overlap where it used to be.
+        timeline.move_clip(a, V1, FrameNum(5)).unwrap();
+        assert_eq!(starts(&timeline, V1), [5, 20]);
+
+        timeline.move_clip(b, v2, FrameNum(0)).unwrap();
+        assert_eq!(starts(&timeline, V1), [5]);
+        assert_eq!(starts(&timeline, v2), [0]);
+        assert_eq!(timeline.find_clip(b).unwrap().0, v2);
+    }
+
+    #[test]
+    fn rejected_moves_leave_the_timeline_alone() {
+        let mut timeline = Timeline::default();
+        let a = add(&mut timeline, V1, 0, 10).unwrap();
+        add(&mut timeline, V1, 20, 10).unwrap();
+        let before = timeline.clone();
+
+        assert!(timeline.move_clip(a, V1, FrameNum(15)).is_err());
+        assert!(timeline.move_clip(a, A1, FrameNum(0)).is_err());
+        let missing_track = TrackRef {
+            kind: TrackKind::Video,
+            index: 5,
+        };
+        assert!(timeline.move_clip(a, missing_track, FrameNum(0)).is_err());
+        assert_eq!(timeline, before);
+    }
+
+    #[test]
+    fn covered_frames_merge_across_tracks() {
+        let mut timeline = Timeline::default();
+        let v2 = timeline.add_track(TrackKind::Video, "V2");
+        add(&mut timeline, V1, 0, 10).unwrap();
+        add(&mut timeline, V1, 30, 5).unwrap();
+        add(&mut timeline, v2, 5, 15).unwrap();
+        add(&mut timeline, A1, 50, 10).unwrap();
+
+        let covered = timeline.covered_frames(TrackKind::Video);
+        let spans: Vec<_> = covered.into_iter().map(Range::from).collect();
+        assert_eq!(spans, [0..20, 30..35]);
+        let covered = timeline.covered_frames(TrackKind::Audio);
+        assert_eq!(covered.spans(), [FrameSpan::try_from(50..60).unwrap()]);
+    }
+
+    fn deserialize_edited(
+        timeline: &Timeline,
+        edit: impl FnOnce(&mut serde_json::Value),
+    ) -> serde_json::Result<Timeline> {
+        let mut value = serde_json::to_value(timeline).unwrap();
+        edit(&mut value);
+        serde_json::from_value(value)
+    }
+
+    #[test]
+    fn deserializing_checks_clip_order_and_overlap() {
+        let mut timeline = Timeline::default();
+        add(&mut timeline, V1, 0, 10).unwrap();
+        add(&mut timeline, V1, 10, 10).unwrap();
+        assert_eq!(deserialize_edited(&timeline, |_| {}).unwrap(), timeline);
+
+        let unsorted = deserialize_edited(&timeline, |value| {
+            value["video_tracks"][0]["clips"]
+                .as_array_mut()
+                .unwrap()
+                .reverse();
+        });
+        assert!(unsorted.is_err());
+        let overlapping = deserialize_edited(&timeline, |value| {
+            value["video_tracks"][0]["clips"][1]["timeline_start"] = 9.into();
+        });
+        assert!(overlapping.is_err());
+        let empty = deserialize_edited(&timeline, |value| {
+            value["video_tracks"][0]["clips"][0]["source"]["to_excl"] = 0.into();
+        });
+        assert!(empty.is_err());
+    }
+
+    #[test]
+    fn deserializing_checks_clip_ids() {
+        let mut timeline = Timeline::default();
+        add(&mut timeline, V1, 0, 10).unwrap();
+        add(&mut timeline, A1, 0, 10).unwrap();
+
+        let reused_next_id = deserialize_edited(&timeline, |value| {
+            value["next_clip_id"] = 1.into();
+        });
+        assert!(reused_next_id.is_err());
+        let duplicate_id = deserialize_edited(&timeline, |value| {
+            value["audio_tracks"][0]["clips"][0]["id"] = 0.into();
+        });
+        assert!(duplicate_id.is_err());
+        let skipped_ids = deserialize_edited(&timeline, |value| {
+            value["next_clip_id"] = 5.into();
+        });
+        assert_eq!(skipped_ids.unwrap().next_clip_id(), ClipId(5));
+    }
+}
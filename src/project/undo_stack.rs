@@ -0,0 +1,289 @@
+use super::{EditCommand, MediaProject};
+
+/// How many edits are remembered before the oldest start being forgotten.
+const MAX_UNDO_DEPTH: usize = 500;
+
+/// The edit history of a project. Edits are applied through `push`, and can
+/// then be stepped back and forth with `undo` and `redo`.
+#[derive(Debug, Default)]
+pub struct UndoStack {
+    undo: Vec<EditCommand>,
+    redo: Vec<EditCommand>,
+    /// Whether an interaction (like a drag) is under way, between
+    /// `begin_merge` and `end_merge`, whose edits make up one undo step.
+    merging: bool,
+    /// Whether the next pushed command may be merged into the last one. Only
+    /// set by pushes made while `merging`, so edits from before the
+    /// interaction are left alone.
+    merge_open: bool,
+}
+
+impl UndoStack {
+    /// Applies a command and records it. Nothing is recorded if it fails.
+    pub fn push(
+        &mut self,
+        project: &mut MediaProject,
+        mut command: EditCommand,
+    ) -> anyhow::Result<()> {
+        command.apply(project)?;
+        self.redo.clear();
+
+        if self.merge_open
+            && let Some(last) = self.undo.last_mut()
+            && last.merge(&command)
+        {
+            return Ok(());
+        }
+        self.undo.push(command);
+        if self.undo.len() > MAX_UNDO_DEPTH {
+            self.undo.remove(0);
+        }
+        self.merge_open = self.merging;
+        Ok(())
+    }
+
+    /// Starts an interaction whose pushed commands should merge into one
+    /// undo step, where they can.
+    pub fn begin_merge(&mut self) {
+        self.merging = true;
+        self.merge_open = false;
+    }
+
+    /// Stops the next pushed command from merging into the last one.
+    pub fn end_merge(&mut self) {
+        self.merging = false;
+        self.merge_open = false;
+    }
+
+    /// Reverts the last edit. Returns whether there was anything to undo.
+    pub fn undo(&mut self, project: &mut MediaProject) -> anyhow::Result<bool> {
+        let Some(mut command) = self.undo.pop() else {
+            return Ok(false);
+        };
+        if let Err(err) = command.revert(project) {
+            self.undo.push(command);
+            return Err(err);
+        }
+        self.redo.push(command);
+        self.merge_open = false;
+        Ok(true)
+    }
+
+    /// Re-applies the last undone edit. Returns whether there was anything to
+    /// redo.
+    pub fn redo(&mut self, project: &mut MediaProject) -> anyhow::Result<bool> {
+        let Some(mut command) = self.redo.pop() else {
+            return Ok(false);
+        };
+        if let Err(err) = command.apply(project) {
+            self.redo.push(command);
+            return Err(err);
+        }
+        self.undo.push(command);
+        self.merge_open = false;
+        Ok(true)
+    }
+
+    pub fn undo_name(&self) -> Option<&'static str> {
+        self.undo.last().map(EditCommand::name)
+    }
+
+    pub fn redo_name(&self) -> Option<&'static str> {
+        self.redo.last().map(EditCommand::name)
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.merging = false;
+        self.merge_open = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::{
+        AudioSpec, Clip, ClipId, FrameNum, FrameSpan, JadeRational, MediaInfo, MediaKey, MediaPool,
+        Resolution, Timeline, TrackKind, TrackRef,
+    };
+    use std::path::PathBuf;
+
+    const V1: TrackRef = TrackRef {
+        kind: TrackKind::Video,
+        index: 0,
+    };
+
+    fn project() -> MediaProject {
+        MediaProject {
+            fps: JadeRational { num: 30, den: 1 },
+            frame_count: 300,
+            resolution: Resolution::default(),
+            audio: AudioSpec::default(),
+            media: MediaPool::default(),
+            timeline: Timeline::default(),
+        }
+    }
+
+    fn info(name: &str) -> MediaInfo {
+        MediaInfo {
+            name: name.to_string(),
+            path: PathBuf::from(format!("/media/{name}.mp4")),
+            fingerprint: None,
+            streams: Vec::new(),
+            proxy: None,
+        }
+    }
+
+    fn imported_key(stack: &UndoStack) -> MediaKey {
+        match stack.undo.last() {
+            Some(EditCommand::ImportMedia { key: Some(key), .. }) => *key,
+            other => panic!("expected an applied import, got {other:?}"),
+        }
+    }
+
+    fn add_clip(project: &MediaProject, media: MediaKey, start: u64) -> EditCommand {
+        EditCommand::AddClip {
+            track: V1,
+            clip: Clip {
+                id: project.timeline.next_clip_id(),
+                media,
+                stream_index: 0,
+                source: FrameSpan::with_len(FrameNum(0), 10).unwrap(),
+                timeline_start: FrameNum(start),
+            },
+        }
+    }
+
+    fn first_clip(project: &MediaProject) -> ClipId {
+        project.timeline.track(V1).unwrap().clips()[0].id
+    }
+
+    #[test]
+    fn separate_edits_are_separate_steps() {
+        let mut project = project();
+        let mut stack = UndoStack::default();
+        stack
+            .push(&mut project, EditCommand::import_media(info("a")))
+            .unwrap();
+        let key = imported_key(&stack);
+        for name in ["b", "c"] {
+            let command = EditCommand::rename_media(&project, key, name.to_string()).unwrap();
+            stack.push(&mut project, command).unwrap();
+        }
+        for fps in [24, 25] {
+            let command = EditCommand::set_fps(&project, JadeRational { num: fps, den: 1 });
+            stack.push(&mut project, command).unwrap();
+        }
+
+        stack.undo(&mut project).unwrap();
+        assert_eq!(project.fps, JadeRational { num: 24, den: 1 });
+        stack.undo(&mut project).unwrap();
+        assert_eq!(project.fps, JadeRational { num: 30, den: 1 });
+        stack.undo(&mut project).unwrap();
+        assert_eq!(project.media[key].name, "b");
+        stack.undo(&mut project).unwrap();
+        assert_eq!(project.media[key].name, "a");
+    }
+
+    #[test]
+    fn a_drag_is_one_step() {
+        let mut project = project();
+        let mut stack = UndoStack::default();
+        stack
+            .push(&mut project, EditCommand::import_media(info("a")))
+            .unwrap();
+        let key = imported_key(&stack);
+        let command = add_clip(&project, key, 0);
+        let EditCommand::AddClip { clip, .. } = &command else {
+            unreachable!()
+        };
+        let id = clip.id;
+        stack.push(&mut project, command).unwrap();
+        let before_drag = project.clone();
+
+        stack.begin_merge();
+        for start in [5, 20, 30] {
+            let command = EditCommand::move_clip(&project, id, V1, FrameNum(start)).unwrap();
+            stack.push(&mut project, command).unwrap();
+        }
+        stack.end_merge();
+        let after_drag = project.clone();
+
+        // Not merged into the drag, since it's over.
+        let command = EditCommand::move_clip(&project, id, V1, FrameNum(40)).unwrap();
+        stack.push(&mut project, command).unwrap();
+        stack.undo(&mut project).unwrap();
+        assert_eq!(project, after_drag);
+
+        stack.undo(&mut project).unwrap();
+        assert_eq!(project, before_drag);
+        assert_eq!(stack.undo_name(), Some("Add Clip"));
+        stack.redo(&mut project).unwrap();
+        assert_eq!(project, after_drag);
+    }
+
+    #[test]
+    fn undoing_everything_restores_every_step() {
+        let mut project = project();
+        let mut stack = UndoStack::default();
+        let mut states = vec![project.clone()];
+
+        stack
+            .push(&mut project, EditCommand::import_media(info("a")))
+            .unwrap();
+        let a = imported_key(&stack);
+        states.push(project.clone());
+        stack
+            .push(&mut project, EditCommand::import_media(info("b")))
+            .unwrap();
+        let b = imported_key(&stack);
+        states.push(project.clone());
+
+        let steps: [fn(&MediaProject) -> EditCommand; 7] = [
+            |project| add_clip(project, project.media.keys().next().unwrap(), 0),
+            |project| {
+                let a = project.media.keys().next().unwrap();
+                EditCommand::rename_media(project, a, "renamed".to_string()).unwrap()
+            },
+            |project| {
+                EditCommand::move_clip(project, first_clip(project), V1, FrameNum(15)).unwrap()
+            },
+            |project| {
+                let source = FrameSpan::with_len(FrameNum(2), 6).unwrap();
+                EditCommand::trim_clip(project, first_clip(project), source, FrameNum(17)).unwrap()
+            },
+            |project| EditCommand::set_fps(project, JadeRational { num: 25, den: 1 }),
+            |project| {
+                let b = project.media.keys().nth(1).unwrap();
+                EditCommand::remove_media(project, b).unwrap()
+            },
+            |project| EditCommand::remove_clip(project, first_clip(project)).unwrap(),
+        ];
+        for step in steps {
+            let command = step(&project);
+            stack.push(&mut project, command).unwrap();
+            states.push(project.clone());
+        }
+        let last = project.clone();
+
+        for state in states.iter().rev().skip(1) {
+            assert!(stack.undo(&mut project).unwrap());
+            assert_eq!(&project, state);
+        }
+        assert!(!stack.undo(&mut project).unwrap());
+
+        for state in states.iter().skip(1) {
+            assert!(stack.redo(&mut project).unwrap());
+            assert_eq!(&project, state);
+        }
+        assert!(!stack.redo(&mut project).unwrap());
+        assert_eq!(project, last);
+        // Media came back under the keys it was imported with.
+        assert!(project.media.contains_key(a));
+        assert!(!project.media.contains_key(b));
+        stack.undo(&mut project).unwrap();
+        stack.undo(&mut project).unwrap();
+        assert_eq!(project.media[b].name, "b");
+    }
+}
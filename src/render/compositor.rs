@@ -0,0 +1,325 @@
+use super::{CachedPixelFormat, FrameCacheKey, SharedFrameCache, YuvFrame};
+use crate::{
+    ff_interop::{
+        proxy::PROXY_STREAM_INDEX, rgba_frame_to_image, video_player::FfmpegVideoDecoder,
+    },
+    project::{Clip, FrameNum, JadeRational, MediaKey, MediaProject, Resolution, TrackKind},
+};
+use anyhow::Context;
+use image::{
+    Rgba, RgbaImage,
+    imageops::{self, FilterType},
+};
+use log::warn;
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    sync::Arc,
+};
+
+/// A frame ready for the preview.
+pub enum PreviewFrame {
+    /// Composited on the CPU, at the project's resolution.
+    Rgba(RgbaImage),
+    /// A single layer left as YUV for the GPU to convert, to be drawn over
+    /// black at `x`, `y`, `width` by `height` within a canvas of the project's
+    /// resolution.
+    Yuv {
+        frame: YuvFrame,
+        canvas: Resolution,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+}
+
+/// Flattens the video tracks of a project into single frames on the CPU, with
+/// higher tracks drawn over lower ones.
+#[derive(Default)]
+pub struct Compositor {
+    /// Keyed by media, stream, and whether it's decoding the proxy.
+    decoders: HashMap<(MediaKey, usize, bool), FfmpegVideoDecoder>,
+    /// The project frame rate the decoders were opened with. They count
+    /// frames at that rate, so they're all reopened when it changes.
+    decoders_fps: Option<JadeRational>,
+    /// Where decoded frames are looked up before decoding them, and kept
+    /// after.
+    cache: Option<SharedFrameCache>,
+    /// Whether to decode the proxies of media that have one instead of the
+    /// originals. Off by default, so exports are always full quality.
+    use_proxies: bool,
+}
+
+impl Compositor {
+    pub fn with_cache(cache: SharedFrameCache) -> Self {
+        Self {
+            decoders: HashMap::new(),
+            decoders_fps: None,
+            cache: Some(cache),
+            use_proxies: false,
+        }
+    }
+
+    pub fn set_use_proxies(&mut self, use_proxies: bool) {
+        self.use_proxies = use_proxies;
+    }
+
+    pub fn uses_proxies(&self) -> bool {
+        self.use_proxies
+    }
+
+    pub fn render_frame(
+        &mut self,
+        project: &MediaProject,
+        frame: FrameNum,
+    ) -> anyhow::Result<RgbaImage> {
+        let mut canvas = RgbaImage::from_pixel(
+            project.resolution.width,
+            project.resolution.height,
+            Rgba([0, 0, 0, 255]),
+        );
+
+        for track in project.timeline.tracks(TrackKind::Video) {
+            let Some(clip) = track.clip_at(frame) else {
+                continue;
+            };
+            let layer = self.fitted_layer(project, clip, frame)?;
+            overlay_centered(&mut canvas, &layer);
+        }
+
+        Ok(canvas)
+    }
+
+    /// Like `render_frame`, but when the frame is one layer that isn't cached
+    /// yet, it's handed back as YUV for the GPU to convert and scale instead,
+    /// which is much cheaper than doing both on the CPU. Frames from the
+    /// cache, several layers, or pixel formats the GPU path doesn't handle are
+    /// composited as usual.
+    pub fn render_preview(
+        &mut self,
+        project: &MediaProject,
+        frame: FrameNum,
+    ) -> anyhow::Result<PreviewFrame> {
+        let layers: Vec<_> = project
+            .timeline
+            .tracks(TrackKind::Video)
+            .iter()
+            .filter_map(|track| track.clip_at(frame))
+            .collect();
+        if let [clip] = layers[..] {
+            let proxy = self.proxy_used_for(project, clip);
+            let key = cache_key(project, clip, frame, proxy);
+            let cached = self
+                .cache
+                .as_ref()
+                .is_some_and(|cache| cache.lock().unwrap().contains(&key));
+            if !cached
+                && let Some(yuv) = self
+                    .decoder_for(project, clip.media, clip.stream_index, proxy)?
+                    .decode_yuv_frame_at(key.frame)
+                    .with_context(|| format!("failed to decode frame for clip {:?}", clip.id))?
+            {
+                let aspect = display_aspect(project, clip, yuv.width, yuv.height);
+                let (width, height) = fitted_size(aspect, key.fit_width, key.fit_height);
+                return Ok(PreviewFrame::Yuv {
+                    frame: yuv,
+                    canvas: project.resolution,
+                    x: (project.resolution.width - width.min(project.resolution.width)) / 2,
+                    y: (project.resolution.height - height.min(project.resolution.height)) / 2,
+                    width,
+                    height,
+                });
+            }
+        }
+        Ok(PreviewFrame::Rgba(self.render_frame(project, frame)?))
+    }
+
+    /// Decodes every layer of a frame into the cache without compositing
+    /// them, skipping layers that are already cached. Does nothing without a
+    /// cache.
+    pub fn cache_frame(&mut self, project: &MediaProject, frame: FrameNum) -> anyhow::Result<()> {
+        let Some(cache) = self.cache.clone() else {
+            return Ok(());
+        };
+        for track in project.timeline.tracks(TrackKind::Video) {
+            let Some(clip) = track.clip_at(frame) else {
+                continue;
+            };
+            let key = cache_key(project, clip, frame, self.proxy_used_for(project, clip));
+            if !cache.lock().unwrap().contains(&key) {
+                self.fitted_layer(project, clip, frame)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The frame of a clip shown at `frame`, scaled to fit the project's
+    /// resolution. Comes from the cache if it can.
+    fn fitted_layer(
+        &mut self,
+        project: &MediaProject,
+        clip: &Clip,
+        frame: FrameNum,
+    ) -> anyhow::Result<Arc<RgbaImage>> {
+        let proxy = self.proxy_used_for(project, clip);
+        let key = cache_key(project, clip, frame, proxy);
+        if let Some(cache) = &self.cache
+            && let Some(image) = cache.lock().unwrap().get(&key)
+        {
+            return Ok(image);
+        }
+
+        let decoded = self
+            .decoder_for(project, clip.media, clip.stream_index, proxy)?
+            .decode_frame_at(key.frame)
+            .with_context(|| format!("failed to decode frame for clip {:?}", clip.id))?;
+        let image = rgba_frame_to_image(&decoded);
+        let aspect = display_aspect(project, clip, image.width(), image.height());
+        let image = Arc::new(fit_within(image, aspect, key.fit_width, key.fit_height));
+        if let Some(cache) = &self.cache {
+            cache.lock().unwrap().insert(key, image.clone());
+        }
+        Ok(image)
+    }
+
+    /// Whether the frames of `clip` come from a proxy.
+    fn proxy_used_for(&self, project: &MediaProject, clip: &Clip) -> bool {
+        self.use_proxies
+            && project
+                .media
+                .get(clip.media)
+                .is_some_and(|info| info.proxy_for(clip.stream_index).is_some())
+    }
+
+    fn decoder_for(
+        &mut self,
+        project: &MediaProject,
+        media: MediaKey,
+        stream_index: usize,
+        proxy: bool,
+    ) -> anyhow::Result<&mut FfmpegVideoDecoder> {
+        if self.decoders_fps != Some(project.fps) {
+            self.decoders.clear();
+            self.decoders_fps = Some(project.fps);
+        }
+        match self.decoders.entry((media, stream_index, proxy)) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                let info = project
+                    .media
+                    .get(media)
+                    .context("clip references media that is not in the project")?;
+                let proxy = info.proxy_for(stream_index).filter(|_| proxy);
+                let (path, stream_index) = match proxy {
+                    Some(proxy) if proxy.path.exists() => (&proxy.path, PROXY_STREAM_INDEX),
+                    Some(proxy) => {
+                        // Caches can be cleared, so fall back to the original.
+                        warn!(
+                            "proxy {} is missing, decoding {} instead",
+                            proxy.path.display(),
+                            info.path.display()
+                        );
+                        (&info.path, stream_index)
+                    }
+                    None => (&info.path, stream_index),
+                };
+                let input_ctx = ffmpeg_next::format::input(path).with_context(|| {
+                    format!("failed to load format info for {}", path.display())
+                })?;
+                let decoder = FfmpegVideoDecoder::new(input_ctx, stream_index, project.fps)?;
+                Ok(entry.insert(decoder))
+            }
+        }
+    }
+}
+
+/// Identifies the frame of `clip` shown at `frame` once fitted to the project.
+fn cache_key(project: &MediaProject, clip: &Clip, frame: FrameNum, proxy: bool) -> FrameCacheKey {
+    FrameCacheKey {
+        media: clip.media,
+        stream_index: clip.stream_index,
+        proxy,
+        frame: clip
+            .source_frame_at(frame)
+            .expect("clip_at returned a clip that doesn't cover the frame"),
+        fps: project.fps,
+        fit_width: project.resolution.width,
+        fit_height: project.resolution.height,
+        format: CachedPixelFormat::Rgba8,
+    }
+}
+
+/// The shape `clip`'s frames should be shown at, from its stream's frame size
+/// and sample aspect ratio. Proxies are made with the original's frame shape,
+/// so this holds for them too. Falls back to the shape of the decoded frame
+/// when the stream's isn't known.
+fn display_aspect(project: &MediaProject, clip: &Clip, width: u32, height: u32) -> f64 {
+    project
+        .media
+        .get(clip.media)
+        .and_then(|info| {
+            info.video_streams()
+                .find(|(stream, _)| stream.index == clip.stream_index)
+        })
+        .and_then(|(_, video)| video.display_aspect_ratio())
+        .unwrap_or(width.max(1) as f64 / height.max(1) as f64)
+}
+
+/// Scales `image` to fit inside `width` by `height`, stretched to the given
+/// display aspect ratio if its pixels aren't square.
+fn fit_within(image: RgbaImage, aspect: f64, width: u32, height: u32) -> RgbaImage {
+    let (fitted_width, fitted_height) = fitted_size(aspect, width, height);
+    if image.dimensions() == (fitted_width, fitted_height) {
+        image
+    } else {
+        imageops::resize(&image, fitted_width, fitted_height, FilterType::Triangle)
+    }
+}
+
+/// The largest size with the given aspect ratio that fits inside `max_width`
+/// by `max_height`.
+fn fitted_size(aspect: f64, max_width: u32, max_height: u32) -> (u32, u32) {
+    let width = f64::min(max_width as f64, max_height as f64 * aspect);
+    let height = width / aspect;
+    (
+        (width.round() as u32).max(1),
+        (height.round() as u32).max(1),
+    )
+}
+
+/// Draws `image` centered over whatever the canvas already holds.
+fn overlay_centered(canvas: &mut RgbaImage, image: &RgbaImage) {
+    let x = (canvas.width() as i64 - image.width() as i64) / 2;
+    let y = (canvas.height() as i64 - image.height() as i64) / 2;
+    imageops::overlay(canvas, image, x, y);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        project::EditCommand,
+        render::{DEFAULT_FRAME_CACHE_BUDGET, FrameCache},
+        test_media::{self, CLIP_FRAMES},
+    };
+
+    #[test]
+    fn follows_frame_rate_changes_and_their_undo() {
+        let (mut project, _) =
+            test_media::numbered_project(test_media::numbered_clip(), CLIP_FRAMES).unwrap();
+        let mut compositor = Compositor::with_cache(FrameCache::shared(DEFAULT_FRAME_CACHE_BUDGET));
+        let shown_at = |compositor: &mut Compositor, project: &MediaProject| {
+            let image = compositor.render_frame(project, FrameNum(10)).unwrap();
+            test_media::frame_number_of(&image)
+        };
+        assert_eq!(shown_at(&mut compositor, &project), 10);
+
+        // At half the clip's rate, each project frame spans two of its frames.
+        let mut command = EditCommand::set_fps(&project, JadeRational::new(15, 1));
+        command.apply(&mut project).unwrap();
+        assert_eq!(shown_at(&mut compositor, &project), 20);
+
+        command.revert(&mut project).unwrap();
+        assert_eq!(shown_at(&mut compositor, &project), 10);
+    }
+}
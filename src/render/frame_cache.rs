@@ -0,0 +1,298 @@
+use crate::project::{FrameNum, JadeRational, MediaKey};
+use image::RgbaImage;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+/// How much memory decoded frames may take up by default, in bytes.
+pub const DEFAULT_FRAME_CACHE_BUDGET: usize = 512 * 1024 * 1024;
+
+/// The layout of a cached frame's pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CachedPixelFormat {
+    Rgba8,
+}
+
+/// Identifies a decoded source frame, as scaled to fit within an output size.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FrameCacheKey {
+    pub media: MediaKey,
+    pub stream_index: usize,
+    /// Whether the frame was decoded from the media's proxy rather than the
+    /// original.
+    pub proxy: bool,
+    /// The frame of the source media, in project frames.
+    pub frame: FrameNum,
+    /// The project frame rate `frame` counts in, since the same number is a
+    /// different source frame at another rate.
+    pub fps: JadeRational,
+    /// The size the frame was fitted within, which is usually the project's
+    /// resolution rather than the size of the cached image.
+    pub fit_width: u32,
+    pub fit_height: u32,
+    pub format: CachedPixelFormat,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct FrameCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub entries: usize,
+    pub bytes_used: usize,
+}
+
+impl FrameCacheStats {
+    /// The fraction of lookups that found their frame, or zero before any.
+    pub fn hit_rate(&self) -> f64 {
+        self.hits as f64 / (self.hits + self.misses).max(1) as f64
+    }
+}
+
+struct CachedFrame {
+    image: Arc<RgbaImage>,
+    last_used: u64,
+}
+
+/// Decoded frames, kept within a memory budget by dropping the least recently
+/// used ones first.
+pub struct FrameCache {
+    budget_bytes: usize,
+    frames: HashMap<FrameCacheKey, CachedFrame>,
+    /// Every cached frame, by when it was last used.
+    by_last_used: BTreeMap<u64, FrameCacheKey>,
+    /// Counts up on every use, to order frames by recency.
+    use_counter: u64,
+    stats: FrameCacheStats,
+}
+
+/// A frame cache shared between the threads that fill and read it.
+pub type SharedFrameCache = Arc<Mutex<FrameCache>>;
+
+impl FrameCache {
+    pub fn new(budget_bytes: usize) -> Self {
+        Self {
+            budget_bytes,
+            frames: HashMap::new(),
+            by_last_used: BTreeMap::new(),
+            use_counter: 0,
+            stats: FrameCacheStats::default(),
+        }
+    }
+
+    pub fn shared(budget_bytes: usize) -> SharedFrameCache {
+        Arc::new(Mutex::new(Self::new(budget_bytes)))
+    }
+
+    pub fn stats(&self) -> FrameCacheStats {
+        self.stats
+    }
+
+    /// Looks up a frame, counting it as a hit or miss and marking it as
+    /// recently used.
+    pub fn get(&mut self, key: &FrameCacheKey) -> Option<Arc<RgbaImage>> {
+        let Some(cached) = self.frames.get_mut(key) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        self.stats.hits += 1;
+        self.by_last_used.remove(&cached.last_used);
+        self.use_counter += 1;
+        cached.last_used = self.use_counter;
+        self.by_last_used.insert(cached.last_used, *key);
+        Some(cached.image.clone())
+    }
+
+    /// Whether a frame is cached, without counting a lookup or touching its
+    /// recency. For deciding what to prefetch.
+    pub fn contains(&self, key: &FrameCacheKey) -> bool {
+        self.frames.contains_key(key)
+    }
+
+    /// Caches a frame as the most recently used, making room for it if
+    /// needed. Frames bigger than the whole budget aren't cached at all.
+    pub fn insert(&mut self, key: FrameCacheKey, image: Arc<RgbaImage>) {
+        self.remove(&key);
+        let size = image_size(&image);
+        if size > self.budget_bytes {
+            return;
+        }
+        self.evict_to(self.budget_bytes - size);
+
+        self.use_counter += 1;
+        self.by_last_used.insert(self.use_counter, key);
+        self.frames.insert(
+            key,
+            CachedFrame {
+                image,
+                last_used: self.use_counter,
+            },
+        );
+        self.stats.entries += 1;
+        self.stats.bytes_used += size;
+    }
+
+    pub fn remove(&mut self, key: &FrameCacheKey) -> bool {
+        let Some(cached) = self.frames.remove(key) else {
+            return false;
+        };
+        self.by_last_used.remove(&cached.last_used);
+        self.stats.entries -= 1;
+        self.stats.bytes_used -= image_size(&cached.image);
+        true
+    }
+
+    /// Drops every frame of a piece of media, for when it's been removed or
+    /// pointed at a different file.
+    pub fn invalidate_media(&mut self, media: MediaKey) {
+        let keys: Vec<FrameCacheKey> = self
+            .frames
+            .keys()
+            .filter(|key| key.media == media)
+            .copied()
+            .collect();
+        for key in keys {
+            self.remove(&key);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.by_last_used.clear();
+        self.stats.entries = 0;
+        self.stats.bytes_used = 0;
+    }
+
+    /// Evicts the least recently used frames until no more than
+    /// `target_bytes` are used.
+    fn evict_to(&mut self, target_bytes: usize) {
+        while self.stats.bytes_used > target_bytes {
+            let Some((_, key)) = self.by_last_used.pop_first() else {
+                break;
+            };
+            let cached = self
+                .frames
+                .remove(&key)
+                .expect("frame cache recency index is out of sync");
+            self.stats.entries -= 1;
+            self.stats.bytes_used -= image_size(&cached.image);
+            self.stats.evictions += 1;
+        }
+    }
+}
+
+fn image_size(image: &RgbaImage) -> usize {
+    image.as_raw().len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The size in bytes of every frame from `frame()`.
+    const FRAME_BYTES: usize = 2 * 2 * 4;
+
+    fn key(frame: u64) -> FrameCacheKey {
+        FrameCacheKey {
+            media: MediaKey::default(),
+            stream_index: 0,
+            proxy: false,
+            frame: FrameNum(frame),
+            fps: JadeRational::new(30, 1),
+            fit_width: 2,
+            fit_height: 2,
+            format: CachedPixelFormat::Rgba8,
+        }
+    }
+
+    fn frame() -> Arc<RgbaImage> {
+        Arc::new(RgbaImage::new(2, 2))
+    }
+
+    fn cached(cache: &FrameCache) -> Vec<u64> {
+        let mut frames: Vec<u64> = cache.frames.keys().map(|key| key.frame.0).collect();
+        frames.sort();
+        frames
+    }
+
+    #[test]
+    fn evicts_least_recently_used_first() {
+        let mut cache = FrameCache::new(3 * FRAME_BYTES);
+        for frame_num in 0..3 {
+            cache.insert(key(frame_num), frame());
+        }
+        assert_eq!(cached(&cache), [0, 1, 2]);
+
+        cache.insert(key(3), frame());
+        assert_eq!(cached(&cache), [1, 2, 3]);
+        cache.insert(key(4), frame());
+        assert_eq!(cached(&cache), [2, 3, 4]);
+    }
+
+    #[test]
+    fn hits_refresh_recency() {
+        let mut cache = FrameCache::new(3 * FRAME_BYTES);
+        for frame_num in 0..3 {
+            cache.insert(key(frame_num), frame());
+        }
+        assert!(cache.get(&key(0)).is_some());
+
+        cache.insert(key(3), frame());
+        assert_eq!(cached(&cache), [0, 2, 3]);
+
+        // Checking for a frame doesn't count as using it.
+        assert!(cache.contains(&key(2)));
+        cache.insert(key(4), frame());
+        assert_eq!(cached(&cache), [0, 3, 4]);
+    }
+
+    #[test]
+    fn skips_frames_bigger_than_the_budget() {
+        let mut cache = FrameCache::new(3 * FRAME_BYTES);
+        cache.insert(key(0), frame());
+
+        let big_key = FrameCacheKey {
+            fit_width: 4,
+            fit_height: 4,
+            ..key(1)
+        };
+        cache.insert(big_key, Arc::new(RgbaImage::new(4, 4)));
+        assert!(!cache.contains(&big_key));
+        assert_eq!(cached(&cache), [0]);
+        assert_eq!(cache.stats().evictions, 0);
+    }
+
+    #[test]
+    fn counts_lookups_and_usage() {
+        let mut cache = FrameCache::new(2 * FRAME_BYTES);
+        assert!(cache.get(&key(0)).is_none());
+        cache.insert(key(0), frame());
+        cache.insert(key(1), frame());
+        assert!(cache.get(&key(0)).is_some());
+        assert!(cache.get(&key(0)).is_some());
+        cache.insert(key(2), frame());
+        // Replacing a frame swaps it rather than adding another.
+        cache.insert(key(2), frame());
+
+        let stats = cache.stats();
+        assert_eq!(
+            stats,
+            FrameCacheStats {
+                hits: 2,
+                misses: 1,
+                evictions: 1,
+                entries: 2,
+                bytes_used: 2 * FRAME_BYTES,
+            }
+        );
+        assert!((stats.hit_rate() - 2.0 / 3.0).abs() < 1e-9);
+
+        assert!(cache.remove(&key(2)));
+        assert!(!cache.remove(&key(2)));
+        assert_eq!(cache.stats().bytes_used, FRAME_BYTES);
+        cache.clear();
+        assert_eq!(cache.stats().entries, 0);
+        assert_eq!(cache.stats().bytes_used, 0);
+    }
+}
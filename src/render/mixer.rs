@@ -0,0 +1,85 @@
+use crate::{
+    ff_interop::audio_player::{AudioChunk, FfmpegAudioDecoder},
+    project::{FrameNum, MediaKey, MediaProject, TrackKind},
+};
+use anyhow::Context;
+use std::collections::{HashMap, hash_map::Entry};
+
+/// Sums the audio tracks of a project into the project's `AudioSpec`, one
+/// frame's worth of samples at a time.
+#[derive(Default)]
+pub struct AudioMixer {
+    decoders: HashMap<(MediaKey, usize), FfmpegAudioDecoder>,
+}
+
+impl AudioMixer {
+    pub fn mix_frame(
+        &mut self,
+        project: &MediaProject,
+        frame: FrameNum,
+    ) -> anyhow::Result<AudioChunk> {
+        let start_sample = project.audio.first_sample_of_frame(frame, project.fps);
+        let sample_count = (project
+            .audio
+            .first_sample_of_frame(FrameNum(frame.0 + 1), project.fps)
+            - start_sample) as usize;
+        let mut planes = vec![vec![0.0f32; sample_count]; project.audio.channels as usize];
+
+        for track in project.timeline.tracks(TrackKind::Audio) {
+            let Some(clip) = track.clip_at(frame) else {
+                continue;
+            };
+            // Offset by whole samples from where the clip starts, rather than
+            // rounding the source frame's first sample, so the source is read
+            // in one gapless run even when frames aren't a whole number of
+            // samples long.
+            let source_start = start_sample
+                - project
+                    .audio
+                    .first_sample_of_frame(clip.timeline_start, project.fps)
+                + project
+                    .audio
+                    .first_sample_of_frame(clip.source.from, project.fps);
+            let chunk = self
+                .decoder_for(project, clip.media, clip.stream_index)?
+                .read_samples(source_start, sample_count)
+                .with_context(|| format!("failed to decode audio for clip {:?}", clip.id))?;
+
+            for (mixed, samples) in planes.iter_mut().zip(&chunk.planes) {
+                for (mixed, sample) in mixed.iter_mut().zip(samples) {
+                    *mixed += sample;
+                }
+            }
+        }
+
+        for sample in planes.iter_mut().flatten() {
+            *sample = sample.clamp(-1.0, 1.0);
+        }
+        Ok(AudioChunk {
+            start_sample,
+            planes,
+        })
+    }
+
+    fn decoder_for(
+        &mut self,
+        project: &MediaProject,
+        media: MediaKey,
+        stream_index: usize,
+    ) -> anyhow::Result<&mut FfmpegAudioDecoder> {
+        match self.decoders.entry((media, stream_index)) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                let info = project
+                    .media
+                    .get(media)
+                    .context("clip references media that is not in the project")?;
+                let input_ctx = ffmpeg_next::format::input(&info.path).with_context(|| {
+                    format!("failed to load format info for {}", info.path.display())
+                })?;
+                let decoder = FfmpegAudioDecoder::new(input_ctx, stream_index, project.audio)?;
+                Ok(entry.insert(decoder))
+            }
+        }
+    }
+}
@@ -0,0 +1,392 @@
+mod compositor;
+mod frame_cache;
+mod mixer;
+mod prefetch;
+mod yuv;
+
+pub use compositor::*;
+pub use frame_cache::*;
+pub use mixer::*;
+pub use prefetch::*;
+pub use yuv::*;
+
+use crate::{
+    disk_cache,
+    ff_interop::encoder::{EncodeSettings, FfmpegEncoder},
+    project::{FrameNum, MediaProject},
+};
+use anyhow::bail;
+use log::info;
+use std::{
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+/// Shared flag for stopping a render from another thread. Clones all refer to
+/// the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RenderProgress {
+    pub frames_done: u64,
+    pub frames_total: u64,
+}
+
+impl RenderProgress {
+    pub fn fraction(&self) -> f64 {
+        self.frames_done as f64 / self.frames_total.max(1) as f64
+    }
+}
+
+/// Renders the whole timeline of a project to a video file, without touching
+/// any windowing or GPU state. `on_progress` is called after every frame. The
+/// file only appears at `output` once it's complete: if `cancel` is triggered
+/// or rendering fails, the partial output is deleted and an error is returned.
+pub fn render_project(
+    project: &MediaProject,
+    output: &Path,
+    settings: &EncodeSettings,
+    cancel: &CancelToken,
+    on_progress: impl FnMut(RenderProgress),
+) -> anyhow::Result<()> {
+    let end_frame = project.timeline.end_frame();
+    if end_frame.0 == 0 {
+        bail!("project timeline is empty, there is nothing to render");
+    }
+
+    info!(
+        "rendering {} frames at {}x{} to \"{}\" with {:?}",
+        end_frame.0,
+        project.resolution.width,
+        project.resolution.height,
+        output.display(),
+        settings
+    );
+    disk_cache::write_atomic(output, |partial| {
+        render_frames(project, partial, settings, cancel, on_progress)
+    })?;
+    info!("finished rendering \"{}\"", output.display());
+    Ok(())
+}
+
+fn render_frames(
+    project: &MediaProject,
+    output: &Path,
+    settings: &EncodeSettings,
+    cancel: &CancelToken,
+    mut on_progress: impl FnMut(RenderProgress),
+) -> anyhow::Result<()> {
+    let end_frame = project.timeline.end_frame();
+    let mut compositor = Compositor::default();
+    let mut mixer = AudioMixer::default();
+    let mut encoder = FfmpegEncoder::new(
+        output,
+        project.resolution,
+        project.fps,
+        project.audio,
+        settings,
+    )?;
+
+    for frame in 0..end_frame.0 {
+        if cancel.is_cancelled() {
+            bail!("render was cancelled");
+        }
+
+        let image = compositor.render_frame(project, FrameNum(frame))?;
+        encoder.encode_frame(&image)?;
+        if encoder.has_audio() {
+            let chunk = mixer.mix_frame(project, FrameNum(frame))?;
+            encoder.encode_audio(&chunk)?;
+        }
+
+        on_progress(RenderProgress {
+            frames_done: frame + 1,
+            frames_total: end_frame.0,
+        });
+    }
+
+    encoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cli::{self, Command},
+        ff_interop::{
+            audio_player::FfmpegAudioDecoder,
+            encoder::{AudioCodec, Container},
+            load_media_sync, rgba_frame_to_image,
+            video_player::FfmpegVideoDecoder,
+        },
+        project::{
+            self, AudioSpec, FrameSpan, JadeRational, MediaPool, MediaStream, Timeline, TrackKind,
+            TrackRef,
+        },
+        test_media::{self, CLIP_FPS, CLIP_FRAMES, CLIP_SIZE},
+    };
+    use std::fs;
+
+    const NTSC_FPS: JadeRational = JadeRational {
+        num: 30000,
+        den: 1001,
+    };
+
+    #[test]
+    fn renders_headless_from_the_command_line() {
+        let dir = test_media::temp_dir("headless-render");
+        let (project, _) =
+            test_media::numbered_project(test_media::numbered_clip(), CLIP_FRAMES).unwrap();
+        let project_path = dir.join("project.jvp");
+        project::save_project(&project, &project_path).unwrap();
+        let output = dir.join("out.mp4");
+
+        let args = [
+            "render",
+            project_path.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "--crf",
+            "10",
+        ];
+        let Command::Render {
+            project,
+            output,
+            mut settings,
+        } = cli::parse_args(args.into_iter().map(String::from)).unwrap()
+        else {
+            panic!("expected a render command");
+        };
+        settings.speed_preset = Some("ultrafast".to_string());
+        let project = project::load_project(&project).unwrap();
+        let mut last_progress = None;
+        render_project(
+            &project,
+            &output,
+            &settings,
+            &CancelToken::default(),
+            |progress| last_progress = Some(progress),
+        )
+        .unwrap();
+        assert_eq!(
+            last_progress,
+            Some(RenderProgress {
+                frames_done: CLIP_FRAMES,
+                frames_total: CLIP_FRAMES,
+            })
+        );
+
+        let info = load_media_sync(output.clone()).unwrap();
+        assert_eq!(info.video_streams().count(), 1);
+        assert_eq!(info.audio_streams().count(), 1);
+        assert_eq!(info.streams.len(), 2);
+        let (_, video) = info.video_streams().next().unwrap();
+        assert_eq!(
+            (video.width, video.height),
+            (CLIP_SIZE.width, CLIP_SIZE.height)
+        );
+        let expected_secs = CLIP_FRAMES as f64 / CLIP_FPS.to_f64();
+        for stream in info.streams.iter().map(MediaStream::info) {
+            let secs = stream.length.secs();
+            assert!(
+                (secs - expected_secs).abs() < 0.1,
+                "stream {} lasts {secs}s rather than {expected_secs}s",
+                stream.index
+            );
+        }
+
+        // The frames made it through in order.
+        let input_ctx = ffmpeg_next::format::input(&output).unwrap();
+        let mut decoder = FfmpegVideoDecoder::new(input_ctx, 0, CLIP_FPS).unwrap();
+        for frame in [0, 1, 42, CLIP_FRAMES - 1] {
+            let decoded = decoder.decode_frame_at(FrameNum(frame)).unwrap();
+            assert_eq!(
+                test_media::frame_number_of(&rgba_frame_to_image(&decoded)),
+                frame
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_an_empty_timeline() {
+        let dir = test_media::temp_dir("empty-render");
+        let (mut project, _) =
+            test_media::numbered_project(test_media::numbered_clip(), 1).unwrap();
+        project.timeline = Default::default();
+        let output = dir.join("out.mp4");
+        let settings = crate::ff_interop::encoder::EncodePreset::H264HighQuality.settings();
+        assert!(
+            render_project(
+                &project,
+                &output,
+                &settings,
+                &CancelToken::default(),
+                |_| {}
+            )
+            .is_err()
+        );
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn renders_gapless_audio_at_fractional_frame_rates() {
+        let dir = test_media::temp_dir("fractional-render");
+        let info = load_media_sync(test_media::tone_clip().to_path_buf()).unwrap();
+        let stream_index = info.audio_streams().next().unwrap().0.index;
+        // At 29.97 fps neither frame 3 nor frame 7 starts on a whole sample.
+        let (source_from, timeline_start, len) = (FrameNum(3), FrameNum(7), 60);
+        let end_frame = timeline_start.0 + len;
+        let mut project = MediaProject {
+            fps: NTSC_FPS,
+            frame_count: end_frame as u32,
+            resolution: CLIP_SIZE,
+            audio: AudioSpec::default(),
+            media: MediaPool::default(),
+            timeline: Timeline::default(),
+        };
+        let key = project.media.insert(info);
+        project
+            .add_clip(
+                TrackRef {
+                    kind: TrackKind::Audio,
+                    index: 0,
+                },
+                key,
+                stream_index,
+                FrameSpan::with_len(source_from, len).unwrap(),
+                timeline_start,
+            )
+            .unwrap();
+
+        let output = dir.join("out.mkv");
+        let settings = EncodeSettings {
+            container: Container::Mkv,
+            audio_codec: Some(AudioCodec::Flac),
+            ..test_media::clip_settings()
+        };
+        let mut progress = vec![];
+        render_project(
+            &project,
+            &output,
+            &settings,
+            &CancelToken::default(),
+            |done| progress.push(done),
+        )
+        .unwrap();
+        let expected_progress: Vec<_> = (1..=end_frame)
+            .map(|frames_done| RenderProgress {
+                frames_done,
+                frames_total: end_frame,
+            })
+            .collect();
+        assert_eq!(progress, expected_progress);
+
+        let spec = project.audio;
+        let clip_start = spec.first_sample_of_frame(timeline_start, NTSC_FPS);
+        let source_start = spec.first_sample_of_frame(source_from, NTSC_FPS);
+        let end_sample = spec.first_sample_of_frame(FrameNum(end_frame), NTSC_FPS);
+        let audio_index = load_media_sync(output.clone())
+            .unwrap()
+            .audio_streams()
+            .next()
+            .unwrap()
+            .0
+            .index;
+        let input_ctx = ffmpeg_next::format::input(&output).unwrap();
+        let mut decoder = FfmpegAudioDecoder::new(input_ctx, audio_index, spec).unwrap();
+        let rendered = decoder.read_samples(0, end_sample as usize).unwrap();
+        // Silence until the clip, then the tone from the clip's first source
+        // sample on, without a sample dropped or repeated at any frame.
+        for plane in &rendered.planes {
+            for (sample, &level) in (0u64..).zip(plane) {
+                let expected = match sample.checked_sub(clip_start) {
+                    Some(offset) => {
+                        test_media::tone_sample(source_start + offset, spec.sample_rate)
+                    }
+                    None => 0.0,
+                };
+                assert!(
+                    (level - expected).abs() < 1e-3,
+                    "sample {sample} is {level} rather than {expected}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn stops_and_removes_the_output_when_cancelled() {
+        let dir = test_media::temp_dir("cancelled-render");
+        let (project, _) =
+            test_media::numbered_project(test_media::numbered_clip(), CLIP_FRAMES).unwrap();
+        let output = dir.join("out.mp4");
+        let cancel = CancelToken::default();
+        let mut frames_done = 0;
+        let result = render_project(
+            &project,
+            &output,
+            &test_media::clip_settings(),
+            &cancel,
+            |progress| {
+                frames_done = progress.frames_done;
+                if frames_done == 10 {
+                    cancel.cancel();
+                }
+            },
+        );
+        assert!(result.is_err());
+        assert_eq!(frames_done, 10);
+        assert!(!output.exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn leaves_no_partial_output_when_a_frame_fails() {
+        let dir = test_media::temp_dir("failed-render");
+        let (mut project, key) =
+            test_media::numbered_project(test_media::numbered_clip(), 20).unwrap();
+        let stream_index = project.media[key].video_streams().next().unwrap().0.index;
+        let mut missing = project.media[key].clone();
+        missing.path = dir.join("missing.mp4");
+        let missing = project.media.insert(missing);
+        project
+            .add_clip(
+                TrackRef {
+                    kind: TrackKind::Video,
+                    index: 0,
+                },
+                missing,
+                stream_index,
+                FrameSpan::with_len(FrameNum(0), 10).unwrap(),
+                FrameNum(20),
+            )
+            .unwrap();
+
+        let output = dir.join("out.mp4");
+        let mut frames_done = 0;
+        let result = render_project(
+            &project,
+            &output,
+            &test_media::clip_settings(),
+            &CancelToken::default(),
+            |progress| frames_done = progress.frames_done,
+        );
+        assert!(result.is_err());
+        assert_eq!(frames_done, 20);
+        assert!(!output.exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+    }
+}
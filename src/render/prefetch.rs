@@ -0,0 +1,118 @@
+use super::{Compositor, SharedFrameCache};
+use crate::project::{FrameNum, FrameSpan, FrameSpanSet, MediaProject, TrackKind};
+use log::warn;
+use std::{
+    ops::Range,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+};
+
+/// How many frames after the playhead are decoded ahead of time.
+const PREFETCH_AHEAD: u64 = 24;
+/// How many frames before the playhead are decoded ahead of time, for
+/// scrubbing backwards.
+const PREFETCH_BEHIND: u64 = 8;
+
+struct PrefetchRequest {
+    project: MediaProject,
+    around: FrameNum,
+    use_proxies: bool,
+    generation: u64,
+}
+
+/// Fills a frame cache with the frames around the playhead on a background
+/// thread, so scrubbing near it doesn't have to wait on the decoder. Each
+/// request replaces the last.
+pub struct FramePrefetcher {
+    requests: Sender<PrefetchRequest>,
+    /// Bumped by every request and cancellation. The thread gives up on a
+    /// request as soon as this moves past it.
+    generation: Arc<AtomicU64>,
+}
+
+impl FramePrefetcher {
+    pub fn new(cache: SharedFrameCache) -> Self {
+        let (requests, receiver) = mpsc::channel();
+        let generation = Arc::new(AtomicU64::new(0));
+        let thread_generation = generation.clone();
+        thread::Builder::new()
+            .name("frame prefetch".to_string())
+            .spawn(move || prefetch_frames(cache, receiver, thread_generation))
+            .expect("failed to spawn frame prefetch thread");
+        Self {
+            requests,
+            generation,
+        }
+    }
+
+    /// Starts caching the frames around `frame`, the ones after it first,
+    /// decoded from proxies or not to match the preview.
+    pub fn prefetch(&self, project: &MediaProject, frame: FrameNum, use_proxies: bool) {
+        let generation = self.generation.fetch_add(1, Ordering::Relaxed) + 1;
+        // The thread only stops once this is dropped, so sending can't fail.
+        let _ = self.requests.send(PrefetchRequest {
+            project: project.clone(),
+            around: frame,
+            use_proxies,
+            generation,
+        });
+    }
+
+    /// Stops working on the last request, e.g. while playback is using the
+    /// decoders.
+    pub fn cancel(&self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for FramePrefetcher {
+    fn drop(&mut self) {
+        // The thread stops once the channel closes, but that's only noticed
+        // between requests.
+        self.cancel();
+    }
+}
+
+fn prefetch_frames(
+    cache: SharedFrameCache,
+    requests: Receiver<PrefetchRequest>,
+    generation: Arc<AtomicU64>,
+) {
+    let mut compositor = Compositor::with_cache(cache);
+    while let Ok(mut request) = requests.recv() {
+        // Only the newest request matters.
+        while let Ok(newer) = requests.try_recv() {
+            request = newer;
+        }
+        compositor.set_use_proxies(request.use_proxies);
+
+        // Frames in gaps between clips have nothing to decode.
+        let video = request.project.timeline.covered_frames(TrackKind::Video);
+        let around = request.around;
+        let ahead = FrameSpan {
+            from: around,
+            to_excl: FrameNum(around.0.saturating_add(PREFETCH_AHEAD)),
+        };
+        let behind = FrameSpan {
+            from: FrameNum(around.0.saturating_sub(PREFETCH_BEHIND)),
+            to_excl: around,
+        };
+        let frames = [ahead, behind]
+            .into_iter()
+            .flat_map(|window| video.intersection(&FrameSpanSet::from_iter([window])))
+            .flat_map(Range::from);
+        for frame in frames {
+            if generation.load(Ordering::Relaxed) != request.generation {
+                break;
+            }
+            if let Err(err) = compositor.cache_frame(&request.project, FrameNum(frame)) {
+                warn!("stopped prefetching, failed to decode frame {frame}: {err:#}");
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,418 @@
+#[cfg(test)]
+use image::{Rgba, RgbaImage};
+
+/// How a YUV frame's planes are laid out.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum YuvLayout {
+    /// Separate Y, U and V planes, with chroma at half width and height.
+    Planar420,
+    /// A Y plane and one plane of interleaved U and V, at half width and
+    /// height, as NV12.
+    SemiPlanar420,
+    /// Separate Y, U and V planes, with chroma at half width.
+    Planar422,
+}
+
+impl YuvLayout {
+    /// How many times to halve luma coordinates, horizontally and vertically,
+    /// to find the matching chroma sample.
+    pub fn chroma_shift(self) -> (u32, u32) {
+        match self {
+            Self::Planar420 | Self::SemiPlanar420 => (1, 1),
+            Self::Planar422 => (1, 0),
+        }
+    }
+
+    pub fn is_interleaved(self) -> bool {
+        self == Self::SemiPlanar420
+    }
+}
+
+/// Which standard's matrix turns Y'CbCr into R'G'B'.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum YuvMatrix {
+    Bt601,
+    Bt709,
+    Bt2020,
+}
+
+impl YuvMatrix {
+    /// The luma weights of red and blue, Kr and Kb.
+    fn weights(self) -> (f32, f32) {
+        match self {
+            Self::Bt601 => (0.299, 0.114),
+            Self::Bt709 => (0.2126, 0.0722),
+            Self::Bt2020 => (0.2627, 0.0593),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum YuvRange {
+    /// Broadcast "TV" range, 16-235 for luma and 16-240 for chroma at 8 bits.
+    Limited,
+    /// "PC" or JPEG range, using every code.
+    Full,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct YuvColorimetry {
+    pub matrix: YuvMatrix,
+    pub range: YuvRange,
+}
+
+impl YuvColorimetry {
+    /// Reads FFmpeg's names for a stream's color space and range, as
+    /// `ColorInfo` holds them. Files that don't say are assumed to be
+    /// limited range, BT.709 if they're HD or bigger and BT.601 otherwise,
+    /// as most players do.
+    pub fn from_names(space: Option<&str>, range: Option<&str>, height: u32) -> Self {
+        let matrix = match space {
+            Some("bt709") => YuvMatrix::Bt709,
+            Some("bt2020nc" | "bt2020c") => YuvMatrix::Bt2020,
+            Some("bt470bg" | "smpte170m" | "fcc") => YuvMatrix::Bt601,
+            _ if height >= 720 => YuvMatrix::Bt709,
+            _ => YuvMatrix::Bt601,
+        };
+        let range = match range {
+            Some("pc" | "jpeg") => YuvRange::Full,
+            _ => YuvRange::Limited,
+        };
+        Self { matrix, range }
+    }
+
+    /// The conversion for samples of the given bit depth.
+    pub fn conversion(self, bit_depth: u8) -> YuvConversion {
+        let (kr, kb) = self.matrix.weights();
+        let kg = 1.0 - kr - kb;
+        let step = (1u32 << (bit_depth - 8)) as f32;
+        let max_code = ((1u32 << bit_depth) - 1) as f32;
+        let (offsets, scales) = match self.range {
+            YuvRange::Limited => (
+                [16.0 * step, 128.0 * step, 128.0 * step],
+                [
+                    1.0 / (219.0 * step),
+                    1.0 / (224.0 * step),
+                    1.0 / (224.0 * step),
+                ],
+            ),
+            YuvRange::Full => (
+                [0.0, 128.0 * step, 128.0 * step],
+                [1.0 / max_code, 1.0 / max_code, 1.0 / max_code],
+            ),
+        };
+        YuvConversion {
+            matrix: [
+                [1.0, 0.0, 2.0 * (1.0 - kr)],
+                [
+                    1.0,
+                    -2.0 * kb * (1.0 - kb) / kg,
+                    -2.0 * kr * (1.0 - kr) / kg,
+                ],
+                [1.0, 2.0 * (1.0 - kb), 0.0],
+            ],
+            offsets,
+            scales,
+        }
+    }
+}
+
+/// Turns raw Y, U and V codes into R'G'B' between 0 and 1. The GPU shader
+/// does the same sums with these numbers, so the two paths agree.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct YuvConversion {
+    /// Rows of the matrix taking (Y', Cb, Cr) to R'G'B'.
+    pub matrix: [[f32; 3]; 3],
+    /// Subtracted from the Y, U and V codes first.
+    pub offsets: [f32; 3],
+    /// Then multiplied in, leaving Y' from 0 to 1 and Cb and Cr from -0.5 to
+    /// 0.5.
+    pub scales: [f32; 3],
+}
+
+impl YuvConversion {
+    #[cfg(test)]
+    pub fn to_rgb(self, codes: [u32; 3]) -> [f32; 3] {
+        let yuv: [f32; 3] =
+            std::array::from_fn(|i| (codes[i] as f32 - self.offsets[i]) * self.scales[i]);
+        self.matrix
+            .map(|row| (row[0] * yuv[0] + row[1] * yuv[1] + row[2] * yuv[2]).clamp(0.0, 1.0))
+    }
+}
+
+/// One plane of a YUV frame, packed with no padding between rows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct YuvPlane {
+    pub width: u32,
+    pub height: u32,
+    /// Samples per pixel, 2 for interleaved chroma and 1 otherwise.
+    pub components: u32,
+    /// Each sample is a byte, or a little-endian `u16` above 8 bits.
+    pub data: Vec<u8>,
+}
+
+impl YuvPlane {
+    #[cfg(test)]
+    fn sample(&self, x: u32, y: u32, component: u32, wide: bool) -> u32 {
+        let index = ((y * self.width + x) * self.components + component) as usize;
+        if wide {
+            u16::from_le_bytes([self.data[index * 2], self.data[index * 2 + 1]]) as u32
+        } else {
+            self.data[index] as u32
+        }
+    }
+}
+
+/// A decoded frame left in its YUV planes, for converting on the GPU.
+#[derive(Debug, Clone, PartialEq)]
+pub struct YuvFrame {
+    pub width: u32,
+    pub height: u32,
+    pub layout: YuvLayout,
+    /// 8 or 10. Deeper samples take two bytes each.
+    pub bit_depth: u8,
+    pub colorimetry: YuvColorimetry,
+    /// Y, then U and V, or the interleaved UV plane.
+    pub planes: Vec<YuvPlane>,
+}
+
+impl YuvFrame {
+    pub fn bytes_per_sample(&self) -> u32 {
+        if self.bit_depth > 8 { 2 } else { 1 }
+    }
+
+    /// The raw Y, U and V codes of a pixel, with chroma taken from the
+    /// nearest sample rather than interpolated, as the shader does.
+    #[cfg(test)]
+    pub fn codes_at(&self, x: u32, y: u32) -> [u32; 3] {
+        let wide = self.bit_depth > 8;
+        let (shift_x, shift_y) = self.layout.chroma_shift();
+        let (cx, cy) = (x >> shift_x, y >> shift_y);
+        let luma = self.planes[0].sample(x, y, 0, wide);
+        if self.layout.is_interleaved() {
+            let chroma = &self.planes[1];
+            [
+                luma,
+                chroma.sample(cx, cy, 0, wide),
+                chroma.sample(cx, cy, 1, wide),
+            ]
+        } else {
+            [
+                luma,
+                self.planes[1].sample(cx, cy, 0, wide),
+                self.planes[2].sample(cx, cy, 0, wide),
+            ]
+        }
+    }
+
+    /// Converts the frame on the CPU. Gives the same picture as the GPU path,
+    /// give or take rounding, for checking one against the other.
+    #[cfg(test)]
+    pub fn to_rgba(&self) -> RgbaImage {
+        let conversion = self.colorimetry.conversion(self.bit_depth);
+        RgbaImage::from_fn(self.width, self.height, |x, y| {
+            let [r, g, b] = conversion
+                .to_rgb(self.codes_at(x, y))
+                .map(|channel| (channel * 255.0).round() as u8);
+            Rgba([r, g, b, 255])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each channel of a 75% bar, in 8-bit R'G'B'.
+    const BAR_LEVEL: f32 = 0.75 * 255.0;
+    /// The R'G'B' of each 75% colour bar, as 0 or `BAR_LEVEL` per channel:
+    /// white, yellow, cyan, green, magenta, red and blue.
+    const BARS: [[bool; 3]; 7] = [
+        [true, true, true],
+        [true, true, false],
+        [false, true, true],
+        [false, true, false],
+        [true, false, true],
+        [true, false, false],
+        [false, false, true],
+    ];
+
+    fn colorimetry(matrix: YuvMatrix, range: YuvRange) -> YuvColorimetry {
+        YuvColorimetry { matrix, range }
+    }
+
+    fn to_8_bit(rgb: [f32; 3]) -> [f32; 3] {
+        rgb.map(|channel| (channel * 255.0).round())
+    }
+
+    #[test]
+    fn converts_black_and_white_at_every_depth() {
+        for matrix in [YuvMatrix::Bt601, YuvMatrix::Bt709, YuvMatrix::Bt2020] {
+            let limited = colorimetry(matrix, YuvRange::Limited);
+            let full = colorimetry(matrix, YuvRange::Full);
+            for (conversion, black, white) in [
+                (limited.conversion(8), [16, 128, 128], [235, 128, 128]),
+                (limited.conversion(10), [64, 512, 512], [940, 512, 512]),
+                (full.conversion(8), [0, 128, 128], [255, 128, 128]),
+                (full.conversion(10), [0, 512, 512], [1023, 512, 512]),
+            ] {
+                assert_eq!(to_8_bit(conversion.to_rgb(black)), [0.0; 3], "{matrix:?}");
+                assert_eq!(to_8_bit(conversion.to_rgb(white)), [255.0; 3], "{matrix:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn clamps_codes_outside_the_legal_range() {
+        let conversion = colorimetry(YuvMatrix::Bt709, YuvRange::Limited).conversion(8);
+        assert_eq!(conversion.to_rgb([0, 128, 128]), [0.0; 3]);
+        assert_eq!(conversion.to_rgb([255, 128, 128]), [1.0; 3]);
+    }
+
+    #[test]
+    fn converts_75_percent_colour_bars() {
+        // Y, Cb and Cr of each bar, in the order of `BARS`.
+        let tables = [
+            (
+                colorimetry(YuvMatrix::Bt601, YuvRange::Limited),
+                [
+                    [180, 128, 128],
+                    [162, 44, 142],
+                    [131, 156, 44],
+                    [112, 72, 58],
+                    [84, 184, 198],
+                    [65, 100, 212],
+                    [35, 212, 114],
+                ],
+            ),
+            (
+                colorimetry(YuvMatrix::Bt601, YuvRange::Full),
+                [
+                    [191, 128, 128],
+                    [169, 32, 144],
+                    [134, 160, 32],
+                    [112, 65, 48],
+                    [79, 191, 208],
+                    [57, 96, 224],
+                    [22, 224, 112],
+                ],
+            ),
+            (
+                colorimetry(YuvMatrix::Bt709, YuvRange::Limited),
+                [
+                    [180, 128, 128],
+                    [168, 44, 136],
+                    [145, 147, 44],
+                    [133, 63, 52],
+                    [63, 193, 204],
+                    [51, 109, 212],
+                    [28, 212, 120],
+                ],
+            ),
+            (
+                colorimetry(YuvMatrix::Bt709, YuvRange::Full),
+                [
+                    [191, 128, 128],
+                    [177, 32, 137],
+                    [151, 150, 32],
+                    [137, 54, 41],
+                    [54, 202, 215],
+                    [41, 106, 224],
+                    [14, 224, 119],
+                ],
+            ),
+            (
+                colorimetry(YuvMatrix::Bt2020, YuvRange::Limited),
+                [
+                    [180, 128, 128],
+                    [171, 44, 135],
+                    [137, 151, 44],
+                    [127, 67, 51],
+                    [69, 189, 205],
+                    [59, 105, 212],
+                    [26, 212, 121],
+                ],
+            ),
+            (
+                colorimetry(YuvMatrix::Bt2020, YuvRange::Full),
+                [
+                    [191, 128, 128],
+                    [180, 32, 136],
+                    [141, 155, 32],
+                    [130, 59, 40],
+                    [62, 197, 216],
+                    [50, 101, 224],
+                    [11, 224, 120],
+                ],
+            ),
+        ];
+        for (colorimetry, codes) in tables {
+            let conversion = colorimetry.conversion(8);
+            for (bar, codes) in BARS.iter().zip(codes) {
+                let expected = bar.map(|on| if on { BAR_LEVEL } else { 0.0 });
+                let rgb = to_8_bit(conversion.to_rgb(codes));
+                // The codes are rounded, so the bars come back within a
+                // couple of levels.
+                assert!(
+                    rgb.iter()
+                        .zip(expected)
+                        .all(|(channel, expected)| (channel - expected).abs() <= 2.0),
+                    "{colorimetry:?} {codes:?} gave {rgb:?}, expected {expected:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn reads_the_codes_of_every_layout() {
+        let y = YuvPlane {
+            width: 4,
+            height: 2,
+            components: 1,
+            data: (0..8).collect(),
+        };
+        let chroma = |width, height, components, first: u8| YuvPlane {
+            width,
+            height,
+            components,
+            data: (first..)
+                .take((width * height * components) as usize)
+                .collect(),
+        };
+        let frame = |layout, planes| YuvFrame {
+            width: 4,
+            height: 2,
+            layout,
+            bit_depth: 8,
+            colorimetry: colorimetry(YuvMatrix::Bt709, YuvRange::Limited),
+            planes,
+        };
+
+        let planar = frame(
+            YuvLayout::Planar420,
+            vec![y.clone(), chroma(2, 1, 1, 100), chroma(2, 1, 1, 200)],
+        );
+        assert_eq!(planar.codes_at(3, 1), [7, 101, 201]);
+        let semi_planar = frame(
+            YuvLayout::SemiPlanar420,
+            vec![y.clone(), chroma(2, 1, 2, 100)],
+        );
+        assert_eq!(semi_planar.codes_at(3, 1), [7, 102, 103]);
+        let planar_422 = frame(
+            YuvLayout::Planar422,
+            vec![y.clone(), chroma(2, 2, 1, 100), chroma(2, 2, 1, 200)],
+        );
+        assert_eq!(planar_422.codes_at(3, 1), [7, 103, 203]);
+
+        let mut wide = planar;
+        wide.bit_depth = 10;
+        for plane in &mut wide.planes {
+            plane.data = plane
+                .data
+                .iter()
+                .flat_map(|code| (*code as u16 * 4).to_le_bytes())
+                .collect();
+        }
+        assert_eq!(wide.codes_at(3, 1), [28, 404, 804]);
+    }
+}
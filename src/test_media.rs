@@ -0,0 +1,283 @@
+//! Media generated on the fly for tests, so none has to be checked in.
+
+use crate::{
+    AppEvent,
+    ff_interop::{
+        audio_player::AudioChunk,
+        encoder::{AudioCodec, Container, EncodeSettings, FfmpegEncoder, VideoCodec},
+        load_media_sync,
+    },
+    project::{
+        AudioSpec, FrameNum, FrameSpan, JadeRational, MediaKey, MediaPool, MediaProject,
+        Resolution, Timeline, TrackKind, TrackRef,
+    },
+    render::{YuvColorimetry, YuvFrame, YuvLayout, YuvPlane},
+};
+use anyhow::Context;
+use fltk::app::{self, Receiver, Sender};
+use image::{Rgba, RgbaImage};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, OnceLock, PoisonError},
+    thread,
+    time::{Duration, Instant},
+};
+
+pub const CLIP_FPS: JadeRational = JadeRational { num: 30, den: 1 };
+pub const CLIP_FRAMES: u64 = 150;
+/// Frames between keyframes, so most frames need decoding from an earlier one.
+pub const CLIP_GOP: u32 = 12;
+/// One band of rows per bit of the frame number, enough to count to 255.
+const BANDS: u32 = 8;
+const BAND_HEIGHT: u32 = 8;
+pub const CLIP_SIZE: Resolution = Resolution {
+    width: 64,
+    height: BANDS * BAND_HEIGHT,
+};
+/// Peak level of the test tone.
+pub const TONE_AMPLITUDE: f32 = 0.5;
+
+/// A fresh directory to write test output into.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("jadevid-test-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("failed to create test directory");
+    dir
+}
+
+/// Frame `frame` of a test clip: its number written in binary as bands of
+/// black and white, least significant bit at the top. Bands survive lossy
+/// encoding far better than exact colours would.
+pub fn numbered_frame(frame: u64) -> RgbaImage {
+    RgbaImage::from_fn(CLIP_SIZE.width, CLIP_SIZE.height, |_, y| {
+        let bit = (frame >> (y / BAND_HEIGHT)) & 1;
+        let level = if bit == 1 { 255 } else { 0 };
+        Rgba([level, level, level, 255])
+    })
+}
+
+/// Reads back the number of a frame made by `numbered_frame`, from the middle
+/// of each band. The image may have been scaled to any size.
+pub fn frame_number_of(image: &RgbaImage) -> u64 {
+    (0..BANDS)
+        .filter(|band| {
+            let y = (band * 2 + 1) * image.height() / (BANDS * 2);
+            image.get_pixel(image.width() / 2, y)[1] > 128
+        })
+        .map(|band| 1 << band)
+        .sum()
+}
+
+/// Sample `n` of the test tone at `sample_rate`: a sine sweeping up from
+/// 200 Hz by 100 Hz a second, so no stretch of it repeats and a sample out of
+/// place shows.
+pub fn tone_sample(n: u64, sample_rate: u32) -> f32 {
+    let secs = n as f64 / sample_rate as f64;
+    let cycles = 200.0 * secs + 50.0 * secs * secs;
+    (TONE_AMPLITUDE as f64 * (cycles * std::f64::consts::TAU).sin()) as f32
+}
+
+/// Quick, near-lossless settings for writing test clips: H.264 in MP4,
+/// without audio.
+pub fn clip_settings() -> EncodeSettings {
+    EncodeSettings {
+        container: Container::Mp4,
+        video_codec: VideoCodec::H264,
+        audio_codec: None,
+        crf: Some(10),
+        video_bitrate: None,
+        audio_bitrate: 0,
+        gop: Some(CLIP_GOP),
+        speed_preset: Some("ultrafast".to_string()),
+    }
+}
+
+/// Encodes `frames` numbered frames to an H.264 MP4.
+pub fn write_numbered_clip(path: &Path, frames: u64) -> anyhow::Result<()> {
+    ffmpeg_next::init()?;
+    let mut encoder = FfmpegEncoder::new(
+        path,
+        CLIP_SIZE,
+        CLIP_FPS,
+        AudioSpec::default(),
+        &clip_settings(),
+    )?;
+    for frame in 0..frames {
+        encoder.encode_frame(&numbered_frame(frame))?;
+    }
+    encoder.finish()
+}
+
+/// Encodes `frames` numbered frames to a Matroska file, along with the test
+/// tone on every channel of the default `AudioSpec`, as lossless FLAC.
+pub fn write_tone_clip(path: &Path, frames: u64) -> anyhow::Result<()> {
+    ffmpeg_next::init()?;
+    let spec = AudioSpec::default();
+    let settings = EncodeSettings {
+        container: Container::Mkv,
+        audio_codec: Some(AudioCodec::Flac),
+        ..clip_settings()
+    };
+    let mut encoder = FfmpegEncoder::new(path, CLIP_SIZE, CLIP_FPS, spec, &settings)?;
+    for frame in 0..frames {
+        encoder.encode_frame(&numbered_frame(frame))?;
+        let start_sample = spec.first_sample_of_frame(FrameNum(frame), CLIP_FPS);
+        let end_sample = spec.first_sample_of_frame(FrameNum(frame + 1), CLIP_FPS);
+        let tone: Vec<f32> = (start_sample..end_sample)
+            .map(|n| tone_sample(n, spec.sample_rate))
+            .collect();
+        encoder.encode_audio(&AudioChunk {
+            start_sample,
+            planes: vec![tone; spec.channels as usize],
+        })?;
+    }
+    encoder.finish()
+}
+
+/// A numbered clip of `CLIP_FRAMES` frames, made once and shared by every test
+/// that only reads it.
+pub fn numbered_clip() -> &'static Path {
+    static CLIP: OnceLock<PathBuf> = OnceLock::new();
+    CLIP.get_or_init(|| {
+        let path = temp_dir("shared").join("numbered.mp4");
+        write_numbered_clip(&path, CLIP_FRAMES).expect("failed to generate test clip");
+        path
+    })
+}
+
+/// A tone clip of `CLIP_FRAMES` frames, made once and shared by every test
+/// that only reads it.
+pub fn tone_clip() -> &'static Path {
+    static CLIP: OnceLock<PathBuf> = OnceLock::new();
+    CLIP.get_or_init(|| {
+        let path = temp_dir("shared-tone").join("tone.mkv");
+        write_tone_clip(&path, CLIP_FRAMES).expect("failed to generate test clip");
+        path
+    })
+}
+
+/// A project the size of a numbered clip, playing all `frames` of it from the
+/// start of the first video track.
+pub fn numbered_project(clip: &Path, frames: u64) -> anyhow::Result<(MediaProject, MediaKey)> {
+    ffmpeg_next::init()?;
+    let info = load_media_sync(clip.to_path_buf())?;
+    let stream_index = info
+        .video_streams()
+        .next()
+        .context("clip has no video")?
+        .0
+        .index;
+    let mut project = MediaProject {
+        fps: CLIP_FPS,
+        frame_count: frames as u32,
+        resolution: CLIP_SIZE,
+        audio: AudioSpec::default(),
+        media: MediaPool::default(),
+        timeline: Timeline::default(),
+    };
+    let key = project.media.insert(info);
+    project.add_clip(
+        TrackRef {
+            kind: TrackKind::Video,
+            index: 0,
+        },
+        key,
+        stream_index,
+        FrameSpan::with_len(FrameNum(0), frames)?,
+        FrameNum(0),
+    )?;
+    Ok((project, key))
+}
+
+/// A YUV frame with codes spread all over the range `bit_depth` allows,
+/// including ones outside the legal range, so every part of a conversion gets
+/// exercised.
+pub fn yuv_pattern_frame(
+    layout: YuvLayout,
+    bit_depth: u8,
+    colorimetry: YuvColorimetry,
+) -> YuvFrame {
+    let (width, height) = (32, 16);
+    let max_code = (1u32 << bit_depth) - 1;
+    let (shift_x, shift_y) = layout.chroma_shift();
+    let plane = |width: u32, height: u32, components: u32, seed: u32| {
+        let mut data = vec![];
+        for y in 0..height {
+            for x in 0..width {
+                for component in 0..components {
+                    let code = (x * 37 + y * 91 + (seed + component) * 53) * 997 % (max_code + 1);
+                    if bit_depth > 8 {
+                        data.extend((code as u16).to_le_bytes());
+                    } else {
+                        data.push(code as u8);
+                    }
+                }
+            }
+        }
+        YuvPlane {
+            width,
+            height,
+            components,
+            data,
+        }
+    };
+    let (chroma_width, chroma_height) = (width >> shift_x, height >> shift_y);
+    let planes = if layout.is_interleaved() {
+        vec![
+            plane(width, height, 1, 0),
+            plane(chroma_width, chroma_height, 2, 1),
+        ]
+    } else {
+        vec![
+            plane(width, height, 1, 0),
+            plane(chroma_width, chroma_height, 1, 1),
+            plane(chroma_width, chroma_height, 1, 2),
+        ]
+    };
+    YuvFrame {
+        width,
+        height,
+        layout,
+        bit_depth,
+        colorimetry,
+        planes,
+    }
+}
+
+/// The app's event channel. FLTK only has the one, shared by every test in
+/// the process, so tests that use it hold the guard to take turns, and start
+/// with whatever the last one left behind drained.
+pub fn event_channel() -> (
+    MutexGuard<'static, ()>,
+    Sender<AppEvent>,
+    Receiver<AppEvent>,
+) {
+    static TURN: Mutex<()> = Mutex::new(());
+    let turn = TURN.lock().unwrap_or_else(PoisonError::into_inner);
+    let (sender, receiver) = app::channel::<AppEvent>();
+    while receiver.recv().is_some() {}
+    (turn, sender, receiver)
+}
+
+/// Waits for the first event `pick` takes something from, dropping the ones
+/// before it. Gives up after a few seconds.
+pub fn wait_for_event<T>(
+    receiver: &Receiver<AppEvent>,
+    mut pick: impl FnMut(AppEvent) -> Option<T>,
+) -> T {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        match receiver.recv() {
+            Some(event) => {
+                if let Some(picked) = pick(event) {
+                    return picked;
+                }
+            }
+            None => {
+                assert!(Instant::now() < deadline, "timed out waiting for an event");
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+}
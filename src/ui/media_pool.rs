@@ -0,0 +1,331 @@
+use crate::{
+    AppEvent,
+    ff_interop::{
+        filmstrip::{Filmstrip, FilmstripCache},
+        waveform::WaveformCache,
+    },
+    jobs::{self, JobStatus, JobSystem},
+    project::{MediaInfo, MediaKey, MediaProject},
+};
+use fltk::{
+    app::{self, Sender},
+    browser::HoldBrowser,
+    button::Button,
+    enums::{ColorDepth, Event},
+    group::Flex,
+    image::RgbImage,
+    misc::Progress,
+    prelude::*,
+};
+use image::RgbaImage;
+use log::warn;
+use slotmap::{Key, KeyData};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    process,
+    rc::Rc,
+    thread,
+};
+
+/// Thumbnails are shrunk to fit in a square this many pixels wide.
+const THUMBNAIL_SIZE: u32 = 48;
+const BUTTON_ROW_HEIGHT: i32 = 25;
+const CANCEL_BUTTON_WIDTH: i32 = 60;
+const MEDIA_DRAG_PREFIX: &str = "jadevid-media:";
+
+/// Lists the project's media pool in the media panel, along with buttons for
+/// acting on the selected entry.
+pub struct MediaPoolPanel {
+    column: Flex,
+    browser: HoldBrowser,
+    /// The media shown on each line of the browser, in order. Shared with the
+    /// browser's event handler, which starts drags onto the timeline.
+    keys: Rc<RefCell<Vec<MediaKey>>>,
+    /// Keyed by media, along with the path the thumbnail was made from, so a
+    /// key reused by another project doesn't show a stale image. `None` while
+    /// the thumbnail is being made, or if it couldn't be.
+    thumbnails: HashMap<MediaKey, (PathBuf, Option<RgbImage>)>,
+    /// Shows background job progress, and is hidden while there are none.
+    job_row: Flex,
+    job_progress: Progress,
+}
+
+impl MediaPoolPanel {
+    pub fn new(media_group: &mut impl GroupExt, event_sender: Sender<AppEvent>) -> Self {
+        let mut column = Flex::new(
+            media_group.x(),
+            media_group.y(),
+            media_group.w(),
+            media_group.h(),
+            None,
+        )
+        .column();
+        column.set_margin(2);
+
+        let mut browser = HoldBrowser::default();
+        browser.set_column_char('\t');
+        browser.set_column_widths(&[140, 70, 80]);
+        let keys = Rc::new(RefCell::new(Vec::new()));
+        browser.handle({
+            let keys = keys.clone();
+            move |browser, event| match event {
+                Event::Drag => {
+                    let line = browser.value();
+                    let Some(key) = (line > 0)
+                        .then(|| keys.borrow().get(line as usize - 1).copied())
+                        .flatten()
+                    else {
+                        return false;
+                    };
+                    app::copy(&media_drag_text(key));
+                    app::dnd();
+                    true
+                }
+                _ => false,
+            }
+        });
+
+        let buttons = Flex::default().row();
+        for (label, event) in [
+            ("Rename", AppEvent::MediaPoolRename),
+            ("Reveal", AppEvent::MediaPoolReveal),
+            ("Proxy", AppEvent::MediaPoolMakeProxy),
+            ("Remove", AppEvent::MediaPoolRemove),
+        ] {
+            let mut button = Button::default().with_label(label);
+            button.emit(event_sender, event);
+        }
+        buttons.end();
+        column.fixed(&buttons, BUTTON_ROW_HEIGHT);
+
+        let mut job_row = Flex::default().row();
+        let job_progress = Progress::default();
+        let mut cancel = Button::default().with_label("Cancel");
+        cancel.emit(event_sender, AppEvent::JobsCancel);
+        job_row.fixed(&cancel, CANCEL_BUTTON_WIDTH);
+        job_row.end();
+        job_row.hide();
+        column.fixed(&job_row, BUTTON_ROW_HEIGHT);
+        column.end();
+
+        media_group.add(&column);
+        media_group.resizable(&column);
+
+        Self {
+            column,
+            browser,
+            keys,
+            thumbnails: HashMap::new(),
+            job_row,
+            job_progress,
+        }
+    }
+
+    /// Rebuilds the list from the project, keeping the selection if the
+    /// selected media is still there. Filmstrips and waveforms for new media
+    /// are made in the background, and thumbnails show up through
+    /// `set_filmstrip`.
+    pub fn refresh(&mut self, project: &MediaProject, jobs: &mut JobSystem) {
+        let selected = self.selected();
+        self.thumbnails.retain(|key, (path, _)| {
+            project
+                .media
+                .get(*key)
+                .is_some_and(|info| info.path == *path)
+        });
+
+        self.browser.clear();
+        self.keys.borrow_mut().clear();
+        for (key, info) in &project.media {
+            self.browser.add(&describe_media(info));
+            self.keys.borrow_mut().push(key);
+
+            let line = self.browser.size();
+            let thumbnail = self.thumbnail(key, info, jobs);
+            self.browser.set_icon(line, thumbnail);
+            if selected == Some(key) {
+                self.browser.select(line);
+            }
+        }
+        self.browser.redraw();
+    }
+
+    pub fn selected(&self) -> Option<MediaKey> {
+        match self.browser.value() {
+            0 => None,
+            line => self.keys.borrow().get(line as usize - 1).copied(),
+        }
+    }
+
+    /// The media's thumbnail, if it's been made yet. The first time it's asked
+    /// for, this starts the jobs making the media's filmstrip and waveform.
+    fn thumbnail(
+        &mut self,
+        key: MediaKey,
+        info: &MediaInfo,
+        jobs: &mut JobSystem,
+    ) -> Option<RgbImage> {
+        let (_, thumbnail) = self.thumbnails.entry(key).or_insert_with(|| {
+            if let Some((stream, _)) = info.video_streams().next() {
+                jobs.spawn(
+                    format!("Making filmstrip for {}", info.name),
+                    jobs::filmstrip_job(
+                        key,
+                        info.path.clone(),
+                        info.fingerprint.clone(),
+                        stream.index,
+                        FilmstripCache::in_cache_dir(),
+                    ),
+                );
+            }
+            if let Some((stream, _)) = info.audio_streams().next() {
+                jobs.spawn(
+                    format!("Measuring waveform of {}", info.name),
+                    jobs::waveform_job(
+                        key,
+                        info.path.clone(),
+                        info.fingerprint.clone(),
+                        stream.index,
+                        WaveformCache::in_cache_dir(),
+                    ),
+                );
+            }
+            (info.path.clone(), None)
+        });
+        thumbnail.clone()
+    }
+
+    /// Shows the first tile of a filmstrip made in the background as the
+    /// media's thumbnail, unless the media has gone or changed file since it
+    /// was asked for.
+    pub fn set_filmstrip(&mut self, key: MediaKey, path: &Path, filmstrip: &Filmstrip) {
+        let Some((thumbnail_path, thumbnail)) = self.thumbnails.get_mut(&key) else {
+            return;
+        };
+        if thumbnail_path != path {
+            return;
+        }
+        let Some(tile) = filmstrip.tiles.first() else {
+            return;
+        };
+        match make_thumbnail(tile) {
+            Ok(image) => *thumbnail = Some(image),
+            Err(err) => {
+                warn!("failed to show thumbnail for {}: {err:#}", path.display());
+                return;
+            }
+        }
+
+        let line = self.keys.borrow().iter().position(|other| *other == key);
+        if let Some(line) = line {
+            self.browser.set_icon(line as i32 + 1, thumbnail.clone());
+            self.browser.redraw();
+        }
+    }
+
+    /// Shows how background jobs are getting on, or hides the progress bar
+    /// when there are none.
+    pub fn set_job_status(&mut self, status: Option<JobStatus>) {
+        match status {
+            Some(status) => {
+                self.job_progress.set_label(&status.label);
+                self.job_progress
+                    .set_value(status.fraction.unwrap_or(0.0) * 100.0);
+                if !self.job_row.visible() {
+                    self.job_row.show();
+                    self.column.layout();
+                }
+            }
+            None if self.job_row.visible() => {
+                self.job_row.hide();
+                self.column.layout();
+            }
+            None => {}
+        }
+        self.column.redraw();
+    }
+}
+
+/// The text media is carried as while being dragged from the pool.
+pub fn media_drag_text(key: MediaKey) -> String {
+    format!("{MEDIA_DRAG_PREFIX}{}", key.data().as_ffi())
+}
+
+pub fn parse_media_drag(text: &str) -> Option<MediaKey> {
+    let ffi = text.trim().strip_prefix(MEDIA_DRAG_PREFIX)?.parse().ok()?;
+    Some(KeyData::from_ffi(ffi).into())
+}
+
+/// Shrinks an image to fit within `THUMBNAIL_SIZE` pixels on each side.
+fn make_thumbnail(image: &RgbaImage) -> anyhow::Result<RgbImage> {
+    let scale = (THUMBNAIL_SIZE as f32 / image.width().max(image.height()) as f32).min(1.0);
+    let image = image::imageops::thumbnail(
+        image,
+        ((image.width() as f32 * scale) as u32).max(1),
+        ((image.height() as f32 * scale) as u32).max(1),
+    );
+    Ok(RgbImage::new(
+        image.as_raw(),
+        image.width() as i32,
+        image.height() as i32,
+        ColorDepth::Rgba8,
+    )?)
+}
+
+/// One browser line: name, duration, frame size and a summary of the streams,
+/// noting whether there's a proxy.
+///
+/// Each column starts with "@." so FLTK doesn't treat characters in the file
+/// name as formatting.
+fn describe_media(info: &MediaInfo) -> String {
+    let total_secs = info.duration_secs().round() as u64;
+    let duration = format!(
+        "{}:{:02}:{:02}",
+        total_secs / 3600,
+        total_secs / 60 % 60,
+        total_secs % 60
+    );
+    let resolution = match info.video_streams().next() {
+        Some((_, video)) if video.width > 0 => format!("{}x{}", video.width, video.height),
+        Some(_) => "?".to_string(),
+        None => "-".to_string(),
+    };
+    let streams = format!(
+        "{}V {}A{}",
+        info.video_streams().count(),
+        info.audio_streams().count(),
+        if info.proxy.is_some() { " proxy" } else { "" }
+    );
+    format!("@.{}\t@.{duration}\t@.{resolution}\t@.{streams}", info.name)
+}
+
+/// Opens the system file manager at the given file.
+pub fn reveal_in_file_manager(path: &Path) -> io::Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = process::Command::new("open");
+        command.arg("-R").arg(path);
+        command
+    } else if cfg!(target_os = "windows") {
+        let mut select = std::ffi::OsString::from("/select,");
+        select.push(path);
+        let mut command = process::Command::new("explorer");
+        command.arg(select);
+        command
+    } else {
+        let mut command = process::Command::new("xdg-open");
+        command.arg(path.parent().unwrap_or(path));
+        command
+    };
+    let mut child = command.spawn()?;
+    // Waited on from a thread of its own so the UI doesn't block, and so the
+    // file manager doesn't linger as a zombie once its launcher exits.
+    thread::spawn(move || {
+        if let Err(err) = child.wait() {
+            warn!("failed to wait for the file manager: {err}");
+        }
+    });
+    Ok(())
+}
@@ -1,4 +1,22 @@
+mod media_pool;
+mod preview_viewport;
+mod snapshot;
+mod timeline_widget;
+
+pub use media_pool::*;
+pub use preview_viewport::*;
+pub use snapshot::*;
+pub use timeline_widget::*;
+
+use crate::{
+    project::Resolution,
+    render::{PreviewFrame, YuvFrame, YuvLayout},
+};
+use anyhow::{Context, bail, ensure};
 use glam::{Vec2, Vec3};
+use image::RgbaImage;
+use log::info;
+use std::sync::mpsc;
 use wgpu::util::DeviceExt;
 
 fl2rust_macro::include_ui!("src/ui/jadevid-ui-main.fl");
@@ -58,20 +76,81 @@ const QUAD_VERTS: &[SimpleVert] = &[
 
 const QUAD_INDS: &[u16] = &[0, 1, 2, 0, 2, 3];
 
+/// `QUAD_VERTS` moved to cover a rectangle given in clip space as left, top,
+/// right and bottom.
+fn quad_verts([left, top, right, bottom]: [f32; 4]) -> [SimpleVert; 4] {
+    [
+        SimpleVert {
+            position: Vec3::new(left, top, 0.0),
+            tex_coords: Vec2::ZERO,
+        },
+        SimpleVert {
+            position: Vec3::new(left, bottom, 0.0),
+            tex_coords: Vec2::Y,
+        },
+        SimpleVert {
+            position: Vec3::new(right, bottom, 0.0),
+            tex_coords: Vec2::ONE,
+        },
+        SimpleVert {
+            position: Vec3::new(right, top, 0.0),
+            tex_coords: Vec2::X,
+        },
+    ]
+}
+
+/// The uniform `yuv_shader.wgsl` reads, laid out to match its `YuvParams`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct YuvUniform {
+    matrix: [[f32; 4]; 3],
+    offsets: [f32; 4],
+    scales: [f32; 4],
+    plane_layout: [u32; 4],
+}
+
+/// What `WgpuState` draws into.
+enum RenderTarget<'a> {
+    Surface(wgpu::Surface<'a>),
+    /// A texture of `OFFSCREEN_FORMAT` that can be read back, for rendering
+    /// without a window.
+    Offscreen(wgpu::Texture),
+}
+
+/// Offscreen renders come out as sRGB RGBA bytes, the way images are saved.
+const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
 pub struct WgpuState<'a> {
     pub device: wgpu::Device,
-    pub surface: wgpu::Surface<'a>,
+    target: RenderTarget<'a>,
+    /// Offscreen, only its size and format mean anything.
     pub surface_config: wgpu::SurfaceConfiguration,
     pub queue: wgpu::Queue,
     pub render_pipeline: wgpu::RenderPipeline,
+    /// Draws `yuv_textures` instead of `texture`, converting to RGB as it goes.
+    yuv_pipeline: wgpu::RenderPipeline,
+    yuv_bind_group_layout: wgpu::BindGroupLayout,
+    yuv_uniform_buffer: wgpu::Buffer,
     vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
     ind_count: u32,
     texture_sampler: wgpu::Sampler,
     texture: NewTexture,
+    /// Kept between frames so they're only remade when the frame's shape
+    /// changes.
+    yuv_textures: Option<YuvTextures>,
+    /// Whether the last frame written was YUV, and so which pipeline draws.
+    showing_yuv: bool,
+    /// The size of the picture being shown, in its own pixels.
+    picture_size: (u32, u32),
+    /// The part of the picture the texture covers, as fractions of its width
+    /// and height from the left, top, right and bottom.
+    texture_part: [f64; 4],
+    zoom: PreviewZoom,
+    pan: (f64, f64),
 }
 
-impl WgpuState<'_> {
+impl<'a> WgpuState<'a> {
     pub async fn new(win: fltk::window::Window) -> Self {
         let (width, height) = (win.pixel_w() as _, win.pixel_h() as _);
         // Instance, surface, adapter, device
@@ -110,6 +189,77 @@ impl WgpuState<'_> {
         };
         surface.configure(&device, &surface_config);
 
+        Self::with_target(
+            device,
+            queue,
+            RenderTarget::Surface(surface),
+            surface_config,
+        )
+    }
+
+    /// Renders into a texture rather than a window, so frames can be read
+    /// back with `read_rgba`. A fallback adapter renders in software, for
+    /// machines without a GPU or display.
+    pub async fn new_offscreen(
+        width: u32,
+        height: u32,
+        force_fallback_adapter: bool,
+    ) -> anyhow::Result<Self> {
+        ensure!(
+            width > 0 && height > 0,
+            "offscreen size must not be empty, but was {width}x{height}"
+        );
+        let instance = wgpu::Instance::default();
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter,
+            })
+            .await
+            .context("failed to find an appropriate adapter")?;
+        info!("rendering offscreen with {:?}", adapter.get_info());
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: Some("device"),
+                    required_features: wgpu::Features::empty(),
+                    required_limits: wgpu::Limits::downlevel_defaults(),
+                    memory_hints: wgpu::MemoryHints::Performance,
+                },
+                None,
+            )
+            .await
+            .context("failed to create device")?;
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: OFFSCREEN_FORMAT,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            desired_maximum_frame_latency: 2,
+            view_formats: vec![],
+        };
+        let texture = Self::make_offscreen_texture(&device, &surface_config);
+
+        Ok(Self::with_target(
+            device,
+            queue,
+            RenderTarget::Offscreen(texture),
+            surface_config,
+        ))
+    }
+
+    fn with_target(
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        target: RenderTarget<'a>,
+        surface_config: wgpu::SurfaceConfiguration,
+    ) -> Self {
+        let (width, height) = (surface_config.width, surface_config.height);
+        let swapchain_format = surface_config.format;
+
         // Texture
         let texture_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
@@ -130,33 +280,69 @@ impl WgpuState<'_> {
         });
 
         let shader = device.create_shader_module(wgpu::include_wgsl!("shader.wgsl"));
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("render_pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),
-                buffers: &[SimpleVert::desc()],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
+        let render_pipeline = Self::make_pipeline(
+            &device,
+            "render_pipeline",
+            &pipeline_layout,
+            &shader,
+            swapchain_format,
+        );
+
+        // YUV pipeline
+        let yuv_plane_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Uint,
             },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(swapchain_format.into())],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            primitive: wgpu::PrimitiveState::default(),
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
-            cache: None,
+            count: None,
+        };
+        let yuv_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    yuv_plane_entry(0),
+                    yuv_plane_entry(1),
+                    yuv_plane_entry(2),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("yuv_bind_group_layout"),
+            });
+        let yuv_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("yuv_pipeline_layout"),
+            bind_group_layouts: &[&yuv_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let yuv_shader = device.create_shader_module(wgpu::include_wgsl!("yuv_shader.wgsl"));
+        let yuv_pipeline = Self::make_pipeline(
+            &device,
+            "yuv_pipeline",
+            &yuv_pipeline_layout,
+            &yuv_shader,
+            swapchain_format,
+        );
+        let yuv_uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("YUV Uniform Buffer"),
+            size: std::mem::size_of::<YuvUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
         });
 
         // Mesh data
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Quad Vertex Buffer"),
             contents: bytemuck::cast_slice(QUAD_VERTS),
-            usage: wgpu::BufferUsages::VERTEX,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
         });
         let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Quad Index Buffer"),
@@ -166,15 +352,24 @@ impl WgpuState<'_> {
 
         let mut s = Self {
             device,
-            surface,
+            target,
             surface_config,
             queue,
             render_pipeline,
+            yuv_pipeline,
+            yuv_bind_group_layout,
+            yuv_uniform_buffer,
             vertex_buffer,
             index_buffer,
             ind_count: QUAD_INDS.len() as u32,
             texture_sampler,
             texture,
+            yuv_textures: None,
+            showing_yuv: false,
+            picture_size: (width, height),
+            texture_part: [0.0, 0.0, 1.0, 1.0],
+            zoom: PreviewZoom::default(),
+            pan: (0.0, 0.0),
         };
         s.write_texture_rgba(width, height, &vec![0x00; (width * height * 4) as usize]);
         s
@@ -184,8 +379,14 @@ impl WgpuState<'_> {
     pub fn resize_surface(&mut self, width: u32, height: u32) {
         self.surface_config.width = width;
         self.surface_config.height = height;
-        if self.valid_size() {
-            self.surface.configure(&self.device, &self.surface_config);
+        if !self.valid_size() {
+            return;
+        }
+        match &mut self.target {
+            RenderTarget::Surface(surface) => surface.configure(&self.device, &self.surface_config),
+            RenderTarget::Offscreen(texture) => {
+                *texture = Self::make_offscreen_texture(&self.device, &self.surface_config)
+            }
         }
     }
 
@@ -193,18 +394,71 @@ impl WgpuState<'_> {
         self.surface_config.width > 0 && self.surface_config.height > 0
     }
 
+    /// Changes how big the picture is shown, centering it again.
+    pub fn set_zoom(&mut self, zoom: PreviewZoom) {
+        self.zoom = zoom;
+        self.pan = (0.0, 0.0);
+        self.redraw();
+    }
+
+    /// Moves the picture by the given number of surface pixels, as far as it
+    /// can go while still covering the surface.
+    pub fn pan_by(&mut self, dx: f64, dy: f64) {
+        self.pan = self
+            .viewport_with_pan((self.pan.0 + dx, self.pan.1 + dy))
+            .pan;
+        self.redraw();
+    }
+
+    /// Where the picture currently sits on the surface.
+    pub fn viewport(&self) -> PreviewViewport {
+        self.viewport_with_pan(self.pan)
+    }
+
+    fn viewport_with_pan(&self, pan: (f64, f64)) -> PreviewViewport {
+        let (width, height) = self.picture_size;
+        // Frames reach here already composited onto the project's canvas,
+        // which has square pixels.
+        preview_viewport(
+            width,
+            height,
+            1.0,
+            self.surface_config.width,
+            self.surface_config.height,
+            self.zoom,
+            pan,
+        )
+    }
+
     pub fn redraw(&self) {
         if !self.valid_size() {
             return;
         }
+        let quad = self.viewport().clip_rect(
+            self.surface_config.width,
+            self.surface_config.height,
+            self.texture_part,
+        );
+        self.queue.write_buffer(
+            &self.vertex_buffer,
+            0,
+            bytemuck::cast_slice(&quad_verts(quad)),
+        );
+
+        match &self.target {
+            RenderTarget::Surface(surface) => {
+                let frame = surface
+                    .get_current_texture()
+                    .expect("Failed to acquire next swap chain texture");
+                self.render_to(&frame.texture);
+                frame.present();
+            }
+            RenderTarget::Offscreen(texture) => self.render_to(texture),
+        }
+    }
 
-        let frame = self
-            .surface
-            .get_current_texture()
-            .expect("Failed to acquire next swap chain texture");
-        let view = frame
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+    fn render_to(&self, target: &wgpu::Texture) {
+        let view = target.create_view(&wgpu::TextureViewDescriptor::default());
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
@@ -226,14 +480,126 @@ impl WgpuState<'_> {
                 timestamp_writes: None,
             });
             // render()
-            rpass.set_pipeline(&self.render_pipeline);
-            rpass.set_bind_group(0, &self.texture.texture_bind_group, &[]); // image
+            match &self.yuv_textures {
+                Some(yuv) if self.showing_yuv => {
+                    rpass.set_pipeline(&self.yuv_pipeline);
+                    rpass.set_bind_group(0, &yuv.bind_group, &[]);
+                }
+                _ => {
+                    rpass.set_pipeline(&self.render_pipeline);
+                    rpass.set_bind_group(0, &self.texture.texture_bind_group, &[]); // image
+                }
+            }
             rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
             rpass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
             rpass.draw_indexed(0..self.ind_count, 0, 0..1);
         }
         self.queue.submit(Some(encoder.finish()));
-        frame.present();
+    }
+
+    /// Copies what was last drawn offscreen back from the GPU, waiting for it
+    /// to finish drawing first. Only works for states made by `new_offscreen`.
+    pub fn read_rgba(&self) -> anyhow::Result<RgbaImage> {
+        let RenderTarget::Offscreen(texture) = &self.target else {
+            bail!("only offscreen renders can be read back");
+        };
+        let (width, height) = (texture.width(), texture.height());
+        // Rows of a copy into a buffer have to be aligned, so they're padded
+        // and the padding is cut off again below.
+        let row_len = 4 * width;
+        let padded_row_len = row_len.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: padded_row_len as wgpu::BufferAddress * height as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("readback_encoder"),
+            });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_len),
+                    rows_per_image: Some(height),
+                },
+            },
+            texture.size(),
+        );
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait).panic_on_timeout();
+        receiver
+            .recv()
+            .context("readback was abandoned")?
+            .context("failed to map readback buffer")?;
+
+        let mut pixels = Vec::with_capacity((row_len * height) as usize);
+        for row in slice.get_mapped_range().chunks(padded_row_len as usize) {
+            pixels.extend_from_slice(&row[..row_len as usize]);
+        }
+        buffer.unmap();
+        RgbaImage::from_raw(width, height, pixels).context("readback was the wrong size")
+    }
+
+    fn make_offscreen_texture(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: config.usage,
+            label: Some("offscreen_texture"),
+            view_formats: &[],
+        })
+    }
+
+    fn make_pipeline(
+        device: &wgpu::Device,
+        label: &str,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: Some("vs_main"),
+                buffers: &[SimpleVert::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(format.into())],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
     }
 
     fn make_texture(
@@ -318,7 +684,7 @@ impl WgpuState<'_> {
                 aspect: wgpu::TextureAspect::All,
             },
             // The actual pixel data
-            &rgb,
+            rgb,
             // The layout of the texture
             wgpu::TexelCopyBufferLayout {
                 offset: 0,
@@ -327,8 +693,189 @@ impl WgpuState<'_> {
             },
             self.texture.texture.size(),
         );
+        self.showing_yuv = false;
+        self.picture_size = (width, height);
+        self.texture_part = [0.0, 0.0, 1.0, 1.0];
         self.redraw();
     }
+
+    /// Shows a frame from `Compositor::render_preview`, whichever form it's in.
+    pub fn write_preview_frame(&mut self, frame: &PreviewFrame) {
+        match frame {
+            PreviewFrame::Rgba(image) => {
+                self.write_texture_rgba(image.width(), image.height(), image)
+            }
+            PreviewFrame::Yuv {
+                frame,
+                canvas,
+                x,
+                y,
+                width,
+                height,
+            } => self.write_texture_yuv(frame, *canvas, *x, *y, *width, *height),
+        }
+    }
+
+    /// Shows a YUV frame, converted to RGB by the GPU and drawn over black at
+    /// `x`, `y`, `width` by `height` within a canvas of the given size. The
+    /// canvas is placed on the surface just as `write_texture_rgba`'s image
+    /// is.
+    pub fn write_texture_yuv(
+        &mut self,
+        frame: &YuvFrame,
+        canvas: Resolution,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) {
+        if !self
+            .yuv_textures
+            .as_ref()
+            .is_some_and(|textures| textures.fits(frame))
+        {
+            self.yuv_textures = Some(YuvTextures::new(
+                &self.device,
+                &self.yuv_bind_group_layout,
+                &self.yuv_uniform_buffer,
+                frame,
+            ));
+        }
+        let textures = self.yuv_textures.as_ref().unwrap();
+
+        let bytes_per_sample = frame.bytes_per_sample();
+        for (texture, plane) in textures.planes.iter().zip(&frame.planes) {
+            self.queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &plane.data,
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(plane.width * plane.components * bytes_per_sample),
+                    rows_per_image: Some(plane.height),
+                },
+                texture.size(),
+            );
+        }
+
+        let conversion = frame.colorimetry.conversion(frame.bit_depth);
+        let extend = |[a, b, c]: [f32; 3]| [a, b, c, 0.0];
+        let (shift_x, shift_y) = frame.layout.chroma_shift();
+        let uniform = YuvUniform {
+            matrix: conversion.matrix.map(extend),
+            offsets: extend(conversion.offsets),
+            scales: extend(conversion.scales),
+            plane_layout: [frame.layout.is_interleaved() as u32, shift_x, shift_y, 0],
+        };
+        self.queue
+            .write_buffer(&self.yuv_uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+        self.showing_yuv = true;
+        self.picture_size = (canvas.width, canvas.height);
+        let (canvas_width, canvas_height) = (canvas.width as f64, canvas.height as f64);
+        self.texture_part = [
+            x as f64 / canvas_width,
+            y as f64 / canvas_height,
+            (x + width) as f64 / canvas_width,
+            (y + height) as f64 / canvas_height,
+        ];
+        self.redraw();
+    }
+}
+
+/// The plane textures of a YUV frame, each holding raw integer codes.
+struct YuvTextures {
+    /// Y, then U and V, or the interleaved UV plane.
+    planes: Vec<wgpu::Texture>,
+    bind_group: wgpu::BindGroup,
+    layout: YuvLayout,
+    bit_depth: u8,
+    width: u32,
+    height: u32,
+}
+
+impl YuvTextures {
+    fn new(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        frame: &YuvFrame,
+    ) -> Self {
+        let wide = frame.bit_depth > 8;
+        let planes: Vec<_> = frame
+            .planes
+            .iter()
+            .map(|plane| {
+                let format = match (plane.components, wide) {
+                    (2, false) => wgpu::TextureFormat::Rg8Uint,
+                    (2, true) => wgpu::TextureFormat::Rg16Uint,
+                    (_, false) => wgpu::TextureFormat::R8Uint,
+                    (_, true) => wgpu::TextureFormat::R16Uint,
+                };
+                device.create_texture(&wgpu::TextureDescriptor {
+                    size: wgpu::Extent3d {
+                        width: plane.width,
+                        height: plane.height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                    label: Some("yuv_plane_texture"),
+                    view_formats: &[],
+                })
+            })
+            .collect();
+        let views: Vec<_> = planes
+            .iter()
+            .map(|plane| plane.create_view(&wgpu::TextureViewDescriptor::default()))
+            .collect();
+        // With U and V interleaved the shader reads both from the second
+        // binding, but the third still needs something bound.
+        let v_view = views.get(2).unwrap_or(&views[1]);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&views[0]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&views[1]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(v_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("yuv_bind_group"),
+        });
+
+        Self {
+            planes,
+            bind_group,
+            layout: frame.layout,
+            bit_depth: frame.bit_depth,
+            width: frame.width,
+            height: frame.height,
+        }
+    }
+
+    /// Whether these textures can hold the given frame.
+    fn fits(&self, frame: &YuvFrame) -> bool {
+        (self.layout, self.bit_depth, self.width, self.height)
+            == (frame.layout, frame.bit_depth, frame.width, frame.height)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -0,0 +1,273 @@
+/// How big the preview shows the picture.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum PreviewZoom {
+    /// As large as fits in the preview, keeping its shape.
+    #[default]
+    Fit,
+    Half,
+    Actual,
+    Double,
+}
+
+impl PreviewZoom {
+    pub const ALL: [PreviewZoom; 4] = [Self::Fit, Self::Half, Self::Actual, Self::Double];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Fit => "Fit",
+            Self::Half => "50%",
+            Self::Actual => "100%",
+            Self::Double => "200%",
+        }
+    }
+
+    /// Surface pixels per picture pixel, or `None` to fit the surface.
+    pub fn scale(self) -> Option<f64> {
+        match self {
+            Self::Fit => None,
+            Self::Half => Some(0.5),
+            Self::Actual => Some(1.0),
+            Self::Double => Some(2.0),
+        }
+    }
+}
+
+/// Where the picture lands on the preview surface, in surface pixels from its
+/// top left corner. Zoomed in, it can spill past the surface's edges.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PreviewViewport {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+    /// The pan actually applied, after keeping the picture covering the
+    /// surface. Storing this back stops panning past an edge from building up
+    /// distance that has to be undone before the picture moves again.
+    pub pan: (f64, f64),
+}
+
+impl PreviewViewport {
+    /// The corners of a part of the picture in clip space, as left, top, right
+    /// and bottom. The part is given as fractions of the picture's width and
+    /// height, the same way.
+    pub fn clip_rect(&self, surface_width: u32, surface_height: u32, part: [f64; 4]) -> [f32; 4] {
+        let [left, top, right, bottom] = part;
+        let to_clip_x = |fraction: f64| {
+            ((self.x + self.width * fraction) / surface_width as f64 * 2.0 - 1.0) as f32
+        };
+        let to_clip_y = |fraction: f64| {
+            (1.0 - (self.y + self.height * fraction) / surface_height as f64 * 2.0) as f32
+        };
+        [
+            to_clip_x(left),
+            to_clip_y(top),
+            to_clip_x(right),
+            to_clip_y(bottom),
+        ]
+    }
+}
+
+/// Places a picture of `width` by `height` pixels, each `sample_aspect` times
+/// as wide as it is tall, on a surface. It's centered, letterboxed or
+/// pillarboxed when it fits and offset by `pan` when it doesn't, though never
+/// so far that the surface shows past the picture's edge.
+pub fn preview_viewport(
+    width: u32,
+    height: u32,
+    sample_aspect: f64,
+    surface_width: u32,
+    surface_height: u32,
+    zoom: PreviewZoom,
+    pan: (f64, f64),
+) -> PreviewViewport {
+    let sample_aspect = if sample_aspect.is_finite() && sample_aspect > 0.0 {
+        sample_aspect
+    } else {
+        1.0
+    };
+    let display_width = width.max(1) as f64 * sample_aspect;
+    let display_height = height.max(1) as f64;
+    let (surface_width, surface_height) = (surface_width as f64, surface_height as f64);
+
+    let scale = zoom.scale().unwrap_or_else(|| {
+        f64::min(
+            surface_width / display_width,
+            surface_height / display_height,
+        )
+    });
+    let (scaled_width, scaled_height) = (display_width * scale, display_height * scale);
+
+    // Centered, then moved by as much of the pan as keeps the surface covered.
+    let place = |scaled: f64, surface: f64, pan: f64| {
+        let spare = (scaled - surface).max(0.0) / 2.0;
+        let pan = pan.clamp(-spare, spare);
+        ((surface - scaled) / 2.0 + pan, pan)
+    };
+    let (x, pan_x) = place(scaled_width, surface_width, pan.0);
+    let (y, pan_y) = place(scaled_height, surface_height, pan.1);
+    PreviewViewport {
+        x,
+        y,
+        width: scaled_width,
+        height: scaled_height,
+        pan: (pan_x, pan_y),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn place(
+        (width, height): (u32, u32),
+        sample_aspect: f64,
+        (surface_width, surface_height): (u32, u32),
+        zoom: PreviewZoom,
+        pan: (f64, f64),
+    ) -> PreviewViewport {
+        preview_viewport(
+            width,
+            height,
+            sample_aspect,
+            surface_width,
+            surface_height,
+            zoom,
+            pan,
+        )
+    }
+
+    /// Checks the viewport's x, y, width and height, allowing for rounding.
+    fn assert_lands_at(viewport: PreviewViewport, expected: [f64; 4]) {
+        let actual = [viewport.x, viewport.y, viewport.width, viewport.height];
+        assert!(
+            actual
+                .iter()
+                .zip(expected)
+                .all(|(actual, expected)| (actual - expected).abs() < 1e-9),
+            "landed at {actual:?}, expected {expected:?}"
+        );
+    }
+
+    #[test]
+    fn letterboxes_wide_pictures() {
+        let viewport = place((1920, 1080), 1.0, (800, 600), PreviewZoom::Fit, (0.0, 0.0));
+        assert_lands_at(viewport, [0.0, 75.0, 800.0, 450.0]);
+    }
+
+    #[test]
+    fn pillarboxes_narrow_pictures() {
+        let viewport = place((640, 480), 1.0, (1280, 720), PreviewZoom::Fit, (0.0, 0.0));
+        assert_lands_at(viewport, [160.0, 0.0, 960.0, 720.0]);
+    }
+
+    #[test]
+    fn widens_anamorphic_pictures() {
+        // 16:9 PAL DVD, stored 720 pixels wide but shown 1024 wide.
+        let viewport = place(
+            (720, 576),
+            64.0 / 45.0,
+            (1024, 576),
+            PreviewZoom::Fit,
+            (0.0, 0.0),
+        );
+        assert_lands_at(viewport, [0.0, 0.0, 1024.0, 576.0]);
+        let viewport = place(
+            (720, 576),
+            64.0 / 45.0,
+            (1024, 576),
+            PreviewZoom::Actual,
+            (0.0, 0.0),
+        );
+        assert_lands_at(viewport, [0.0, 0.0, 1024.0, 576.0]);
+
+        // Nonsense aspect ratios are taken as square pixels.
+        for sample_aspect in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let viewport = place(
+                (640, 480),
+                sample_aspect,
+                (640, 480),
+                PreviewZoom::Fit,
+                (0.0, 0.0),
+            );
+            assert_lands_at(viewport, [0.0, 0.0, 640.0, 480.0]);
+        }
+    }
+
+    #[test]
+    fn scales_by_each_zoom_level() {
+        for zoom in PreviewZoom::ALL {
+            let expected = match zoom {
+                PreviewZoom::Fit => [0.0, 0.0, 1280.0, 720.0],
+                PreviewZoom::Half => [480.0, 270.0, 320.0, 180.0],
+                PreviewZoom::Actual => [320.0, 180.0, 640.0, 360.0],
+                PreviewZoom::Double => [0.0, 0.0, 1280.0, 720.0],
+            };
+            let viewport = place((640, 360), 1.0, (1280, 720), zoom, (0.0, 0.0));
+            assert_lands_at(viewport, expected);
+            assert_eq!(viewport.pan, (0.0, 0.0), "{zoom:?}");
+        }
+    }
+
+    #[test]
+    fn pans_within_a_zoomed_picture() {
+        let viewport = place(
+            (640, 360),
+            1.0,
+            (640, 360),
+            PreviewZoom::Double,
+            (100.0, -50.0),
+        );
+        assert_lands_at(viewport, [-220.0, -230.0, 1280.0, 720.0]);
+        assert_eq!(viewport.pan, (100.0, -50.0));
+    }
+
+    #[test]
+    fn stops_panning_at_the_picture_edges() {
+        // Twice the size, so there's half a surface spare on each side.
+        let viewport = place(
+            (640, 360),
+            1.0,
+            (640, 360),
+            PreviewZoom::Double,
+            (1000.0, -1000.0),
+        );
+        assert_eq!(viewport.pan, (320.0, -180.0));
+        // The left edge of the picture meets the left of the surface, and its
+        // bottom edge the bottom.
+        assert_lands_at(viewport, [0.0, -360.0, 1280.0, 720.0]);
+
+        let viewport = place(
+            (640, 360),
+            1.0,
+            (640, 360),
+            PreviewZoom::Double,
+            (-1000.0, 1000.0),
+        );
+        assert_eq!(viewport.pan, (-320.0, 180.0));
+        assert_lands_at(viewport, [-640.0, 0.0, 1280.0, 720.0]);
+    }
+
+    #[test]
+    fn keeps_small_pictures_centered_however_far_panned() {
+        let viewport = place(
+            (640, 360),
+            1.0,
+            (1280, 720),
+            PreviewZoom::Half,
+            (300.0, -300.0),
+        );
+        assert_eq!(viewport.pan, (0.0, 0.0));
+        assert_lands_at(viewport, [480.0, 270.0, 320.0, 180.0]);
+
+        // Only the direction the picture overflows in can be panned.
+        let viewport = place(
+            (1920, 360),
+            1.0,
+            (1280, 720),
+            PreviewZoom::Actual,
+            (900.0, 900.0),
+        );
+        assert_eq!(viewport.pan, (320.0, 0.0));
+        assert_lands_at(viewport, [0.0, 180.0, 1920.0, 360.0]);
+    }
+}
@@ -0,0 +1,122 @@
+use super::WgpuState;
+use crate::{
+    project::{FrameNum, MediaProject},
+    render::Compositor,
+};
+use anyhow::ensure;
+use image::RgbaImage;
+
+/// Renders one frame of a project on the GPU the way the preview shows it, at
+/// the project's resolution, without a window. A fallback adapter renders in
+/// software, for machines without a GPU or display.
+pub fn render_snapshot(
+    project: &MediaProject,
+    frame: FrameNum,
+    force_fallback_adapter: bool,
+) -> anyhow::Result<RgbaImage> {
+    let mut state = futures_lite::future::block_on(WgpuState::new_offscreen(
+        project.resolution.width,
+        project.resolution.height,
+        force_fallback_adapter,
+    ))?;
+    let preview = Compositor::default().render_preview(project, frame)?;
+    state.write_preview_frame(&preview);
+    state.read_rgba()
+}
+
+/// The largest difference between any channel of the same pixel in two
+/// images of the same size. Different GPUs round a little differently, so
+/// comparisons against a golden image should allow for some.
+pub fn max_difference(actual: &RgbaImage, expected: &RgbaImage) -> anyhow::Result<u8> {
+    ensure!(
+        actual.dimensions() == expected.dimensions(),
+        "image is {}x{}, but expected {}x{}",
+        actual.width(),
+        actual.height(),
+        expected.width(),
+        expected.height()
+    );
+    Ok(actual
+        .iter()
+        .zip(expected.iter())
+        .map(|(a, b)| a.abs_diff(*b))
+        .max()
+        .unwrap_or(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        project::Resolution,
+        render::{YuvColorimetry, YuvLayout, YuvMatrix, YuvRange},
+        test_media,
+    };
+
+    /// How far the GPU may round differently from the CPU, per channel.
+    const GPU_TOLERANCE: u8 = 2;
+    /// The frame of the numbered clip the golden image shows.
+    const GOLDEN_FRAME: u64 = 42;
+    const GOLDEN_IMAGE: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/golden/numbered-frame-42.png"
+    );
+    /// How far a snapshot may stray from the golden image, per channel. This
+    /// allows for the clip's encoding as well as GPU rounding.
+    const GOLDEN_TOLERANCE: u8 = 16;
+    /// Set to rewrite the golden image from the snapshot instead of checking
+    /// it, after a change meant to alter what's rendered.
+    const UPDATE_GOLDEN_VAR: &str = "JADEVID_UPDATE_GOLDEN";
+
+    #[test]
+    fn renders_a_project_like_the_golden_image() {
+        let (project, _) =
+            test_media::numbered_project(test_media::numbered_clip(), test_media::CLIP_FRAMES)
+                .unwrap();
+        let snapshot = render_snapshot(&project, FrameNum(GOLDEN_FRAME), true).unwrap();
+        if std::env::var_os(UPDATE_GOLDEN_VAR).is_some() {
+            snapshot.save(GOLDEN_IMAGE).unwrap();
+            return;
+        }
+
+        let golden = image::open(GOLDEN_IMAGE).unwrap().to_rgba8();
+        let difference = max_difference(&snapshot, &golden).unwrap();
+        assert!(
+            difference <= GOLDEN_TOLERANCE,
+            "snapshot of frame {GOLDEN_FRAME} differs from {GOLDEN_IMAGE} by up to {difference}; \
+             if that's expected, run again with {UPDATE_GOLDEN_VAR}=1 to update it"
+        );
+        assert_eq!(test_media::frame_number_of(&snapshot), GOLDEN_FRAME);
+    }
+
+    #[test]
+    fn gpu_converts_yuv_like_the_cpu() {
+        for (layout, bit_depth) in [
+            (YuvLayout::Planar420, 8),
+            (YuvLayout::SemiPlanar420, 8),
+            (YuvLayout::Planar422, 8),
+            (YuvLayout::Planar420, 10),
+        ] {
+            for (matrix, range) in [
+                (YuvMatrix::Bt601, YuvRange::Limited),
+                (YuvMatrix::Bt709, YuvRange::Full),
+                (YuvMatrix::Bt2020, YuvRange::Limited),
+            ] {
+                let colorimetry = YuvColorimetry { matrix, range };
+                let frame = test_media::yuv_pattern_frame(layout, bit_depth, colorimetry);
+                let (width, height) = (frame.width, frame.height);
+                let mut state =
+                    futures_lite::future::block_on(WgpuState::new_offscreen(width, height, true))
+                        .unwrap();
+                state.write_texture_yuv(&frame, Resolution { width, height }, 0, 0, width, height);
+
+                let difference =
+                    max_difference(&state.read_rgba().unwrap(), &frame.to_rgba()).unwrap();
+                assert!(
+                    difference <= GPU_TOLERANCE,
+                    "{layout:?} at {bit_depth} bits in {colorimetry:?} is off by {difference}"
+                );
+            }
+        }
+    }
+}
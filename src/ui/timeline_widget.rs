@@ -0,0 +1,894 @@
+use super::parse_media_drag;
+use crate::{
+    AppEvent,
+    ff_interop::{filmstrip::Filmstrip, waveform::Waveform},
+    project::{
+        Clip, ClipId, EditCommand, FrameNum, FrameSpan, JadeRational, MediaKey, MediaProject,
+        MediaStream, TrackKind, TrackRef,
+    },
+};
+use anyhow::Context;
+use fltk::{
+    app::{self, MouseWheel, Sender},
+    draw,
+    enums::{Align, Color, ColorDepth, Cursor, Event, Font, Key},
+    prelude::*,
+    widget::Widget,
+};
+use image::{
+    RgbaImage,
+    imageops::{self, FilterType},
+};
+use log::warn;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+};
+
+const RULER_HEIGHT: i32 = 20;
+const HEADER_WIDTH: i32 = 60;
+const TRACK_HEIGHT: i32 = 40;
+/// The height of a clip's body, which is a little less than its track's.
+const CLIP_HEIGHT: i32 = TRACK_HEIGHT - 5;
+/// How close the pointer has to be to the end of a clip, in pixels, to grab
+/// the end for trimming rather than the whole clip for moving.
+const TRIM_HANDLE_WIDTH: f64 = 5.0;
+/// How close a dragged edge has to come to a clip edge or the playhead, in
+/// pixels, to snap to it.
+const SNAP_DISTANCE: f64 = 8.0;
+const MIN_PIXELS_PER_FRAME: f64 = 0.01;
+const MAX_PIXELS_PER_FRAME: f64 = 40.0;
+const ZOOM_STEP: f64 = 1.25;
+const SCROLL_STEP: f64 = 60.0;
+/// Ruler ticks are spaced at least this many pixels apart.
+const MIN_TICK_SPACING: f64 = 80.0;
+
+const BACKGROUND_COLOR: Color = Color::from_rgb(40, 40, 44);
+const RULER_COLOR: Color = Color::from_rgb(60, 60, 66);
+const HEADER_COLOR: Color = Color::from_rgb(52, 52, 58);
+const VIDEO_CLIP_COLOR: Color = Color::from_rgb(70, 110, 170);
+const AUDIO_CLIP_COLOR: Color = Color::from_rgb(70, 150, 95);
+const WAVEFORM_PEAK_COLOR: Color = Color::from_rgb(35, 85, 50);
+const WAVEFORM_RMS_COLOR: Color = Color::from_rgb(160, 220, 175);
+const PLAYHEAD_COLOR: Color = Color::from_rgb(230, 60, 60);
+
+enum Drag {
+    Scrub,
+    Move { original: Clip, grab_offset: i64 },
+    TrimStart { original: Clip },
+    TrimEnd { original: Clip },
+}
+
+/// What part of a clip the pointer is over.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum ClipPart {
+    Start,
+    Body,
+    End,
+}
+
+/// The position and size of the widget, which everything is laid out from.
+#[derive(Copy, Clone)]
+struct Bounds {
+    x: i32,
+    y: i32,
+    w: i32,
+    h: i32,
+}
+
+impl Bounds {
+    fn of(widget: &Widget) -> Self {
+        Self {
+            x: widget.x(),
+            y: widget.y(),
+            w: widget.w(),
+            h: widget.h(),
+        }
+    }
+
+    fn tracks_x(&self) -> i32 {
+        self.x + HEADER_WIDTH
+    }
+
+    fn row_y(&self, row: usize) -> i32 {
+        self.y + RULER_HEIGHT + row as i32 * TRACK_HEIGHT
+    }
+}
+
+struct TimelineState {
+    /// A copy of the open project, refreshed whenever it changes. Edits are
+    /// never made to it directly, only sent out as commands.
+    project: MediaProject,
+    playhead: FrameNum,
+    /// The frame at the left edge of the track area.
+    scroll_frame: f64,
+    pixels_per_frame: f64,
+    selected: Option<ClipId>,
+    drag: Option<Drag>,
+    /// Where something dragged from the media pool would land.
+    drop_position: Option<(i32, i32)>,
+    filmstrips: HashMap<MediaKey, ClipFilmstrip>,
+    /// Waveforms shown along the bodies of audio clips, with the file each
+    /// was measured from.
+    waveforms: HashMap<MediaKey, (PathBuf, Arc<Waveform>)>,
+}
+
+/// A filmstrip shown along the bodies of video clips, with its tiles shrunk
+/// to fit inside them.
+struct ClipFilmstrip {
+    /// The file the filmstrip was made from, so it's dropped if the media is
+    /// relinked.
+    path: PathBuf,
+    filmstrip: Arc<Filmstrip>,
+    tiles: Vec<RgbaImage>,
+}
+
+impl TimelineState {
+    /// Tracks from top to bottom: video tracks with the top-most layer first,
+    /// then audio tracks.
+    fn rows(&self) -> Vec<TrackRef> {
+        let timeline = &self.project.timeline;
+        let video = (0..timeline.tracks(TrackKind::Video).len())
+            .rev()
+            .map(|index| TrackRef {
+                kind: TrackKind::Video,
+                index,
+            });
+        let audio = (0..timeline.tracks(TrackKind::Audio).len()).map(|index| TrackRef {
+            kind: TrackKind::Audio,
+            index,
+        });
+        video.chain(audio).collect()
+    }
+
+    fn row_at(&self, bounds: Bounds, y: i32) -> Option<TrackRef> {
+        if y < bounds.row_y(0) {
+            return None;
+        }
+        let row = ((y - bounds.row_y(0)) / TRACK_HEIGHT) as usize;
+        self.rows().get(row).copied()
+    }
+
+    fn frame_to_x(&self, bounds: Bounds, frame: f64) -> f64 {
+        bounds.tracks_x() as f64 + (frame - self.scroll_frame) * self.pixels_per_frame
+    }
+
+    fn x_to_frame(&self, bounds: Bounds, x: i32) -> f64 {
+        self.scroll_frame + (x - bounds.tracks_x()) as f64 / self.pixels_per_frame
+    }
+
+    /// The frame under the pointer, rounded to the nearest frame boundary.
+    fn frame_at(&self, bounds: Bounds, x: i32) -> i64 {
+        self.x_to_frame(bounds, x).round().max(0.0) as i64
+    }
+
+    fn clip_at(&self, bounds: Bounds, x: i32, y: i32) -> Option<(&Clip, ClipPart)> {
+        let track = self.project.timeline.track(self.row_at(bounds, y)?)?;
+        track.clips().iter().find_map(|clip| {
+            let span = clip.timeline_span();
+            let start_x = self.frame_to_x(bounds, span.from.0 as f64);
+            let end_x = self.frame_to_x(bounds, span.to_excl.0 as f64);
+            if (x as f64) < start_x || (x as f64) > end_x {
+                return None;
+            }
+            let part = if end_x - start_x < TRIM_HANDLE_WIDTH * 3.0 {
+                // Too small to tell the ends apart from the body.
+                ClipPart::Body
+            } else if x as f64 - start_x <= TRIM_HANDLE_WIDTH {
+                ClipPart::Start
+            } else if end_x - x as f64 <= TRIM_HANDLE_WIDTH {
+                ClipPart::End
+            } else {
+                ClipPart::Body
+            };
+            Some((clip, part))
+        })
+    }
+
+    /// Every frame an edge can snap to: the edges of all clips but the one
+    /// being dragged, and the playhead.
+    fn snap_points(&self, dragged: ClipId) -> Vec<i64> {
+        let mut points = vec![self.playhead.0 as i64];
+        for track_ref in self.project.timeline.track_refs() {
+            let Some(track) = self.project.timeline.track(track_ref) else {
+                continue;
+            };
+            for clip in track.clips().iter().filter(|clip| clip.id != dragged) {
+                let span = clip.timeline_span();
+                points.extend([span.from.0 as i64, span.to_excl.0 as i64]);
+            }
+        }
+        points
+    }
+
+    /// How far `frame` needs to move to land on the closest snap point, if any
+    /// is near enough.
+    fn snap_offset(&self, frame: i64, points: &[i64]) -> Option<i64> {
+        let max_distance = (SNAP_DISTANCE / self.pixels_per_frame).max(1.0) as i64;
+        points
+            .iter()
+            .map(|point| point - frame)
+            .filter(|offset| offset.abs() <= max_distance)
+            .min_by_key(|offset| offset.abs())
+    }
+
+    /// The longest a clip of the given source stream can be, or `None` if the
+    /// stream's length isn't known.
+    fn source_frames(&self, clip: &Clip) -> Option<u64> {
+        let info = self.project.media.get(clip.media)?;
+        let stream = info
+            .streams
+            .iter()
+            .find(|stream| stream.info().index == clip.stream_index)?;
+        match stream.info().length.frames_at(self.project.fps) {
+            0 => None,
+            frames => Some(frames),
+        }
+    }
+
+    fn is_free(&self, track: TrackRef, span: &FrameSpan, clip: ClipId) -> bool {
+        self.project
+            .timeline
+            .track(track)
+            .is_some_and(|track| track.is_free(span, Some(clip)))
+    }
+
+    fn drag_move(
+        &self,
+        bounds: Bounds,
+        original: &Clip,
+        grab_offset: i64,
+        x: i32,
+        y: i32,
+    ) -> Option<EditCommand> {
+        let (current_track, current) = self.project.timeline.find_clip(original.id)?;
+        let track = self
+            .row_at(bounds, y)
+            .filter(|track| track.kind == current_track.kind)
+            .unwrap_or(current_track);
+
+        let start = (self.frame_at(bounds, x) - grab_offset).max(0);
+        let points = self.snap_points(original.id);
+        let snap = [
+            self.snap_offset(start, &points),
+            self.snap_offset(start + original.len() as i64, &points),
+        ]
+        .into_iter()
+        .flatten()
+        .min_by_key(|offset| offset.abs());
+        let start = FrameNum((start + snap.unwrap_or(0)).max(0) as u64);
+
+        let span = FrameSpan {
+            from: start,
+            to_excl: FrameNum(start.0 + original.len()),
+        };
+        if (track, start) == (current_track, current.timeline_start)
+            || !self.is_free(track, &span, original.id)
+        {
+            return None;
+        }
+        EditCommand::move_clip(&self.project, original.id, track, start).ok()
+    }
+
+    fn drag_trim_start(&self, bounds: Bounds, original: &Clip, x: i32) -> Option<EditCommand> {
+        let (track, current) = self.project.timeline.find_clip(original.id)?;
+        let mut frame = self.frame_at(bounds, x);
+        frame += self
+            .snap_offset(frame, &self.snap_points(original.id))
+            .unwrap_or(0);
+
+        // Can't start before the source does, before the timeline does, or
+        // at or after the clip's end.
+        let offset = (frame - original.timeline_start.0 as i64)
+            .max(-(original.source.from.0 as i64))
+            .max(-(original.timeline_start.0 as i64))
+            .min(original.len() as i64 - 1);
+        let source = FrameSpan {
+            from: FrameNum((original.source.from.0 as i64 + offset) as u64),
+            to_excl: original.source.to_excl,
+        };
+        let start = FrameNum((original.timeline_start.0 as i64 + offset) as u64);
+        let span = FrameSpan {
+            from: start,
+            to_excl: original.timeline_span().to_excl,
+        };
+        if (source == current.source && start == current.timeline_start)
+            || !self.is_free(track, &span, original.id)
+        {
+            return None;
+        }
+        EditCommand::trim_clip(&self.project, original.id, source, start).ok()
+    }
+
+    fn drag_trim_end(&self, bounds: Bounds, original: &Clip, x: i32) -> Option<EditCommand> {
+        let (track, current) = self.project.timeline.find_clip(original.id)?;
+        let mut frame = self.frame_at(bounds, x);
+        frame += self
+            .snap_offset(frame, &self.snap_points(original.id))
+            .unwrap_or(0);
+
+        let mut len = (frame - original.timeline_start.0 as i64).max(1) as u64;
+        if let Some(source_frames) = self.source_frames(original) {
+            len = len.min(source_frames.saturating_sub(original.source.from.0).max(1));
+        }
+        let source = FrameSpan {
+            from: original.source.from,
+            to_excl: FrameNum(original.source.from.0 + len),
+        };
+        let span = FrameSpan {
+            from: original.timeline_start,
+            to_excl: FrameNum(original.timeline_start.0 + len),
+        };
+        if source == current.source || !self.is_free(track, &span, original.id) {
+            return None;
+        }
+        EditCommand::trim_clip(&self.project, original.id, source, original.timeline_start).ok()
+    }
+
+    /// Builds the command for dropping media from the pool at a point, using
+    /// the media's first stream that fits the track under the point.
+    fn drop_media(
+        &self,
+        bounds: Bounds,
+        text: &str,
+        x: i32,
+        y: i32,
+    ) -> anyhow::Result<EditCommand> {
+        let media =
+            parse_media_drag(text).context("only media from the media pool can be dropped")?;
+        let track = self
+            .row_at(bounds, y)
+            .context("media must be dropped onto a track")?;
+        let info = self
+            .project
+            .media
+            .get(media)
+            .context("dropped media is not in the project")?;
+        let stream = info
+            .streams
+            .iter()
+            .find(|stream| {
+                matches!(
+                    (stream, track.kind),
+                    (MediaStream::Video(..), TrackKind::Video)
+                        | (MediaStream::Audio(..), TrackKind::Audio)
+                )
+            })
+            .with_context(|| format!("media has no stream for a {:?} track", track.kind))?
+            .info();
+        let len = stream.length.frames_at(self.project.fps);
+        let start = FrameNum(self.frame_at(bounds, x) as u64);
+        EditCommand::add_clip(
+            &self.project,
+            track,
+            media,
+            stream.index,
+            FrameSpan::with_len(FrameNum(0), len)?,
+            start,
+        )
+    }
+
+    /// Zooms in or out, keeping the frame under `anchor_x` in place.
+    fn zoom(&mut self, bounds: Bounds, anchor_x: i32, factor: f64) {
+        let anchor_frame = self.x_to_frame(bounds, anchor_x);
+        self.pixels_per_frame =
+            (self.pixels_per_frame * factor).clamp(MIN_PIXELS_PER_FRAME, MAX_PIXELS_PER_FRAME);
+        self.scroll_frame =
+            (anchor_frame - (anchor_x - bounds.tracks_x()) as f64 / self.pixels_per_frame).max(0.0);
+    }
+}
+
+/// Shows the project's tracks and clips along a zoomable, scrollable ruler.
+/// Clips can be moved and trimmed with the mouse, and media dropped on from
+/// the media pool; every change is sent out as an `AppEvent::TimelineEdit`.
+pub struct TimelineWidget {
+    widget: Widget,
+    state: Rc<RefCell<TimelineState>>,
+}
+
+impl TimelineWidget {
+    pub fn new(
+        timeline_group: &mut impl GroupExt,
+        project: &MediaProject,
+        event_sender: Sender<AppEvent>,
+    ) -> Self {
+        let mut widget = Widget::new(
+            timeline_group.x(),
+            timeline_group.y(),
+            timeline_group.w(),
+            timeline_group.h(),
+            None,
+        );
+        timeline_group.add(&widget);
+        timeline_group.resizable(&widget);
+
+        let state = Rc::new(RefCell::new(TimelineState {
+            project: project.clone(),
+            playhead: FrameNum(0),
+            scroll_frame: 0.0,
+            pixels_per_frame: 2.0,
+            selected: None,
+            drag: None,
+            drop_position: None,
+            filmstrips: HashMap::new(),
+            waveforms: HashMap::new(),
+        }));
+
+        widget.draw({
+            let state = state.clone();
+            move |widget| draw_timeline(&state.borrow(), Bounds::of(widget))
+        });
+        widget.handle({
+            let state = state.clone();
+            move |widget, event| {
+                let handled = handle_event(&mut state.borrow_mut(), widget, event, event_sender);
+                if handled {
+                    widget.redraw();
+                }
+                handled
+            }
+        });
+
+        Self { widget, state }
+    }
+
+    pub fn set_project(&mut self, project: &MediaProject) {
+        let mut state = self.state.borrow_mut();
+        state.project = project.clone();
+        state.filmstrips.retain(|key, filmstrip| {
+            project
+                .media
+                .get(*key)
+                .is_some_and(|info| info.path == filmstrip.path)
+        });
+        state.waveforms.retain(|key, (path, _)| {
+            project
+                .media
+                .get(*key)
+                .is_some_and(|info| info.path == *path)
+        });
+        if let Some(selected) = state.selected
+            && state.project.timeline.find_clip(selected).is_none()
+        {
+            state.selected = None;
+        }
+        drop(state);
+        self.widget.redraw();
+    }
+
+    pub fn playhead(&self) -> FrameNum {
+        self.state.borrow().playhead
+    }
+
+    pub fn set_playhead(&mut self, frame: FrameNum) {
+        self.state.borrow_mut().playhead = frame;
+        self.widget.redraw();
+    }
+
+    /// Shows a filmstrip along the clips of the media it was made from,
+    /// unless the media has gone or changed file since it was asked for.
+    pub fn set_filmstrip(&mut self, key: MediaKey, path: &Path, filmstrip: Arc<Filmstrip>) {
+        let mut state = self.state.borrow_mut();
+        if state
+            .project
+            .media
+            .get(key)
+            .is_none_or(|info| info.path != path)
+        {
+            return;
+        }
+
+        // Tiles sit inside the clip's border.
+        let height = (CLIP_HEIGHT - 2) as u32;
+        let tiles = filmstrip
+            .tiles
+            .iter()
+            .map(|tile| {
+                let width = (tile.width() * height / tile.height().max(1)).max(1);
+                imageops::resize(tile, width, height, FilterType::Triangle)
+            })
+            .collect();
+        state.filmstrips.insert(
+            key,
+            ClipFilmstrip {
+                path: path.to_path_buf(),
+                filmstrip,
+                tiles,
+            },
+        );
+        drop(state);
+        self.widget.redraw();
+    }
+
+    /// Shows a waveform along the clips of the media it was measured from,
+    /// unless the media has gone or changed file since it was asked for.
+    pub fn set_waveform(&mut self, key: MediaKey, path: &Path, waveform: Arc<Waveform>) {
+        let mut state = self.state.borrow_mut();
+        if state
+            .project
+            .media
+            .get(key)
+            .is_none_or(|info| info.path != path)
+        {
+            return;
+        }
+        state.waveforms.insert(key, (path.to_path_buf(), waveform));
+        drop(state);
+        self.widget.redraw();
+    }
+}
+
+fn handle_event(
+    state: &mut TimelineState,
+    widget: &mut Widget,
+    event: Event,
+    event_sender: Sender<AppEvent>,
+) -> bool {
+    let bounds = Bounds::of(widget);
+    let (x, y) = app::event_coords();
+
+    match event {
+        Event::Focus | Event::Unfocus => true,
+        Event::Push => {
+            let _ = widget.take_focus();
+            if y < bounds.row_y(0) {
+                state.drag = Some(Drag::Scrub);
+                scrub(state, bounds, x, event_sender);
+                return true;
+            }
+
+            match state.clip_at(bounds, x, y) {
+                Some((clip, part)) => {
+                    let original = clip.clone();
+                    state.selected = Some(original.id);
+                    state.drag = Some(match part {
+                        ClipPart::Start => Drag::TrimStart { original },
+                        ClipPart::End => Drag::TrimEnd { original },
+                        ClipPart::Body => Drag::Move {
+                            grab_offset: state.frame_at(bounds, x)
+                                - original.timeline_start.0 as i64,
+                            original,
+                        },
+                    });
+                    event_sender.send(AppEvent::TimelineDragStarted);
+                }
+                None => state.selected = None,
+            }
+            true
+        }
+        Event::Drag => {
+            if let Some(Drag::Scrub) = state.drag {
+                scrub(state, bounds, x, event_sender);
+                return true;
+            }
+            let command = match &state.drag {
+                Some(Drag::Move {
+                    original,
+                    grab_offset,
+                }) => state.drag_move(bounds, original, *grab_offset, x, y),
+                Some(Drag::TrimStart { original }) => state.drag_trim_start(bounds, original, x),
+                Some(Drag::TrimEnd { original }) => state.drag_trim_end(bounds, original, x),
+                Some(Drag::Scrub) | None => None,
+            };
+            if let Some(command) = command {
+                event_sender.send(AppEvent::TimelineEdit(command));
+            }
+            true
+        }
+        Event::Released => {
+            if let Some(Drag::Move { .. } | Drag::TrimStart { .. } | Drag::TrimEnd { .. }) =
+                state.drag.take()
+            {
+                event_sender.send(AppEvent::TimelineDragEnded);
+            }
+            true
+        }
+        Event::Move => {
+            let cursor = match state.clip_at(bounds, x, y) {
+                Some((_, ClipPart::Start | ClipPart::End)) => Cursor::WE,
+                _ => Cursor::Default,
+            };
+            draw::set_cursor(cursor);
+            false
+        }
+        Event::Leave => {
+            draw::set_cursor(Cursor::Default);
+            false
+        }
+        Event::MouseWheel => {
+            let steps = match app::event_dy() {
+                MouseWheel::Up => -1.0,
+                MouseWheel::Down => 1.0,
+                _ => 0.0,
+            };
+            if app::is_event_ctrl() {
+                state.zoom(bounds, x, ZOOM_STEP.powf(-steps));
+            } else {
+                state.scroll_frame =
+                    (state.scroll_frame + steps * SCROLL_STEP / state.pixels_per_frame).max(0.0);
+            }
+            true
+        }
+        Event::KeyDown => match app::event_key() {
+            Key::Delete | Key::BackSpace => {
+                if let Some(selected) = state.selected {
+                    match EditCommand::remove_clip(&state.project, selected) {
+                        Ok(command) => event_sender.send(AppEvent::TimelineEdit(command)),
+                        Err(err) => warn!("failed to delete clip: {err:#}"),
+                    }
+                }
+                true
+            }
+            _ => false,
+        },
+        Event::DndEnter | Event::DndDrag | Event::DndRelease => {
+            state.drop_position = Some((x, y));
+            true
+        }
+        Event::DndLeave => {
+            state.drop_position = None;
+            true
+        }
+        Event::Paste => {
+            let Some((drop_x, drop_y)) = state.drop_position.take() else {
+                return false;
+            };
+            match state.drop_media(bounds, &app::event_text(), drop_x, drop_y) {
+                Ok(command) => event_sender.send(AppEvent::TimelineEdit(command)),
+                Err(err) => warn!("failed to drop media onto timeline: {err:#}"),
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+fn scrub(state: &mut TimelineState, bounds: Bounds, x: i32, event_sender: Sender<AppEvent>) {
+    let frame = FrameNum(state.frame_at(bounds, x) as u64);
+    if frame != state.playhead {
+        state.playhead = frame;
+        event_sender.send(AppEvent::TimelineSeek(frame));
+    }
+}
+
+fn draw_timeline(state: &TimelineState, bounds: Bounds) {
+    draw::push_clip(bounds.x, bounds.y, bounds.w, bounds.h);
+    draw::draw_rect_fill(bounds.x, bounds.y, bounds.w, bounds.h, BACKGROUND_COLOR);
+    draw::set_font(Font::Helvetica, 11);
+
+    draw_ruler(state, bounds);
+
+    let track_area_w = bounds.w - HEADER_WIDTH;
+    for (row, track_ref) in state.rows().into_iter().enumerate() {
+        let Some(track) = state.project.timeline.track(track_ref) else {
+            continue;
+        };
+        let row_y = bounds.row_y(row);
+
+        draw::draw_rect_fill(bounds.x, row_y, HEADER_WIDTH, TRACK_HEIGHT, HEADER_COLOR);
+        draw::set_draw_color(Color::Light2);
+        draw::draw_text2(
+            &track.name,
+            bounds.x + 4,
+            row_y,
+            HEADER_WIDTH - 8,
+            TRACK_HEIGHT,
+            Align::Left | Align::Inside,
+        );
+        draw::set_draw_color(Color::Dark3);
+        draw::draw_line(
+            bounds.x,
+            row_y + TRACK_HEIGHT - 1,
+            bounds.x + bounds.w,
+            row_y + TRACK_HEIGHT - 1,
+        );
+
+        draw::push_clip(bounds.tracks_x(), row_y, track_area_w, TRACK_HEIGHT);
+        for clip in track.clips() {
+            draw_clip(state, bounds, clip, track_ref.kind, row_y);
+        }
+        draw::pop_clip();
+    }
+
+    draw::push_clip(bounds.tracks_x(), bounds.y, track_area_w, bounds.h);
+    if let Some((drop_x, _)) = state.drop_position {
+        draw::set_draw_color(Color::Yellow);
+        draw::draw_line(drop_x, bounds.row_y(0), drop_x, bounds.y + bounds.h);
+    }
+    let playhead_x = state.frame_to_x(bounds, state.playhead.0 as f64).round() as i32;
+    draw::set_draw_color(PLAYHEAD_COLOR);
+    draw::draw_line(playhead_x, bounds.y, playhead_x, bounds.y + bounds.h);
+    draw::pop_clip();
+
+    draw::pop_clip();
+}
+
+fn draw_ruler(state: &TimelineState, bounds: Bounds) {
+    draw::draw_rect_fill(bounds.x, bounds.y, bounds.w, RULER_HEIGHT, RULER_COLOR);
+    draw::push_clip(
+        bounds.tracks_x(),
+        bounds.y,
+        bounds.w - HEADER_WIDTH,
+        RULER_HEIGHT,
+    );
+
+    let fps = state.project.fps;
+    let interval = tick_interval(fps, state.pixels_per_frame);
+    let first_frame = state.scroll_frame as u64 / interval * interval;
+    let last_frame = state.x_to_frame(bounds, bounds.x + bounds.w).ceil() as u64;
+    draw::set_draw_color(Color::Light2);
+    for frame in (first_frame..=last_frame).step_by(interval as usize) {
+        let x = state.frame_to_x(bounds, frame as f64).round() as i32;
+        draw::draw_line(x, bounds.y + RULER_HEIGHT - 6, x, bounds.y + RULER_HEIGHT);
+        draw::draw_text2(
+            &FrameNum(frame).timecode(fps).to_string(),
+            x + 3,
+            bounds.y,
+            MIN_TICK_SPACING as i32,
+            RULER_HEIGHT - 4,
+            Align::Left | Align::Inside,
+        );
+    }
+
+    draw::pop_clip();
+}
+
+fn draw_clip(state: &TimelineState, bounds: Bounds, clip: &Clip, kind: TrackKind, row_y: i32) {
+    let span = clip.timeline_span();
+    let start_x = state.frame_to_x(bounds, span.from.0 as f64).round() as i32;
+    let end_x = state.frame_to_x(bounds, span.to_excl.0 as f64).round() as i32;
+    if end_x < bounds.tracks_x() || start_x > bounds.x + bounds.w {
+        return;
+    }
+    let (clip_y, clip_h) = (row_y + 2, CLIP_HEIGHT);
+    let clip_w = (end_x - start_x).max(1);
+
+    let color = match kind {
+        TrackKind::Video => VIDEO_CLIP_COLOR,
+        TrackKind::Audio => AUDIO_CLIP_COLOR,
+    };
+    draw::draw_rect_fill(start_x, clip_y, clip_w, clip_h, color);
+    match kind {
+        TrackKind::Video => draw_clip_filmstrip(state, bounds, clip, start_x, clip_y, clip_w),
+        TrackKind::Audio => draw_clip_waveform(state, bounds, clip, start_x, clip_y, clip_w),
+    }
+    let border = if state.selected == Some(clip.id) {
+        Color::White
+    } else {
+        color.darker()
+    };
+    draw::set_draw_color(border);
+    draw::draw_rect(start_x, clip_y, clip_w, clip_h);
+
+    if let Some(info) = state.project.media.get(clip.media) {
+        draw::push_clip(start_x, clip_y, clip_w, clip_h);
+        draw::set_draw_color(Color::White);
+        draw::draw_text2(
+            &info.name,
+            start_x.max(bounds.tracks_x()) + 4,
+            clip_y,
+            clip_w,
+            clip_h,
+            Align::Left | Align::Inside,
+        );
+        draw::pop_clip();
+    }
+}
+
+/// Tiles the body of a video clip with its media's filmstrip, if there is one,
+/// each tile showing the frame at its left edge.
+fn draw_clip_filmstrip(
+    state: &TimelineState,
+    bounds: Bounds,
+    clip: &Clip,
+    start_x: i32,
+    clip_y: i32,
+    clip_w: i32,
+) {
+    let Some(strip) = state.filmstrips.get(&clip.media) else {
+        return;
+    };
+    let Some(tile_w) = strip.tiles.first().map(|tile| tile.width() as i32) else {
+        return;
+    };
+    if strip.filmstrip.stream_index != clip.stream_index {
+        return;
+    }
+
+    let (tile_y, tile_h) = (clip_y + 1, CLIP_HEIGHT - 2);
+    draw::push_clip(start_x + 1, tile_y, clip_w - 2, tile_h);
+    // Start from the first tile that's on screen.
+    let skipped = (bounds.tracks_x() - start_x).max(0) / tile_w;
+    let mut x = start_x + skipped * tile_w;
+    while x < (start_x + clip_w).min(bounds.x + bounds.w) {
+        let offset = state.x_to_frame(bounds, x) - clip.timeline_start.0 as f64;
+        let source_frame = FrameNum(clip.source.from.0 + offset.max(0.0) as u64);
+        let tile = strip
+            .filmstrip
+            .tile_index_at_secs(source_frame.secs(state.project.fps))
+            .and_then(|index| strip.tiles.get(index));
+        if let Some(tile) = tile {
+            let _ = draw::draw_image(
+                tile.as_raw(),
+                x,
+                tile_y,
+                tile.width() as i32,
+                tile.height() as i32,
+                ColorDepth::Rgba8,
+            );
+        }
+        x += tile_w;
+    }
+    draw::pop_clip();
+}
+
+/// Draws the waveform of an audio clip's media across its body, if it's been
+/// measured: the peaks in a dark shade, with the RMS level over them in a light
+/// one.
+fn draw_clip_waveform(
+    state: &TimelineState,
+    bounds: Bounds,
+    clip: &Clip,
+    start_x: i32,
+    clip_y: i32,
+    clip_w: i32,
+) {
+    let Some((_, waveform)) = state.waveforms.get(&clip.media) else {
+        return;
+    };
+    if waveform.stream_index != clip.stream_index {
+        return;
+    }
+
+    // Only the whole frames that are on screen are measured, since clips can
+    // be far wider than the widget.
+    let visible_start = start_x.max(bounds.tracks_x());
+    let visible_end = (start_x + clip_w).min(bounds.x + bounds.w);
+    let first = (state.x_to_frame(bounds, visible_start).floor() as u64)
+        .clamp(clip.timeline_start.0, clip.timeline_span().to_excl.0);
+    let last = (state.x_to_frame(bounds, visible_end).ceil() as u64)
+        .clamp(first, clip.timeline_span().to_excl.0);
+    let span_x = state.frame_to_x(bounds, first as f64).round() as i32;
+    let width = (state.frame_to_x(bounds, last as f64).round() as i32 - span_x).max(0);
+    let Ok(source) = FrameSpan::with_len(
+        FrameNum(clip.source.from.0 + (first - clip.timeline_start.0)),
+        last - first,
+    ) else {
+        return;
+    };
+    let peaks = waveform.peaks_for(&source, state.project.fps, width as usize);
+
+    let (wave_y, wave_h) = (clip_y + 2, CLIP_HEIGHT - 4);
+    let center = wave_y as f32 + wave_h as f32 / 2.0;
+    let half = wave_h as f32 / 2.0;
+    let to_y = |level: f32| (center - level.clamp(-1.0, 1.0) * half).round() as i32;
+    draw::push_clip(start_x + 1, wave_y, clip_w - 2, wave_h);
+    for (column, peak) in peaks.iter().enumerate() {
+        let x = span_x + column as i32;
+        draw::set_draw_color(WAVEFORM_PEAK_COLOR);
+        draw::draw_line(x, to_y(peak.max), x, to_y(peak.min));
+        draw::set_draw_color(WAVEFORM_RMS_COLOR);
+        draw::draw_line(x, to_y(peak.rms), x, to_y(-peak.rms));
+    }
+    draw::pop_clip();
+}
+
+/// Picks the spacing between ruler ticks, in frames: the shortest "round"
+/// interval that leaves at least `MIN_TICK_SPACING` pixels between ticks.
+fn tick_interval(fps: JadeRational, pixels_per_frame: f64) -> u64 {
+    let frames_per_sec = fps.num as f64 / fps.den as f64;
+    let frame_intervals = [1.0, 2.0, 5.0, 10.0];
+    let sec_intervals = [
+        1.0, 2.0, 5.0, 10.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 900.0, 1800.0, 3600.0,
+    ];
+    frame_intervals
+        .into_iter()
+        .filter(|frames| *frames < frames_per_sec)
+        .chain(sec_intervals.map(|secs| (secs * frames_per_sec).round()))
+        .find(|frames| frames * pixels_per_frame >= MIN_TICK_SPACING)
+        .unwrap_or(3600.0 * frames_per_sec)
+        .max(1.0) as u64
+}
@@ -0,0 +1,87 @@
+// Draws a YUV frame, converting it to RGB on the way. The sums match
+// `YuvConversion::to_rgb`, which does the same on the CPU.
+
+struct YuvParams {
+    // Rows of the matrix from (Y', Cb, Cr) to R'G'B'. The last lane is unused.
+    matrix_r: vec4<f32>,
+    matrix_g: vec4<f32>,
+    matrix_b: vec4<f32>,
+    // Subtracted from the Y, U and V codes, then multiplied by `scales`.
+    offsets: vec4<f32>,
+    scales: vec4<f32>,
+    // Whether U and V are interleaved in one plane, then the chroma shifts.
+    plane_layout: vec4<u32>,
+};
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var t_y: texture_2d<u32>;
+@group(0) @binding(1)
+var t_u: texture_2d<u32>;
+@group(0) @binding(2)
+var t_v: texture_2d<u32>;
+@group(0) @binding(3)
+var<uniform> params: YuvParams;
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.clip_position = vec4<f32>(model.position, 1.0);
+    return out;
+}
+
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    let low = color / 12.92;
+    let high = pow((color + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, color <= vec3<f32>(0.04045));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Samples are read exactly rather than filtered, and chroma from the
+    // nearest sample, like the CPU path.
+    let size = textureDimensions(t_y);
+    let pixel = min(vec2<u32>(in.tex_coords * vec2<f32>(size)), size - 1u);
+    let chroma_pixel = vec2<u32>(
+        pixel.x >> params.plane_layout.y,
+        pixel.y >> params.plane_layout.z,
+    );
+
+    let y = f32(textureLoad(t_y, pixel, 0).r);
+    var u: f32;
+    var v: f32;
+    if params.plane_layout.x == 1u {
+        let uv = textureLoad(t_u, chroma_pixel, 0);
+        u = f32(uv.r);
+        v = f32(uv.g);
+    } else {
+        u = f32(textureLoad(t_u, chroma_pixel, 0).r);
+        v = f32(textureLoad(t_v, chroma_pixel, 0).r);
+    }
+
+    let yuv = (vec3<f32>(y, u, v) - params.offsets.xyz) * params.scales.xyz;
+    let rgb = clamp(
+        vec3<f32>(
+            dot(params.matrix_r.xyz, yuv),
+            dot(params.matrix_g.xyz, yuv),
+            dot(params.matrix_b.xyz, yuv),
+        ),
+        vec3<f32>(0.0),
+        vec3<f32>(1.0),
+    );
+    // RGBA frames are uploaded as sRGB textures and so come out of sampling
+    // linear. Doing the same here keeps both paths looking alike.
+    return vec4<f32>(srgb_to_linear(rgb), 1.0);
+}